@@ -1,12 +1,20 @@
 use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 
 use clap::{Args, Parser, Subcommand};
 use strum::IntoEnumIterator;
-use tracing::{error, Level};
+use tracing::{error, warn, Level};
 
+use chip8::assembly::diagnostic::Diagnostic;
+use chip8::assembly::format;
 use chip8::assembly::lexer::Lexer;
-use chip8::instructions::Instruction;
+use chip8::assembly::linker;
+use chip8::assembly::parser::ParserOptions;
+use chip8::assembly::testing::{AssertionStatus, TestRunner};
+use chip8::assembly::StreamError;
+use chip8::disassembly::{diff, disassemble, Decoded, Disassembly, DisassemblyOptions, Line};
+use chip8::emulator::START_ADDR;
+use chip8::instructions::{Instruction, Target};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -30,19 +38,72 @@ struct CliArgs {
 enum Commands {
     Asm(AssemblyCommands),
     Disasm(DisassembleCommands),
+    Diff(DiffCommands),
     Instr,
+    Test(TestCommands),
 }
 
 #[derive(Debug, Args)]
 struct AssemblyCommands {
-    #[arg(short, long)]
-    input: Option<String>,
+    #[arg(short, long, num_args = 1.., help = "files to assemble and link in order")]
+    input: Vec<String>,
 
     #[arg(short, long)]
     ast: bool,
 
+    #[arg(long, value_enum, help = "format of the printed ast, implies --ast")]
+    ast_format: Option<AstFormat>,
+
     #[arg(short, long)]
     output: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RomFormat::Bin)]
+    #[arg(help = "format of the written rom")]
+    format: RomFormat,
+
+    #[arg(long, default_value = "ROM", help = "name of the rust or c array")]
+    array_name: String,
+
+    #[arg(long, help = "accept mnemonics and registers regardless of case")]
+    ignore_case: bool,
+
+    #[arg(long, help = "require commas between instruction arguments")]
+    require_commas: bool,
+
+    #[arg(long, default_value_t = 'r', help = "prefix used for registers")]
+    register_prefix: char,
+
+    #[arg(long, help = "disable the pseudo-instructions nop, inc, dec and mov")]
+    no_pseudo: bool,
+
+    #[arg(short = 'D', long = "define", value_name = "KEY=VALUE")]
+    #[arg(help = "define a symbol for %ifdef and equ, the value defaults to 1")]
+    defines: Vec<String>,
+
+    #[arg(
+        long,
+        help = "platform to assemble for, enables the schip mnemonics and rejects unavailable instructions"
+    )]
+    target: Option<Target>,
+
+    #[arg(
+        long,
+        help = "assemble a single file in two passes without buffering it"
+    )]
+    stream: bool,
+
+    #[arg(short, long, help = "print a listing of addresses and opcodes")]
+    listing: bool,
+
+    #[arg(long, help = "write the font defined by font directives to this file")]
+    font_output: Option<String>,
+
+    #[arg(long, help = "write the label addresses to this symbol file")]
+    symbols: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Plain)]
+    #[arg(help = "format of reported parse and assemble errors")]
+    error_format: ErrorFormat,
 }
 
 #[derive(Debug, Args)]
@@ -55,6 +116,120 @@ struct DisassembleCommands {
 
     #[arg(short, long)]
     output: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RomFormat::Bin)]
+    #[arg(help = "format of the read rom")]
+    format: RomFormat,
+
+    #[arg(long, help = "keep absolute addresses instead of synthesizing labels")]
+    no_labels: bool,
+
+    #[arg(long, help = "prefix each line with its address and opcode bytes")]
+    addresses: bool,
+
+    #[arg(long, help = "don't preview data drawn as sprites in comments")]
+    no_sprites: bool,
+
+    #[arg(long, help = "comment each instruction with what it does")]
+    describe: bool,
+
+    #[arg(
+        long,
+        help = "report the extension opcodes and the platform the rom requires"
+    )]
+    detect_target: bool,
+
+    #[arg(
+        long,
+        help = "print instruction, register and address usage counts instead"
+    )]
+    stats: bool,
+
+    #[arg(long, help = "name addresses with the symbols of this file")]
+    symbols: Option<String>,
+
+    #[arg(
+        long,
+        help = "append a table of the instructions referencing each address"
+    )]
+    xref: bool,
+
+    #[arg(long, value_enum, help = "write the call graph of subroutines instead")]
+    call_graph: Option<GraphFormat>,
+
+    #[arg(short, long, help = "follow the control flow from the start address")]
+    #[arg(long_help = "follow jumps, calls and skips from the start address, \
+                       and emit unreachable bytes as data")]
+    recursive: bool,
+
+    #[arg(long, default_value = "0", value_parser = parse_offset)]
+    #[arg(help = "offset in the rom where decoding starts, e.g. 0x40")]
+    start: usize,
+
+    #[arg(long, value_parser = parse_offset)]
+    #[arg(help = "offset in the rom where decoding stops, exclusive")]
+    end: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct DiffCommands {
+    #[arg(help = "the original rom")]
+    old: String,
+
+    #[arg(help = "the changed rom")]
+    new: String,
+
+    #[arg(short, long, value_enum, default_value_t = RomFormat::Bin)]
+    #[arg(help = "format of the read roms")]
+    format: RomFormat,
+
+    #[arg(short, long, help = "follow the control flow from the start address")]
+    recursive: bool,
+}
+
+#[derive(Debug, Args)]
+struct TestCommands {
+    #[arg(short, long)]
+    input: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 100_000,
+        help = "cycles to run before giving up"
+    )]
+    max_cycles: usize,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum AstFormat {
+    Debug,
+    Json,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum RomFormat {
+    /// Raw binary
+    Bin,
+    /// Whitespace-separated hex bytes
+    Hex,
+    /// Intel HEX records
+    Ihex,
+    /// Rust byte array constant, only for writing
+    Rust,
+    /// C byte array, only for writing
+    C,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum ErrorFormat {
+    Plain,
+    Json,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
@@ -99,6 +274,12 @@ fn main() {
         Some(Commands::Disasm(a)) => {
             run_disassembler(a, &args);
         }
+        Some(Commands::Diff(a)) => {
+            run_diff(a, &args);
+        }
+        Some(Commands::Test(a)) => {
+            run_tests(a, &args);
+        }
         Some(Commands::Instr) => {
             let mut longest = 0;
             for i in Instruction::iter() {
@@ -119,8 +300,17 @@ fn main() {
     };
 }
 
-fn run_assembler(args: &AssemblyCommands, _global_args: &CliArgs) {
-    let lexer: Box<dyn Lexer> = if let Some(f) = &args.input {
+/// Parses a decimal or `0x` prefixed hex offset
+fn parse_offset(value: &str) -> Result<usize, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn create_lexer(input: Option<&String>) -> Box<dyn Lexer> {
+    if let Some(f) = input {
         let file = File::open(f).unwrap();
         let lexer = chip8::assembly::lexer::StreamLexer::new(file);
         Box::new(lexer)
@@ -128,63 +318,406 @@ fn run_assembler(args: &AssemblyCommands, _global_args: &CliArgs) {
         let reader = BufReader::new(io::stdin());
         let lexer = chip8::assembly::lexer::StreamLexer::new(reader);
         Box::new(lexer)
+    }
+}
+
+fn run_assembler(args: &AssemblyCommands, _global_args: &CliArgs) {
+    let options = ParserOptions {
+        case_sensitive: !args.ignore_case,
+        require_commas: args.require_commas,
+        register_prefix: args.register_prefix,
+        pseudo_instructions: !args.no_pseudo,
+        defines: args
+            .defines
+            .iter()
+            .map(|d| match d.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (d.clone(), "1".to_string()),
+            })
+            .collect(),
+        target: args.target,
+    };
+    if args.stream {
+        run_streaming_assembler(args, options);
+        return;
+    }
+
+    let assembly = if args.input.len() > 1 {
+        let mut objects = Vec::new();
+        for f in &args.input {
+            let lexer = create_lexer(Some(f));
+            let mut parser =
+                chip8::assembly::parser::Parser::new_with_options(lexer, options.clone())
+                    .with_file(f);
+            match parser.parse_object() {
+                Ok(object) => objects.push(object),
+                Err(e) => {
+                    let diagnostic = Diagnostic::from(&e).with_file(f);
+                    report_error(args, diagnostic, "failed to parse assembly");
+                    return;
+                }
+            }
+        }
+        match linker::link(objects) {
+            Ok(asm) => asm,
+            Err(e) => {
+                report_error(args, (&e).into(), "failed to link assembly");
+                return;
+            }
+        }
+    } else {
+        let lexer = create_lexer(args.input.first());
+        let mut parser = chip8::assembly::parser::Parser::new_with_options(lexer, options);
+        if let Some(f) = args.input.first() {
+            parser = parser.with_file(f);
+        }
+        match parser.parse() {
+            Ok(asm) => asm,
+            Err(e) => {
+                let diagnostic = Diagnostic::from(&e);
+                let diagnostic = match args.input.first() {
+                    Some(f) => diagnostic.with_file(f),
+                    None => diagnostic,
+                };
+                report_error(args, diagnostic, "failed to parse assembly");
+                return;
+            }
+        }
+    };
+
+    if args.ast || args.ast_format.is_some() {
+        match args.ast_format {
+            Some(AstFormat::Json) => match serde_json::to_string_pretty(&assembly) {
+                Ok(json) => println!("{}", json),
+                Err(e) => error!("failed to serialize ast: {}", e.to_string()),
+            },
+            _ => {
+                for i in assembly.instructions {
+                    println!("{:?}", i);
+                }
+            }
+        }
+        return;
+    }
+
+    let binary = match assembly.binary() {
+        Ok(binary) => binary,
+        Err(e) => {
+            report_error(args, (&e).into(), "failed to assemble binary");
+            return;
+        }
+    };
+
+    if let Some(output) = &args.font_output {
+        match &assembly.font {
+            Some(font) => {
+                let mut file = File::create(output).unwrap();
+                file.write_all(font.as_flattened()).unwrap();
+            }
+            None => error!("no font directives, not writing '{}'", output),
+        }
+    }
+
+    if let Some(symbols) = &args.symbols {
+        let mut file = File::create(symbols).unwrap();
+        file.write_all(format::to_symbols(&assembly.symbols()).as_bytes())
+            .unwrap();
+    }
+
+    if args.listing {
+        for (index, (parsed, opcode)) in assembly
+            .instructions
+            .iter()
+            .zip(binary.chunks(2))
+            .enumerate()
+        {
+            for comment in assembly.comments.iter().filter(|c| c.index == index) {
+                println!(";{}", comment.text);
+            }
+            let instruction =
+                Instruction::from_opcode_u8(opcode[0], opcode[1]).unwrap_or(parsed.instruction);
+            let line = format!(
+                "0x{:04x}  {:02x}{:02x}  {}",
+                START_ADDR + index * 2,
+                opcode[0],
+                opcode[1],
+                instruction.to_assembly()
+            );
+            match (&parsed.expanded_from, &parsed.comment) {
+                (Some(pseudo), _) => println!("{:<32}; expanded from '{}'", line, pseudo),
+                (None, Some(comment)) => println!("{:<32};{}", line, comment),
+                (None, None) => println!("{}", line),
+            }
+        }
+        return;
+    }
+    let output = match args.format {
+        RomFormat::Bin => binary,
+        RomFormat::Hex => format::to_hex(&binary).into_bytes(),
+        RomFormat::Ihex => format::to_intel_hex(&binary).into_bytes(),
+        RomFormat::Rust => format::to_rust_array(&binary, &args.array_name).into_bytes(),
+        RomFormat::C => format::to_c_array(&binary, &args.array_name).into_bytes(),
+    };
+    if let Some(output_file) = &args.output {
+        let mut file = File::create(output_file).unwrap();
+        file.write_all(output.as_ref()).unwrap();
+    } else {
+        let mut stdout = io::stdout();
+        stdout.write_all(output.as_ref()).unwrap();
+    }
+}
+
+fn run_streaming_assembler(args: &AssemblyCommands, options: ParserOptions) {
+    let [input] = args.input.as_slice() else {
+        error!("--stream requires exactly one input file, as it's read twice");
+        return;
+    };
+    if !matches!(args.format, RomFormat::Bin) {
+        error!("--stream only writes the bin format");
+        return;
+    }
+    let parser = || {
+        chip8::assembly::parser::Parser::new_with_options(
+            create_lexer(Some(input)),
+            options.clone(),
+        )
+        .with_file(input)
+    };
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(f) => Box::new(BufWriter::new(File::create(f).unwrap())),
+        None => Box::new(io::stdout()),
+    };
+    let result = chip8::assembly::write_binary(parser, &mut writer);
+    let diagnostic = match result {
+        Ok(_) => return,
+        Err(StreamError::Parsing(e)) => Diagnostic::from(&e).with_file(input),
+        Err(StreamError::Binary(e)) => Diagnostic::from(&e),
+        Err(StreamError::IO(e)) => {
+            error!("failed to write binary: {}", e.to_string());
+            return;
+        }
     };
+    report_error(args, diagnostic, "failed to assemble");
+}
+
+fn report_error(args: &AssemblyCommands, diagnostic: Diagnostic, context: &str) {
+    match args.error_format {
+        ErrorFormat::Plain => error!("{}: {}", context, diagnostic),
+        ErrorFormat::Json => match serde_json::to_string(&diagnostic) {
+            Ok(json) => eprintln!("{}", json),
+            Err(e) => error!("failed to serialize diagnostic: {}", e.to_string()),
+        },
+    }
+}
+
+fn run_tests(args: &TestCommands, _global_args: &CliArgs) {
+    let lexer = create_lexer(args.input.as_ref());
     let mut parser = chip8::assembly::parser::Parser::new(lexer);
+    if let Some(f) = &args.input {
+        parser = parser.with_file(f);
+    }
     let assembly = match parser.parse() {
         Ok(asm) => asm,
         Err(e) => {
             error!("failed to parse assembly: {}", e.to_string());
-            return;
+            std::process::exit(1);
         }
     };
 
-    if args.ast {
-        for i in assembly.instructions {
-            println!("{:?}", i);
+    let runner = TestRunner::new().with_max_cycles(args.max_cycles);
+    let report = match runner.run(&assembly) {
+        Ok(report) => report,
+        Err(e) => {
+            error!("failed to run tests: {}", e.to_string());
+            std::process::exit(1);
         }
-        return;
+    };
+
+    for result in &report.results {
+        let status = match result.status {
+            AssertionStatus::Passed => "PASS",
+            AssertionStatus::Failed => "FAIL",
+            AssertionStatus::NotReached => "SKIP",
+        };
+        let assertion = &result.assertion;
+        let line = format!(
+            "{} line {}: assert {}",
+            status,
+            assertion.line + 1,
+            assertion.condition
+        );
+        match &assertion.message {
+            Some(message) => println!("{} ({})", line, message),
+            None => println!("{}", line),
+        }
+    }
+    let passed = report
+        .results
+        .iter()
+        .filter(|r| r.status == AssertionStatus::Passed)
+        .count();
+    println!(
+        "{}/{} assertions passed in {} cycles",
+        passed,
+        report.results.len(),
+        report.cycles
+    );
+    if !report.passed() {
+        std::process::exit(1);
     }
+}
 
-    let binary = assembly.binary().unwrap();
-    if let Some(output) = &args.output {
-        let mut file = File::create(output).unwrap();
-        file.write_all(binary.as_ref()).unwrap();
-    } else {
-        let mut stdout = io::stdout();
-        stdout.write_all(binary.as_ref()).unwrap();
+/// Reads and decodes the rom, errors are logged
+fn read_rom(mut reader: Box<dyn Read>, format: &RomFormat) -> Option<Vec<u8>> {
+    let mut input: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut input).unwrap();
+
+    let decoded = match format {
+        RomFormat::Bin => Ok(input),
+        RomFormat::Hex => format::from_hex(&String::from_utf8_lossy(&input)),
+        RomFormat::Ihex => format::from_intel_hex(&String::from_utf8_lossy(&input)),
+        RomFormat::Rust | RomFormat::C => {
+            error!("array source can't be disassembled, use bin, hex or ihex");
+            return None;
+        }
+    };
+    match decoded {
+        Ok(buffer) => Some(buffer),
+        Err(e) => {
+            error!("failed to read rom: {}", e.to_string());
+            None
+        }
     }
 }
 
+fn run_diff(args: &DiffCommands, _global_args: &CliArgs) {
+    let options = DisassemblyOptions {
+        recursive: args.recursive,
+        ..Default::default()
+    };
+    let mut disassemblies = Vec::new();
+    for f in [&args.old, &args.new] {
+        let reader = Box::new(File::open(f).unwrap());
+        let Some(rom) = read_rom(reader, &args.format) else {
+            return;
+        };
+        disassemblies.push(disassemble(&rom, &options));
+    }
+    let [old, new] = disassemblies.as_slice() else {
+        unreachable!()
+    };
+
+    let format = |disassembly: &Disassembly, line: &Line| {
+        let hex: String = line.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{:<4}  {}", hex, disassembly.format_line(line))
+    };
+    let differences = diff(old, new);
+    for difference in &differences {
+        if let Some(line) = &difference.old {
+            println!("0x{:04x}  - {}", difference.address, format(old, line));
+        }
+        if let Some(line) = &difference.new {
+            println!("0x{:04x}  + {}", difference.address, format(new, line));
+        }
+    }
+    if differences.is_empty() {
+        println!("no differences");
+    }
+}
+
+/// Formats the statistics as histograms, most used first
+fn format_statistics(disassembly: &Disassembly) -> String {
+    const WIDTH: usize = 40;
+    let statistics = disassembly.statistics();
+    let histogram = |title: &str, mut counts: Vec<(String, usize)>| {
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let max = counts.first().map(|(_, count)| *count).unwrap_or(1);
+        let mut output = format!("{}\n", title);
+        for (name, count) in counts {
+            let bar = "#".repeat((count * WIDTH).div_ceil(max));
+            output.push_str(&format!("  {:<10} {:>5}  {}\n", name, count, bar));
+        }
+        output
+    };
+
+    let instructions = statistics
+        .instructions
+        .iter()
+        .map(|(mnemonic, count)| (mnemonic.to_string(), *count))
+        .collect();
+    let registers = (0..16)
+        .filter(|r| statistics.registers[*r] > 0)
+        .map(|r| (format!("V{:X}", r), statistics.registers[r]))
+        .collect();
+    let addresses = statistics
+        .addresses
+        .iter()
+        .map(|(address, count)| match disassembly.labels.get(address) {
+            Some(label) => (label.clone(), *count),
+            None => (format!("0x{:04x}", address), *count),
+        })
+        .collect();
+    [
+        histogram("instructions", instructions),
+        histogram("registers", registers),
+        histogram("addresses", addresses),
+    ]
+    .join("\n")
+}
+
 fn run_disassembler(args: &DisassembleCommands, _global_args: &CliArgs) {
-    let mut reader: Box<dyn Read> = if let Some(f) = &args.input {
+    let reader: Box<dyn Read> = if let Some(f) = &args.input {
         Box::new(File::open(f).unwrap())
     } else {
         Box::new(BufReader::new(io::stdin()))
     };
 
-    let mut buffer: Vec<u8> = Vec::new();
-    reader.read_to_end(&mut buffer).unwrap();
+    let Some(buffer) = read_rom(reader, &args.format) else {
+        return;
+    };
 
-    let mut cursor = 0;
-    let mut instructions: Vec<Instruction> = Vec::new();
-    loop {
-        if cursor >= buffer.len() || cursor + 1 >= buffer.len() {
-            break;
-        }
-        let b1 = buffer[cursor];
-        let b2 = buffer[cursor + 1];
-        match Instruction::from_opcode_u8(b1, b2) {
-            Some(i) => instructions.push(i),
-            None => {
-                error!("unknown opcode '0x{:02x}{:02x}'", b1, b2);
+    let options = DisassemblyOptions {
+        recursive: args.recursive,
+        labels: !args.no_labels,
+        start: args.start,
+        end: args.end,
+    };
+    let mut disassembly = disassemble(&buffer, &options);
+    if let Some(f) = &args.symbols {
+        let symbols = std::fs::read_to_string(f)
+            .map_err(|e| e.to_string())
+            .and_then(|s| format::from_symbols(&s).map_err(|e| e.to_string()));
+        let symbols = match symbols {
+            Ok(symbols) => symbols,
+            Err(e) => {
+                error!("failed to read symbols '{}': {}", f, e);
+                return;
             }
         };
-        cursor += 2;
+        disassembly = disassembly.with_symbols(&symbols);
+    }
+    let extensions = disassembly.extensions();
+    for address in &disassembly.unknown {
+        let offset = address - START_ADDR;
+        match extensions.iter().find(|e| e.address == *address) {
+            Some(e) => warn!(
+                "{} opcode '0x{:04x}' ({}), emitted as data",
+                e.target, e.opcode, e.mnemonic
+            ),
+            None => warn!(
+                "unknown opcode '0x{:02x}{:02x}', emitted as data",
+                buffer[offset],
+                buffer[offset + 1]
+            ),
+        }
     }
 
     if args.ast {
-        for i in instructions {
-            println!("{:?}", i);
+        for line in &disassembly.lines {
+            match &line.decoded {
+                Decoded::Instruction(i) => println!("{:?}", i),
+                Decoded::Data(bytes) => println!("Data({:?})", bytes),
+            }
         }
         return;
     }
@@ -195,9 +728,75 @@ fn run_disassembler(args: &DisassembleCommands, _global_args: &CliArgs) {
         Box::new(io::stdout())
     };
 
-    for i in instructions {
-        let mut asm = i.to_assembly();
+    if args.detect_target {
+        let mut report = String::new();
+        for e in &extensions {
+            report.push_str(&format!(
+                "0x{:04x}  {:04x}  {:<10}  {}\n",
+                e.address, e.opcode, e.mnemonic, e.target
+            ));
+        }
+        report.push_str(&format!("requires {}\n", disassembly.required_target()));
+        writer.write_all(report.as_bytes()).unwrap();
+        return;
+    }
+
+    if args.stats {
+        let report = format_statistics(&disassembly);
+        writer.write_all(report.as_bytes()).unwrap();
+        return;
+    }
+
+    if let Some(GraphFormat::Dot) = args.call_graph {
+        let dot = disassembly.call_graph().to_dot(&disassembly.labels);
+        writer.write_all(dot.as_bytes()).unwrap();
+        return;
+    }
+
+    let sprites = if args.no_sprites {
+        Vec::new()
+    } else {
+        disassembly.sprites()
+    };
+    for line in &disassembly.lines {
+        let mut asm = String::new();
+        if let Some(label) = disassembly.labels.get(&line.address) {
+            asm.push_str(&format!("{}:\n", label));
+        }
+        for sprite in sprites.iter().filter(|s| line.contains(s.address)) {
+            for row in sprite.preview() {
+                asm.push_str(&format!("; {}\n", row));
+            }
+        }
+        if args.addresses {
+            let hex: String = line.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            asm.push_str(&format!("0x{:04x}  {:<4}  ", line.address, hex));
+        }
+        match &line.decoded {
+            Decoded::Instruction(i) if args.describe => {
+                let formatted = disassembly.format_line(line);
+                asm.push_str(&format!("{:<24}; {}", formatted, i.describe()));
+            }
+            _ => asm.push_str(&disassembly.format_line(line)),
+        }
         asm.push('\n');
         writer.write_all(asm.as_bytes()).unwrap();
     }
+
+    if args.xref {
+        // Written as comments, so the output can still be reassembled
+        let mut xref = String::from("\n; Cross references\n");
+        for (target, references) in disassembly.cross_references() {
+            let name = match disassembly.labels.get(&target) {
+                Some(label) => format!("{} (0x{:04x})", label, target),
+                None => format!("0x{:04x}", target),
+            };
+            let references: Vec<String> = references
+                .iter()
+                .map(|r| format!("{} 0x{:04x}", r.kind, r.address))
+                .collect();
+            xref.push_str(&format!("; {}: {}\n", name, references.join(", ")));
+        }
+        writer.write_all(xref.as_bytes()).unwrap();
+    }
 }
@@ -0,0 +1,155 @@
+//!
+//! Throughput of the core loop, executing instruction mixes, drawing and
+//! whole frames, also with tick tracing under a listening subscriber
+//!
+//! cargo bench -p chip8 --bench core
+//!
+use std::fmt::Debug;
+use std::hint::black_box;
+use std::time::Instant;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use chip8::emulator::{Builder, Emulator, ManualClock, RunLimit, FRAME_DURATION};
+use chip8::instructions::Instruction;
+
+const ITERATIONS: usize = 100_000;
+/// Instructions per frame at 4000 Hz
+const FRAME_TICKS: u64 = 4000 / 60;
+
+/// Times the iterations of `f` and prints the time per iteration
+fn bench(name: &str, iterations: usize, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10.2} ns per iteration, {:?} in total",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        elapsed
+    );
+}
+
+/// Listens to everything and formats the fields of events, as a logging
+/// frontend at the debug level would, without writing them anywhere
+struct FormattingSubscriber;
+
+struct Formatter(String);
+
+impl Visit for Formatter {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0 = format!("{}={:?}", field.name(), value);
+    }
+}
+
+impl Subscriber for FormattingSubscriber {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut formatter = Formatter(String::new());
+        event.record(&mut formatter);
+        black_box(formatter.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+/// Runs frames of the busy loop at 4000 hz
+fn frames(name: &str, rom: &[u8], tick_tracing: bool) {
+    let clock = ManualClock::new();
+    let mut emulator = Builder::new()
+        .with_clock(Box::new(clock.clone()))
+        .with_tick_tracing(tick_tracing)
+        .load_bytes(rom)
+        .unwrap();
+    bench(name, ITERATIONS / 100, || {
+        black_box(emulator.run_for(RunLimit::Ticks(FRAME_TICKS)));
+        clock.advance(FRAME_DURATION);
+    });
+}
+
+/// Executes the instructions in order, outside of any program
+fn execute_mix(name: &str, emulator: &mut Emulator, mix: &[Instruction]) {
+    bench(name, ITERATIONS, || {
+        for instruction in mix {
+            black_box(emulator.execute(black_box(*instruction))).unwrap();
+        }
+    });
+}
+
+fn main() {
+    let arithmetic = [
+        Instruction::SetRegisterByte(1.into(), 0x2a),
+        Instruction::Add(1.into(), 3),
+        Instruction::SetRegisterRegister(2.into(), 1.into()),
+        Instruction::Xor(2.into(), 1.into()),
+        Instruction::AddChecked(1.into(), 2.into()),
+        Instruction::SubChecked(1.into(), 2.into()),
+        Instruction::ShiftLeft(1.into(), 2.into()),
+        Instruction::ShiftRight(1.into(), 2.into()),
+    ];
+    let mut emulator = Builder::new().build();
+    execute_mix("execute arithmetic", &mut emulator, &arithmetic);
+
+    let memory = [
+        Instruction::SetMemRegister(0x300.into()),
+        Instruction::SetBcd(1.into()),
+        Instruction::MemWrite(7.into()),
+        Instruction::MemRead(7.into()),
+        Instruction::AddMemReg(1.into()),
+    ];
+    execute_mix("execute memory", &mut emulator, &memory);
+
+    let control = [
+        Instruction::Call(0x300.into()),
+        Instruction::Return,
+        Instruction::SkipEqual(1.into(), 0),
+        Instruction::SkipRegistersNotEqual(1.into(), 2.into()),
+        Instruction::Jump(0x200.into()),
+    ];
+    execute_mix("execute control flow", &mut emulator, &control);
+
+    // 15 rows of the font at every position of the display
+    let mut emulator = Builder::new().build();
+    emulator
+        .execute(Instruction::SetMemRegister(0.into()))
+        .unwrap();
+    let mut position = 0u8;
+    bench("draw", ITERATIONS, || {
+        position = position.wrapping_add(7);
+        emulator.set_register(0.into(), position % 64);
+        emulator.set_register(1.into(), position % 32);
+        let draw = Instruction::Draw(0.into(), 1.into(), 15.into());
+        black_box(emulator.execute(black_box(draw))).unwrap();
+    });
+
+    // A busy loop drawing and clearing a sprite, the timers advancing a
+    // frame per frame of instructions
+    // ldi 0, draw r0 r1 5, add r0 1, add r1 1, draw r0 r1 5, cls, jmp 0x200
+    let rom = [
+        0xA0, 0x00, 0xD0, 0x15, 0x70, 0x01, 0x71, 0x01, 0xD0, 0x15, 0x00, 0xE0, 0x12, 0x00,
+    ];
+    frames("frame at 4000 hz", &rom, false);
+
+    // Tracing only costs while a subscriber listens
+    tracing::subscriber::with_default(FormattingSubscriber, || {
+        frames("frame, subscriber", &rom, false);
+        frames("frame, tick tracing", &rom, true);
+    });
+}
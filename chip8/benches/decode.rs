@@ -0,0 +1,36 @@
+//!
+//! Decoding throughput, compare the match and the lookup table with
+//!
+//! cargo bench -p chip8 --bench decode
+//! cargo bench -p chip8 --bench decode --features decode-table
+//!
+use std::hint::black_box;
+use std::time::Instant;
+
+use chip8::instructions::Instruction;
+
+const ROUNDS: usize = 100;
+
+fn main() {
+    // Build the table, if enabled, outside of the measurement
+    black_box(Instruction::from_opcode_u16(0));
+
+    let start = Instant::now();
+    let mut decoded = 0;
+    for _ in 0..ROUNDS {
+        for opcode in 0..=u16::MAX {
+            if black_box(Instruction::from_opcode_u16(black_box(opcode))).is_some() {
+                decoded += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    let count = ROUNDS * (u16::MAX as usize + 1);
+    println!(
+        "decoded {} of {} opcodes in {:?}, {:.2} ns per opcode",
+        decoded,
+        count,
+        elapsed,
+        elapsed.as_nanos() as f64 / count as f64
+    );
+}
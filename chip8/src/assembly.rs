@@ -1,92 +1,214 @@
+pub mod diagnostic;
+pub mod format;
+pub mod highlight;
 pub mod lexer;
+pub mod linker;
 pub mod parser;
+pub mod testing;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
 
-use crate::emulator::START_ADDR;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::assembly::parser::{Parser, ParsingError};
+use crate::emulator::{Font, MEMSIZE, START_ADDR};
 use crate::instructions::{u12, Instruction};
 
 #[derive(Debug)]
 pub enum BinaryError {
     MissingLabelAddress(String),
+    /// The label resolves to an address which doesn't fit in 12 bits
+    AddressOutOfRange(String, usize),
+    /// The program doesn't fit in memory, (size, max size)
+    ProgramTooLarge(usize, usize),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::MissingLabelAddress(ref label) => {
+                write!(f, "Missing address for label '{}'", label)
+            }
+            Self::AddressOutOfRange(ref label, address) => {
+                write!(
+                    f,
+                    "Label '{}' resolves to 0x{:04x}, which exceeds 12 bits",
+                    label, address
+                )
+            }
+            Self::ProgramTooLarge(size, max) => {
+                write!(
+                    f,
+                    "Program is {} bytes, which exceeds the available {} bytes",
+                    size, max
+                )
+            }
+        }
+    }
+}
+
+impl Error for BinaryError {}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Parsing(ParsingError),
+    Binary(BinaryError),
+    IO(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Parsing(ref err) => err.fmt(f),
+            Self::Binary(ref err) => err.fmt(f),
+            Self::IO(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for StreamError {}
+
+impl From<ParsingError> for StreamError {
+    fn from(err: ParsingError) -> Self {
+        StreamError::Parsing(err)
+    }
+}
+
+impl From<BinaryError> for StreamError {
+    fn from(err: BinaryError) -> Self {
+        StreamError::Binary(err)
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        StreamError::IO(err)
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Assembly {
     pub instructions: Vec<ParsedInstruction>,
     pub labels: HashMap<String, usize>,
+    /// Where each label is defined
+    pub label_sources: HashMap<String, Source>,
+    pub assertions: Vec<Assertion>,
+    /// Comments on lines of their own
+    pub comments: Vec<Comment>,
+    /// Custom font defined by `font` directives
+    pub font: Option<Font>,
 }
 
 impl Assembly {
     /// Converts the assembly to binary
     pub fn binary(&self) -> Result<Vec<u8>, BinaryError> {
+        let size = self.instructions.len() * 2;
+        let max = MEMSIZE - START_ADDR;
+        if size > max {
+            return Err(BinaryError::ProgramTooLarge(size, max));
+        }
+
         let mut buffer = Vec::new();
         for instr in &self.instructions {
-            // Check if we need to resolve labels
-            let instruction = match instr.instruction {
-                Instruction::Call(a) => {
-                    if let Some(label) = &instr.label {
-                        if let Some(offset) = self.labels.get(label) {
-                            let address: u12 = ((START_ADDR + (offset * 2)) as u16).into();
-                            Instruction::Call(address)
-                        } else {
-                            return Err(BinaryError::MissingLabelAddress(label.clone()));
-                        }
-                    } else {
-                        Instruction::Call(a)
-                    }
-                }
-                Instruction::Jump(a) => {
-                    if let Some(label) = &instr.label {
-                        if let Some(offset) = self.labels.get(label) {
-                            let address: u12 = ((START_ADDR + (offset * 2)) as u16).into();
-                            Instruction::Jump(address)
-                        } else {
-                            return Err(BinaryError::MissingLabelAddress(label.clone()));
-                        }
-                    } else {
-                        Instruction::Jump(a)
-                    }
-                }
-                Instruction::SetMemRegister(a) => {
-                    if let Some(label) = &instr.label {
-                        if let Some(offset) = self.labels.get(label) {
-                            let address: u12 = ((START_ADDR + (offset * 2)) as u16).into();
-                            Instruction::SetMemRegister(address)
-                        } else {
-                            return Err(BinaryError::MissingLabelAddress(label.clone()));
-                        }
-                    } else {
-                        Instruction::SetMemRegister(a)
-                    }
-                }
-                Instruction::JumpOffset(a) => {
-                    if let Some(label) = &instr.label {
-                        if let Some(offset) = self.labels.get(label) {
-                            let address: u12 = ((START_ADDR + (offset * 2)) as u16).into();
-                            Instruction::JumpOffset(address)
-                        } else {
-                            return Err(BinaryError::MissingLabelAddress(label.clone()));
-                        }
-                    } else {
-                        Instruction::JumpOffset(a)
-                    }
-                }
-                i => i,
-            };
-            let opcode = instruction.opcode();
+            let opcode = resolve_labels(instr, &self.labels)?.opcode();
             let bytes = opcode.to_be_bytes();
             buffer.extend(bytes);
         }
         Ok(buffer)
     }
+
+    /// Addresses of the labels, as written to symbol files
+    pub fn symbols(&self) -> BTreeMap<usize, String> {
+        self.labels
+            .iter()
+            .map(|(label, index)| (START_ADDR + index * 2, label.clone()))
+            .collect()
+    }
+}
+
+/// Assembles without holding the parsed program in memory, by parsing the
+/// source twice: first collecting the label addresses, then writing the
+/// opcodes. `parser` is called once per pass and has to return a parser
+/// over the same source. Returns the number of bytes written.
+pub fn write_binary<W: Write>(
+    mut parser: impl FnMut() -> Parser,
+    writer: &mut W,
+) -> Result<usize, StreamError> {
+    let mut first = parser();
+    let mut instructions = first.instructions();
+    let mut size = 0;
+    for instruction in instructions.by_ref() {
+        instruction?;
+        size += 2;
+    }
+    let labels = instructions.labels().clone();
+    let max = MEMSIZE - START_ADDR;
+    if size > max {
+        return Err(BinaryError::ProgramTooLarge(size, max).into());
+    }
+
+    let mut second = parser();
+    for instruction in second.instructions() {
+        let instruction = instruction?;
+        if let Some(label) = &instruction.label {
+            if !labels.contains_key(label) {
+                return Err(ParsingError::MissingReferencedLabel(label.clone()).into());
+            }
+        }
+        let opcode = resolve_labels(&instruction, &labels)?.opcode();
+        writer.write_all(&opcode.to_be_bytes())?;
+    }
+    Ok(size)
+}
+
+/// Replaces the address of instructions referencing a label
+pub(crate) fn resolve_labels(
+    instr: &ParsedInstruction,
+    labels: &HashMap<String, usize>,
+) -> Result<Instruction, BinaryError> {
+    let instruction = match (instr.instruction, &instr.label) {
+        (Instruction::Call(_), Some(label)) => Instruction::Call(address(labels, label)?),
+        (Instruction::Jump(_), Some(label)) => Instruction::Jump(address(labels, label)?),
+        (Instruction::SetMemRegister(_), Some(label)) => {
+            Instruction::SetMemRegister(address(labels, label)?)
+        }
+        (Instruction::JumpOffset(_), Some(label)) => {
+            Instruction::JumpOffset(address(labels, label)?)
+        }
+        (i, _) => i,
+    };
+    Ok(instruction)
+}
+
+/// Resolves the memory address of a label
+fn address(labels: &HashMap<String, usize>, label: &String) -> Result<u12, BinaryError> {
+    let offset = match labels.get(label) {
+        Some(offset) => offset,
+        None => return Err(BinaryError::MissingLabelAddress(label.clone())),
+    };
+    let address = START_ADDR + (offset * 2);
+    if address > 0xFFF {
+        return Err(BinaryError::AddressOutOfRange(label.clone(), address));
+    }
+    Ok((address as u16).into())
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ParsedInstruction {
     pub instruction: Instruction,
     pub label: Option<String>,
     pub source: Option<Source>,
+    /// The pseudo-instruction this was expanded from, e.g. `inc r1`
+    pub expanded_from: Option<String>,
+    /// Trailing comment on the same line, without the ';'
+    pub comment: Option<String>,
 }
 
 impl ParsedInstruction {
@@ -95,15 +217,57 @@ impl ParsedInstruction {
             instruction,
             label: None,
             source: None,
+            expanded_from: None,
+            comment: None,
         }
     }
 }
 
+/// A comment on a line of its own
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Comment {
+    /// Index of the instruction following the comment
+    pub index: usize,
+    pub line: usize,
+    /// The text following the ';'
+    pub text: String,
+}
+
+/// An `assert` directive, compiled into a check followed by a
+/// breakpoint which is only reached when the assertion fails
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Assertion {
+    /// Index of the checking instruction
+    pub index: usize,
+    /// The asserted condition, e.g. `r1 == 42`
+    pub condition: String,
+    pub message: Option<String>,
+    pub line: usize,
+}
+
+impl Assertion {
+    /// Memory address of the checking instruction
+    pub fn address(&self) -> usize {
+        START_ADDR + self.index * 2
+    }
+
+    /// Memory address of the breakpoint hit on failure
+    pub fn failure_address(&self) -> usize {
+        self.address() + 2
+    }
+}
+
+/// Where in the source something was parsed from, line and column are 0-based
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Source {
     pub file: String,
     pub line: usize,
     pub column: usize,
+    /// Byte offsets in the source
+    pub span: Range<usize>,
 }
 
 #[cfg(test)]
@@ -117,46 +281,64 @@ mod test {
                 instruction: Instruction::SetRegisterByte(1.into(), 0),
                 label: Some("main".to_string()),
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::SetRegisterByte(2.into(), 4),
                 label: None,
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::Call(0.into()),
                 label: Some("loop".to_string()),
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::SkipNotEqual(2.into(), 3),
                 label: None,
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::Jump(0.into()),
                 label: Some("exit".to_string()),
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::Add(1.into(), 4),
                 label: None,
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::Add(2.into(), 1),
                 label: None,
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::Jump(0.into()),
                 label: Some("loop".to_string()),
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
             ParsedInstruction {
                 instruction: Instruction::SetRegisterByte(4.into(), 0),
                 label: Some("exit".to_string()),
                 source: None,
+                expanded_from: None,
+                comment: None,
             },
         ];
         let mut labels = HashMap::new();
@@ -166,6 +348,10 @@ mod test {
         let assembly = Assembly {
             instructions,
             labels,
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            font: None,
         };
         let binary = assembly.binary().unwrap();
         assert_addr(binary.as_ref(), 2, 0x206.into());
@@ -173,6 +359,111 @@ mod test {
         assert_addr(binary.as_ref(), 7, 0x206.into());
     }
 
+    #[test]
+    fn test_program_too_large() {
+        let max = (MEMSIZE - START_ADDR) / 2;
+        let assembly = Assembly {
+            instructions: vec![ParsedInstruction::new(Instruction::Clear); max + 1],
+            labels: HashMap::new(),
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            font: None,
+        };
+        assert!(matches!(
+            assembly.binary(),
+            Err(BinaryError::ProgramTooLarge(size, _)) if size == (max + 1) * 2
+        ));
+
+        let assembly = Assembly {
+            instructions: vec![ParsedInstruction::new(Instruction::Clear); max],
+            labels: HashMap::new(),
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            font: None,
+        };
+        assert_eq!(assembly.binary().unwrap().len(), MEMSIZE - START_ADDR);
+    }
+
+    #[test]
+    fn test_label_out_of_range() {
+        let mut labels = HashMap::new();
+        labels.insert("far".to_string(), 0x1000);
+        let assembly = Assembly {
+            instructions: vec![ParsedInstruction {
+                instruction: Instruction::Jump(0.into()),
+                label: Some("far".to_string()),
+                source: None,
+                expanded_from: None,
+                comment: None,
+            }],
+            labels,
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            font: None,
+        };
+        assert!(matches!(
+            assembly.binary(),
+            Err(BinaryError::AddressOutOfRange(label, _)) if label == "far"
+        ));
+    }
+
+    #[test]
+    fn test_write_binary() {
+        use crate::assembly::lexer::StreamLexer;
+        use std::io::Cursor;
+
+        let source = "call sub\nexit\nsub:\nldb r1 2\nret";
+        let parser = || Parser::new(Box::new(StreamLexer::new(Cursor::new(source.as_bytes()))));
+        let mut written = Vec::new();
+        let size = write_binary(parser, &mut written).unwrap();
+        assert_eq!(size, 8);
+        assert_eq!(written, parser().parse().unwrap().binary().unwrap());
+
+        let source = "jmp missing";
+        let parser = || Parser::new(Box::new(StreamLexer::new(Cursor::new(source.as_bytes()))));
+        assert!(matches!(
+            write_binary(parser, &mut Vec::new()),
+            Err(StreamError::Parsing(ParsingError::MissingReferencedLabel(
+                _
+            )))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let mut labels = HashMap::new();
+        labels.insert("main".to_string(), 0);
+        let assembly = Assembly {
+            instructions: vec![
+                ParsedInstruction::new(Instruction::Draw(1.into(), 2.into(), 5.into())),
+                ParsedInstruction {
+                    label: Some("main".to_string()),
+                    ..ParsedInstruction::new(Instruction::Jump(0x200.into()))
+                },
+            ],
+            labels,
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            font: None,
+        };
+        let json = serde_json::to_value(&assembly).unwrap();
+        assert_eq!(
+            json["instructions"][0]["instruction"],
+            serde_json::json!({ "Draw": [1, 2, 5] })
+        );
+        assert_eq!(
+            json["instructions"][1]["instruction"],
+            serde_json::json!({ "Jump": 0x200 })
+        );
+        assert_eq!(json["instructions"][1]["label"], "main");
+        assert_eq!(json["labels"]["main"], 0);
+    }
+
     fn assert_addr(binary: &[u8], location: usize, addr: u12) {
         let b1 = binary[location * 2];
         let b2 = binary[location * 2 + 1];
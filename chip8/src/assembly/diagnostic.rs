@@ -0,0 +1,288 @@
+//!
+//! Structured diagnostics for parse and assemble errors
+//!
+//! Each diagnostic carries a stable error code and a 1-based location,
+//! so editors and CI can consume them, serialized with the `serde` feature.
+//!
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use crate::assembly::lexer::LexerError;
+use crate::assembly::linker::LinkError;
+use crate::assembly::parser::{ArgumentError, ParsingError};
+use crate::assembly::BinaryError;
+use crate::instructions::Instruction;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Diagnostic {
+    /// Stable error code, e.g. `E102`
+    pub code: &'static str,
+    pub file: Option<String>,
+    /// 1-based line
+    pub line: Option<usize>,
+    /// 1-based column
+    pub column: Option<usize>,
+    pub message: String,
+    /// Suggested fix, if there's an obvious one
+    pub fix: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(code: &'static str, message: String) -> Self {
+        Self {
+            code,
+            file: None,
+            line: None,
+            column: None,
+            message,
+            fix: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Sets the location from the 0-based location used by the lexer
+    fn with_location(mut self, location: (usize, usize)) -> Self {
+        self.line = Some(location.0 + 1);
+        self.column = Some(location.1 + 1);
+        self
+    }
+
+    fn with_fix(mut self, fix: String) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "{}:{}:", line, column)?;
+        }
+        write!(f, " error[{}]: {}", self.code, self.message)?;
+        if let Some(fix) = &self.fix {
+            write!(f, " (fix: {})", fix)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&LexerError> for Diagnostic {
+    fn from(err: &LexerError) -> Self {
+        match *err {
+            LexerError::IO(ref e) => Diagnostic::new("E001", e.to_string()),
+            LexerError::IntegerParsing(ref e) => Diagnostic::new("E002", e.to_string()),
+            LexerError::UnterminatedCharacter(line, column) => {
+                Diagnostic::new("E003", "Unterminated character literal".to_string())
+                    .with_location((line, column))
+                    .with_fix("close the literal with ', e.g. 'A'".to_string())
+            }
+            LexerError::InvalidHexadecimal(line, column) => {
+                Diagnostic::new("E004", "Expected hexadecimal digits after '0x'".to_string())
+                    .with_location((line, column))
+            }
+            LexerError::UnterminatedString(line, column) => {
+                Diagnostic::new("E005", "Unterminated string literal".to_string())
+                    .with_location((line, column))
+                    .with_fix("close the string with \" on the same line".to_string())
+            }
+        }
+    }
+}
+
+impl From<&ParsingError> for Diagnostic {
+    fn from(err: &ParsingError) -> Self {
+        match *err {
+            ParsingError::Lexer(ref e) => e.into(),
+            ParsingError::ArgumentError(instruction, location, ref e) => {
+                let diagnostic = Diagnostic::new(
+                    "E101",
+                    format!("Invalid argument for '{}': {}", instruction, e),
+                )
+                .with_location(location);
+                match e {
+                    ArgumentError::MissingRegistryPrefix(prefix, v) => {
+                        diagnostic.with_fix(format!(
+                            "prefix the register with '{}', e.g. '{}{}'",
+                            prefix, prefix, v
+                        ))
+                    }
+                    _ => diagnostic,
+                }
+            }
+            ParsingError::UnknownInstruction(ref instr, location) => {
+                let diagnostic =
+                    Diagnostic::new("E102", format!("Unknown instruction '{}'", instr))
+                        .with_location(location);
+                match closest_mnemonic(instr) {
+                    Some(m) => diagnostic.with_fix(format!("did you mean '{}'?", m)),
+                    None => diagnostic,
+                }
+            }
+            ParsingError::UnexpectedToken(step, ref token, location) => Diagnostic::new(
+                "E103",
+                format!(
+                    "Unexpected token '{}' while processing step '{}'",
+                    token, step
+                ),
+            )
+            .with_location(location),
+            ParsingError::MissingReferencedLabel(ref label) => {
+                Diagnostic::new("E104", format!("Missing referenced label '{}'", label))
+                    .with_fix(format!("define the label with '{}:'", label))
+            }
+            ParsingError::UnbalancedConditional(location) => Diagnostic::new(
+                "E106",
+                "Unbalanced conditional, expected matching %ifdef and %endif".to_string(),
+            )
+            .with_location(location),
+            ParsingError::UnsupportedInstruction(ref instr, target, location) => Diagnostic::new(
+                "E107",
+                format!(
+                    "Instruction '{}' isn't supported by target {}",
+                    instr, target
+                ),
+            )
+            .with_location(location)
+            .with_fix("emit it with the opcode directive if it's intentional".to_string()),
+            ParsingError::ExtensionInstruction(ref instr, target, location) => Diagnostic::new(
+                "E108",
+                format!(
+                    "Instruction '{}' requires target {} or newer",
+                    instr, target
+                ),
+            )
+            .with_location(location)
+            .with_fix(format!("select the platform with --target {}", target)),
+            ParsingError::IncompleteFont(_) => Diagnostic::new("E105", err.to_string())
+                .with_fix("define a sprite for every digit 0 to 15".to_string()),
+            ParsingError::NotSingleInstruction(_) => Diagnostic::new("E109", err.to_string()),
+            ParsingError::Unknown(ref msg) => Diagnostic::new("E199", msg.clone()),
+        }
+    }
+}
+
+impl From<&BinaryError> for Diagnostic {
+    fn from(err: &BinaryError) -> Self {
+        let code = match err {
+            BinaryError::MissingLabelAddress(_) => "E201",
+            BinaryError::AddressOutOfRange(..) => "E202",
+            BinaryError::ProgramTooLarge(..) => "E203",
+        };
+        Diagnostic::new(code, err.to_string())
+    }
+}
+
+impl From<&LinkError> for Diagnostic {
+    fn from(err: &LinkError) -> Self {
+        let diagnostic = match err {
+            LinkError::DuplicateLabel(..) => Diagnostic::new("E301", err.to_string()),
+            LinkError::DuplicateFont => Diagnostic::new("E302", err.to_string()),
+            LinkError::MissingReferencedLabel(_) => Diagnostic::new("E303", err.to_string()),
+        };
+        match err {
+            LinkError::DuplicateLabel(_, _, Some(source)) => Diagnostic {
+                file: Some(source.file.clone()),
+                ..diagnostic.with_location((source.line, source.column))
+            },
+            _ => diagnostic,
+        }
+    }
+}
+
+/// Finds the native mnemonic closest to a misspelled one
+fn closest_mnemonic(instr: &str) -> Option<String> {
+    let instr = instr.to_ascii_lowercase();
+    Instruction::iter()
+        .filter_map(|i| i.to_assembly().split(' ').next().map(|m| m.to_string()))
+        .map(|m| (edit_distance(&instr, &m), m))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, m)| m)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            let value = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufReader;
+
+    use crate::assembly::lexer::StreamLexer;
+    use crate::assembly::parser::Parser;
+
+    fn diagnose(input: &'static str) -> Diagnostic {
+        let lexer = StreamLexer::new(BufReader::new(input.as_bytes()));
+        let err = Parser::new(Box::new(lexer)).parse().unwrap_err();
+        (&err).into()
+    }
+
+    #[test]
+    fn test_unknown_instruction() {
+        let diagnostic = diagnose("clear\nldbb r1 2").with_file("main.asm");
+        assert_eq!(diagnostic.code, "E102");
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.column, Some(1));
+        assert_eq!(diagnostic.fix, Some("did you mean 'ldb'?".to_string()));
+    }
+
+    #[test]
+    fn test_argument_error() {
+        let diagnostic = diagnose("ldb 1 2");
+        assert_eq!(diagnostic.code, "E101");
+        assert_eq!(
+            diagnostic.fix,
+            Some("prefix the register with 'r', e.g. 'r1'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lexer_error() {
+        let diagnostic = diagnose("debug '1");
+        assert_eq!(diagnostic.code, "E003");
+        assert_eq!(diagnostic.line, Some(1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let diagnostic = diagnose("clear\nldbb r1 2").with_file("main.asm");
+        assert_eq!(
+            serde_json::to_string(&diagnostic).unwrap(),
+            "{\"code\":\"E102\",\"file\":\"main.asm\",\"line\":2,\"column\":1,\
+             \"message\":\"Unknown instruction 'ldbb'\",\"fix\":\"did you mean 'ldb'?\"}"
+        );
+        let diagnostic = Diagnostic::new("E199", "a \"quoted\"\\path\n".to_string());
+        assert_eq!(
+            serde_json::to_string(&diagnostic).unwrap(),
+            "{\"code\":\"E199\",\"file\":null,\"line\":null,\"column\":null,\
+             \"message\":\"a \\\"quoted\\\"\\\\path\\n\",\"fix\":null}"
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//!
+//! Text encodings of assembled ROMs
+//!
+//! Besides the raw binary, a ROM can be written as whitespace-separated
+//! hex bytes or as an Intel HEX file addressed from [START_ADDR].
+//! Both can be read back to the raw binary. For embedding a ROM in other
+//! projects it can also be written as Rust or C array source.
+//!
+//! Label addresses are written as symbol files, one `0x0246 draw_score`
+//! pair per line, which the disassembler reads to name known addresses.
+//!
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::emulator::START_ADDR;
+
+/// Bytes per line of hex text, and per Intel HEX data record
+const BYTES_PER_LINE: usize = 16;
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+
+#[derive(Debug, PartialEq)]
+pub enum FormatError {
+    /// A character which isn't a hex digit, (line)
+    InvalidHex(usize),
+    /// A malformed Intel HEX record, (line)
+    InvalidRecord(usize),
+    /// The checksum of an Intel HEX record doesn't match, (line)
+    Checksum(usize),
+    /// A record type other than data and end of file, (line, type)
+    UnsupportedRecord(usize, u8),
+    /// Data is placed below the start address, (line, address)
+    AddressOutOfRange(usize, usize),
+    /// A symbol line which isn't an address followed by a name, (line)
+    InvalidSymbol(usize),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::InvalidHex(line) => write!(f, "Invalid hex digits on line {}", line + 1),
+            Self::InvalidRecord(line) => write!(f, "Malformed record on line {}", line + 1),
+            Self::Checksum(line) => write!(f, "Checksum mismatch on line {}", line + 1),
+            Self::UnsupportedRecord(line, kind) => {
+                write!(
+                    f,
+                    "Unsupported record type {:02x} on line {}",
+                    kind,
+                    line + 1
+                )
+            }
+            Self::AddressOutOfRange(line, address) => {
+                write!(
+                    f,
+                    "Address 0x{:04x} on line {} is below 0x{:04x}",
+                    address,
+                    line + 1,
+                    START_ADDR
+                )
+            }
+            Self::InvalidSymbol(line) => write!(f, "Invalid symbol on line {}", line + 1),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// Formats the bytes as lowercase hex, 16 bytes per line
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        output.push_str(&line.join(" "));
+        output.push('\n');
+    }
+    output
+}
+
+/// Parses whitespace-separated hex bytes, a word may hold several bytes
+/// (e.g. `00e0`)
+pub fn from_hex(text: &str) -> Result<Vec<u8>, FormatError> {
+    let mut bytes = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        for word in content.split_whitespace() {
+            bytes.extend(decode_hex(word).ok_or(FormatError::InvalidHex(line))?);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Formats the bytes as Intel HEX data records, addressed from [START_ADDR]
+pub fn to_intel_hex(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for (index, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = START_ADDR + index * BYTES_PER_LINE;
+        output.push_str(&record(RECORD_DATA, address as u16, chunk));
+    }
+    output.push_str(&record(RECORD_EOF, 0, &[]));
+    output
+}
+
+/// Parses Intel HEX data records into the binary loaded at [START_ADDR],
+/// gaps between records are filled with zeroes
+pub fn from_intel_hex(text: &str) -> Result<Vec<u8>, FormatError> {
+    let mut bytes = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let content = content.trim();
+        if content.is_empty() {
+            continue;
+        }
+        let record = content
+            .strip_prefix(':')
+            .and_then(decode_hex)
+            .ok_or(FormatError::InvalidRecord(line))?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(FormatError::InvalidRecord(line));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(FormatError::Checksum(line));
+        }
+
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            RECORD_DATA => {
+                if address < START_ADDR {
+                    return Err(FormatError::AddressOutOfRange(line, address));
+                }
+                let offset = address - START_ADDR;
+                if bytes.len() < offset + data.len() {
+                    bytes.resize(offset + data.len(), 0);
+                }
+                bytes[offset..offset + data.len()].copy_from_slice(data);
+            }
+            RECORD_EOF => break,
+            kind => return Err(FormatError::UnsupportedRecord(line, kind)),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Formats the bytes as a Rust constant, e.g. `const ROM: [u8; 2] = [...];`
+pub fn to_rust_array(bytes: &[u8], name: &str) -> String {
+    format!(
+        "const {}: [u8; {}] = [\n{}];\n",
+        name,
+        bytes.len(),
+        array_body(bytes)
+    )
+}
+
+/// Formats the bytes as a C array, e.g. `const unsigned char ROM[2] = {...};`
+pub fn to_c_array(bytes: &[u8], name: &str) -> String {
+    format!(
+        "const unsigned char {}[{}] = {{\n{}}};\n",
+        name,
+        bytes.len(),
+        array_body(bytes)
+    )
+}
+
+/// Formats the symbols as lines of address and name, ordered by address
+pub fn to_symbols(symbols: &BTreeMap<usize, String>) -> String {
+    let mut output = String::new();
+    for (address, name) in symbols {
+        output.push_str(&format!("0x{:04x} {}\n", address, name));
+    }
+    output
+}
+
+/// Parses lines of address and name, empty lines and `;` comments are
+/// skipped
+pub fn from_symbols(text: &str) -> Result<HashMap<usize, String>, FormatError> {
+    let mut symbols = HashMap::new();
+    for (line, content) in text.lines().enumerate() {
+        let content = match content.split_once(';') {
+            Some((content, _)) => content,
+            None => content,
+        };
+        let mut words = content.split_whitespace();
+        let (address, name) = match (words.next(), words.next(), words.next()) {
+            (None, _, _) => continue,
+            (Some(address), Some(name), None) => (address, name),
+            _ => return Err(FormatError::InvalidSymbol(line)),
+        };
+        let address = address
+            .strip_prefix("0x")
+            .and_then(|a| usize::from_str_radix(a, 16).ok())
+            .ok_or(FormatError::InvalidSymbol(line))?;
+        if !is_identifier(name) {
+            return Err(FormatError::InvalidSymbol(line));
+        }
+        symbols.insert(address, name.to_string());
+    }
+    Ok(symbols)
+}
+
+/// Names which can be used as labels in the assembly
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn array_body(bytes: &[u8]) -> String {
+    let mut body = String::new();
+    for chunk in bytes.chunks(BYTES_PER_LINE / 2) {
+        let line: Vec<String> = chunk.iter().map(|b| format!("0x{:02x},", b)).collect();
+        body.push_str(&format!("    {}\n", line.join(" ")));
+    }
+    body
+}
+
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    bytes.push(checksum);
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", hex)
+}
+
+fn decode_hex(word: &str) -> Option<Vec<u8>> {
+    if !word.len().is_multiple_of(2) || !word.is_ascii() {
+        return None;
+    }
+    (0..word.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&word[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        let bytes: Vec<u8> = (0..20).collect();
+        let text = to_hex(&bytes);
+        assert_eq!(
+            text,
+            "00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n10 11 12 13\n"
+        );
+        assert_eq!(from_hex(&text), Ok(bytes));
+        assert_eq!(from_hex("00e0 12\n00"), Ok(vec![0x00, 0xe0, 0x12, 0x00]));
+        assert_eq!(from_hex("00\n0g"), Err(FormatError::InvalidHex(1)));
+        assert_eq!(from_hex("123"), Err(FormatError::InvalidHex(0)));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let bytes: Vec<u8> = (0..10).collect();
+        assert_eq!(
+            to_rust_array(&bytes, "ROM"),
+            "const ROM: [u8; 10] = [\n    \
+             0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,\n    \
+             0x08, 0x09,\n];\n"
+        );
+        assert_eq!(
+            to_c_array(&bytes[..2], "ROM"),
+            "const unsigned char ROM[2] = {\n    0x00, 0x01,\n};\n"
+        );
+        assert_eq!(
+            to_rust_array(&[], "EMPTY"),
+            "const EMPTY: [u8; 0] = [\n];\n"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = BTreeMap::from([
+            (0x246, "draw_score".to_string()),
+            (0x200, "main".to_string()),
+        ]);
+        let text = to_symbols(&symbols);
+        assert_eq!(text, "0x0200 main\n0x0246 draw_score\n");
+        assert_eq!(
+            from_symbols(&text),
+            Ok(symbols.into_iter().collect::<HashMap<_, _>>())
+        );
+        assert_eq!(
+            from_symbols("; header\n\n0x0300 _loop ; comment\n"),
+            Ok(HashMap::from([(0x300, "_loop".to_string())]))
+        );
+        assert_eq!(from_symbols("0x0200\n"), Err(FormatError::InvalidSymbol(0)));
+        assert_eq!(
+            from_symbols("\n200 main"),
+            Err(FormatError::InvalidSymbol(1))
+        );
+        assert_eq!(
+            from_symbols("0x0200 2nd"),
+            Err(FormatError::InvalidSymbol(0))
+        );
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let bytes: Vec<u8> = (0..20).collect();
+        let text = to_intel_hex(&bytes);
+        assert_eq!(
+            text,
+            ":10020000000102030405060708090A0B0C0D0E0F76\n\
+             :0402100010111213A4\n\
+             :00000001FF\n"
+        );
+        assert_eq!(from_intel_hex(&text), Ok(bytes));
+    }
+
+    #[test]
+    fn test_intel_hex_errors() {
+        assert_eq!(
+            from_intel_hex(":0102000000FC\n"),
+            Err(FormatError::Checksum(0))
+        );
+        assert_eq!(
+            from_intel_hex("0102000000FD\n"),
+            Err(FormatError::InvalidRecord(0))
+        );
+        assert_eq!(
+            from_intel_hex(":0201000000FD\n"),
+            Err(FormatError::InvalidRecord(0))
+        );
+        assert_eq!(
+            from_intel_hex(":0101000000FE\n"),
+            Err(FormatError::AddressOutOfRange(0, 0x100))
+        );
+        assert_eq!(
+            from_intel_hex(":020000040000FA\n"),
+            Err(FormatError::UnsupportedRecord(0, 0x04))
+        );
+        // Gaps are zero filled
+        assert_eq!(
+            from_intel_hex(":01020200AA51\n:00000001FF\n"),
+            Ok(vec![0x00, 0x00, 0xAA])
+        );
+    }
+}
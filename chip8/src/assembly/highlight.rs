@@ -0,0 +1,167 @@
+//!
+//! Classified source spans for syntax highlighting
+//!
+//! Lexes a source string and classifies the tokens by their role on the
+//! line, with the byte offsets of each span, so editors can color the
+//! source without reimplementing the grammar.
+//!
+use std::io::Cursor;
+use std::ops::Range;
+
+use crate::assembly::lexer::{Lexer, LexerError, StreamLexer, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighlightKind {
+    /// Instructions and directives, e.g. `ldb` or `equ`
+    Mnemonic,
+    /// Registers, e.g. `r1`, `V1`, `I` or `DT`
+    Register,
+    /// Integer and character literals
+    Number,
+    /// Label definitions and references, and constants
+    Label,
+    /// Message strings, e.g. of `assert`
+    String,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightSpan {
+    pub kind: HighlightKind,
+    /// Byte offsets in the source
+    pub span: Range<usize>,
+}
+
+/// Classifies the tokens of the source, spans are ordered by offset.
+/// Punctuation and whitespace aren't included.
+pub fn highlight(source: &str) -> Result<Vec<HighlightSpan>, LexerError> {
+    let mut lexer = StreamLexer::new(Cursor::new(source.as_bytes()));
+    let mut spans = Vec::new();
+    let mut line: Vec<(Token, Range<usize>)> = Vec::new();
+    loop {
+        let start = lexer.offset();
+        let token = lexer.next()?;
+        let span = start..lexer.offset();
+        match token {
+            Token::Semicolon => {
+                lexer.rest_of_line()?;
+                let end = source[start..]
+                    .find('\n')
+                    .map(|i| start + i)
+                    .unwrap_or(source.len());
+                let end = if source[..end].ends_with('\r') {
+                    end - 1
+                } else {
+                    end
+                };
+                classify_line(&line, &mut spans);
+                line.clear();
+                spans.push(HighlightSpan {
+                    kind: HighlightKind::Comment,
+                    span: start..end,
+                });
+            }
+            Token::EOL => {
+                classify_line(&line, &mut spans);
+                line.clear();
+            }
+            Token::EOF => {
+                classify_line(&line, &mut spans);
+                return Ok(spans);
+            }
+            Token::Whitespace => {}
+            token => line.push((token, span)),
+        }
+    }
+}
+
+fn classify_line(line: &[(Token, Range<usize>)], spans: &mut Vec<HighlightSpan>) {
+    let mut seen_mnemonic = false;
+    for (index, (token, span)) in line.iter().enumerate() {
+        let next = line.get(index + 1).map(|(t, _)| t);
+        let kind = match token {
+            Token::Alphanumeric(_) if next == Some(&Token::Colon) => HighlightKind::Label,
+            Token::Alphanumeric(v) if is_register(v) => HighlightKind::Register,
+            Token::Alphanumeric(_) if !seen_mnemonic => {
+                seen_mnemonic = true;
+                match next {
+                    // Constant definition, e.g. `SPEED equ 5`
+                    Some(Token::Alphanumeric(n)) if n.eq_ignore_ascii_case("equ") => {
+                        HighlightKind::Label
+                    }
+                    _ => HighlightKind::Mnemonic,
+                }
+            }
+            Token::Alphanumeric(v) if v.eq_ignore_ascii_case("equ") => HighlightKind::Mnemonic,
+            Token::Alphanumeric(_) => HighlightKind::Label,
+            Token::Integer(_) | Token::Character(_) => HighlightKind::Number,
+            Token::String(_) => HighlightKind::String,
+            _ => continue,
+        };
+        spans.push(HighlightSpan {
+            kind,
+            span: span.clone(),
+        });
+    }
+}
+
+/// Native `r<n>` registers, CHIPPER `V<x>` registers and special registers
+fn is_register(value: &str) -> bool {
+    let lower = value.to_ascii_lowercase();
+    match lower.as_bytes() {
+        [b'r', digits @ ..] if !digits.is_empty() => digits.iter().all(u8::is_ascii_digit),
+        [b'v', x] => x.is_ascii_hexdigit(),
+        _ => matches!(lower.as_str(), "i" | "dt" | "st"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(HighlightKind, &str)> {
+        highlight(source)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.kind, &source[s.span]))
+            .collect()
+    }
+
+    #[test]
+    fn test_highlight() {
+        use HighlightKind::*;
+        let source = "; header\nmain: ldb r1, 0x10 ; don't\r\nSPEED equ 'A'\n\
+                      LD V1, #0A\njmp main\nassert r1 == 2 \"msg\"";
+        assert_eq!(
+            kinds(source),
+            vec![
+                (Comment, "; header"),
+                (Label, "main"),
+                (Mnemonic, "ldb"),
+                (Register, "r1"),
+                (Number, "0x10"),
+                (Comment, "; don't"),
+                (Label, "SPEED"),
+                (Mnemonic, "equ"),
+                (Number, "'A'"),
+                (Mnemonic, "LD"),
+                (Register, "V1"),
+                (Number, "#0A"),
+                (Mnemonic, "jmp"),
+                (Label, "main"),
+                (Mnemonic, "assert"),
+                (Register, "r1"),
+                (Number, "2"),
+                (String, "\"msg\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlight_error() {
+        assert!(matches!(
+            highlight("ldb r1 'A"),
+            Err(LexerError::UnterminatedCharacter(0, _))
+        ));
+    }
+}
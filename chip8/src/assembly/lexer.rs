@@ -15,6 +15,9 @@ const BUFFER_SIZE: usize = 128;
 pub enum LexerError {
     IO(io::Error),
     IntegerParsing(ParseIntError),
+    UnterminatedCharacter(usize, usize),
+    InvalidHexadecimal(usize, usize),
+    UnterminatedString(usize, usize),
 }
 
 impl fmt::Display for LexerError {
@@ -22,6 +25,23 @@ impl fmt::Display for LexerError {
         match *self {
             LexerError::IO(ref err) => err.fmt(f),
             LexerError::IntegerParsing(ref err) => err.fmt(f),
+            LexerError::UnterminatedCharacter(line, column) => {
+                write!(
+                    f,
+                    "Unterminated character literal (Loc: {},{})",
+                    line, column
+                )
+            }
+            LexerError::UnterminatedString(line, column) => {
+                write!(f, "Unterminated string literal (Loc: {},{})", line, column)
+            }
+            LexerError::InvalidHexadecimal(line, column) => {
+                write!(
+                    f,
+                    "Expected hexadecimal digits after '0x' (Loc: {},{})",
+                    line, column
+                )
+            }
         }
     }
 }
@@ -44,6 +64,20 @@ impl From<ParseIntError> for LexerError {
 pub trait Lexer {
     fn next(&mut self) -> Result<Token, LexerError>;
     fn location(&self) -> (usize, usize);
+    /// Byte offset of the next token
+    fn offset(&self) -> usize;
+
+    /// Consumes the rest of the current line including the line break,
+    /// and returns the text preceding the line break
+    fn rest_of_line(&mut self) -> Result<String, LexerError> {
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                Token::EOL | Token::EOF => return Ok(text),
+                token => text.push_str(&token.text()),
+            }
+        }
+    }
 
     fn line(&self) -> usize {
         let (line, _) = self.location();
@@ -66,6 +100,7 @@ pub struct StreamLexer<T: Read> {
     buffer_size: usize,
     line: usize,
     column: usize,
+    offset: usize,
 }
 
 impl<T: Read> StreamLexer<T> {
@@ -77,6 +112,7 @@ impl<T: Read> StreamLexer<T> {
             buffer_size: BUFFER_SIZE,
             line: 0,
             column: 0,
+            offset: 0,
         }
     }
 
@@ -97,6 +133,10 @@ impl<T: Read> StreamLexer<T> {
     fn peek(&mut self) -> Result<char, LexerError> {
         if self.is_buffer_end() {
             self.load()?;
+            if self.buffer_size == 0 {
+                // Nothing left in the stream
+                return Ok('\0');
+            }
         }
         Ok(self.buffer[self.cursor] as char)
     }
@@ -105,6 +145,7 @@ impl<T: Read> StreamLexer<T> {
         let ret = self.peek()?;
         self.cursor += 1;
         self.column += 1;
+        self.offset += 1;
 
         Ok(ret)
     }
@@ -156,6 +197,27 @@ impl<T: Read> Lexer for StreamLexer<T> {
         (self.line, self.column)
     }
 
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn rest_of_line(&mut self) -> Result<String, LexerError> {
+        let mut text = String::new();
+        while !self.is_stream_end() {
+            let c = self.pop()?;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+                break;
+            }
+            text.push(c);
+        }
+        if text.ends_with('\r') {
+            text.pop();
+        }
+        Ok(text)
+    }
+
     fn next(&mut self) -> Result<Token, LexerError> {
         if self.is_stream_end() {
             return Ok(Token::EOF);
@@ -185,7 +247,59 @@ impl<T: Read> Lexer for StreamLexer<T> {
                     Token::Symbol(byte)
                 }
             }
-            b if b.is_ascii_punctuation() => Token::Symbol(b),
+            '#' if self.peek()?.is_ascii_hexdigit() => {
+                // '#' prefixed hexadecimal, as used by the classic CHIPPER dialect
+                let first = self.pop()?;
+                let number: String = self
+                    .collect(first, |e| e.is_ascii_hexdigit())?
+                    .into_iter()
+                    .collect();
+                let integer = usize::from_str_radix(&number, 16)?;
+                Token::Integer(integer)
+            }
+            '\'' => {
+                let c = self.peek()?;
+                if self.is_stream_end() || !(c.is_ascii_graphic() || c == ' ') {
+                    Token::Symbol(byte)
+                } else {
+                    self.pop()?;
+                    if self.peek()? != '\'' || self.is_stream_end() {
+                        return Err(LexerError::UnterminatedCharacter(self.line, self.column));
+                    }
+                    self.pop()?;
+                    Token::Character(c)
+                }
+            }
+            '"' => {
+                let mut literal = String::new();
+                loop {
+                    let c = self.peek()?;
+                    if self.is_stream_end() || c == '\n' || c == '\r' {
+                        return Err(LexerError::UnterminatedString(self.line, self.column));
+                    }
+                    self.pop()?;
+                    if c == '"' {
+                        break;
+                    }
+                    literal.push(c);
+                }
+                Token::String(literal)
+            }
+            b if b.is_ascii_punctuation() && b != '_' => Token::Symbol(b),
+            '0' if self.peek()? == 'x' => {
+                // '0x' prefixed hexadecimal
+                self.pop()?;
+                if !self.peek()?.is_ascii_hexdigit() {
+                    return Err(LexerError::InvalidHexadecimal(self.line, self.column));
+                }
+                let first = self.pop()?;
+                let number: String = self
+                    .collect(first, |e| e.is_ascii_hexdigit())?
+                    .into_iter()
+                    .collect();
+                let integer = usize::from_str_radix(&number, 16)?;
+                Token::Integer(integer)
+            }
             b if b.is_ascii_digit() => {
                 let number: String = self
                     .collect(b, |e| e.is_ascii_digit())?
@@ -194,9 +308,9 @@ impl<T: Read> Lexer for StreamLexer<T> {
                 let integer: usize = number.parse()?;
                 Token::Integer(integer)
             }
-            b if b.is_ascii_alphanumeric() => {
+            b if b.is_ascii_alphanumeric() || b == '_' => {
                 let literal: String = self
-                    .collect(b, |e| e.is_ascii_alphanumeric())?
+                    .collect(b, |e| e.is_ascii_alphanumeric() || e == '_')?
                     .into_iter()
                     .collect();
                 Token::Alphanumeric(literal)
@@ -207,6 +321,64 @@ impl<T: Read> Lexer for StreamLexer<T> {
     }
 }
 
+/// In-memory lexer over already lexed tokens, so tools and tests can
+/// feed tokens directly to the parser. Yields EOF once the tokens run out.
+/// The offset is the index of the next token, and columns count tokens.
+pub struct VecLexer {
+    tokens: Vec<Token>,
+    cursor: usize,
+    line: usize,
+    column: usize,
+}
+
+impl VecLexer {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            cursor: 0,
+            line: 0,
+            column: 0,
+        }
+    }
+}
+
+impl From<Vec<Token>> for VecLexer {
+    fn from(tokens: Vec<Token>) -> Self {
+        Self::new(tokens)
+    }
+}
+
+impl From<&[Token]> for VecLexer {
+    fn from(tokens: &[Token]) -> Self {
+        Self::new(tokens.to_vec())
+    }
+}
+
+impl Lexer for VecLexer {
+    fn location(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+
+    fn offset(&self) -> usize {
+        self.cursor
+    }
+
+    fn next(&mut self) -> Result<Token, LexerError> {
+        let token = match self.tokens.get(self.cursor) {
+            Some(token) => token.clone(),
+            None => return Ok(Token::EOF),
+        };
+        self.cursor += 1;
+        if token == Token::EOL {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Ok(token)
+    }
+}
+
 fn is_whitespace(c: char) -> bool {
     c == ' ' || c == '\t'
 }
@@ -218,6 +390,8 @@ pub enum Token {
     Semicolon,
     Symbol(char),
     Integer(usize),
+    Character(char),
+    String(String),
     Alphanumeric(String),
     Whitespace,
     Unknown(u8),
@@ -225,6 +399,25 @@ pub enum Token {
     EOF,
 }
 
+impl Token {
+    /// The token as it would be written in source
+    pub fn text(&self) -> String {
+        match self {
+            Token::Comma => ",".to_string(),
+            Token::Colon => ":".to_string(),
+            Token::Semicolon => ";".to_string(),
+            Token::Symbol(c) => c.to_string(),
+            Token::Integer(v) => v.to_string(),
+            Token::Character(c) => format!("'{}'", c),
+            Token::String(v) => format!("\"{}\"", v),
+            Token::Alphanumeric(v) => v.clone(),
+            Token::Whitespace => " ".to_string(),
+            Token::Unknown(b) => (*b as char).to_string(),
+            Token::EOL | Token::EOF => "".to_string(),
+        }
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -340,6 +533,15 @@ mod tests {
             "tJKo32Ii",
             vec![Token::Alphanumeric("tJKo32Ii".to_string()), Token::EOF],
         );
+        lex_and_assert(
+            "L_0206 _start",
+            vec![
+                Token::Alphanumeric("L_0206".to_string()),
+                Token::Whitespace,
+                Token::Alphanumeric("_start".to_string()),
+                Token::EOF,
+            ],
+        );
     }
 
     #[test]
@@ -355,6 +557,121 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hex_integer() {
+        lex_and_assert(
+            "#0A #ff #",
+            vec![
+                Token::Integer(0x0A),
+                Token::Whitespace,
+                Token::Integer(0xFF),
+                Token::Whitespace,
+                Token::Symbol('#'),
+                Token::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn prefixed_hex_integer() {
+        lex_and_assert(
+            "0xABCD 0x1f 0 01",
+            vec![
+                Token::Integer(0xABCD),
+                Token::Whitespace,
+                Token::Integer(0x1F),
+                Token::Whitespace,
+                Token::Integer(0),
+                Token::Whitespace,
+                Token::Integer(1),
+                Token::EOF,
+            ],
+        );
+
+        let mut lexer = StreamLexer::new(BufReader::new("0xZ".as_bytes()));
+        assert!(matches!(
+            lexer.next(),
+            Err(LexerError::InvalidHexadecimal(0, 2))
+        ));
+    }
+
+    #[test]
+    fn character() {
+        lex_and_assert(
+            "'A' ' '",
+            vec![
+                Token::Character('A'),
+                Token::Whitespace,
+                Token::Character(' '),
+                Token::EOF,
+            ],
+        );
+    }
+
+    #[test]
+    fn string() {
+        lex_and_assert(
+            "\"r1 is 42\" \"\"",
+            vec![
+                Token::String("r1 is 42".to_string()),
+                Token::Whitespace,
+                Token::String("".to_string()),
+                Token::EOF,
+            ],
+        );
+
+        let mut lexer = StreamLexer::new(BufReader::new("\"abc\nabc\"".as_bytes()));
+        assert!(matches!(
+            lexer.next(),
+            Err(LexerError::UnterminatedString(0, 4))
+        ));
+    }
+
+    #[test]
+    fn rest_of_line() {
+        let mut lexer = StreamLexer::new(BufReader::new("a ; #1 'x\r\nb".as_bytes()));
+        assert_eq!(lexer.next().unwrap(), Token::Alphanumeric("a".to_string()));
+        assert_eq!(lexer.next().unwrap(), Token::Whitespace);
+        assert_eq!(lexer.next().unwrap(), Token::Semicolon);
+        assert_eq!(lexer.rest_of_line().unwrap(), " #1 'x");
+        assert_eq!(lexer.location(), (1, 0));
+        assert_eq!(lexer.next().unwrap(), Token::Alphanumeric("b".to_string()));
+        assert_eq!(lexer.rest_of_line().unwrap(), "");
+    }
+
+    #[test]
+    fn vec_lexer() {
+        let tokens = vec![
+            Token::Alphanumeric("clear".to_string()),
+            Token::EOL,
+            Token::Semicolon,
+            Token::Whitespace,
+            Token::Integer(1),
+            Token::EOL,
+            Token::Alphanumeric("exit".to_string()),
+        ];
+        let mut lexer = VecLexer::from(tokens.as_slice());
+        assert_eq!(lexer.next().unwrap(), tokens[0]);
+        assert_eq!(lexer.next().unwrap(), Token::EOL);
+        assert_eq!(lexer.location(), (1, 0));
+        assert_eq!(lexer.next().unwrap(), Token::Semicolon);
+        assert_eq!(lexer.rest_of_line().unwrap(), " 1");
+        assert_eq!(lexer.location(), (2, 0));
+        assert_eq!(lexer.offset(), 6);
+        assert_eq!(lexer.next().unwrap(), tokens[6]);
+        assert_eq!(lexer.next().unwrap(), Token::EOF);
+        assert_eq!(lexer.next().unwrap(), Token::EOF);
+    }
+
+    #[test]
+    fn unterminated_character() {
+        let mut lexer = StreamLexer::new(BufReader::new("'AB".as_bytes()));
+        assert!(matches!(
+            lexer.next(),
+            Err(LexerError::UnterminatedCharacter(0, 2))
+        ));
+    }
+
     #[test]
     fn unknown() {
         lex_and_assert("\x02", vec![Token::Unknown(0x02), Token::EOF]);
@@ -0,0 +1,160 @@
+//!
+//! Links separately parsed files into a single assembly
+//!
+//! Each file is parsed with [crate::assembly::parser::Parser::parse_object],
+//! which leaves references to labels in other files unresolved. Linking
+//! places the objects after each other in the given order and checks
+//! that every referenced label is defined exactly once.
+//!
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::assembly::{Assembly, Source};
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// The label is defined in several objects, (label, first definition,
+    /// second definition)
+    DuplicateLabel(String, Option<Box<Source>>, Option<Box<Source>>),
+    /// More than one object defines a font
+    DuplicateFont,
+    MissingReferencedLabel(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = |source: &Option<Box<Source>>| match source {
+            Some(s) => format!("{}:{}", s.file, s.line + 1),
+            None => "<unknown>".to_string(),
+        };
+        match *self {
+            Self::DuplicateLabel(ref label, ref first, ref second) => {
+                write!(
+                    f,
+                    "Label '{}' is defined in both {} and {}",
+                    label,
+                    location(first),
+                    location(second)
+                )
+            }
+            Self::DuplicateFont => write!(f, "Font is defined in more than one file"),
+            Self::MissingReferencedLabel(ref label) => {
+                write!(f, "Missing referenced label '{}'", label)
+            }
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Links the objects into one assembly, in the given order
+pub fn link(objects: Vec<Assembly>) -> Result<Assembly, LinkError> {
+    let mut linked = Assembly {
+        instructions: Vec::new(),
+        labels: HashMap::new(),
+        label_sources: HashMap::new(),
+        assertions: Vec::new(),
+        comments: Vec::new(),
+        font: None,
+    };
+
+    for object in objects {
+        let offset = linked.instructions.len();
+        for (label, index) in object.labels {
+            let source = object.label_sources.get(&label).cloned();
+            if linked.labels.contains_key(&label) {
+                let first = linked.label_sources.get(&label).cloned().map(Box::new);
+                return Err(LinkError::DuplicateLabel(
+                    label,
+                    first,
+                    source.map(Box::new),
+                ));
+            }
+            if let Some(source) = source {
+                linked.label_sources.insert(label.clone(), source);
+            }
+            linked.labels.insert(label, index + offset);
+        }
+        for mut assertion in object.assertions {
+            assertion.index += offset;
+            linked.assertions.push(assertion);
+        }
+        for mut comment in object.comments {
+            comment.index += offset;
+            linked.comments.push(comment);
+        }
+        if object.font.is_some() {
+            if linked.font.is_some() {
+                return Err(LinkError::DuplicateFont);
+            }
+            linked.font = object.font;
+        }
+        linked.instructions.extend(object.instructions);
+    }
+
+    for i in &linked.instructions {
+        if let Some(label) = &i.label {
+            if !linked.labels.contains_key(label) {
+                return Err(LinkError::MissingReferencedLabel(label.clone()));
+            }
+        }
+    }
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufReader;
+
+    use crate::assembly::lexer::StreamLexer;
+    use crate::assembly::parser::Parser;
+
+    fn object(file: &str, input: &'static str) -> Assembly {
+        let lexer = StreamLexer::new(BufReader::new(input.as_bytes()));
+        Parser::new(Box::new(lexer))
+            .with_file(file)
+            .parse_object()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let main = object("main.asm", "; entry\ncall square\nexit");
+        let lib = object("lib.asm", "square:\nldb r1 2\n; done\nret");
+        let linked = link(vec![main, lib]).unwrap();
+
+        assert_eq!(linked.instructions.len(), 4);
+        assert_eq!(linked.labels.get("square"), Some(&2));
+        assert_eq!(linked.label_sources["square"].file, "lib.asm");
+        assert_eq!(
+            linked.comments.iter().map(|c| c.index).collect::<Vec<_>>(),
+            vec![0, 3]
+        );
+        assert_eq!(
+            linked.binary().unwrap(),
+            vec![0x22, 0x04, 0xF1, 0xEE, 0x61, 0x02, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let result = link(vec![
+            object("a.asm", "f:\nret"),
+            object("b.asm", "clear\nf:\nret"),
+        ]);
+        match result {
+            Err(LinkError::DuplicateLabel(label, Some(first), Some(second))) => {
+                assert_eq!(label, "f");
+                assert_eq!((first.file.as_str(), first.line), ("a.asm", 0));
+                assert_eq!((second.file.as_str(), second.line), ("b.asm", 1));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let result = link(vec![object("a.asm", "jmp missing")]);
+        assert!(matches!(result, Err(LinkError::MissingReferencedLabel(l)) if l == "missing"));
+    }
+}
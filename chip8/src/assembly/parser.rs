@@ -1,26 +1,56 @@
 //!
 //! Chip-8 parser
 //!
+//! Accepts both the native mnemonics (`ldb r1 10`) and the classic
+//! CHIPPER dialect (`LD V1, #0A`).
+//!
+//! Directives:
+//!
+//! * `opcode 0xABCD` emits a 16 bit opcode as-is
+//! * `db 0xAB 0xCD` emits data bytes
+//! * `assert r1 == 42 "message"` checks a register at a breakpoint, see
+//!   [crate::assembly::testing]
+//! * `font 0 0xF0909090F0` defines the sprite of a digit, 16 of them make a
+//!   custom font
+//! * `SPEED equ 5` defines a constant
+//! * `%ifdef`, `%ifndef`, `%else` and `%endif` assemble lines depending on
+//!   the symbols defined by `equ` or [ParserOptions::defines]
+//!
+//! Arguments in parentheses are constant expressions folded at parse time,
+//! e.g. `ldb r1 (SPEED * 2 + 1)`, with the operators
+//! `+ - * / % & | ^ << >>`. Values which don't fit their operand are
+//! reported with the operand type.
+//!
+//! The SUPER-CHIP and XO-CHIP mnemonics are only accepted when
+//! [ParserOptions::target] has them. The SUPER-CHIP 16x16 sprite is drawn
+//! with a height of 0.
+//!
 //! There's implied whitespace everywhere
 //! There's implied comment ignore
 //! <end> ::= <eol> | <eof>
 //! <empty> ::= <whitespace> ...
 //! <comment> ::= <semi-colon> <anything>
 //! <label> ::= <literal> <colon>
-//! <literal> ::= <integer> | <alphanumeric>
-//! <instruction> ::= <literal> <literal> <literal> <end>
-//!     | <literal> <literal> <eol>
-//!     | <literal> <eol>
+//! <literal> ::= <integer> | <character> | <alphanumeric>
+//! <operator> ::= == | !=
+//! <expression> ::= ( <anything> )
+//! <argument> ::= <literal> | [ <alphanumeric> ] | <operator> | <expression>
+//! <arguments> ::= <argument> | <argument> <arguments> | <argument> <comma> <arguments>
+//! <instruction> ::= <alphanumeric> <arguments> <end>
+//!     | <alphanumeric> <arguments> <string> <end>
+//!     | <alphanumeric> <end>
 //! <instruction-opt-label> ::= <label> <instruction> | <instruction>
 //! <instruction-opt-label-opt-comment> ::= <instruction-opt-label> <comment>
 //! <line> ::= <comment><end> | <instruction-opt-label-opt-comment><end> | <empty><end>
 //! <assembly> ::= <assembly> <line> | <line>
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
 
 use crate::assembly::lexer::{Lexer, LexerError, Token};
-use crate::assembly::{Assembly, ParsedInstruction};
-use crate::instructions::{u12, u4, Instruction};
+use crate::assembly::{Assembly, Assertion, Comment, ParsedInstruction, Source};
+use crate::emulator::Font;
+use crate::instructions::{u12, u4, Instruction, Target};
 
 use std::error::Error;
 use std::fmt;
@@ -29,20 +59,33 @@ use std::num::ParseIntError;
 #[derive(Debug)]
 pub enum ArgumentError {
     IntegerParse(ParseIntError),
-    MissingRegistryPrefix(String),
+    MissingRegistryPrefix(char, String),
     UnexpectedArgument(String),
     MissingArgument,
+    /// The value doesn't fit in the operand, (value, operand type)
+    OutOfRange(String, &'static str),
+    /// A malformed constant expression, (expression, reason)
+    InvalidExpression(String, &'static str),
+    /// A name in an expression which isn't a defined constant
+    UndefinedSymbol(String),
 }
 
 impl fmt::Display for ArgumentError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Self::IntegerParse(ref err) => err.fmt(f),
-            Self::MissingRegistryPrefix(ref v) => {
-                write!(f, "Missing registry prefix 'r' for '{}'", v)
+            Self::MissingRegistryPrefix(prefix, ref v) => {
+                write!(f, "Missing registry prefix '{}' for '{}'", prefix, v)
             }
             Self::UnexpectedArgument(ref v) => write!(f, "Unexpected argument '{}'", v),
             Self::MissingArgument => write!(f, "Missing required argument"),
+            Self::OutOfRange(ref v, operand) => {
+                write!(f, "value {} does not fit in {} operand", v, operand)
+            }
+            Self::InvalidExpression(ref v, reason) => {
+                write!(f, "invalid expression '{}': {}", v, reason)
+            }
+            Self::UndefinedSymbol(ref v) => write!(f, "undefined symbol '{}'", v),
         }
     }
 }
@@ -56,6 +99,10 @@ impl From<ParseIntError> for ArgumentError {
 }
 
 type Location = (usize, usize);
+
+/// The maximum number of arguments an instruction can have
+const MAX_ARGUMENTS: usize = 3;
+
 #[derive(Debug)]
 pub enum ParsingError {
     Lexer(LexerError),
@@ -63,6 +110,17 @@ pub enum ParsingError {
     UnknownInstruction(String, Location),
     UnexpectedToken(&'static str, Token, Location),
     MissingReferencedLabel(String),
+    /// Only some of the 16 sprites of a font were defined
+    IncompleteFont(usize),
+    /// The instruction isn't available on the target platform
+    UnsupportedInstruction(String, Target, Location),
+    /// An extension instruction used without selecting a target which has
+    /// it, (mnemonic, minimum target, location)
+    ExtensionInstruction(String, Target, Location),
+    /// `%else` or `%endif` without `%ifdef`, or `%ifdef` without `%endif`
+    UnbalancedConditional(Location),
+    /// Parsing a single instruction found none or several, (count)
+    NotSingleInstruction(usize),
     Unknown(String),
 }
 
@@ -97,6 +155,33 @@ impl fmt::Display for ParsingError {
             Self::MissingReferencedLabel(ref label) => {
                 write!(f, "Missing referenced label {}", label)
             }
+            Self::UnbalancedConditional(location) => {
+                write!(
+                    f,
+                    "Unbalanced conditional, expected matching %ifdef and %endif (Loc: {},{})",
+                    location.0, location.1
+                )
+            }
+            Self::ExtensionInstruction(ref instr, target, location) => {
+                write!(
+                    f,
+                    "Instruction '{}' requires target {} or newer (Loc: {},{})",
+                    instr, target, location.0, location.1
+                )
+            }
+            Self::UnsupportedInstruction(ref instr, target, location) => {
+                write!(
+                    f,
+                    "Instruction '{}' isn't supported by target {} (Loc: {},{})",
+                    instr, target, location.0, location.1
+                )
+            }
+            Self::IncompleteFont(defined) => {
+                write!(f, "Font defines {} of the 16 required sprites", defined)
+            }
+            Self::NotSingleInstruction(count) => {
+                write!(f, "Expected a single instruction, found {}", count)
+            }
         }
     }
 }
@@ -109,20 +194,503 @@ impl From<LexerError> for ParsingError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct RawInstr {
     operation: String,
     arg1: Option<String>,
     arg2: Option<String>,
     arg3: Option<String>,
-    _comment: Option<String>,
+    /// Trailing string literal, e.g. the message of an `assert`
+    message: Option<String>,
+    comment: Option<String>,
     location: Location,
+    /// Byte offsets of the instruction in the source
+    span: Range<usize>,
+}
+
+/// Mnemonics of the classic CHIPPER dialect (Cowgod's reference)
+const CHIPPER_MNEMONICS: [&str; 19] = [
+    "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN",
+    "SHL", "RND", "DRW", "SKP", "SKNP",
+];
+
+/// Operands in the classic CHIPPER dialect, where the mnemonic alone
+/// doesn't decide the instruction
+#[derive(Debug)]
+enum ChipperOperand {
+    Register(u4),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Literal(String),
+}
+
+impl ChipperOperand {
+    fn parse(arg: &str) -> Self {
+        match arg.to_ascii_uppercase().as_str() {
+            "I" => Self::I,
+            "[I]" => Self::IndirectI,
+            "DT" => Self::DelayTimer,
+            "ST" => Self::SoundTimer,
+            "K" => Self::Key,
+            "F" => Self::Font,
+            "B" => Self::Bcd,
+            upper => match upper.strip_prefix('V') {
+                Some(index) if index.len() == 1 => match u8::from_str_radix(index, 16) {
+                    Ok(index) => Self::Register(u4::little(index)),
+                    Err(_) => Self::Literal(arg.to_string()),
+                },
+                _ => Self::Literal(arg.to_string()),
+            },
+        }
+    }
 }
 
 impl RawInstr {
-    fn try_to_instruction(&self) -> Result<ParsedInstruction, ParsingError> {
+    /// Converts the raw instruction, accepting both the native mnemonics
+    /// and the classic CHIPPER dialect (`LD V1, #0A`)
+    fn try_to_instruction(
+        &self,
+        options: &ParserOptions,
+    ) -> Result<ParsedInstruction, ParsingError> {
+        if let Some(message) = &self.message {
+            return Err(ParsingError::ArgumentError(
+                "message",
+                self.location,
+                ArgumentError::UnexpectedArgument(format!("\"{}\"", message)),
+            ));
+        }
+        if options.pseudo_instructions {
+            if let Some(lowered) = self.lower(options)? {
+                let mut parsed = lowered.try_to_native_instruction(options)?;
+                parsed.expanded_from = Some(self.to_source());
+                return Ok(parsed);
+            }
+        }
+        if let Some(parsed) = self.try_to_extension_instruction(options)? {
+            return Ok(parsed);
+        }
+        let native = self.try_to_native_instruction(options);
+        if native.is_ok() {
+            return native;
+        }
+        match self.try_to_chipper_instruction() {
+            Ok(parsed) => Ok(parsed),
+            Err(ParsingError::UnknownInstruction(..)) => native,
+            Err(err) => match native {
+                Err(ParsingError::UnknownInstruction(..)) => Err(err),
+                native => native,
+            },
+        }
+    }
+
+    /// Parses the SUPER-CHIP and XO-CHIP extensions, which are only
+    /// accepted when targeting a platform which has them
+    ///
+    /// scd n     => 00Cn
+    /// scr       => 00FB
+    /// scl       => 00FC
+    /// halt      => 00FD
+    /// lores     => 00FE
+    /// hires     => 00FF
+    /// ldhf rx   => Fx30
+    /// saveflags rx => Fx75
+    /// loadflags rx => Fx85
+    /// plane n   => Fn01
+    fn try_to_extension_instruction(
+        &self,
+        options: &ParserOptions,
+    ) -> Result<Option<ParsedInstruction>, ParsingError> {
+        let operation = if options.case_sensitive {
+            self.operation.clone()
+        } else {
+            self.operation.to_ascii_lowercase()
+        };
+        let (mnemonic, expected, required) = match operation.as_str() {
+            "scd" => ("scd", 1, Target::Schip),
+            "scr" => ("scr", 0, Target::Schip),
+            "scl" => ("scl", 0, Target::Schip),
+            "lores" => ("lores", 0, Target::Schip),
+            "hires" => ("hires", 0, Target::Schip),
+            "saveflags" => ("saveflags", 1, Target::Schip),
+            "loadflags" => ("loadflags", 1, Target::Schip),
+            "halt" => ("halt", 0, Target::Schip),
+            "ldhf" => ("ldhf", 1, Target::Schip),
+            "plane" => ("plane", 1, Target::XoChip),
+            _ => return Ok(None),
+        };
+        let target = match options.target {
+            Some(target) if target >= required => target,
+            _ => {
+                return Err(ParsingError::ExtensionInstruction(
+                    mnemonic.to_string(),
+                    required,
+                    self.location,
+                ))
+            }
+        };
+        let error = |e| ParsingError::ArgumentError(mnemonic, self.location, e);
+        let args = [&self.arg1, &self.arg2, &self.arg3];
+        if let Some(Some(v)) = args.get(expected) {
+            return Err(error(ArgumentError::UnexpectedArgument(v.clone())));
+        }
+
+        let instruction = match mnemonic {
+            "scd" => Instruction::ScrollDown(
+                RawInstr::parse_as_nibble(self.arg1.as_ref()).map_err(error)?,
+            ),
+            "scr" => Instruction::ScrollRight,
+            "scl" => Instruction::ScrollLeft,
+            "halt" => Instruction::Halt,
+            "plane" => Instruction::SelectPlane(
+                RawInstr::parse_as_nibble(self.arg1.as_ref()).map_err(error)?,
+            ),
+            "lores" => Instruction::LowRes,
+            "hires" => Instruction::HighRes,
+            "ldhf" => Instruction::SetMemRegisterLargeSprite(
+                RawInstr::parse_as_registry(self.arg1.as_ref(), options).map_err(error)?,
+            ),
+            _ => {
+                let x = RawInstr::parse_as_registry(self.arg1.as_ref(), options).map_err(error)?;
+                // SUPER-CHIP only has 8 flag registers, XO-CHIP has 16
+                if target == Target::Schip && x.value() > 7 {
+                    let value = self.arg1.clone().unwrap_or_default();
+                    return Err(error(ArgumentError::OutOfRange(value, "flags register")));
+                }
+                if mnemonic == "saveflags" {
+                    Instruction::SaveFlags(x)
+                } else {
+                    Instruction::LoadFlags(x)
+                }
+            }
+        };
+        Ok(Some(ParsedInstruction::new(instruction)))
+    }
+
+    /// Lowers pseudo-instructions into the native instruction they expand to
+    ///
+    /// nop       => ldr r0 r0
+    /// inc rx    => add rx 1
+    /// dec rx    => add rx 255
+    /// mov rx ry => ldr rx ry
+    /// mov rx kk => ldb rx kk
+    fn lower(&self, options: &ParserOptions) -> Result<Option<RawInstr>, ParsingError> {
+        let operation = if options.case_sensitive {
+            self.operation.clone()
+        } else {
+            self.operation.to_ascii_lowercase()
+        };
+        let (mnemonic, expected) = match operation.as_str() {
+            "nop" => ("nop", 0),
+            "inc" => ("inc", 1),
+            "dec" => ("dec", 1),
+            "mov" => ("mov", 2),
+            _ => return Ok(None),
+        };
+        let args = [&self.arg1, &self.arg2, &self.arg3];
+        if let Some(Some(v)) = args.get(expected) {
+            return Err(ParsingError::ArgumentError(
+                mnemonic,
+                self.location,
+                ArgumentError::UnexpectedArgument(v.clone()),
+            ));
+        }
+        if args[..expected].iter().any(|a| a.is_none()) {
+            return Err(ParsingError::ArgumentError(
+                mnemonic,
+                self.location,
+                ArgumentError::MissingArgument,
+            ));
+        }
+
+        let (operation, arg1, arg2) = match mnemonic {
+            "nop" => {
+                let r0 = format!("{}0", options.register_prefix);
+                ("ldr", Some(r0.clone()), Some(r0))
+            }
+            "inc" => ("add", self.arg1.clone(), Some("1".to_string())),
+            "dec" => ("add", self.arg1.clone(), Some("255".to_string())),
+            _ => {
+                if RawInstr::parse_as_registry(self.arg2.as_ref(), options).is_ok() {
+                    ("ldr", self.arg1.clone(), self.arg2.clone())
+                } else {
+                    ("ldb", self.arg1.clone(), self.arg2.clone())
+                }
+            }
+        };
+        Ok(Some(RawInstr {
+            operation: operation.to_string(),
+            arg1,
+            arg2,
+            arg3: None,
+            message: None,
+            comment: None,
+            location: self.location,
+            span: self.span.clone(),
+        }))
+    }
+
+    fn check_target(
+        &self,
+        parsed: &ParsedInstruction,
+        options: &ParserOptions,
+    ) -> Result<(), ParsingError> {
+        match (options.target, &parsed.instruction) {
+            // Decodes as the SUPER-CHIP 16x16 sprite, drawing nothing before
+            (Some(target), Instruction::Draw(_, _, n))
+                if n.value() == 0 && target < Target::Schip =>
+            {
+                Err(ParsingError::ExtensionInstruction(
+                    self.to_source(),
+                    Target::Schip,
+                    self.location,
+                ))
+            }
+            (Some(target), instruction) if !target.supports(instruction) => Err(
+                ParsingError::UnsupportedInstruction(self.to_source(), target, self.location),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether this is a constant definition, e.g. `SPEED equ 5`
+    fn is_constant(&self, options: &ParserOptions) -> bool {
+        match &self.arg1 {
+            Some(arg) if options.case_sensitive => arg == "equ",
+            Some(arg) => arg.eq_ignore_ascii_case("equ"),
+            None => false,
+        }
+    }
+
+    /// Replaces arguments naming a symbol with the value of the symbol,
+    /// and folds constant expressions into their value
+    fn substitute(&self, symbols: &HashMap<String, String>) -> Result<RawInstr, ParsingError> {
+        let resolve = |arg: &Option<String>| match arg {
+            Some(a) => resolve_symbol(a, symbols)
+                .map(Some)
+                .map_err(|e| ParsingError::ArgumentError("expression", self.location, e)),
+            None => Ok(None),
+        };
+        Ok(RawInstr {
+            arg1: resolve(&self.arg1)?,
+            arg2: resolve(&self.arg2)?,
+            arg3: resolve(&self.arg3)?,
+            ..self.clone()
+        })
+    }
+
+    fn is_directive(&self, directive: &str, options: &ParserOptions) -> bool {
+        if options.case_sensitive {
+            self.operation == directive
+        } else {
+            self.operation.eq_ignore_ascii_case(directive)
+        }
+    }
+
+    /// Parses a sprite of a custom font, with the 5 bytes of the sprite
+    /// packed into one integer
+    ///
+    /// font 0 0xF0909090F0
+    fn try_to_font_sprite(&self) -> Result<(usize, [u8; 5]), ParsingError> {
+        let error = |e| ParsingError::ArgumentError("font", self.location, e);
+        if let Some(v) = &self.arg3 {
+            return Err(error(ArgumentError::UnexpectedArgument(v.clone())));
+        }
+        let (Some(digit), Some(sprite)) = (&self.arg1, &self.arg2) else {
+            return Err(error(ArgumentError::MissingArgument));
+        };
+        let index = digit.parse::<usize>().map_err(|e| error(e.into()))?;
+        if index > 0xF {
+            return Err(error(ArgumentError::UnexpectedArgument(digit.clone())));
+        }
+        let value = sprite.parse::<u64>().map_err(|e| error(e.into()))?;
+        if value > 0xFF_FFFF_FFFF {
+            return Err(error(ArgumentError::UnexpectedArgument(sprite.clone())));
+        }
+        let mut bytes = [0; 5];
+        bytes.copy_from_slice(&value.to_be_bytes()[3..]);
+        Ok((index, bytes))
+    }
+
+    /// Lowers an assertion into a check which skips over a breakpoint,
+    /// the breakpoint is only hit when the assertion fails
+    ///
+    /// assert rx == kk => se rx kk, break
+    /// assert rx == ry => sre rx ry, break
+    /// assert rx != kk => sne rx kk, break
+    /// assert rx != ry => srne rx ry, break
+    fn try_to_assertion(
+        &self,
+        options: &ParserOptions,
+    ) -> Result<Vec<ParsedInstruction>, ParsingError> {
+        let error = |e| ParsingError::ArgumentError("assert", self.location, e);
+        let (Some(left), Some(operator), Some(right)) = (&self.arg1, &self.arg2, &self.arg3) else {
+            return Err(error(ArgumentError::MissingArgument));
+        };
+        let is_register = RawInstr::parse_as_registry(Some(right), options).is_ok();
+        let operation = match (operator.as_str(), is_register) {
+            ("==", true) => "sre",
+            ("==", false) => "se",
+            ("!=", true) => "srne",
+            ("!=", false) => "sne",
+            (op, _) => return Err(error(ArgumentError::UnexpectedArgument(op.to_string()))),
+        };
+        let check = RawInstr {
+            operation: operation.to_string(),
+            arg1: Some(left.clone()),
+            arg2: Some(right.clone()),
+            arg3: None,
+            message: None,
+            comment: None,
+            location: self.location,
+            span: self.span.clone(),
+        };
+        let source = self.to_source();
+        let mut check = check.try_to_native_instruction(options)?;
+        check.expanded_from = Some(source.clone());
+        let mut trap = ParsedInstruction::new(Instruction::Breakpoint);
+        trap.expanded_from = Some(source);
+        Ok(vec![check, trap])
+    }
+
+    fn source(&self, file: &str) -> Source {
+        Source {
+            file: file.to_string(),
+            line: self.location.0,
+            column: self.location.1,
+            span: self.span.clone(),
+        }
+    }
+
+    /// The instruction as it was written in the source
+    fn to_source(&self) -> String {
+        [&self.arg1, &self.arg2, &self.arg3]
+            .into_iter()
+            .flatten()
+            .fold(self.operation.clone(), |acc, arg| acc + " " + arg)
+    }
+
+    fn try_to_chipper_instruction(&self) -> Result<ParsedInstruction, ParsingError> {
+        use ChipperOperand as Op;
+
+        let mnemonic = match CHIPPER_MNEMONICS
+            .iter()
+            .find(|m| m.eq_ignore_ascii_case(&self.operation))
+        {
+            Some(m) => *m,
+            None => {
+                return Err(ParsingError::UnknownInstruction(
+                    self.operation.clone(),
+                    self.location,
+                ))
+            }
+        };
+        let raw_args: Vec<&String> = [&self.arg1, &self.arg2, &self.arg3]
+            .into_iter()
+            .flatten()
+            .collect();
+        let args: Vec<Op> = raw_args.iter().map(|a| Op::parse(a)).collect();
+        let error = |e| ParsingError::ArgumentError(mnemonic, self.location, e);
+
+        let mut label: Option<String> = None;
+        let instruction = match (mnemonic, args.as_slice()) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("JP", [Op::Literal(a)]) => Instruction::Jump(Self::address_or_label(a, &mut label)),
+            ("JP", [Op::Register(r), Op::Literal(a)]) if *r == 0 => {
+                Instruction::JumpOffset(Self::address_or_label(a, &mut label))
+            }
+            ("CALL", [Op::Literal(a)]) => Instruction::Call(Self::address_or_label(a, &mut label)),
+            ("SE", [Op::Register(x), Op::Register(y)]) => Instruction::SkipRegistersEqual(*x, *y),
+            ("SE", [Op::Register(x), Op::Literal(v)]) => {
+                Instruction::SkipEqual(*x, Self::parse_as_value(Some(v)).map_err(error)?)
+            }
+            ("SNE", [Op::Register(x), Op::Register(y)]) => {
+                Instruction::SkipRegistersNotEqual(*x, *y)
+            }
+            ("SNE", [Op::Register(x), Op::Literal(v)]) => {
+                Instruction::SkipNotEqual(*x, Self::parse_as_value(Some(v)).map_err(error)?)
+            }
+            ("LD", [Op::Register(x), Op::Register(y)]) => Instruction::SetRegisterRegister(*x, *y),
+            ("LD", [Op::Register(x), Op::Literal(v)]) => {
+                Instruction::SetRegisterByte(*x, Self::parse_as_value(Some(v)).map_err(error)?)
+            }
+            ("LD", [Op::I, Op::Literal(a)]) => {
+                Instruction::SetMemRegister(Self::address_or_label(a, &mut label))
+            }
+            ("LD", [Op::Register(x), Op::DelayTimer]) => Instruction::SetRegisterDelayTimer(*x),
+            ("LD", [Op::Register(x), Op::Key]) => Instruction::WaitForKey(*x),
+            ("LD", [Op::DelayTimer, Op::Register(x)]) => Instruction::SetDelayTimer(*x),
+            ("LD", [Op::SoundTimer, Op::Register(x)]) => Instruction::SetSoundTimer(*x),
+            ("LD", [Op::Font, Op::Register(x)]) => Instruction::SetMemRegisterDefaultSprit(*x),
+            ("LD", [Op::Bcd, Op::Register(x)]) => Instruction::SetBcd(*x),
+            ("LD", [Op::IndirectI, Op::Register(x)]) => Instruction::MemWrite(*x),
+            ("LD", [Op::Register(x), Op::IndirectI]) => Instruction::MemRead(*x),
+            ("ADD", [Op::Register(x), Op::Register(y)]) => Instruction::AddChecked(*x, *y),
+            ("ADD", [Op::Register(x), Op::Literal(v)]) => {
+                Instruction::Add(*x, Self::parse_as_value(Some(v)).map_err(error)?)
+            }
+            ("ADD", [Op::I, Op::Register(x)]) => Instruction::AddMemReg(*x),
+            ("OR", [Op::Register(x), Op::Register(y)]) => Instruction::Or(*x, *y),
+            ("AND", [Op::Register(x), Op::Register(y)]) => Instruction::And(*x, *y),
+            ("XOR", [Op::Register(x), Op::Register(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [Op::Register(x), Op::Register(y)]) => Instruction::SubChecked(*x, *y),
+            ("SUBN", [Op::Register(x), Op::Register(y)]) => Instruction::SubNChecked(*x, *y),
+            // Vy is optional, and ignored by most interpreters
+            ("SHR", [Op::Register(x)]) => Instruction::ShiftRight(*x, *x),
+            ("SHR", [Op::Register(x), Op::Register(y)]) => Instruction::ShiftRight(*x, *y),
+            ("SHL", [Op::Register(x)]) => Instruction::ShiftLeft(*x, *x),
+            ("SHL", [Op::Register(x), Op::Register(y)]) => Instruction::ShiftLeft(*x, *y),
+            ("RND", [Op::Register(x), Op::Literal(v)]) => {
+                Instruction::Randomize(*x, Self::parse_as_value(Some(v)).map_err(error)?)
+            }
+            ("DRW", [Op::Register(x), Op::Register(y), Op::Literal(n)]) => {
+                Instruction::Draw(*x, *y, Self::parse_as_nibble(Some(n)).map_err(error)?)
+            }
+            ("SKP", [Op::Register(x)]) => Instruction::SkipKeyPressed(*x),
+            ("SKNP", [Op::Register(x)]) => Instruction::SkipKeyNotPressed(*x),
+            (_, []) => return Err(error(ArgumentError::MissingArgument)),
+            (_, _) => {
+                let args: Vec<&str> = raw_args.iter().map(|a| a.as_str()).collect();
+                return Err(error(ArgumentError::UnexpectedArgument(args.join(", "))));
+            }
+        };
+        Ok(ParsedInstruction {
+            instruction,
+            label,
+            source: None,
+            expanded_from: None,
+            comment: None,
+        })
+    }
+
+    /// Parses an address, and falls back to treating the argument
+    /// as a label which is resolved later on
+    fn address_or_label(arg: &String, label: &mut Option<String>) -> u12 {
+        match RawInstr::parse_as_address(Some(arg)) {
+            Ok(v) => v,
+            Err(_) => {
+                *label = Some(arg.clone());
+                0.into()
+            }
+        }
+    }
+
+    fn try_to_native_instruction(
+        &self,
+        options: &ParserOptions,
+    ) -> Result<ParsedInstruction, ParsingError> {
         let mut label: Option<String> = None;
-        let instruction = match self.operation.as_str() {
+        let operation = if options.case_sensitive {
+            self.operation.clone()
+        } else {
+            self.operation.to_ascii_lowercase()
+        };
+        let instruction = match operation.as_str() {
             "exit" => {
                 if let Some(v) = &self.arg1 {
                     return Err(ParsingError::ArgumentError(
@@ -169,6 +737,32 @@ impl RawInstr {
                 }
                 Instruction::Debug(value.into())
             }
+            "opcode" => {
+                let opcode = RawInstr::parse_as_opcode(self.arg1.as_ref())
+                    .map_err(|e| ParsingError::ArgumentError("opcode", self.location, e))?;
+                if let Some(v) = &self.arg2 {
+                    return Err(ParsingError::ArgumentError(
+                        "opcode",
+                        self.location,
+                        ArgumentError::UnexpectedArgument(v.clone()),
+                    ));
+                }
+                Instruction::Raw(opcode)
+            }
+            "db" => {
+                // Data bytes, a single byte is padded with a zero byte to
+                // keep instructions aligned
+                let error = |e| ParsingError::ArgumentError("db", self.location, e);
+                let high = RawInstr::parse_as_value(self.arg1.as_ref()).map_err(error)?;
+                let low = match &self.arg2 {
+                    Some(_) => RawInstr::parse_as_value(self.arg2.as_ref()).map_err(error)?,
+                    None => 0,
+                };
+                if let Some(v) = &self.arg3 {
+                    return Err(error(ArgumentError::UnexpectedArgument(v.clone())));
+                }
+                Instruction::Raw(u16::from_be_bytes([high, low]))
+            }
             "clear" => {
                 if let Some(v) = &self.arg1 {
                     return Err(ParsingError::ArgumentError(
@@ -207,6 +801,9 @@ impl RawInstr {
                 let addr = if let Some(s) = self.arg1.as_ref() {
                     match RawInstr::parse_as_address(self.arg1.as_ref()) {
                         Ok(v) => v,
+                        Err(e @ ArgumentError::OutOfRange(..)) => {
+                            return Err(ParsingError::ArgumentError("call", self.location, e))
+                        }
                         Err(_) => {
                             // No integer found, this must be a label
                             label = Some(s.clone());
@@ -233,6 +830,9 @@ impl RawInstr {
                 let addr = if let Some(s) = self.arg1.as_ref() {
                     match RawInstr::parse_as_address(self.arg1.as_ref()) {
                         Ok(v) => v,
+                        Err(e @ ArgumentError::OutOfRange(..)) => {
+                            return Err(ParsingError::ArgumentError("jmp", self.location, e))
+                        }
                         Err(_) => {
                             // No integer found, this must be a label
                             label = Some(s.clone());
@@ -259,6 +859,9 @@ impl RawInstr {
                 let addr = if let Some(s) = self.arg1.as_ref() {
                     match RawInstr::parse_as_address(self.arg1.as_ref()) {
                         Ok(v) => v,
+                        Err(e @ ArgumentError::OutOfRange(..)) => {
+                            return Err(ParsingError::ArgumentError("ldi", self.location, e))
+                        }
                         Err(_) => {
                             // No integer found, this must be a label
                             label = Some(s.clone());
@@ -285,6 +888,9 @@ impl RawInstr {
                 let addr = if let Some(s) = self.arg1.as_ref() {
                     match RawInstr::parse_as_address(self.arg1.as_ref()) {
                         Ok(v) => v,
+                        Err(e @ ArgumentError::OutOfRange(..)) => {
+                            return Err(ParsingError::ArgumentError("jmpr", self.location, e))
+                        }
                         Err(_) => {
                             // No integer found, this must be a label
                             label = Some(s.clone());
@@ -308,7 +914,7 @@ impl RawInstr {
                 Instruction::JumpOffset(addr)
             }
             "se" => {
-                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("se", self.location, e))?;
                 let value = RawInstr::parse_as_value(self.arg2.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("se", self.location, e))?;
@@ -322,7 +928,7 @@ impl RawInstr {
                 Instruction::SkipEqual(reg_index, value)
             }
             "sne" => {
-                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sne", self.location, e))?;
                 let value = RawInstr::parse_as_value(self.arg2.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("sne", self.location, e))?;
@@ -336,9 +942,9 @@ impl RawInstr {
                 Instruction::SkipNotEqual(reg_index, value)
             }
             "sre" => {
-                let regx = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sre", self.location, e))?;
-                let regy = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sre", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -350,7 +956,7 @@ impl RawInstr {
                 Instruction::SkipRegistersEqual(regx, regy)
             }
             "ldb" => {
-                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("ldb", self.location, e))?;
                 let value = RawInstr::parse_as_value(self.arg2.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("ldb", self.location, e))?;
@@ -364,13 +970,13 @@ impl RawInstr {
                 Instruction::SetRegisterByte(reg_index, value)
             }
             "ldr" => {
-                let regx = RawInstr::parse_as_registry(self.arg1.as_ref())
-                    .map_err(|e| ParsingError::ArgumentError("ldb", self.location, e))?;
-                let regy = RawInstr::parse_as_registry(self.arg2.as_ref())
-                    .map_err(|e| ParsingError::ArgumentError("ldb", self.location, e))?;
+                let regx = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
+                    .map_err(|e| ParsingError::ArgumentError("ldr", self.location, e))?;
+                let regy = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
+                    .map_err(|e| ParsingError::ArgumentError("ldr", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
-                        "ldb",
+                        "ldr",
                         self.location,
                         ArgumentError::UnexpectedArgument(v.clone()),
                     ));
@@ -378,7 +984,7 @@ impl RawInstr {
                 Instruction::SetRegisterRegister(regx, regy)
             }
             "add" => {
-                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("add", self.location, e))?;
                 let value = RawInstr::parse_as_value(self.arg2.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("add", self.location, e))?;
@@ -392,9 +998,9 @@ impl RawInstr {
                 Instruction::Add(reg_index, value)
             }
             "or" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("or", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("or", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -406,9 +1012,9 @@ impl RawInstr {
                 Instruction::Or(regx_index, regy_index)
             }
             "and" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("and", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("and", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -420,9 +1026,9 @@ impl RawInstr {
                 Instruction::And(regx_index, regy_index)
             }
             "xor" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("xor", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("xor", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -434,9 +1040,9 @@ impl RawInstr {
                 Instruction::Xor(regx_index, regy_index)
             }
             "addc" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("addc", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("addc", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -448,9 +1054,9 @@ impl RawInstr {
                 Instruction::AddChecked(regx_index, regy_index)
             }
             "subc" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("subc", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("subc", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -462,9 +1068,9 @@ impl RawInstr {
                 Instruction::SubChecked(regx_index, regy_index)
             }
             "subnc" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("subnc", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("subnc", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -476,9 +1082,9 @@ impl RawInstr {
                 Instruction::SubNChecked(regx_index, regy_index)
             }
             "shr" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("shr", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("shr", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -490,9 +1096,9 @@ impl RawInstr {
                 Instruction::ShiftRight(regx_index, regy_index)
             }
             "shl" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("shl", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("shl", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -504,9 +1110,9 @@ impl RawInstr {
                 Instruction::ShiftLeft(regx_index, regy_index)
             }
             "srne" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("srne", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("srne", self.location, e))?;
                 if let Some(v) = &self.arg3 {
                     return Err(ParsingError::ArgumentError(
@@ -518,7 +1124,7 @@ impl RawInstr {
                 Instruction::SkipRegistersNotEqual(regx_index, regy_index)
             }
             "ldf" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("ldf", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -537,7 +1143,7 @@ impl RawInstr {
                 Instruction::SetMemRegisterDefaultSprit(regx_index)
             }
             "addi" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("addi", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -556,7 +1162,7 @@ impl RawInstr {
                 Instruction::AddMemReg(regx_index)
             }
             "sbcd" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sbcd", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -575,7 +1181,7 @@ impl RawInstr {
                 Instruction::SetBcd(regx_index)
             }
             "write" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("write", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -594,7 +1200,7 @@ impl RawInstr {
                 Instruction::MemWrite(regx_index)
             }
             "read" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("read", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -613,7 +1219,7 @@ impl RawInstr {
                 Instruction::MemRead(regx_index)
             }
             "rand" => {
-                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let reg_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("rand", self.location, e))?;
                 let value = RawInstr::parse_as_value(self.arg2.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("rand", self.location, e))?;
@@ -627,16 +1233,16 @@ impl RawInstr {
                 Instruction::Randomize(reg_index, value)
             }
             "draw" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("draw", self.location, e))?;
-                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref())
+                let regy_index = RawInstr::parse_as_registry(self.arg2.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("draw", self.location, e))?;
                 let value = RawInstr::parse_as_nibble(self.arg3.as_ref())
                     .map_err(|e| ParsingError::ArgumentError("draw", self.location, e))?;
                 Instruction::Draw(regx_index, regy_index, value)
             }
             "ldd" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("ldd", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -655,7 +1261,7 @@ impl RawInstr {
                 Instruction::SetRegisterDelayTimer(regx_index)
             }
             "delay" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("delay", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -674,7 +1280,7 @@ impl RawInstr {
                 Instruction::SetDelayTimer(regx_index)
             }
             "sound" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sound", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -693,7 +1299,7 @@ impl RawInstr {
                 Instruction::SetSoundTimer(regx_index)
             }
             "skp" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("skp", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -712,7 +1318,7 @@ impl RawInstr {
                 Instruction::SkipKeyPressed(regx_index)
             }
             "sknp" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("sknp", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -731,7 +1337,7 @@ impl RawInstr {
                 Instruction::SkipKeyNotPressed(regx_index)
             }
             "input" => {
-                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref())
+                let regx_index = RawInstr::parse_as_registry(self.arg1.as_ref(), options)
                     .map_err(|e| ParsingError::ArgumentError("input", self.location, e))?;
                 if let Some(v) = &self.arg2 {
                     return Err(ParsingError::ArgumentError(
@@ -760,21 +1366,37 @@ impl RawInstr {
             instruction,
             label,
             source: None,
+            expanded_from: None,
+            comment: None,
         };
         Ok(parsed)
     }
-    fn parse_as_registry(arg: Option<&String>) -> Result<u4, ArgumentError> {
+    fn parse_as_registry(
+        arg: Option<&String>,
+        options: &ParserOptions,
+    ) -> Result<u4, ArgumentError> {
         let value = if let Some(value) = arg {
             value
         } else {
             return Err(ArgumentError::MissingArgument);
         };
-        let index = match value.strip_prefix('r') {
+        let prefix = options.register_prefix;
+        let index = match value.strip_prefix(prefix) {
             Some(n) => n,
-            None => return Err(ArgumentError::MissingRegistryPrefix(value.clone())),
+            None if !options.case_sensitive => {
+                let upper = prefix.to_ascii_uppercase();
+                let lower = prefix.to_ascii_lowercase();
+                match value.strip_prefix([upper, lower]) {
+                    Some(n) => n,
+                    None => {
+                        return Err(ArgumentError::MissingRegistryPrefix(prefix, value.clone()))
+                    }
+                }
+            }
+            None => return Err(ArgumentError::MissingRegistryPrefix(prefix, value.clone())),
         };
-        let index = index.parse::<u8>()?;
-        Ok(u4::little(index))
+        let index = parse_in_range(index, 0xF, "register")?;
+        Ok(u4::little(index as u8))
     }
 
     fn parse_as_nibble(arg: Option<&String>) -> Result<u4, ArgumentError> {
@@ -783,8 +1405,8 @@ impl RawInstr {
         } else {
             return Err(ArgumentError::MissingArgument);
         };
-        let index = value.parse::<u8>()?;
-        Ok(u4::little(index))
+        let index = parse_in_range(value, 0xF, "u4")?;
+        Ok(u4::little(index as u8))
     }
 
     fn parse_as_value(arg: Option<&String>) -> Result<u8, ArgumentError> {
@@ -793,8 +1415,24 @@ impl RawInstr {
         } else {
             return Err(ArgumentError::MissingArgument);
         };
-        let num = value.parse::<u8>()?;
-        Ok(num)
+        // Character literals, e.g. 'A'
+        if let Some(c) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            if let [c] = c.as_bytes() {
+                return Ok(*c);
+            }
+        }
+        let num = parse_in_range(value, 0xFF, "u8")?;
+        Ok(num as u8)
+    }
+
+    fn parse_as_opcode(arg: Option<&String>) -> Result<u16, ArgumentError> {
+        let value = if let Some(value) = arg {
+            value
+        } else {
+            return Err(ArgumentError::MissingArgument);
+        };
+        let num = parse_in_range(value, 0xFFFF, "u16")?;
+        Ok(num as u16)
     }
 
     fn parse_as_address(arg: Option<&String>) -> Result<u12, ArgumentError> {
@@ -803,45 +1441,266 @@ impl RawInstr {
         } else {
             return Err(ArgumentError::MissingArgument);
         };
-        let num = value.parse::<u16>()?;
-        Ok(u12::from_u16(num))
+        let num = parse_in_range(value, 0xFFF, "u12")?;
+        Ok(u12::from_u16(num as u16))
+    }
+}
+
+/// Parses an integer, reporting values outside `0..=max` as out of range
+/// for the operand rather than as a parse error
+fn parse_in_range(value: &str, max: i64, operand: &'static str) -> Result<i64, ArgumentError> {
+    let num = value.parse::<i64>()?;
+    if !(0..=max).contains(&num) {
+        return Err(ArgumentError::OutOfRange(value.to_string(), operand));
+    }
+    Ok(num)
+}
+
+/// Resolves an argument naming a constant or holding a constant expression
+fn resolve_symbol(arg: &str, symbols: &HashMap<String, String>) -> Result<String, ArgumentError> {
+    if let Some(value) = symbols.get(arg) {
+        return Ok(value.clone());
+    }
+    match arg.strip_prefix('(').and_then(|a| a.strip_suffix(')')) {
+        Some(_) => Ok(fold_expression(arg, symbols)?.to_string()),
+        None => Ok(arg.to_string()),
+    }
+}
+
+/// Evaluates a constant expression, as produced by `try_parse_argument`
+/// with the tokens separated by whitespace
+fn fold_expression(expr: &str, symbols: &HashMap<String, String>) -> Result<i64, ArgumentError> {
+    let tokens: Vec<&str> = expr.split_whitespace().collect();
+    // Split off the parentheses glued to the first and last token
+    let mut parts = Vec::new();
+    for token in tokens {
+        let mut rest = token;
+        while let Some(r) = rest.strip_prefix('(') {
+            parts.push("(");
+            rest = r;
+        }
+        let mut closing = 0;
+        while let Some(r) = rest.strip_suffix(')') {
+            closing += 1;
+            rest = r;
+        }
+        if !rest.is_empty() {
+            parts.push(rest);
+        }
+        parts.extend(std::iter::repeat_n(")", closing));
+    }
+
+    let mut folder = Folder {
+        expr,
+        symbols,
+        parts,
+        cursor: 0,
+    };
+    let value = folder.fold(0)?;
+    if folder.cursor != folder.parts.len() {
+        return Err(folder.invalid("unexpected trailing tokens"));
+    }
+    Ok(value)
+}
+
+/// Precedence climbing evaluator for constant expressions
+struct Folder<'a> {
+    expr: &'a str,
+    symbols: &'a HashMap<String, String>,
+    parts: Vec<&'a str>,
+    cursor: usize,
+}
+
+impl Folder<'_> {
+    /// Binary operators with their precedence, higher binds tighter
+    const OPERATORS: [(&'static str, usize); 10] = [
+        ("|", 1),
+        ("^", 2),
+        ("&", 3),
+        ("<<", 4),
+        (">>", 4),
+        ("+", 5),
+        ("-", 5),
+        ("*", 6),
+        ("/", 6),
+        ("%", 6),
+    ];
+
+    fn invalid(&self, reason: &'static str) -> ArgumentError {
+        ArgumentError::InvalidExpression(self.expr.to_string(), reason)
+    }
+
+    /// The operator at the cursor, shifts are lexed as two symbols
+    fn operator(&self) -> Option<(&'static str, usize, usize)> {
+        let current = *self.parts.get(self.cursor)?;
+        let next = self.parts.get(self.cursor + 1).copied();
+        for (op, precedence) in Self::OPERATORS {
+            let matches = match op {
+                "<<" => current == "<" && next == Some("<"),
+                ">>" => current == ">" && next == Some(">"),
+                op => current == op,
+            };
+            if matches {
+                return Some((op, precedence, op.len()));
+            }
+        }
+        None
+    }
+
+    fn fold(&mut self, min_precedence: usize) -> Result<i64, ArgumentError> {
+        let mut lhs = self.operand()?;
+        while let Some((op, precedence, width)) = self.operator() {
+            if precedence < min_precedence {
+                break;
+            }
+            self.cursor += width;
+            let rhs = self.fold(precedence + 1)?;
+            let value = match op {
+                "|" => Some(lhs | rhs),
+                "^" => Some(lhs ^ rhs),
+                "&" => Some(lhs & rhs),
+                "<<" => u32::try_from(rhs).ok().and_then(|r| lhs.checked_shl(r)),
+                ">>" => u32::try_from(rhs).ok().and_then(|r| lhs.checked_shr(r)),
+                "+" => lhs.checked_add(rhs),
+                "-" => lhs.checked_sub(rhs),
+                "*" => lhs.checked_mul(rhs),
+                "/" => lhs.checked_div(rhs),
+                _ => lhs.checked_rem(rhs),
+            };
+            lhs = value.ok_or_else(|| self.invalid("overflow or division by zero"))?;
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<i64, ArgumentError> {
+        let part = *self
+            .parts
+            .get(self.cursor)
+            .ok_or_else(|| self.invalid("missing operand"))?;
+        self.cursor += 1;
+        match part {
+            "(" => {
+                let value = self.fold(0)?;
+                if self.parts.get(self.cursor) != Some(&")") {
+                    return Err(self.invalid("missing ')'"));
+                }
+                self.cursor += 1;
+                Ok(value)
+            }
+            "-" => Ok(-self.operand()?),
+            part => {
+                if let Ok(value) = part.parse::<i64>() {
+                    return Ok(value);
+                }
+                if let Some(c) = part.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                    if let [c] = c.as_bytes() {
+                        return Ok(*c as i64);
+                    }
+                }
+                match self.symbols.get(part) {
+                    Some(value) => value
+                        .parse::<i64>()
+                        .map_err(|_| self.invalid("constant isn't an integer")),
+                    None if part.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                        Err(ArgumentError::UndefinedSymbol(part.to_string()))
+                    }
+                    None => Err(self.invalid("unexpected token")),
+                }
+            }
+        }
     }
 }
 
 #[derive(Debug)]
 enum Line {
-    Comment(String),
-    Label(String),
+    Comment(String, usize),
+    Label(String, Location, Range<usize>),
+    /// Conditional assembly, e.g. `%ifdef DEBUG`
+    Conditional(String, Option<String>, Location),
     Instruction(RawInstr),
 }
 
-impl Line {
-    fn is_comment(&self) -> bool {
-        matches!(self, Line::Comment(_))
+/// Configures which source variations the parser accepts
+#[derive(Debug, Clone)]
+pub struct ParserOptions {
+    /// Require mnemonics and register prefixes to match case exactly
+    pub case_sensitive: bool,
+    /// Require arguments to be separated by commas, e.g. `ldb r1, 5`
+    pub require_commas: bool,
+    /// Prefix used for registers in the native mnemonics, e.g. 'r' for `r1`
+    pub register_prefix: char,
+    /// Expand the pseudo-instructions `nop`, `inc`, `dec` and `mov`
+    pub pseudo_instructions: bool,
+    /// Predefined symbols, visible to `%ifdef` and usable like `equ` constants
+    pub defines: HashMap<String, String>,
+    /// Reject instructions the platform can't execute, any instruction is
+    /// accepted when unset
+    pub target: Option<Target>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            require_commas: false,
+            register_prefix: 'r',
+            pseudo_instructions: true,
+            defines: HashMap::new(),
+            target: None,
+        }
     }
 }
 
 pub struct Parser {
     lexer: Box<dyn Lexer>,
+    options: ParserOptions,
+    file: String,
     has_peeked: bool,
     peek: Token,
+    peek_start: (Location, usize),
+    peek_end: usize,
+    /// Location and byte offset where the last popped token starts
+    start: (Location, usize),
+    /// Byte offset where the last popped token ends
+    end: usize,
 }
 
 impl Parser {
     pub fn new(lexer: Box<dyn Lexer>) -> Self {
+        Self::new_with_options(lexer, ParserOptions::default())
+    }
+
+    pub fn new_with_options(lexer: Box<dyn Lexer>, options: ParserOptions) -> Self {
         Self {
             lexer,
+            options,
+            file: "<input>".to_string(),
             peek: Token::EOF,
             has_peeked: false,
+            peek_start: ((0, 0), 0),
+            peek_end: 0,
+            start: ((0, 0), 0),
+            end: 0,
         }
     }
 
+    /// Sets the file name used for the source of parsed instructions
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
     fn pop(&mut self) -> Result<Token, ParsingError> {
         if self.has_peeked {
             self.has_peeked = false;
+            self.start = self.peek_start;
+            self.end = self.peek_end;
             Ok(self.peek.clone())
         } else {
-            Ok(self.lexer.next()?)
+            self.start = (self.lexer.location(), self.lexer.offset());
+            let token = self.lexer.next()?;
+            self.end = self.lexer.offset();
+            Ok(token)
         }
     }
 
@@ -849,7 +1708,12 @@ impl Parser {
         if self.has_peeked {
             return Ok(&self.peek);
         }
+        // Peeking mustn't move the start of the last popped token
+        let (start, end) = (self.start, self.end);
         self.peek = self.pop()?;
+        self.peek_start = self.start;
+        self.peek_end = self.end;
+        (self.start, self.end) = (start, end);
         self.has_peeked = true;
         Ok(&self.peek)
     }
@@ -864,16 +1728,11 @@ impl Parser {
         }
     }
 
+    /// Parses the rest of the line following a consumed ';'
     fn try_parse_comment(&mut self) -> Result<Line, ParsingError> {
-        if matches!(self.peek()?, Token::Semicolon) {
-            self.pop()?;
-        }
-        loop {
-            let token = self.pop()?;
-            if matches!(token, Token::EOL | Token::EOF) {
-                return Ok(Line::Comment("".to_string()));
-            }
-        }
+        let line = self.lexer.line();
+        let text = self.lexer.rest_of_line()?;
+        Ok(Line::Comment(text, line))
     }
 
     fn try_parse_label(
@@ -881,18 +1740,20 @@ impl Parser {
         previous: &Token,
         location: Location,
     ) -> Result<Line, ParsingError> {
+        let (label_location, start) = self.start;
         if let Token::Alphanumeric(v) = previous {
             let location = self.lexer.location();
             let token = self.peek()?;
             if matches!(token, Token::Colon) {
                 self.pop()?;
+                let span = start..self.end;
                 let label = v.clone();
                 loop {
                     let location = self.lexer.location();
                     match self.peek()? {
                         Token::EOL => {
                             self.pop()?;
-                            return Ok(Line::Label(label));
+                            return Ok(Line::Label(label, label_location, span));
                         }
                         Token::Whitespace => self.pop()?,
                         t => {
@@ -918,220 +1779,223 @@ impl Parser {
         ))
     }
 
-    fn try_parse_instruction(&mut self, previous: &Token) -> Result<Line, ParsingError> {
-        let line = self.lexer.line();
-        let start_location = (line, 0);
-        if let Token::Alphanumeric(op) = previous {
-            self.trim_whitespace()?;
-
-            let location = self.lexer.location();
-            let second = match self.peek()? {
-                Token::Alphanumeric(p1) => {
-                    let ret = p1.clone();
-                    self.pop()?;
-                    ret
-                }
-                Token::Integer(p2) => {
-                    let ret = p2.to_string();
-                    self.pop()?;
-                    ret
+    fn try_parse_argument(&mut self) -> Result<String, ParsingError> {
+        let location = self.lexer.location();
+        match self.pop()? {
+            Token::Alphanumeric(v) => Ok(v),
+            Token::Integer(v) => Ok(v.to_string()),
+            Token::Character(c) => Ok(format!("'{}'", c)),
+            Token::Symbol(c @ ('=' | '!')) => {
+                // Comparison operator, e.g. '==' or '!='
+                let location = self.lexer.location();
+                match self.pop()? {
+                    Token::Symbol('=') => Ok(format!("{}=", c)),
+                    token => Err(ParsingError::UnexpectedToken(
+                        "parse:argument:operator",
+                        token,
+                        location,
+                    )),
                 }
-                Token::Semicolon => {
-                    let result = self.try_parse_comment()?;
-                    if let Line::Comment(comment) = result {
-                        let instr = RawInstr {
-                            operation: op.clone(),
-                            arg1: None,
-                            arg2: None,
-                            arg3: None,
-                            _comment: Some(comment.clone()),
-                            location: start_location,
-                        };
-                        return Ok(Line::Instruction(instr));
+            }
+            Token::Symbol('(') => {
+                // Constant expression, e.g. '(SPEED * 2)', folded once
+                // constants are known
+                let mut parts = Vec::new();
+                let mut depth = 1;
+                loop {
+                    let location = self.lexer.location();
+                    match self.pop()? {
+                        Token::Whitespace => continue,
+                        token @ (Token::EOL | Token::EOF | Token::Semicolon | Token::String(_)) => {
+                            return Err(ParsingError::UnexpectedToken(
+                                "parse:argument:expression",
+                                token,
+                                location,
+                            ))
+                        }
+                        token => {
+                            match token {
+                                Token::Symbol('(') => depth += 1,
+                                Token::Symbol(')') => depth -= 1,
+                                _ => {}
+                            }
+                            if depth == 0 {
+                                break;
+                            }
+                            parts.push(token.text());
+                        }
                     }
-                    // Something weird happened
-                    return Err(ParsingError::Unknown(format!(
-                        "expected parsed comment, received {:?}",
-                        result
-                    )));
-                }
-                Token::EOL | Token::EOF => {
-                    self.pop()?;
-                    let instr = RawInstr {
-                        operation: op.clone(),
-                        arg1: None,
-                        arg2: None,
-                        arg3: None,
-                        _comment: None,
-                        location: start_location,
-                    };
-                    return Ok(Line::Instruction(instr));
                 }
-                token => {
-                    return Err(ParsingError::UnexpectedToken(
-                        "parse:instruction:second",
-                        token.clone(),
+                Ok(format!("({})", parts.join(" ")))
+            }
+            Token::Symbol('[') => {
+                // Indirect operand, e.g. '[I]'
+                let location = self.lexer.location();
+                let inner = match self.pop()? {
+                    Token::Alphanumeric(v) => v,
+                    token => {
+                        return Err(ParsingError::UnexpectedToken(
+                            "parse:argument:indirect",
+                            token,
+                            location,
+                        ))
+                    }
+                };
+                let location = self.lexer.location();
+                match self.pop()? {
+                    Token::Symbol(']') => Ok(format!("[{}]", inner)),
+                    token => Err(ParsingError::UnexpectedToken(
+                        "parse:argument:indirect-end",
+                        token,
                         location,
-                    ))
+                    )),
                 }
-            };
-
-            self.trim_whitespace()?;
+            }
+            token => Err(ParsingError::UnexpectedToken(
+                "parse:argument",
+                token,
+                location,
+            )),
+        }
+    }
 
-            let location = self.lexer.location();
-            let third = match self.peek()? {
-                Token::Alphanumeric(p1) => {
-                    let ret = p1.clone();
-                    self.pop()?;
-                    ret
-                }
-                Token::Integer(p2) => {
-                    let ret = p2.to_string();
-                    self.pop()?;
-                    ret
-                }
-                Token::Semicolon => {
-                    let result = self.try_parse_comment()?;
-                    if let Line::Comment(comment) = result {
-                        let instr = RawInstr {
-                            operation: op.clone(),
-                            arg1: Some(second),
-                            arg2: None,
-                            arg3: None,
-                            _comment: Some(comment.clone()),
-                            location: start_location,
-                        };
-                        return Ok(Line::Instruction(instr));
+    fn try_parse_instruction(&mut self, previous: &Token) -> Result<Line, ParsingError> {
+        let (start_location, start) = self.start;
+        let mut end = self.end;
+        if let Token::Alphanumeric(op) = previous {
+            let mut args: Vec<String> = Vec::new();
+            let mut comment = None;
+            let mut message = None;
+            let mut separated = false;
+            loop {
+                self.trim_whitespace()?;
+                let location = self.lexer.location();
+                match self.peek()? {
+                    Token::Alphanumeric(_)
+                    | Token::Integer(_)
+                    | Token::Character(_)
+                    | Token::Symbol('[' | '(' | '=' | '!') => {
+                        if args.len() >= MAX_ARGUMENTS || message.is_some() {
+                            return Err(ParsingError::UnexpectedToken(
+                                "parse:instruction:end",
+                                self.peek()?.clone(),
+                                location,
+                            ));
+                        }
+                        if self.options.require_commas && !args.is_empty() && !separated {
+                            return Err(ParsingError::UnexpectedToken(
+                                "parse:instruction:separator",
+                                self.peek()?.clone(),
+                                location,
+                            ));
+                        }
+                        args.push(self.try_parse_argument()?);
+                        end = self.end;
+                        separated = false;
                     }
-                    // Something weird happened
-                    return Err(ParsingError::Unknown(format!(
-                        "expected parsed comment, received {:?}",
-                        result
-                    )));
-                }
-                Token::EOL | Token::EOF => {
-                    self.pop()?;
-                    let instr = RawInstr {
-                        operation: op.clone(),
-                        arg1: Some(second),
-                        arg2: None,
-                        arg3: None,
-                        _comment: None,
-                        location: start_location,
-                    };
-                    return Ok(Line::Instruction(instr));
-                }
-                token => {
-                    return Err(ParsingError::UnexpectedToken(
-                        "parse:instruction:third",
-                        token.clone(),
-                        location,
-                    ))
-                }
-            };
-
-            self.trim_whitespace()?;
-
-            let location = self.lexer.location();
-            let fourth = match self.peek()? {
-                Token::Alphanumeric(p1) => {
-                    let ret = p1.clone();
-                    self.pop()?;
-                    ret
-                }
-                Token::Integer(p2) => {
-                    let ret = p2.to_string();
-                    self.pop()?;
-                    ret
-                }
-                Token::Semicolon => {
-                    let result = self.try_parse_comment()?;
-                    if let Line::Comment(comment) = result {
-                        let instr = RawInstr {
-                            operation: op.clone(),
-                            arg1: Some(second),
-                            arg2: Some(third),
-                            arg3: None,
-                            _comment: Some(comment.clone()),
-                            location: start_location,
-                        };
-                        return Ok(Line::Instruction(instr));
+                    Token::String(_) if message.is_none() => {
+                        if let Token::String(v) = self.pop()? {
+                            message = Some(v);
+                        }
+                        end = self.end;
+                        separated = false;
+                    }
+                    Token::Comma if !args.is_empty() && !separated => {
+                        // Separator between arguments
+                        self.pop()?;
+                        separated = true;
+                    }
+                    token @ (Token::Semicolon | Token::EOL | Token::EOF) if separated => {
+                        // A separator has to be followed by an argument
+                        return Err(ParsingError::UnexpectedToken(
+                            "parse:instruction:argument",
+                            token.clone(),
+                            location,
+                        ));
+                    }
+                    Token::Semicolon => {
+                        self.pop()?;
+                        let result = self.try_parse_comment()?;
+                        if let Line::Comment(c, _) = result {
+                            comment = Some(c);
+                            break;
+                        }
+                        // Something weird happened
+                        return Err(ParsingError::Unknown(format!(
+                            "expected parsed comment, received {:?}",
+                            result
+                        )));
+                    }
+                    Token::EOL | Token::EOF => {
+                        self.pop()?;
+                        break;
+                    }
+                    token => {
+                        return Err(ParsingError::UnexpectedToken(
+                            "parse:instruction:argument",
+                            token.clone(),
+                            location,
+                        ))
                     }
-                    // Something weird happened
-                    return Err(ParsingError::Unknown(format!(
-                        "expected parsed comment, received {:?}",
-                        result
-                    )));
-                }
-                Token::EOL | Token::EOF => {
-                    self.pop()?;
-                    let instr = RawInstr {
-                        operation: op.clone(),
-                        arg1: Some(second),
-                        arg2: Some(third),
-                        arg3: None,
-                        _comment: None,
-                        location: start_location,
-                    };
-                    return Ok(Line::Instruction(instr));
-                }
-                token => {
-                    return Err(ParsingError::UnexpectedToken(
-                        "parse:instruction:fourth",
-                        token.clone(),
-                        location,
-                    ))
                 }
+            }
+
+            let mut args = args.into_iter();
+            let instr = RawInstr {
+                operation: op.clone(),
+                arg1: args.next(),
+                arg2: args.next(),
+                arg3: args.next(),
+                message,
+                comment,
+                location: start_location,
+                span: start..end,
             };
+            return Ok(Line::Instruction(instr));
+        }
+        // Unexpected token start
+        Err(ParsingError::Unknown(format!(
+            "failed to find complete instruction, starting with: {:?}",
+            previous
+        )))
+    }
 
+    /// Parses a conditional assembly directive following a consumed '%',
+    /// e.g. `%ifdef DEBUG`
+    fn try_parse_conditional(&mut self, location: Location) -> Result<Line, ParsingError> {
+        let name = match self.pop()? {
+            Token::Alphanumeric(v) => v,
+            token => {
+                let (location, _) = self.start;
+                return Err(ParsingError::UnexpectedToken(
+                    "parse:conditional:name",
+                    token,
+                    location,
+                ));
+            }
+        };
+        let mut symbol = None;
+        loop {
             self.trim_whitespace()?;
-            let location = self.lexer.location();
-            match self.peek()? {
+            let token = self.pop()?;
+            let (token_location, _) = self.start;
+            match token {
+                Token::Alphanumeric(v) if symbol.is_none() => symbol = Some(v),
                 Token::Semicolon => {
-                    let result = self.try_parse_comment()?;
-                    if let Line::Comment(comment) = result {
-                        let instr = RawInstr {
-                            operation: op.clone(),
-                            arg1: Some(second),
-                            arg2: Some(third),
-                            arg3: Some(fourth),
-                            _comment: Some(comment.clone()),
-                            location: start_location,
-                        };
-                        return Ok(Line::Instruction(instr));
-                    }
-                    // Something weird happened
-                    return Err(ParsingError::Unknown(format!(
-                        "expected parsed comment, received {:?}",
-                        result
-                    )));
-                }
-                Token::EOL | Token::EOF => {
-                    self.pop()?;
-                    let instr = RawInstr {
-                        operation: op.clone(),
-                        arg1: Some(second),
-                        arg2: Some(third),
-                        arg3: Some(fourth),
-                        _comment: None,
-                        location: start_location,
-                    };
-                    return Ok(Line::Instruction(instr));
+                    self.try_parse_comment()?;
+                    break;
                 }
+                Token::EOL | Token::EOF => break,
                 token => {
                     return Err(ParsingError::UnexpectedToken(
-                        "parse:instruction:end",
-                        token.clone(),
-                        location,
+                        "parse:conditional:end",
+                        token,
+                        token_location,
                     ))
                 }
             }
         }
-        // Unexpected token start
-        Err(ParsingError::Unknown(format!(
-            "failed to find complete instruction, starting with: {:?}",
-            previous
-        )))
+        Ok(Line::Conditional(name, symbol, location))
     }
 
     fn try_parse_line(&mut self) -> Result<Option<Line>, ParsingError> {
@@ -1149,11 +2013,12 @@ impl Parser {
                 _ => break,
             };
         }
-        let location = self.lexer.location();
         let token = self.pop()?;
+        let (location, _) = self.start;
         match &token {
             Token::EOF => Ok(None),
             Token::Semicolon => self.try_parse_comment().map(Some),
+            Token::Symbol('%') => self.try_parse_conditional(location).map(Some),
             Token::Alphanumeric(_) => {
                 if matches!(self.peek()?, Token::Colon) {
                     return self.try_parse_label(&token, location).map(Some);
@@ -1169,6 +2034,23 @@ impl Parser {
     }
 
     pub fn parse(&mut self) -> Result<Assembly, ParsingError> {
+        let assembly = self.parse_object()?;
+
+        // Check for non-existent addresses
+        for i in &assembly.instructions {
+            if let Some(label) = &i.label {
+                if !assembly.labels.contains_key(label) {
+                    return Err(ParsingError::MissingReferencedLabel(label.clone()));
+                }
+            }
+        }
+
+        Ok(assembly)
+    }
+
+    /// Parses the input without requiring referenced labels to be defined,
+    /// they're resolved when linking (see [crate::assembly::linker])
+    pub fn parse_object(&mut self) -> Result<Assembly, ParsingError> {
         let mut lines = Vec::new();
 
         loop {
@@ -1181,74 +2063,315 @@ impl Parser {
             lines.push(line);
         }
 
-        let (instructions, labels) = convert_to_instructions(lines)?;
+        convert_to_instructions(lines, &self.options, &self.file)
+    }
 
-        // Check for non-existent addresses
-        for i in &instructions {
-            if let Some(label) = &i.label {
-                if !labels.contains_key(label) {
-                    return Err(ParsingError::MissingReferencedLabel(label.clone()));
+    /// Parses and converts the input one line at a time, without buffering
+    /// the whole program. Referenced labels are left unresolved, see
+    /// [crate::assembly::write_binary] for assembling in two passes.
+    pub fn instructions(&mut self) -> Instructions<'_> {
+        let converter = Converter::new(&self.options);
+        Instructions {
+            parser: self,
+            converter,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+/// Iterator over the instructions of a parser, see [Parser::instructions]
+pub struct Instructions<'a> {
+    parser: &'a mut Parser,
+    converter: Converter,
+    /// Converted instructions not yet yielded, a line can expand to several
+    pending: VecDeque<ParsedInstruction>,
+    done: bool,
+}
+
+impl Instructions<'_> {
+    /// Labels defined so far, with the index of the instruction they
+    /// refer to
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.converter.labels
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<ParsedInstruction, ParsingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(instruction) = self.pending.pop_front() {
+                return Some(Ok(instruction));
+            }
+            if self.done {
+                return None;
+            }
+            let result = match self.parser.try_parse_line() {
+                Ok(Some(line)) => {
+                    self.converter
+                        .convert(&line, &self.parser.options, &self.parser.file)
+                }
+                Ok(None) => {
+                    self.done = true;
+                    self.converter.check().map(|_| Vec::new())
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(instructions) => self.pending.extend(instructions),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
                 }
             }
         }
-
-        Ok(Assembly {
-            instructions,
-            labels,
-        })
     }
 }
 
 fn convert_to_instructions(
     lines: Vec<Line>,
-) -> Result<(Vec<ParsedInstruction>, HashMap<String, usize>), ParsingError> {
-    // Filter comments
-    let lines: Vec<&Line> = lines.iter().filter(|l| !l.is_comment()).collect();
-
+    options: &ParserOptions,
+    file: &str,
+) -> Result<Assembly, ParsingError> {
+    let mut converter = Converter::new(options);
     let mut instructions = Vec::new();
-    let mut labels = HashMap::new();
-    let mut cursor = 0;
-    let mut instr_cursor = 0;
-    loop {
-        if cursor >= lines.len() {
-            break;
+    for line in &lines {
+        instructions.extend(converter.convert(line, options, file)?);
+    }
+    converter.finish(instructions)
+}
+
+/// Converts parsed lines into instructions one line at a time, keeping
+/// only the state which spans lines
+struct Converter {
+    labels: HashMap<String, usize>,
+    label_sources: HashMap<String, Source>,
+    assertions: Vec<Assertion>,
+    comments: Vec<Comment>,
+    sprites: [Option<[u8; 5]>; 16],
+    symbols: HashMap<String, String>,
+    /// Whether each of the nested conditional blocks is assembled
+    conditions: Vec<(bool, Location)>,
+    /// Index of the next instruction
+    instr_cursor: usize,
+}
+
+impl Converter {
+    fn new(options: &ParserOptions) -> Self {
+        Self {
+            labels: HashMap::new(),
+            label_sources: HashMap::new(),
+            assertions: Vec::new(),
+            comments: Vec::new(),
+            sprites: [None; 16],
+            symbols: options.defines.clone(),
+            conditions: Vec::new(),
+            instr_cursor: 0,
         }
+    }
 
-        let line = lines[cursor];
-        match line {
+    /// Converts the line, returning the instructions it assembles to
+    fn convert(
+        &mut self,
+        line: &Line,
+        options: &ParserOptions,
+        file: &str,
+    ) -> Result<Vec<ParsedInstruction>, ParsingError> {
+        let conditions = &mut self.conditions;
+        let symbols = &mut self.symbols;
+        let mut instructions = Vec::new();
+        if let Line::Conditional(name, symbol, location) = line {
+            let directive = if options.case_sensitive {
+                name.clone()
+            } else {
+                name.to_ascii_lowercase()
+            };
+            match (directive.as_str(), symbol) {
+                ("ifdef", Some(symbol)) => {
+                    conditions.push((symbols.contains_key(symbol), *location));
+                }
+                ("ifndef", Some(symbol)) => {
+                    conditions.push((!symbols.contains_key(symbol), *location));
+                }
+                ("else", None) => match conditions.last_mut() {
+                    Some((condition, _)) => *condition = !*condition,
+                    None => return Err(ParsingError::UnbalancedConditional(*location)),
+                },
+                ("endif", None) => {
+                    if conditions.pop().is_none() {
+                        return Err(ParsingError::UnbalancedConditional(*location));
+                    }
+                }
+                ("ifdef" | "ifndef", None) => {
+                    return Err(ParsingError::ArgumentError(
+                        "conditional",
+                        *location,
+                        ArgumentError::MissingArgument,
+                    ))
+                }
+                ("else" | "endif", Some(symbol)) => {
+                    return Err(ParsingError::ArgumentError(
+                        "conditional",
+                        *location,
+                        ArgumentError::UnexpectedArgument(symbol.clone()),
+                    ))
+                }
+                _ => {
+                    return Err(ParsingError::UnknownInstruction(
+                        format!("%{}", name),
+                        *location,
+                    ))
+                }
+            }
+            return Ok(instructions);
+        }
+        if !conditions.iter().all(|(condition, _)| *condition) {
+            return Ok(instructions);
+        }
+
+        let substituted;
+        let line = match line {
+            Line::Instruction(raw) if raw.is_constant(options) => {
+                let error = |e| ParsingError::ArgumentError("equ", raw.location, e);
+                if let Some(v) = &raw.arg3 {
+                    return Err(error(ArgumentError::UnexpectedArgument(v.clone())));
+                }
+                let value = match &raw.arg2 {
+                    Some(value) => resolve_symbol(value, symbols).map_err(error)?,
+                    None => return Err(error(ArgumentError::MissingArgument)),
+                };
+                symbols.insert(raw.operation.clone(), value);
+                return Ok(instructions);
+            }
             Line::Instruction(raw) => {
-                instructions.push(raw.try_to_instruction()?);
-                instr_cursor += 1;
+                substituted = Line::Instruction(raw.substitute(symbols)?);
+                &substituted
+            }
+            line => line,
+        };
+        match line {
+            Line::Comment(text, line) => {
+                self.comments.push(Comment {
+                    index: self.instr_cursor,
+                    line: *line,
+                    text: text.clone(),
+                });
+            }
+            Line::Instruction(raw) if raw.is_directive("assert", options) => {
+                let mut expanded = raw.try_to_assertion(options)?;
+                for parsed in &expanded {
+                    raw.check_target(parsed, options)?;
+                }
+                expanded[0].comment = raw.comment.clone();
+                for parsed in expanded.iter_mut() {
+                    parsed.source = Some(raw.source(file));
+                }
+                let condition = [&raw.arg1, &raw.arg2, &raw.arg3]
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                self.assertions.push(Assertion {
+                    index: self.instr_cursor,
+                    condition,
+                    message: raw.message.clone(),
+                    line: raw.location.0,
+                });
+                self.instr_cursor += expanded.len();
+                instructions.extend(expanded);
             }
-            Line::Label(label) => {
-                labels.insert(label.clone(), instr_cursor);
+            Line::Instruction(raw) if raw.is_directive("font", options) => {
+                let (digit, sprite) = raw.try_to_font_sprite()?;
+                if self.sprites[digit].is_some() {
+                    return Err(ParsingError::ArgumentError(
+                        "font",
+                        raw.location,
+                        ArgumentError::UnexpectedArgument(format!(
+                            "duplicate sprite for digit {}",
+                            digit
+                        )),
+                    ));
+                }
+                self.sprites[digit] = Some(sprite);
             }
-            line => {
-                return Err(ParsingError::Unknown(format!(
-                    "Failed to create instruction or label from {:?}",
-                    line
-                )));
+            Line::Instruction(raw) => {
+                let mut parsed = raw.try_to_instruction(options)?;
+                raw.check_target(&parsed, options)?;
+                parsed.comment = raw.comment.clone();
+                parsed.source = Some(raw.source(file));
+                instructions.push(parsed);
+                self.instr_cursor += 1;
+            }
+            Line::Label(label, location, span) => {
+                self.labels.insert(label.clone(), self.instr_cursor);
+                let source = Source {
+                    file: file.to_string(),
+                    line: location.0,
+                    column: location.1,
+                    span: span.clone(),
+                };
+                self.label_sources.insert(label.clone(), source);
             }
+            Line::Conditional(..) => {}
         };
-        cursor += 1;
+        Ok(instructions)
+    }
+
+    /// Checks the state left when all lines are converted, returning the
+    /// font if one was defined
+    fn check(&self) -> Result<Option<Font>, ParsingError> {
+        if let Some((_, location)) = self.conditions.first() {
+            return Err(ParsingError::UnbalancedConditional(*location));
+        }
+
+        let defined = self.sprites.iter().filter(|s| s.is_some()).count();
+        match defined {
+            0 => Ok(None),
+            16 => Ok(Some(self.sprites.map(|s| s.unwrap_or_default()))),
+            _ => Err(ParsingError::IncompleteFont(defined)),
+        }
+    }
+
+    /// Assembles the converted instructions
+    fn finish(self, instructions: Vec<ParsedInstruction>) -> Result<Assembly, ParsingError> {
+        let font = self.check()?;
+        Ok(Assembly {
+            instructions,
+            labels: self.labels,
+            label_sources: self.label_sources,
+            assertions: self.assertions,
+            comments: self.comments,
+            font,
+        })
     }
-    Ok((instructions, labels))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::BufReader;
+    use std::io::{BufReader, Cursor};
+    use strum::IntoEnumIterator;
 
     use crate::assembly::lexer::StreamLexer;
 
+    /// Strips the source, which most tests don't care about
+    fn without_source(parsed: &ParsedInstruction) -> ParsedInstruction {
+        ParsedInstruction {
+            source: None,
+            ..parsed.clone()
+        }
+    }
+
     fn parse_and_assert(input: &'static str, expected: Vec<ParsedInstruction>) {
         let reader = BufReader::new(input.as_bytes());
         let lexer = StreamLexer::new(reader);
         let mut parser = Parser::new(Box::new(lexer));
         let assembly = parser.parse().unwrap();
         for (e, r) in expected.iter().zip(&assembly.instructions) {
-            assert_eq!(e, r);
+            assert_eq!(e, &without_source(r));
         }
     }
 
@@ -1545,7 +2668,7 @@ mod test {
     fn parse_rand() {
         parse_and_assert(
             "rand r4 0",
-            [Instruction::Randomize(4.into(), 0.into())]
+            [Instruction::Randomize(4.into(), 0)]
                 .iter()
                 .map(|e| ParsedInstruction::new(*e))
                 .collect(),
@@ -1662,6 +2785,959 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_chipper() {
+        let input = "
+    CLS
+    LD V1, #0A
+    LD VA, V2
+    ADD V1, 1
+    ADD V1, V2
+    ADD I, V3
+    SE V1, V2
+    SNE V1, #FF
+    LD I, #300
+    LD DT, V4
+    LD V4, DT
+    LD ST, V5
+    LD V6, K
+    LD F, V1
+    LD B, V1
+    LD [I], V2
+    LD V2, [I]
+    SHR V3
+    SUBN V1, V2
+    RND V1, #0F
+    DRW V1, V2, 5
+    SKNP V7
+    JP V0, #210
+    JP #200
+    RET
+";
+        let expected = vec![
+            Instruction::Clear,
+            Instruction::SetRegisterByte(1.into(), 0x0A),
+            Instruction::SetRegisterRegister(0xA.into(), 2.into()),
+            Instruction::Add(1.into(), 1),
+            Instruction::AddChecked(1.into(), 2.into()),
+            Instruction::AddMemReg(3.into()),
+            Instruction::SkipRegistersEqual(1.into(), 2.into()),
+            Instruction::SkipNotEqual(1.into(), 0xFF),
+            Instruction::SetMemRegister(0x300.into()),
+            Instruction::SetDelayTimer(4.into()),
+            Instruction::SetRegisterDelayTimer(4.into()),
+            Instruction::SetSoundTimer(5.into()),
+            Instruction::WaitForKey(6.into()),
+            Instruction::SetMemRegisterDefaultSprit(1.into()),
+            Instruction::SetBcd(1.into()),
+            Instruction::MemWrite(2.into()),
+            Instruction::MemRead(2.into()),
+            Instruction::ShiftRight(3.into(), 3.into()),
+            Instruction::SubNChecked(1.into(), 2.into()),
+            Instruction::Randomize(1.into(), 0x0F),
+            Instruction::Draw(1.into(), 2.into(), 5.into()),
+            Instruction::SkipKeyNotPressed(7.into()),
+            Instruction::JumpOffset(0x210.into()),
+            Instruction::Jump(0x200.into()),
+            Instruction::Return,
+        ];
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let assembly = parser.parse().unwrap();
+        let instructions: Vec<Instruction> = assembly
+            .instructions
+            .iter()
+            .map(|i| i.instruction)
+            .collect();
+        assert_eq!(instructions, expected);
+    }
+
+    #[test]
+    fn parse_chipper_label() {
+        let input = "main:\n    CALL main";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let assembly = parser.parse().unwrap();
+        assert_eq!(
+            assembly.instructions[0].instruction,
+            Instruction::Call(0.into())
+        );
+        assert_eq!(assembly.instructions[0].label, Some("main".to_string()));
+    }
+
+    #[test]
+    fn parse_chipper_invalid_operands() {
+        let input = "LD DT, 4";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        assert!(matches!(
+            parser.parse(),
+            Err(ParsingError::ArgumentError("LD", _, _))
+        ));
+    }
+
+    #[test]
+    fn parse_character_literal() {
+        parse_and_assert(
+            "ldb r1 'A'\nse r2, ' '\nLD V3, 'z'",
+            [
+                Instruction::SetRegisterByte(1.into(), b'A'),
+                Instruction::SkipEqual(2.into(), b' '),
+                Instruction::SetRegisterByte(3.into(), b'z'),
+            ]
+            .iter()
+            .map(|e| ParsedInstruction::new(*e))
+            .collect(),
+        );
+    }
+
+    #[test]
+    fn parse_comma_separated() {
+        parse_and_assert(
+            "ldb r1, 42\ndraw r1, r2, 5\nldr r1,r2",
+            [
+                Instruction::SetRegisterByte(1.into(), 42),
+                Instruction::Draw(1.into(), 2.into(), 5.into()),
+                Instruction::SetRegisterRegister(1.into(), 2.into()),
+            ]
+            .iter()
+            .map(|e| ParsedInstruction::new(*e))
+            .collect(),
+        );
+    }
+
+    #[test]
+    fn parse_comma_same_as_whitespace() {
+        for input in [
+            "ldb r1, 42",
+            "draw r1, r2, 5",
+            "ldr r3, r4",
+            "add r1, 1",
+            "se r1, 0x2A",
+            "srne r1, r2",
+            "addc r1, r2",
+            "shl r1, r2",
+            "rand r5, 0xFF",
+            "ldb r1, (2 * 21)",
+            "draw r1, r2, 5 ; comment",
+        ] {
+            let spaced = input.replace(',', "");
+            let binary = |input: &str| {
+                let reader = BufReader::new(std::io::Cursor::new(input.to_string()));
+                let mut parser = Parser::new(Box::new(StreamLexer::new(reader)));
+                parser.parse().unwrap().binary().unwrap()
+            };
+            assert_eq!(binary(input), binary(&spaced), "'{}'", input);
+        }
+    }
+
+    #[test]
+    fn parse_misplaced_comma() {
+        for input in [
+            "ldb , r1 42",
+            "ldb r1,, 42",
+            "ldb r1 42,",
+            "ldb r1 42, ; comment",
+        ] {
+            let result = parse_with_options(input, ParserOptions::default());
+            assert!(
+                matches!(result, Err(ParsingError::UnexpectedToken(..))),
+                "expected '{}' to fail, got {:?}",
+                input,
+                result
+            );
+        }
+    }
+
+    fn parse_with_options(
+        input: &'static str,
+        options: ParserOptions,
+    ) -> Result<Assembly, ParsingError> {
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new_with_options(Box::new(lexer), options);
+        parser.parse()
+    }
+
+    #[test]
+    fn parse_case_insensitive() {
+        let options = ParserOptions {
+            case_sensitive: false,
+            ..Default::default()
+        };
+        let assembly = parse_with_options("LDB R1, 5\nldb r1 5", options).unwrap();
+        let expected = ParsedInstruction::new(Instruction::SetRegisterByte(1.into(), 5));
+        let instructions: Vec<ParsedInstruction> =
+            assembly.instructions.iter().map(without_source).collect();
+        assert_eq!(instructions, vec![expected.clone(), expected]);
+    }
+
+    #[test]
+    fn parse_case_sensitive() {
+        let result = parse_with_options("LDB r1 5", ParserOptions::default());
+        assert!(matches!(result, Err(ParsingError::UnknownInstruction(..))));
+        let result = parse_with_options("ldb R1 5", ParserOptions::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "ldb",
+                _,
+                ArgumentError::MissingRegistryPrefix('r', _)
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_require_commas() {
+        let options = ParserOptions {
+            require_commas: true,
+            ..Default::default()
+        };
+        let result = parse_with_options("draw r1, r2 5", options.clone());
+        assert!(matches!(result, Err(ParsingError::UnexpectedToken(..))));
+        let assembly = parse_with_options("draw r1, r2, 5", options).unwrap();
+        assert_eq!(
+            assembly.instructions[0].instruction,
+            Instruction::Draw(1.into(), 2.into(), 5.into())
+        );
+    }
+
+    #[test]
+    fn parse_register_prefix() {
+        let options = ParserOptions {
+            register_prefix: 'v',
+            ..Default::default()
+        };
+        let assembly = parse_with_options("ldr v1 v7", options).unwrap();
+        assert_eq!(
+            assembly.instructions[0].instruction,
+            Instruction::SetRegisterRegister(1.into(), 7.into())
+        );
+    }
+
+    #[test]
+    fn parse_disassembly() {
+        // Everything the disassembler emits must be possible to reassemble,
+        // the extension instructions when targeting them
+        let (extensions, base): (Vec<Instruction>, Vec<Instruction>) = Instruction::iter()
+            .partition(|i| Target::XoChip.supports(i) && !Target::Chip8.supports(i));
+        // Custom opcodes are reassembled as raw opcodes
+        let instructions: Vec<Instruction> = base
+            .into_iter()
+            .filter(|i| !matches!(i, Instruction::Custom(_)))
+            .chain([
+                Instruction::Debug(3.into()),
+                Instruction::Jump(0x123.into()),
+                Instruction::Call(0x321.into()),
+                Instruction::SkipEqual(3.into(), 8),
+                Instruction::SkipNotEqual(5.into(), 4),
+                Instruction::SkipRegistersEqual(1.into(), 7.into()),
+                Instruction::SetRegisterByte(2.into(), 0x42),
+                Instruction::SetRegisterRegister(3.into(), 4.into()),
+                Instruction::Add(4.into(), 2),
+                Instruction::Or(2.into(), 3.into()),
+                Instruction::And(2.into(), 3.into()),
+                Instruction::Xor(2.into(), 3.into()),
+                Instruction::AddChecked(2.into(), 3.into()),
+                Instruction::SubChecked(2.into(), 3.into()),
+                Instruction::ShiftRight(2.into(), 3.into()),
+                Instruction::SubNChecked(2.into(), 3.into()),
+                Instruction::ShiftLeft(2.into(), 3.into()),
+                Instruction::SkipRegistersNotEqual(2.into(), 3.into()),
+                Instruction::SetMemRegister(0x321.into()),
+                Instruction::JumpOffset(0x321.into()),
+                Instruction::Randomize(2.into(), 0x21),
+                Instruction::Draw(4.into(), 5.into(), 0x0F.into()),
+                Instruction::SkipKeyPressed(6.into()),
+                Instruction::SkipKeyNotPressed(5.into()),
+                Instruction::WaitForKey(3.into()),
+                Instruction::SetMemRegisterDefaultSprit(2.into()),
+                Instruction::SetRegisterDelayTimer(7.into()),
+                Instruction::SetDelayTimer(2.into()),
+                Instruction::SetSoundTimer(2.into()),
+                Instruction::AddMemReg(2.into()),
+                Instruction::SetBcd(2.into()),
+                Instruction::MemWrite(2.into()),
+                Instruction::MemRead(15.into()),
+            ])
+            .collect();
+        let input: String = instructions
+            .iter()
+            .map(|i| i.to_assembly() + "\n")
+            .collect();
+        let lexer = StreamLexer::new(Cursor::new(input.into_bytes()));
+        let mut parser = Parser::new(Box::new(lexer));
+        let assembly = parser.parse().unwrap();
+        let parsed: Vec<Instruction> = assembly
+            .instructions
+            .iter()
+            .map(|i| i.instruction)
+            .collect();
+        assert_eq!(parsed, instructions);
+
+        let input: String = extensions.iter().map(|i| i.to_assembly() + "\n").collect();
+        let options = ParserOptions {
+            target: Some(Target::XoChip),
+            ..Default::default()
+        };
+        let lexer = StreamLexer::new(Cursor::new(input.into_bytes()));
+        let assembly = Parser::new_with_options(Box::new(lexer), options)
+            .parse()
+            .unwrap();
+        // The 16x16 draw is parsed as a draw of 0 rows, with the same opcode
+        let parsed: Vec<u16> = assembly
+            .instructions
+            .iter()
+            .map(|i| i.instruction.opcode())
+            .collect();
+        let expected: Vec<u16> = extensions.iter().map(|i| i.opcode()).collect();
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parse_pseudo_instructions() {
+        let assembly = parse_with_options(
+            "nop\ninc r1\ndec r2\nmov r3 r4\nmov r5, 42",
+            ParserOptions::default(),
+        )
+        .unwrap();
+        let expected = [
+            (Instruction::SetRegisterRegister(0.into(), 0.into()), "nop"),
+            (Instruction::Add(1.into(), 1), "inc r1"),
+            (Instruction::Add(2.into(), 255), "dec r2"),
+            (
+                Instruction::SetRegisterRegister(3.into(), 4.into()),
+                "mov r3 r4",
+            ),
+            (Instruction::SetRegisterByte(5.into(), 42), "mov r5 42"),
+        ];
+        assert_eq!(assembly.instructions.len(), expected.len());
+        for (parsed, (instruction, source)) in assembly.instructions.iter().zip(expected) {
+            assert_eq!(parsed.instruction, instruction);
+            assert_eq!(parsed.expanded_from.as_deref(), Some(source));
+        }
+    }
+
+    #[test]
+    fn parse_pseudo_instructions_arguments() {
+        let result = parse_with_options("inc", ParserOptions::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "inc",
+                _,
+                ArgumentError::MissingArgument
+            ))
+        ));
+        let result = parse_with_options("nop r1", ParserOptions::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "nop",
+                _,
+                ArgumentError::UnexpectedArgument(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_pseudo_instructions_disabled() {
+        let options = ParserOptions {
+            pseudo_instructions: false,
+            ..Default::default()
+        };
+        let result = parse_with_options("inc r1", options);
+        assert!(matches!(result, Err(ParsingError::UnknownInstruction(..))));
+    }
+
+    #[test]
+    fn parse_opcode_directive() {
+        let assembly =
+            parse_with_options("opcode 0x5AB1\nopcode 0x00e0", Default::default()).unwrap();
+        let instructions: Vec<Instruction> = assembly
+            .instructions
+            .iter()
+            .map(|e| e.instruction)
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![Instruction::Raw(0x5AB1), Instruction::Raw(0x00E0)]
+        );
+        assert_eq!(assembly.binary().unwrap(), vec![0x5A, 0xB1, 0x00, 0xE0]);
+
+        let result = parse_with_options("opcode 0x10000", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError("opcode", _, _))
+        ));
+        let result = parse_with_options("opcode", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "opcode",
+                _,
+                ArgumentError::MissingArgument
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_data_bytes() {
+        let assembly = parse_string("db 0xF0 0x90\ndb 'A'".to_string()).unwrap();
+        assert_eq!(assembly.binary().unwrap(), vec![0xF0, 0x90, 0x41, 0x00]);
+        let result = parse_string("db 0xF0 0x90 0x90".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "db",
+                _,
+                ArgumentError::UnexpectedArgument(_)
+            ))
+        ));
+        let result = parse_string("db 256".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "db",
+                _,
+                ArgumentError::OutOfRange(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_assertions() {
+        let assembly = parse_with_options(
+            "clear\nassert r1 == 42 \"loaded\"\nassert r1 != r2\nmain:\nexit",
+            Default::default(),
+        )
+        .unwrap();
+        let instructions: Vec<Instruction> = assembly
+            .instructions
+            .iter()
+            .map(|e| e.instruction)
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Clear,
+                Instruction::SkipEqual(1.into(), 42),
+                Instruction::Breakpoint,
+                Instruction::SkipRegistersNotEqual(1.into(), 2.into()),
+                Instruction::Breakpoint,
+                Instruction::Exit,
+            ]
+        );
+        assert_eq!(assembly.labels.get("main"), Some(&5));
+        assert_eq!(
+            assembly.assertions,
+            vec![
+                Assertion {
+                    index: 1,
+                    condition: "r1 == 42".to_string(),
+                    message: Some("loaded".to_string()),
+                    line: 1,
+                },
+                Assertion {
+                    index: 3,
+                    condition: "r1 != r2".to_string(),
+                    message: None,
+                    line: 2,
+                },
+            ]
+        );
+
+        let result = parse_with_options("assert r1 = 2", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::UnexpectedToken("parse:argument:operator", ..))
+        ));
+        let result = parse_with_options("assert r1 42", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "assert",
+                _,
+                ArgumentError::MissingArgument
+            ))
+        ));
+        let result = parse_with_options("assert r1 42 2", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "assert",
+                _,
+                ArgumentError::UnexpectedArgument(..)
+            ))
+        ));
+        let result = parse_with_options("clear \"message\"", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError("message", ..))
+        ));
+        let result = parse_with_options("assert r1 == 1 \"a\" 2", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::UnexpectedToken("parse:instruction:end", ..))
+        ));
+    }
+
+    #[test]
+    fn parse_comments() {
+        let input = "; draws the screen\r\nmain:\nclear ; wipe it\n\n;; done\nexit;";
+        let assembly = parse_with_options(input, Default::default()).unwrap();
+        let comments: Vec<Option<&str>> = assembly
+            .instructions
+            .iter()
+            .map(|e| e.comment.as_deref())
+            .collect();
+        assert_eq!(comments, vec![Some(" wipe it"), Some("")]);
+        assert_eq!(
+            assembly.comments,
+            vec![
+                Comment {
+                    index: 0,
+                    line: 0,
+                    text: " draws the screen".to_string(),
+                },
+                Comment {
+                    index: 1,
+                    line: 4,
+                    text: "; done".to_string(),
+                },
+            ]
+        );
+        assert_eq!(assembly.labels.get("main"), Some(&0));
+    }
+
+    #[test]
+    fn parse_sources() {
+        let input = "; header\nmain:\n  ldb r1, 42 ; load\n\tassert r1 == 42 \"ok\"\n  jmp main";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let assembly = Parser::new(Box::new(lexer))
+            .with_file("main.asm")
+            .parse()
+            .unwrap();
+        let source = |line, column, span: Range<usize>| Source {
+            file: "main.asm".to_string(),
+            line,
+            column,
+            span,
+        };
+        let sources: Vec<Source> = assembly
+            .instructions
+            .iter()
+            .map(|e| e.source.clone().unwrap())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                source(2, 2, 17..27),
+                source(3, 1, 36..56),
+                source(3, 1, 36..56),
+                source(4, 2, 59..67),
+            ]
+        );
+        assert_eq!(&input[17..27], "ldb r1, 42");
+        assert_eq!(&input[59..67], "jmp main");
+        assert_eq!(
+            assembly.label_sources.get("main"),
+            Some(&source(1, 0, 9..14))
+        );
+    }
+
+    #[test]
+    fn parse_tokens() {
+        use crate::assembly::lexer::VecLexer;
+
+        let tokens = vec![
+            Token::Alphanumeric("ldb".to_string()),
+            Token::Whitespace,
+            Token::Alphanumeric("r1".to_string()),
+            Token::Comma,
+            Token::Integer(42),
+            Token::EOL,
+            Token::Alphanumeric("exit".to_string()),
+        ];
+        let mut parser = Parser::new(Box::new(VecLexer::new(tokens)));
+        let assembly = parser.parse().unwrap();
+        let instructions: Vec<Instruction> = assembly
+            .instructions
+            .iter()
+            .map(|e| e.instruction)
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::SetRegisterByte(1.into(), 42),
+                Instruction::Exit
+            ]
+        );
+    }
+
+    fn parse_string(input: String) -> Result<Assembly, ParsingError> {
+        let lexer = StreamLexer::new(Cursor::new(input.into_bytes()));
+        Parser::new(Box::new(lexer)).parse()
+    }
+
+    #[test]
+    fn parse_font() {
+        use crate::emulator::DEFAULT_SPRITES;
+
+        let mut font = DEFAULT_SPRITES;
+        font[0xA] = [0xFF, 0x81, 0xFF, 0x81, 0x81];
+        let directives: Vec<String> = font
+            .iter()
+            .enumerate()
+            .map(|(digit, sprite)| {
+                let hex: String = sprite.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("font {} 0x{}", digit, hex)
+            })
+            .collect();
+        let input = format!("clear\n{}\nexit", directives.join("\n"));
+        let assembly = parse_string(input).unwrap();
+        assert_eq!(assembly.font, Some(font));
+        assert_eq!(assembly.instructions.len(), 2);
+
+        let assembly = parse_string("clear".to_string()).unwrap();
+        assert_eq!(assembly.font, None);
+
+        let input = directives[..15].join("\n");
+        let result = parse_string(input);
+        assert!(matches!(result, Err(ParsingError::IncompleteFont(15))));
+
+        let input = format!("{}\n{}", directives.join("\n"), directives[3]);
+        let result = parse_string(input);
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError("font", ..))
+        ));
+
+        for input in ["font 16 0", "font 1 0x1FFFFFFFFFF", "font 1", "font 1 2 3"] {
+            let result = parse_string(input.to_string());
+            assert!(
+                matches!(result, Err(ParsingError::ArgumentError("font", ..))),
+                "expected '{}' to fail, got {:?}",
+                input,
+                result
+            );
+        }
+    }
+
+    fn instructions_of(assembly: &Assembly) -> Vec<Instruction> {
+        assembly
+            .instructions
+            .iter()
+            .map(|e| e.instruction)
+            .collect()
+    }
+
+    #[test]
+    fn parse_conditionals() {
+        let input = "%ifdef DEBUG
+debug 1
+%else
+clear
+%endif
+%ifndef SPEED ; default speed
+SPEED equ 5
+%endif
+ldb r1 SPEED
+main:
+%ifdef TARGET
+%ifndef DEBUG
+exit
+%endif
+%endif
+jmp main";
+        let assembly = parse_with_options(input, Default::default()).unwrap();
+        assert_eq!(
+            instructions_of(&assembly),
+            vec![
+                Instruction::Clear,
+                Instruction::SetRegisterByte(1.into(), 5),
+                Instruction::Jump(0.into()),
+            ]
+        );
+        assert_eq!(assembly.labels.get("main"), Some(&2));
+
+        let mut defines = HashMap::new();
+        defines.insert("DEBUG".to_string(), "1".to_string());
+        defines.insert("SPEED".to_string(), "9".to_string());
+        defines.insert("TARGET".to_string(), "1".to_string());
+        let options = ParserOptions {
+            defines,
+            ..Default::default()
+        };
+        let assembly = parse_with_options(input, options).unwrap();
+        assert_eq!(
+            instructions_of(&assembly),
+            vec![
+                Instruction::Debug(1.into()),
+                Instruction::SetRegisterByte(1.into(), 9),
+                Instruction::Jump(0.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let input = "SPEED equ 5\nBASE equ (SPEED * 2)\nldb r1 (BASE + 1)\n\
+                     ldb r2 ((1 << 4) | 0x3)\nldb r3 ('A' - 1)\nldb r4 (-(2 - 3))\n\
+                     ldb r5 (17 % 5 + 7 / 2)";
+        assert_eq!(
+            instructions_of(&parse_string(input.to_string()).unwrap()),
+            vec![
+                Instruction::SetRegisterByte(1.into(), 11),
+                Instruction::SetRegisterByte(2.into(), 0x13),
+                Instruction::SetRegisterByte(3.into(), 64),
+                Instruction::SetRegisterByte(4.into(), 1),
+                Instruction::SetRegisterByte(5.into(), 5),
+            ]
+        );
+
+        let result = parse_string("ldb r1 (UNKNOWN + 1)".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(_, _, ArgumentError::UndefinedSymbol(ref s))) if s == "UNKNOWN"
+        ));
+        let result = parse_string("ldb r1 (1 +)".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                _,
+                _,
+                ArgumentError::InvalidExpression(..)
+            ))
+        ));
+        let result = parse_string("ldb r1 (1 / 0)".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                _,
+                _,
+                ArgumentError::InvalidExpression(..)
+            ))
+        ));
+        let result = parse_string("ldb r1 (1 + 2\n".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::UnexpectedToken(
+                "parse:argument:expression",
+                ..
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_out_of_range() {
+        let result = parse_string("clear\nldb r1 (150 * 2)".to_string());
+        match result {
+            Err(e @ ParsingError::ArgumentError("ldb", (1, 0), ArgumentError::OutOfRange(..))) => {
+                assert!(e
+                    .to_string()
+                    .ends_with("value 300 does not fit in u8 operand"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        let result = parse_string("ldb r16 1".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(_, _, ArgumentError::OutOfRange(ref v, "register"))) if v == "16"
+        ));
+        let result = parse_string("jmp 0x1000".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                _,
+                _,
+                ArgumentError::OutOfRange(_, "u12")
+            ))
+        ));
+        let result = parse_string("ldb r1 (0 - 1)".to_string());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(_, _, ArgumentError::OutOfRange(ref v, "u8"))) if v == "-1"
+        ));
+    }
+
+    #[test]
+    fn parse_streaming() {
+        let input = "SPEED equ 3\nmain:\nldb r1 SPEED\nassert r1 == 3\njmp main";
+        let lexer = StreamLexer::new(Cursor::new(input.as_bytes()));
+        let mut parser = Parser::new(Box::new(lexer));
+        let mut instructions = parser.instructions();
+        let mut streamed = Vec::new();
+        for instruction in instructions.by_ref() {
+            streamed.push(instruction.unwrap());
+        }
+        assert_eq!(instructions.labels().get("main"), Some(&0));
+        assert_eq!(
+            streamed,
+            parse_string(input.to_string()).unwrap().instructions
+        );
+
+        let lexer = StreamLexer::new(Cursor::new("clear\n%ifdef X".as_bytes()));
+        let mut parser = Parser::new(Box::new(lexer));
+        let results: Vec<_> = parser.instructions().collect();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[1],
+            Err(ParsingError::UnbalancedConditional(_))
+        ));
+    }
+
+    #[test]
+    fn parse_constants() {
+        let input =
+            "START equ 512\nREG equ r3\nVALUE equ 'A'\nCOPY equ VALUE\njmp START\nldb REG COPY";
+        let assembly = parse_with_options(input, Default::default()).unwrap();
+        assert_eq!(
+            instructions_of(&assembly),
+            vec![
+                Instruction::Jump(512.into()),
+                Instruction::SetRegisterByte(3.into(), b'A'),
+            ]
+        );
+
+        let result = parse_with_options("A equ", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError("equ", ..))
+        ));
+    }
+
+    #[test]
+    fn parse_unbalanced_conditionals() {
+        for input in [
+            "%ifdef A\nclear",
+            "%endif",
+            "%else\n%endif",
+            "clear\n%ifdef A\n%ifdef B\n%endif",
+        ] {
+            let result = parse_with_options(input, Default::default());
+            assert!(
+                matches!(result, Err(ParsingError::UnbalancedConditional(..))),
+                "expected '{}' to fail, got {:?}",
+                input,
+                result
+            );
+        }
+        let result = parse_with_options("%ifdef\n%endif", Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError("conditional", ..))
+        ));
+        let result = parse_with_options("%if A\n%endif", Default::default());
+        assert!(matches!(result, Err(ParsingError::UnknownInstruction(..))));
+    }
+
+    #[test]
+    fn parse_target() {
+        let input = "clear\nopcode 0xF1EE\nexit";
+        assert!(parse_with_options(input, Default::default()).is_ok());
+
+        let options = ParserOptions {
+            target: Some(Target::Chip8),
+            ..Default::default()
+        };
+        let result = parse_with_options(input, options.clone());
+        assert!(matches!(
+            result,
+            Err(ParsingError::UnsupportedInstruction(ref instr, Target::Chip8, (2, 0))) if instr == "exit"
+        ));
+        let result = parse_with_options("assert r1 == 2", options.clone());
+        assert!(matches!(
+            result,
+            Err(ParsingError::UnsupportedInstruction(..))
+        ));
+        assert!(parse_with_options("clear\nopcode 0xF1EE", options.clone()).is_ok());
+
+        // A height of 0 is the SUPER-CHIP 16x16 sprite
+        let result = parse_with_options("draw r1 r2 0", options.clone());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ExtensionInstruction(ref instr, Target::Schip, (0, 0))) if instr == "draw r1 r2 0"
+        ));
+        assert!(parse_with_options("draw r1 r2 1", options).is_ok());
+        let options = ParserOptions {
+            target: Some(Target::Schip),
+            ..Default::default()
+        };
+        assert!(parse_with_options("draw r1 r2 0", options).is_ok());
+        assert!(parse_with_options("draw r1 r2 0", Default::default()).is_ok());
+    }
+
+    #[test]
+    fn parse_extension_instructions() {
+        let input = "scd 4\nscr\nscl\nlores\nhires\nsaveflags r7\nloadflags r2\nhalt\nldhf r3";
+        let result = parse_with_options(input, Default::default());
+        assert!(matches!(
+            result,
+            Err(ParsingError::ExtensionInstruction(ref i, Target::Schip, (0, 0))) if i == "scd"
+        ));
+        let result = parse_with_options(
+            "hires",
+            ParserOptions {
+                target: Some(Target::Chip8),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            result,
+            Err(ParsingError::ExtensionInstruction(..))
+        ));
+
+        let options = ParserOptions {
+            target: Some(Target::Schip),
+            ..Default::default()
+        };
+        let assembly = parse_with_options(input, options.clone()).unwrap();
+        assert_eq!(
+            assembly.binary().unwrap(),
+            vec![
+                0x00, 0xC4, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFE, 0x00, 0xFF, 0xF7, 0x75, 0xF2, 0x85,
+                0x00, 0xFD, 0xF3, 0x30
+            ]
+        );
+        assert_eq!(
+            assembly.instructions[0].instruction,
+            Instruction::ScrollDown(4.into())
+        );
+        let result = parse_with_options("saveflags r8", options);
+        assert!(matches!(
+            result,
+            Err(ParsingError::ArgumentError(
+                "saveflags",
+                _,
+                ArgumentError::OutOfRange(..)
+            ))
+        ));
+        let options = ParserOptions {
+            target: Some(Target::XoChip),
+            ..Default::default()
+        };
+        assert!(parse_with_options("saveflags r8", options.clone()).is_ok());
+        let assembly = parse_with_options("plane 3", options).unwrap();
+        assert_eq!(
+            assembly.instructions[0].instruction,
+            Instruction::SelectPlane(3.into())
+        );
+        let options = ParserOptions {
+            target: Some(Target::Schip),
+            ..Default::default()
+        };
+        let result = parse_with_options("plane 3", options);
+        assert!(matches!(
+            result,
+            Err(ParsingError::ExtensionInstruction(ref i, Target::XoChip, _)) if i == "plane"
+        ));
+    }
+
     #[test]
     fn parse_label() {
         let input = "main:\nadd r14 30";
@@ -1674,7 +3750,7 @@ mod test {
         let mut parser = Parser::new(Box::new(lexer));
         let assembly = parser.parse().unwrap();
         for (e, r) in expected.iter().zip(&assembly.instructions) {
-            assert_eq!(e, r);
+            assert_eq!(e, &without_source(r));
         }
 
         let location = assembly.labels.get("main");
@@ -1689,7 +3765,10 @@ mod test {
             ParsedInstruction::new(Instruction::SetRegisterByte(u4::little(1), 0)),
             ParsedInstruction::new(Instruction::Add(u4::little(1), 1)),
             ParsedInstruction::new(Instruction::Clear),
-            ParsedInstruction::new(Instruction::SkipNotEqual(u4::little(1), 4)),
+            ParsedInstruction {
+                comment: Some(" abort".to_string()),
+                ..ParsedInstruction::new(Instruction::SkipNotEqual(u4::little(1), 4))
+            },
             ParsedInstruction::new(Instruction::Jump(u12::from_u16(123))),
         ];
         let input = "; this asm contains a little bit of everything
@@ -1710,7 +3789,7 @@ other:
         let mut parser = Parser::new(Box::new(lexer));
         let assembly = parser.parse().unwrap();
         for (e, r) in expected.iter().zip(&assembly.instructions) {
-            assert_eq!(e, r);
+            assert_eq!(e, &without_source(r));
         }
 
         let location = assembly.labels.get("main");
@@ -0,0 +1,189 @@
+//!
+//! Headless test runner for `assert` directives
+//!
+//! Each assertion compiles into a check which skips over a breakpoint,
+//! so a failing assertion halts the emulator on a known address. The
+//! runner records the failure and resumes execution, until the program
+//! exits, hits any other breakpoint or runs out of cycles.
+//!
+use std::error::Error;
+use std::io::Cursor;
+
+use crate::assembly::{Assembly, Assertion};
+use crate::emulator::{Builder, TickOutcome};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssertionStatus {
+    Passed,
+    Failed,
+    /// The program stopped before executing the assertion
+    NotReached,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    pub status: AssertionStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub results: Vec<AssertionResult>,
+    /// Number of executed cycles
+    pub cycles: usize,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| r.status == AssertionStatus::Passed)
+    }
+}
+
+pub struct TestRunner {
+    max_cycles: usize,
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRunner {
+    pub fn new() -> Self {
+        Self {
+            max_cycles: 100_000,
+        }
+    }
+
+    pub fn with_max_cycles(mut self, max_cycles: usize) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    /// Executes the assembled program and reports the outcome of every assertion
+    pub fn run(&self, assembly: &Assembly) -> Result<TestReport, Box<dyn Error>> {
+        let binary = assembly.binary()?;
+        let builder = match assembly.font {
+            Some(font) => Builder::new().with_font(font),
+            None => Builder::new(),
+        };
+        let mut emulator = builder.build();
+        emulator.load(Cursor::new(binary))?;
+
+        let mut results: Vec<AssertionResult> = assembly
+            .assertions
+            .iter()
+            .map(|assertion| AssertionResult {
+                assertion: assertion.clone(),
+                status: AssertionStatus::NotReached,
+            })
+            .collect();
+
+        let mut cycles = 0;
+        while cycles < self.max_cycles {
+            let address = emulator.create_snapshot().program_counter;
+            cycles += 1;
+            if let Some(result) = results
+                .iter_mut()
+                .find(|r| r.assertion.address() == address)
+            {
+                if result.status == AssertionStatus::NotReached {
+                    result.status = AssertionStatus::Passed;
+                }
+            }
+            if matches!(
+                emulator.tick()?,
+                TickOutcome::Continue | TickOutcome::WaitingForKey
+            ) {
+                continue;
+            }
+            match results
+                .iter_mut()
+                .find(|r| r.assertion.failure_address() == address)
+            {
+                Some(result) => result.status = AssertionStatus::Failed,
+                // Exit or a regular breakpoint
+                None => break,
+            }
+        }
+
+        Ok(TestReport { results, cycles })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufReader;
+
+    use crate::assembly::lexer::StreamLexer;
+    use crate::assembly::parser::Parser;
+
+    fn run(input: &'static str) -> TestReport {
+        let lexer = StreamLexer::new(BufReader::new(input.as_bytes()));
+        let assembly = Parser::new(Box::new(lexer)).parse().unwrap();
+        TestRunner::new().run(&assembly).unwrap()
+    }
+
+    #[test]
+    fn test_assertions() {
+        let report = run("ldb r1 42
+ldb r2 42
+assert r1 == 42 \"r1 is loaded\"
+assert r1 != r2
+assert r1 == r2
+exit
+assert r1 == 0");
+        let statuses: Vec<AssertionStatus> = report.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                AssertionStatus::Passed,
+                AssertionStatus::Failed,
+                AssertionStatus::Passed,
+                AssertionStatus::NotReached,
+            ]
+        );
+        assert_eq!(
+            report.results[0].assertion.message,
+            Some("r1 is loaded".to_string())
+        );
+        assert_eq!(report.results[1].assertion.condition, "r1 != r2");
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_assertion_in_loop() {
+        let report = run("ldb r1 0
+loop:
+add r1 1
+assert r1 != 3
+sne r1 5
+exit
+jmp loop");
+        assert_eq!(report.results[0].status, AssertionStatus::Failed);
+
+        let report = run("ldb r1 0
+loop:
+add r1 1
+assert r1 != 6
+sne r1 5
+exit
+jmp loop");
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_max_cycles() {
+        let lexer = StreamLexer::new(BufReader::new("loop:\njmp loop".as_bytes()));
+        let assembly = Parser::new(Box::new(lexer)).parse().unwrap();
+        let report = TestRunner::new()
+            .with_max_cycles(10)
+            .run(&assembly)
+            .unwrap();
+        assert_eq!(report.cycles, 10);
+    }
+}
@@ -0,0 +1,265 @@
+//!
+//! Runs many headless emulators across threads, e.g. to regression test a
+//! corpus of roms, to fuzz or to train agents
+//!
+//! Every job runs a number of frames with [Emulator::tick_frame], seeded
+//! and on a [ManualClock], so its result only depends on the job and the
+//! configuration, never on the threads or the speed of the host.
+//!
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::emulator::{
+    Builder, Emulator, EmulatorError, FrameBuffer, InputRecording, ManualClock, Snapshot, Stats,
+    StopReason, TickOutcome,
+};
+
+/// A rom to run, see [Batch::add_job]
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Identifies the job in its result, e.g. the file of the rom
+    pub name: String,
+    pub rom: Vec<u8>,
+    /// Seed of the random number generator, see [Builder::with_seed]
+    pub seed: u64,
+    /// Replayed while running, its seed replaces the job's
+    pub input: Option<InputRecording>,
+}
+
+impl Job {
+    pub fn new(name: impl Into<String>, rom: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.into(),
+            rom: rom.into(),
+            seed: 0,
+            input: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_input(mut self, input: InputRecording) -> Self {
+        self.input = Some(input);
+        self
+    }
+}
+
+/// The state of an emulator after running a [Job]
+#[derive(Debug)]
+pub struct JobResult {
+    pub name: String,
+    /// Frames run, fewer than configured when the execution stopped early
+    pub frames: usize,
+    /// [StopReason::Limit] when every frame ran
+    pub stop_reason: StopReason,
+    pub stats: Stats,
+    pub snapshot: Snapshot,
+    pub framebuffer: FrameBuffer,
+}
+
+type Configure = dyn Fn(Builder) -> Builder + Send + Sync;
+
+/// Runs jobs on a pool of threads, see the [module](self) documentation
+pub struct Batch {
+    jobs: Vec<Job>,
+    frames: usize,
+    threads: usize,
+    configure: Box<Configure>,
+}
+
+impl Batch {
+    /// Runs every job for the 60 Hz frames
+    pub fn new(frames: usize) -> Self {
+        Self {
+            jobs: Vec::new(),
+            frames,
+            threads: 0,
+            configure: Box::new(|builder| builder),
+        }
+    }
+
+    /// Threads running jobs, by default as many as the available
+    /// parallelism
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Configures the builder of every emulator, e.g. the hertz or quirks.
+    /// The seed and clock are set per job afterwards.
+    pub fn with_configure(
+        mut self,
+        configure: impl Fn(Builder) -> Builder + Send + Sync + 'static,
+    ) -> Self {
+        self.configure = Box::new(configure);
+        self
+    }
+
+    pub fn add_job(&mut self, job: Job) {
+        self.jobs.push(job);
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Runs every job, the results are in the order the jobs were added.
+    /// A job fails when its rom can't be loaded, or when it panics, which
+    /// leaves the other jobs running.
+    pub fn run(&self) -> Vec<Result<JobResult, EmulatorError>> {
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        }
+        .min(self.jobs.len());
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new((0..self.jobs.len()).map(|_| None).collect::<Vec<_>>());
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let mut index = next.fetch_add(1, Ordering::Relaxed);
+                    while let Some(job) = self.jobs.get(index) {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| self.run_job(job)))
+                            .unwrap_or_else(|payload| Err(EmulatorError::from_panic(payload)));
+                        results.lock().unwrap()[index] = Some(result);
+                        index = next.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.expect("every job runs"))
+            .collect()
+    }
+
+    fn run_job(&self, job: &Job) -> Result<JobResult, EmulatorError> {
+        let builder = (self.configure)(Builder::new())
+            .with_seed(job.seed)
+            .with_clock(Box::new(ManualClock::new()));
+        let mut emulator = builder.load_bytes(&job.rom)?;
+        if let Some(input) = &job.input {
+            emulator.replay(input.clone());
+        }
+        let (frames, stop_reason) = self.run_frames(&mut emulator);
+        Ok(JobResult {
+            name: job.name.clone(),
+            frames,
+            stop_reason,
+            stats: emulator.stats(),
+            snapshot: emulator.create_snapshot(),
+            framebuffer: emulator.copy_graphics_buffer(),
+        })
+    }
+
+    fn run_frames(&self, emulator: &mut Emulator) -> (usize, StopReason) {
+        for frame in 1..=self.frames {
+            let stop_reason = match emulator.tick_frame() {
+                Ok(TickOutcome::Continue | TickOutcome::WaitingForKey) => continue,
+                Ok(TickOutcome::Exit) => StopReason::Exit,
+                Ok(TickOutcome::Stopped(breakpoint)) => StopReason::Breakpoint(breakpoint),
+                Err(error) => StopReason::Error(error),
+            };
+            return (frame, stop_reason);
+        }
+        (self.frames, StopReason::Limit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{ExecutionObserver, MEMSIZE};
+
+    #[test]
+    fn test_deterministic() {
+        // rnd r0 0xff, ldf r0, draw r1 r1 5, jmp 0x206
+        let rom = [0xC0, 0xFF, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+        let run = |threads: usize| {
+            let mut batch = Batch::new(10)
+                .with_threads(threads)
+                .with_configure(|builder| builder.with_hertz(600));
+            for seed in 0..8 {
+                batch.add_job(Job::new(format!("seed {}", seed), rom).with_seed(seed));
+            }
+            batch
+                .run()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        let single = run(1);
+        let parallel = run(4);
+        assert_eq!(single.len(), 8);
+        for (single, parallel) in single.iter().zip(&parallel) {
+            assert_eq!(single.name, parallel.name);
+            assert_eq!(single.frames, 10);
+            assert!(matches!(single.stop_reason, StopReason::Limit));
+            assert_eq!(single.stats, parallel.stats);
+            assert_eq!(single.snapshot.registries, parallel.snapshot.registries);
+            assert_eq!(single.framebuffer, parallel.framebuffer);
+        }
+        assert_eq!(single[3].name, "seed 3");
+        assert_eq!(single[0].stats.instructions, 100);
+        assert!(single
+            .iter()
+            .any(|result| result.snapshot.registries[0] != single[0].snapshot.registries[0]));
+    }
+
+    #[test]
+    fn test_stops() {
+        let mut batch = Batch::new(10);
+        // ldb r0 0, exit
+        batch.add_job(Job::new("exit", [0x60, 0x00, 0xF1, 0xEE]));
+        batch.add_job(Job::new("invalid", [0xE0, 0x00]));
+        batch.add_job(Job::new("too large", vec![0; MEMSIZE]));
+        assert!(Batch::new(1).run().is_empty());
+
+        let results = batch.run();
+        let exit = results[0].as_ref().unwrap();
+        assert_eq!(exit.frames, 1);
+        assert!(matches!(exit.stop_reason, StopReason::Exit));
+        let invalid = results[1].as_ref().unwrap();
+        assert!(matches!(
+            invalid.stop_reason,
+            StopReason::Error(EmulatorError::InvalidOpcode(_))
+        ));
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_panic() {
+        struct PanicOnDraw;
+        impl ExecutionObserver for PanicOnDraw {
+            fn on_draw(&mut self, _: &mut Emulator, _: usize, _: usize, _: bool) {
+                panic!("drew");
+            }
+        }
+        let mut batch = Batch::new(10)
+            .with_threads(2)
+            .with_configure(|builder| builder.with_observer(Box::new(PanicOnDraw)));
+        // draw r0 r0 1
+        batch.add_job(Job::new("draws", [0xD0, 0x01]));
+        // jmp 0x200
+        batch.add_job(Job::new("loops", [0x12, 0x00]));
+        // ldi 0xfff, draw r0 r0 15 past the memory
+        batch.add_job(Job::new("past memory", [0xAF, 0xFF, 0xD0, 0x0F]));
+        let results = batch.run();
+        assert!(matches!(
+            &results[0],
+            Err(EmulatorError::Panicked(message)) if message == "drew"
+        ));
+        assert_eq!(results[1].as_ref().unwrap().frames, 10);
+        assert!(matches!(
+            results[2].as_ref().unwrap().stop_reason,
+            StopReason::Error(EmulatorError::InvalidAddress(_))
+        ));
+    }
+}
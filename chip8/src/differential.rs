@@ -0,0 +1,460 @@
+//!
+//! Differential testing of two implementations, run in lockstep on the
+//! same rom and input until their state first differs
+//!
+//! Either side is an [Implementation], e.g. a reference interpreter wrapped
+//! in the trait, or this emulator with other quirks. The sides should be
+//! deterministic to compare: loaded with the same rom, seeded alike and
+//! driven by a [ManualClock](crate::emulator::ManualClock).
+//!
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::emulator::{
+    Emulator, EmulatorError, FrameBuffer, InputEvent, InputRecording, KeyStatus, Resolution,
+    Snapshot, TickOutcome,
+};
+use crate::instructions::u4;
+
+/// An emulator compared by [Lockstep]
+pub trait Implementation {
+    /// Executes the next instruction
+    fn step(&mut self) -> Result<TickOutcome, EmulatorError>;
+    fn set_key(&mut self, key: u4, status: KeyStatus);
+    /// The state compared after every step, including the memory
+    fn snapshot(&self) -> Snapshot;
+    fn framebuffer(&self) -> FrameBuffer;
+}
+
+impl Implementation for Emulator {
+    fn step(&mut self) -> Result<TickOutcome, EmulatorError> {
+        self.tick()
+    }
+
+    fn set_key(&mut self, key: u4, status: KeyStatus) {
+        Emulator::set_key(self, key, status);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Emulator::snapshot(self, true)
+    }
+
+    fn framebuffer(&self) -> FrameBuffer {
+        self.copy_graphics_buffer()
+    }
+}
+
+/// Where the state of the sides differs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// The result of the step, e.g. only one side failed
+    Outcome,
+    Register(u8),
+    ProgramCounter,
+    AddressRegister,
+    StackPointer,
+    /// An entry up to the stack pointer
+    Stack(usize),
+    DelayTimer,
+    SoundTimer,
+    MemorySize,
+    Memory(usize),
+    Resolution,
+    /// A pixel with another color, see [FrameBuffer::color]
+    Pixel(usize, usize),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Outcome => write!(f, "outcome"),
+            Self::Register(register) => write!(f, "v{:x}", register),
+            Self::ProgramCounter => write!(f, "pc"),
+            Self::AddressRegister => write!(f, "i"),
+            Self::StackPointer => write!(f, "sp"),
+            Self::Stack(index) => write!(f, "stack[{}]", index),
+            Self::DelayTimer => write!(f, "delay timer"),
+            Self::SoundTimer => write!(f, "sound timer"),
+            Self::MemorySize => write!(f, "memory size"),
+            Self::Memory(address) => write!(f, "memory 0x{:03x}", address),
+            Self::Resolution => write!(f, "resolution"),
+            Self::Pixel(x, y) => write!(f, "pixel {},{}", x, y),
+        }
+    }
+}
+
+/// A value of the left side which isn't the value of the right side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub location: Location,
+    pub left: String,
+    pub right: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} != {}", self.location, self.left, self.right)
+    }
+}
+
+/// Differences reported per region, i.e. the memory and the display
+pub const MAX_REGION_DIFFERENCES: usize = 16;
+
+/// The first step after which the sides differ. Every difference is
+/// reported, except past [MAX_REGION_DIFFERENCES] in memory and on the
+/// display, which are only counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Steps taken, 0 when the sides differ before executing
+    pub step: u64,
+    pub differences: Vec<Difference>,
+    /// Differences in memory and on the display left out
+    pub omitted: usize,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let differences: Vec<String> = self.differences.iter().map(ToString::to_string).collect();
+        write!(f, "step {}: {}", self.step, differences.join(", "))?;
+        if self.omitted > 0 {
+            write!(f, " and {} more", self.omitted)?;
+        }
+        Ok(())
+    }
+}
+
+/// Steps two implementations in lockstep, comparing them after every step
+pub struct Lockstep<L, R> {
+    left: L,
+    right: R,
+    input: VecDeque<InputEvent>,
+    step: u64,
+}
+
+impl<L: Implementation, R: Implementation> Lockstep<L, R> {
+    pub fn new(left: L, right: R) -> Self {
+        Self {
+            left,
+            right,
+            input: VecDeque::new(),
+            step: 0,
+        }
+    }
+
+    /// Sends the key events to both sides, before the step at their tick.
+    /// The seed isn't applied, the sides have to be seeded alike.
+    pub fn with_input(mut self, recording: &InputRecording) -> Self {
+        self.input = recording.events.iter().copied().collect();
+        self
+    }
+
+    /// Steps taken so far
+    pub fn steps(&self) -> u64 {
+        self.step
+    }
+
+    pub fn left(&self) -> &L {
+        &self.left
+    }
+
+    pub fn right(&self) -> &R {
+        &self.right
+    }
+
+    pub fn into_inner(self) -> (L, R) {
+        (self.left, self.right)
+    }
+
+    /// Steps both sides up to the steps, returning the first divergence.
+    /// Ends early without one when both sides exit, or fail alike.
+    pub fn run(&mut self, steps: u64) -> Option<Divergence> {
+        if self.step == 0 {
+            if let Some(divergence) = self.compare(Vec::new()) {
+                return Some(divergence);
+            }
+        }
+        for _ in 0..steps {
+            while let Some(event) = self.input.front().copied() {
+                if event.tick > self.step {
+                    break;
+                }
+                self.input.pop_front();
+                self.left.set_key(event.key, event.status);
+                self.right.set_key(event.key, event.status);
+            }
+            let left = self.left.step();
+            let right = self.right.step();
+            self.step += 1;
+            let (left, right) = (outcome(&left), outcome(&right));
+            let finished = left == right && (left == "exit" || left.starts_with("error"));
+            let mut differences = Vec::new();
+            if left != right {
+                differences.push(Difference {
+                    location: Location::Outcome,
+                    left,
+                    right,
+                });
+            }
+            if let Some(divergence) = self.compare(differences) {
+                return Some(divergence);
+            }
+            if finished {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Adds the differences in state to the differences of the step
+    fn compare(&self, mut differences: Vec<Difference>) -> Option<Divergence> {
+        let (left, right) = (self.left.snapshot(), self.right.snapshot());
+        let mut differ = |location, left: String, right: String| {
+            if left != right {
+                differences.push(Difference {
+                    location,
+                    left,
+                    right,
+                });
+            }
+        };
+        for (register, (l, r)) in left.registries.iter().zip(right.registries).enumerate() {
+            differ(Location::Register(register as u8), hex(*l), hex(r));
+        }
+        differ(
+            Location::ProgramCounter,
+            hex(left.program_counter),
+            hex(right.program_counter),
+        );
+        differ(
+            Location::AddressRegister,
+            hex(left.address_register),
+            hex(right.address_register),
+        );
+        differ(
+            Location::StackPointer,
+            left.stack_pointer.to_string(),
+            right.stack_pointer.to_string(),
+        );
+        let depth = left.stack_pointer.min(right.stack_pointer);
+        for (index, (l, r)) in left.stack.iter().zip(right.stack).take(depth).enumerate() {
+            differ(Location::Stack(index), hex(*l), hex(r));
+        }
+        differ(
+            Location::DelayTimer,
+            left.delay_timer.to_string(),
+            right.delay_timer.to_string(),
+        );
+        differ(
+            Location::SoundTimer,
+            left.sound_timer.to_string(),
+            right.sound_timer.to_string(),
+        );
+        let (left_memory, right_memory) = (
+            left.memory.unwrap_or_default(),
+            right.memory.unwrap_or_default(),
+        );
+        differ(
+            Location::MemorySize,
+            left_memory.len().to_string(),
+            right_memory.len().to_string(),
+        );
+        let mut omitted = 0;
+        let memory = left_memory
+            .iter()
+            .zip(&right_memory)
+            .enumerate()
+            .filter(|(_, (l, r))| l != r);
+        for (index, (address, (l, r))) in memory.enumerate() {
+            if index < MAX_REGION_DIFFERENCES {
+                differ(Location::Memory(address), hex(*l), hex(*r));
+            } else {
+                omitted += 1;
+            }
+        }
+
+        let (left, right) = (self.left.framebuffer(), self.right.framebuffer());
+        if left.resolution != right.resolution {
+            differ(
+                Location::Resolution,
+                resolution(left.resolution),
+                resolution(right.resolution),
+            );
+        } else {
+            let pixels = (0..left.height())
+                .flat_map(|y| (0..left.width()).map(move |x| (x, y)))
+                .filter(|(x, y)| left.color(*x, *y) != right.color(*x, *y));
+            for (index, (x, y)) in pixels.enumerate() {
+                if index < MAX_REGION_DIFFERENCES {
+                    differ(
+                        Location::Pixel(x, y),
+                        left.color(x, y).to_string(),
+                        right.color(x, y).to_string(),
+                    );
+                } else {
+                    omitted += 1;
+                }
+            }
+        }
+
+        if differences.is_empty() {
+            return None;
+        }
+        Some(Divergence {
+            step: self.step,
+            differences,
+            omitted,
+        })
+    }
+}
+
+fn outcome(outcome: &Result<TickOutcome, EmulatorError>) -> String {
+    match outcome {
+        Ok(TickOutcome::Continue) => "continue".to_string(),
+        Ok(TickOutcome::Exit) => "exit".to_string(),
+        Ok(TickOutcome::Stopped(breakpoint)) => format!("stopped at {:?}", breakpoint),
+        Ok(TickOutcome::WaitingForKey) => "waiting for key".to_string(),
+        Err(error) => format!("error: {}", error),
+    }
+}
+
+fn hex(value: impl Into<usize>) -> String {
+    format!("0x{:02x}", value.into())
+}
+
+fn resolution(resolution: Resolution) -> String {
+    format!("{}x{}", resolution.width(), resolution.height())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{Builder, ManualClock, Quirks, MEMSIZE};
+
+    fn emulator(rom: &[u8], quirks: Quirks) -> Emulator {
+        Builder::new()
+            .with_seed(1)
+            .with_clock(Box::new(ManualClock::new()))
+            .with_quirks(quirks)
+            .load_bytes(rom)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_same() {
+        // rnd r0 0xff, ldf r0, draw r1 r1 5, add r1 4, jmp 0x200
+        let rom = [0xC0, 0xFF, 0xF0, 0x29, 0xD1, 0x15, 0x71, 0x04, 0x12, 0x00];
+        let quirks = Quirks::default();
+        let mut lockstep = Lockstep::new(emulator(&rom, quirks), emulator(&rom, quirks));
+        assert_eq!(lockstep.run(100), None);
+        assert_eq!(lockstep.steps(), 100);
+        assert_eq!(lockstep.run(10), None);
+        assert_eq!(lockstep.steps(), 110);
+
+        // Failing alike ends the run
+        let rom = [0xE0, 0x00];
+        let mut lockstep = Lockstep::new(emulator(&rom, quirks), emulator(&rom, quirks));
+        assert_eq!(lockstep.run(10), None);
+        assert_eq!(lockstep.steps(), 1);
+    }
+
+    #[test]
+    fn test_divergence() {
+        // draw r0 r0 1, the font's 0 is at address 0
+        let rom = [0xD0, 0x01];
+        let display_wait = Quirks {
+            display_wait: true,
+            ..Default::default()
+        };
+        let mut lockstep = Lockstep::new(
+            emulator(&rom, Quirks::default()),
+            emulator(&rom, display_wait),
+        );
+        let divergence = lockstep.run(10).unwrap();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.omitted, 0);
+        assert_eq!(
+            divergence.differences[..2],
+            [
+                Difference {
+                    location: Location::ProgramCounter,
+                    left: "0x202".to_string(),
+                    right: "0x200".to_string(),
+                },
+                Difference {
+                    location: Location::Pixel(0, 0),
+                    left: "1".to_string(),
+                    right: "0".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            divergence.to_string(),
+            "step 1: pc 0x202 != 0x200, pixel 0,0 1 != 0, pixel 1,0 1 != 0, \
+             pixel 2,0 1 != 0, pixel 3,0 1 != 0"
+        );
+
+        // Past the limit differences are counted, the font's 0, 1 and 2
+        // differ in 36 pixels
+        let rom = [0xD0, 0x0F];
+        let mut lockstep = Lockstep::new(
+            emulator(&rom, Quirks::default()),
+            emulator(&rom, display_wait),
+        );
+        let divergence = lockstep.run(10).unwrap();
+        assert_eq!(divergence.differences.len(), 1 + MAX_REGION_DIFFERENCES);
+        assert_eq!(divergence.omitted, 36 - MAX_REGION_DIFFERENCES);
+        assert!(divergence.to_string().ends_with(" and 20 more"));
+
+        // Before executing
+        let larger = Builder::new()
+            .with_memory_size(MEMSIZE * 2)
+            .load_bytes(&rom)
+            .unwrap();
+        let mut lockstep = Lockstep::new(emulator(&rom, Quirks::default()), larger);
+        let divergence = lockstep.run(10).unwrap();
+        assert_eq!(divergence.step, 0);
+        assert_eq!(divergence.differences[0].location, Location::MemorySize);
+    }
+
+    #[test]
+    fn test_input() {
+        // wkey r0, jmp 0x202
+        let rom = [0xF0, 0x0A, 0x12, 0x02];
+        let wait_for_release = Quirks {
+            wait_for_release: true,
+            ..Default::default()
+        };
+        let recording = InputRecording {
+            seed: 1,
+            events: vec![
+                InputEvent {
+                    tick: 1,
+                    key: 5.into(),
+                    status: KeyStatus::Pressed,
+                },
+                InputEvent {
+                    tick: 3,
+                    key: 5.into(),
+                    status: KeyStatus::Up,
+                },
+            ],
+        };
+        let mut lockstep = Lockstep::new(
+            emulator(&rom, Quirks::default()),
+            emulator(&rom, wait_for_release),
+        )
+        .with_input(&recording);
+        let divergence = lockstep.run(10).unwrap();
+        assert_eq!(divergence.step, 2);
+        assert_eq!(
+            divergence.to_string(),
+            "step 2: outcome continue != waiting for key, v0 0x05 != 0x00"
+        );
+
+        // The divergence is kept in the sides
+        assert_eq!(lockstep.steps(), 2);
+        let (left, right) = lockstep.into_inner();
+        assert_eq!(left.create_snapshot().registries[0], 5);
+        assert_eq!(right.create_snapshot().registries[0], 0);
+    }
+}
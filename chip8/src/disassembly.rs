@@ -0,0 +1,1059 @@
+//!
+//! Chip-8 disassembler
+//!
+//! Decodes a ROM into instructions and data, with the memory address of
+//! each line. A linear sweep decodes every opcode. A recursive traversal
+//! follows the control flow from [START_ADDR] and keeps the bytes it
+//! doesn't reach as data. Opcodes which can't be decoded are kept as data
+//! too, so the layout of the rom is preserved.
+//!
+//! Addresses referenced by jumps, calls and `ldi` get labels like `L_0206`,
+//! so the output can be reassembled. Known names are given with
+//! [Disassembly::with_symbols]. Data drawn as sprites is previewed in
+//! comments above its `db` lines.
+//!
+//! The decoded code can be analysed as basic blocks with
+//! [Disassembly::control_flow_graph] or as subroutines with
+//! [Disassembly::call_graph]. [Disassembly::extensions] reports the
+//! SUPER-CHIP and XO-CHIP opcodes, and [diff] compares two disassemblies.
+//!
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+use crate::emulator::START_ADDR;
+use crate::instructions::{extension_opcode, Instruction, Target};
+
+#[derive(Debug, Clone)]
+pub struct DisassemblyOptions {
+    /// Follow the control flow instead of decoding every opcode
+    pub recursive: bool,
+    /// Name referenced addresses instead of using absolute addresses
+    pub labels: bool,
+    /// Offset in the rom of the first decoded byte
+    pub start: usize,
+    /// Offset in the rom after the last decoded byte, the end of the rom
+    /// when unset
+    pub end: Option<usize>,
+}
+
+impl Default for DisassemblyOptions {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            labels: true,
+            start: 0,
+            end: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoded {
+    Instruction(Instruction),
+    /// Bytes which aren't code, at most two per line
+    Data(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Memory address of the line
+    pub address: usize,
+    /// The bytes of the line in the rom
+    pub bytes: Vec<u8>,
+    pub decoded: Decoded,
+}
+
+impl Line {
+    /// Whether the address is one of the bytes of the line
+    pub fn contains(&self, address: usize) -> bool {
+        (self.address..self.address + self.bytes.len()).contains(&address)
+    }
+}
+
+/// Data drawn as a sprite
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sprite {
+    pub address: usize,
+    /// One byte per row
+    pub bytes: Vec<u8>,
+}
+
+impl Sprite {
+    /// The rows of the sprite with set pixels as `#` and unset as `.`
+    pub fn preview(&self) -> Vec<String> {
+        self.bytes
+            .iter()
+            .map(|byte| {
+                (0..8)
+                    .rev()
+                    .map(|bit| if byte & (1 << bit) != 0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    /// Synthesized labels and symbols by address
+    pub labels: HashMap<usize, String>,
+    /// Addresses of opcodes which couldn't be decoded in a linear sweep,
+    /// and were kept as data
+    pub unknown: Vec<usize>,
+}
+
+impl Disassembly {
+    /// Instructions with their memory address
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+        self.lines.iter().filter_map(|line| match &line.decoded {
+            Decoded::Instruction(i) => Some((line.address, i)),
+            Decoded::Data(_) => None,
+        })
+    }
+
+    /// Address ranges of consecutive data lines
+    pub fn data_regions(&self) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        for line in &self.lines {
+            if !matches!(line.decoded, Decoded::Data(_)) {
+                continue;
+            }
+            let end = line.address + line.bytes.len();
+            match regions.last_mut() {
+                Some(region) if region.end == line.address => region.end = end,
+                _ => regions.push(line.address..end),
+            }
+        }
+        regions
+    }
+
+    /// Formats the line as assembly, using labels for referenced addresses.
+    /// The label defined at the line isn't included.
+    pub fn format_line(&self, line: &Line) -> String {
+        match &line.decoded {
+            Decoded::Instruction(i) => {
+                match target_address(i).and_then(|target| self.labels.get(&target)) {
+                    Some(label) => {
+                        let asm = i.to_assembly();
+                        let mnemonic = asm.split(' ').next().unwrap_or_default();
+                        format!("{} {}", mnemonic, label)
+                    }
+                    None => i.to_assembly(),
+                }
+            }
+            Decoded::Data(bytes) => {
+                let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                format!("db {}", values.join(" "))
+            }
+        }
+    }
+
+    /// Every referenced address with the instructions referencing it
+    pub fn cross_references(&self) -> BTreeMap<usize, Vec<Reference>> {
+        let mut references: BTreeMap<usize, Vec<Reference>> = BTreeMap::new();
+        for (address, instruction) in self.instructions() {
+            let kind = match instruction {
+                Instruction::Jump(_) => ReferenceKind::Jump,
+                Instruction::JumpOffset(_) => ReferenceKind::JumpOffset,
+                Instruction::Call(_) => ReferenceKind::Call,
+                Instruction::SetMemRegister(_) => ReferenceKind::LoadI,
+                _ => continue,
+            };
+            if let Some(target) = target_address(instruction) {
+                references
+                    .entry(target)
+                    .or_default()
+                    .push(Reference { address, kind });
+            }
+        }
+        references
+    }
+
+    /// Names the addresses with the symbols, replacing synthesized labels.
+    /// Symbols which don't start a line are ignored, as their definition
+    /// couldn't be placed in the assembly.
+    pub fn with_symbols(mut self, symbols: &HashMap<usize, String>) -> Self {
+        for line in &self.lines {
+            if let Some(name) = symbols.get(&line.address) {
+                self.labels.insert(line.address, name.clone());
+            }
+        }
+        self
+    }
+
+    /// Data regions which are plausibly sprites: loaded into I by `ldi`
+    /// and then drawn, before I is changed again. The size of the sprite
+    /// is the largest height it's drawn with.
+    pub fn sprites(&self) -> Vec<Sprite> {
+        let mut heights: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut loaded = None;
+        for (_, instruction) in self.instructions() {
+            match instruction {
+                Instruction::SetMemRegister(addr) => loaded = Some(addr.value() as usize),
+                Instruction::Draw(_, _, n) if n.value() > 0 => {
+                    if let Some(address) = loaded {
+                        let height = heights.entry(address).or_default();
+                        *height = (*height).max(n.value() as usize);
+                    }
+                }
+                Instruction::AddMemReg(_)
+                | Instruction::SetMemRegisterDefaultSprit(_)
+                | Instruction::Jump(_)
+                | Instruction::JumpOffset(_)
+                | Instruction::Call(_)
+                | Instruction::Return => loaded = None,
+                _ => {}
+            }
+        }
+
+        let data: HashMap<usize, u8> = self
+            .lines
+            .iter()
+            .filter(|line| matches!(line.decoded, Decoded::Data(_)))
+            .flat_map(|line| (line.address..).zip(line.bytes.iter().copied()))
+            .collect();
+        heights
+            .into_iter()
+            .filter_map(|(address, height)| {
+                let bytes = (address..address + height)
+                    .map(|a| data.get(&a).copied())
+                    .collect::<Option<Vec<u8>>>()?;
+                Some(Sprite { address, bytes })
+            })
+            .collect()
+    }
+
+    /// Opcodes of extension platforms, ordered by address. The SUPER-CHIP
+    /// ones are decoded, the XO-CHIP ones are kept as data. Lines holding
+    /// sprite data are skipped, as they commonly look like opcodes.
+    pub fn extensions(&self) -> Vec<Extension> {
+        let sprites = self.sprites();
+        self.lines
+            .iter()
+            .filter(|line| {
+                !sprites.iter().any(|s| {
+                    line.address < s.address + s.bytes.len()
+                        && s.address < line.address + line.bytes.len()
+                })
+            })
+            .filter_map(|line| {
+                let [b1, b2] = line.bytes[..] else {
+                    return None;
+                };
+                let opcode = u16::from_be_bytes([b1, b2]);
+                let (target, mnemonic) = extension_opcode(opcode)?;
+                Some(Extension {
+                    address: line.address,
+                    opcode,
+                    target,
+                    mnemonic,
+                })
+            })
+            .collect()
+    }
+
+    /// Counts of the decoded instructions by mnemonic, of the registers
+    /// named by their operands and of the referenced addresses
+    pub fn statistics(&self) -> Statistics {
+        let mut statistics = Statistics::default();
+        for (_, instruction) in self.instructions() {
+            let info = instruction.info();
+            *statistics.instructions.entry(info.mnemonic).or_default() += 1;
+
+            let opcode = instruction.opcode();
+            let registers = match instruction {
+                // Every register up to x is stored or loaded
+                Instruction::MemWrite(x) | Instruction::MemRead(x) => (0..=x.value()).collect(),
+                _ => {
+                    let mut registers = Vec::new();
+                    if info.pattern.as_bytes()[1] == b'x' {
+                        registers.push(((opcode >> 8) & 0xF) as u8);
+                    }
+                    if info.pattern.as_bytes()[2] == b'y' {
+                        registers.push(((opcode >> 4) & 0xF) as u8);
+                    }
+                    registers
+                }
+            };
+            for register in registers {
+                statistics.registers[register as usize] += 1;
+            }
+
+            if let Some(target) = target_address(instruction) {
+                *statistics.addresses.entry(target).or_default() += 1;
+            }
+        }
+        statistics
+    }
+
+    /// The platform the rom likely requires, judging by the opcodes of
+    /// extension platforms
+    pub fn required_target(&self) -> Target {
+        self.extensions()
+            .iter()
+            .map(|e| e.target)
+            .max()
+            .unwrap_or(Target::Chip8)
+    }
+
+    /// Formats the whole disassembly as assembly, with label definitions
+    /// and sprite previews
+    pub fn to_assembly(&self) -> String {
+        let sprites = self.sprites();
+        let mut asm = String::new();
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.address) {
+                asm.push_str(&format!("{}:\n", label));
+            }
+            for sprite in sprites.iter().filter(|s| line.contains(s.address)) {
+                for row in sprite.preview() {
+                    asm.push_str(&format!("; {}\n", row));
+                }
+            }
+            asm.push_str(&self.format_line(line));
+            asm.push('\n');
+        }
+        asm
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReferenceKind {
+    Jump,
+    /// `jmpr`, jumping to the address offset by V0
+    JumpOffset,
+    Call,
+    /// `ldi`, loading the address into I
+    LoadI,
+}
+
+impl fmt::Display for ReferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Jump => "jmp",
+            Self::JumpOffset => "jmpr",
+            Self::Call => "call",
+            Self::LoadI => "ldi",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An instruction referencing an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reference {
+    /// Address of the referencing instruction
+    pub address: usize,
+    pub kind: ReferenceKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    Call,
+    /// A jump into another subroutine, e.g. a tail call
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallEdge {
+    /// Entry address of the calling subroutine
+    pub from: usize,
+    /// Entry address of the called subroutine
+    pub to: usize,
+    pub kind: CallKind,
+}
+
+/// Subroutines and the calls between them, the entry point at
+/// [START_ADDR] is treated as a subroutine
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    /// Entry addresses, sorted
+    pub subroutines: Vec<usize>,
+    pub edges: Vec<CallEdge>,
+}
+
+impl CallGraph {
+    /// Formats the graph as Graphviz DOT, naming subroutines by their label
+    pub fn to_dot(&self, labels: &HashMap<usize, String>) -> String {
+        let name = |address: &usize| match labels.get(address) {
+            Some(label) => format!("\"{}\"", label),
+            None => format!("\"0x{:04x}\"", address),
+        };
+        let mut dot = String::from("digraph calls {\n");
+        for subroutine in &self.subroutines {
+            dot.push_str(&format!("    {};\n", name(subroutine)));
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                CallKind::Call => "",
+                CallKind::Jump => " [style=dashed]",
+            };
+            dot.push_str(&format!(
+                "    {} -> {}{};\n",
+                name(&edge.from),
+                name(&edge.to),
+                style
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl Disassembly {
+    /// Analyses which subroutines call or jump into each other, following
+    /// the control flow of each subroutine until it returns
+    pub fn call_graph(&self) -> CallGraph {
+        let code: HashMap<usize, &Instruction> = self.instructions().collect();
+        let mut subroutines: Vec<usize> = code
+            .values()
+            .filter_map(|i| match i {
+                Instruction::Call(addr) => Some(addr.value() as usize),
+                _ => None,
+            })
+            .filter(|address| code.contains_key(address))
+            .collect();
+        if code.contains_key(&START_ADDR) {
+            subroutines.push(START_ADDR);
+        }
+        subroutines.sort();
+        subroutines.dedup();
+        let entries: HashSet<usize> = subroutines.iter().copied().collect();
+
+        let mut edges = Vec::new();
+        for entry in &subroutines {
+            let mut visited = HashSet::new();
+            let mut pending = vec![*entry];
+            while let Some(address) = pending.pop() {
+                if !visited.insert(address) {
+                    continue;
+                }
+                let Some(instruction) = code.get(&address) else {
+                    continue;
+                };
+                let mut edge = |to, kind| {
+                    let edge = CallEdge {
+                        from: *entry,
+                        to,
+                        kind,
+                    };
+                    if !edges.contains(&edge) {
+                        edges.push(edge);
+                    }
+                };
+                if let Instruction::Call(addr) = instruction {
+                    let target = addr.value() as usize;
+                    if entries.contains(&target) {
+                        edge(target, CallKind::Call);
+                    }
+                }
+                for (next, kind) in successors(address, instruction) {
+                    if kind == EdgeKind::Jump && entries.contains(&next) && next != *entry {
+                        edge(next, CallKind::Jump);
+                    } else {
+                        pending.push(next);
+                    }
+                }
+            }
+        }
+        CallGraph { subroutines, edges }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// Continues with the next instruction
+    Fallthrough,
+    Jump,
+    /// A skip instruction skipping the next instruction
+    Skip,
+}
+
+/// A sequence of instructions only entered at the start, and only left
+/// after the last instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// Address following the last instruction
+    pub end: usize,
+    /// Blocks where execution continues after the block
+    pub successors: Vec<(usize, EdgeKind)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph {
+    /// Blocks by their start address
+    pub blocks: BTreeMap<usize, BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    /// The block containing the address
+    pub fn block_at(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end)
+    }
+
+    /// Blocks where execution can come from before the block
+    pub fn predecessors(&self, start: usize) -> Vec<(usize, EdgeKind)> {
+        self.blocks
+            .values()
+            .flat_map(|block| {
+                block
+                    .successors
+                    .iter()
+                    .filter(|(to, _)| *to == start)
+                    .map(|(_, kind)| (block.start, *kind))
+            })
+            .collect()
+    }
+}
+
+impl Disassembly {
+    /// Splits the decoded instructions into basic blocks, a block starts at
+    /// every jump, skip and call target and after every branch
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        let code: BTreeMap<usize, &Instruction> = self.instructions().collect();
+        let mut leaders: HashSet<usize> = HashSet::new();
+        leaders.insert(START_ADDR);
+        for (address, instruction) in &code {
+            let next = successors(*address, instruction);
+            if next != [(address + 2, EdgeKind::Fallthrough)] {
+                leaders.extend(next.iter().map(|(a, _)| *a));
+                leaders.insert(address + 2);
+            }
+            if let Instruction::Call(addr) | Instruction::JumpOffset(addr) = instruction {
+                leaders.insert(addr.value() as usize);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<BasicBlock> = None;
+        for (address, instruction) in &code {
+            let block = match current.take() {
+                Some(block) if block.end == *address && !leaders.contains(address) => block,
+                previous => {
+                    if let Some(block) = previous {
+                        blocks.insert(block.start, block);
+                    }
+                    BasicBlock {
+                        start: *address,
+                        end: *address,
+                        successors: Vec::new(),
+                    }
+                }
+            };
+            current = Some(BasicBlock {
+                end: address + 2,
+                successors: successors(*address, instruction)
+                    .into_iter()
+                    .filter(|(a, _)| code.contains_key(a))
+                    .collect(),
+                ..block
+            });
+        }
+        if let Some(block) = current {
+            blocks.insert(block.start, block);
+        }
+        ControlFlowGraph { blocks }
+    }
+}
+
+/// Disassembles a rom loaded at [START_ADDR]
+pub fn disassemble(rom: &[u8], options: &DisassemblyOptions) -> Disassembly {
+    let code = if options.recursive {
+        Some(find_code(rom))
+    } else {
+        None
+    };
+
+    // The control flow is still followed through the whole rom, only the
+    // decoded lines are limited to the range
+    let end = options.end.unwrap_or(rom.len()).min(rom.len());
+    let start = options.start.min(end);
+
+    let mut lines = Vec::new();
+    let mut unknown = Vec::new();
+    for (index, bytes) in rom[start..end].chunks(2).enumerate() {
+        let address = START_ADDR + start + index * 2;
+        let decoded = match bytes {
+            [b1, b2] => Instruction::from_opcode_u8(*b1, *b2),
+            _ => None,
+        };
+        let decoded = match (&code, decoded) {
+            (Some(code), Some(i)) if code.contains(&address) => Decoded::Instruction(i),
+            (Some(_), _) => Decoded::Data(bytes.to_vec()),
+            (None, Some(i)) => Decoded::Instruction(i),
+            (None, None) => {
+                if bytes.len() == 2 {
+                    unknown.push(address);
+                }
+                Decoded::Data(bytes.to_vec())
+            }
+        };
+        lines.push(Line {
+            address,
+            bytes: bytes.to_vec(),
+            decoded,
+        });
+    }
+
+    let labels = if options.labels {
+        synthesize_labels(&lines)
+    } else {
+        HashMap::new()
+    };
+    Disassembly {
+        lines,
+        labels,
+        unknown,
+    }
+}
+
+/// Usage counts of the decoded instructions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// Instructions by mnemonic
+    pub instructions: BTreeMap<&'static str, usize>,
+    /// Instructions naming each register as an operand
+    pub registers: [usize; 16],
+    /// Instructions referencing each address
+    pub addresses: BTreeMap<usize, usize>,
+}
+
+/// An opcode of an extension platform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extension {
+    pub address: usize,
+    pub opcode: u16,
+    /// The platform introducing the opcode
+    pub target: Target,
+    pub mnemonic: &'static str,
+}
+
+/// A line which differs between two disassemblies
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub address: usize,
+    /// The line in the old disassembly, unset when it's shorter
+    pub old: Option<Line>,
+    /// The line in the new disassembly, unset when it's shorter
+    pub new: Option<Line>,
+}
+
+/// Compares the disassemblies aligned by address, returning the lines
+/// which differ in bytes or decoding, ordered by address
+pub fn diff(old: &Disassembly, new: &Disassembly) -> Vec<Difference> {
+    let old_lines: BTreeMap<usize, &Line> = old.lines.iter().map(|l| (l.address, l)).collect();
+    let new_lines: BTreeMap<usize, &Line> = new.lines.iter().map(|l| (l.address, l)).collect();
+    let addresses: BTreeSet<usize> = old_lines.keys().chain(new_lines.keys()).copied().collect();
+    addresses
+        .into_iter()
+        .filter_map(|address| {
+            let old = old_lines.get(&address).copied();
+            let new = new_lines.get(&address).copied();
+            if old == new {
+                return None;
+            }
+            Some(Difference {
+                address,
+                old: old.cloned(),
+                new: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Addresses reachable by following the control flow from the start address
+fn find_code(rom: &[u8]) -> HashSet<usize> {
+    let mut code = HashSet::new();
+    let mut pending = vec![START_ADDR];
+    while let Some(address) = pending.pop() {
+        // Only decode aligned instructions within the rom
+        let offset = match address.checked_sub(START_ADDR) {
+            Some(offset) if offset % 2 == 0 && offset + 1 < rom.len() => offset,
+            _ => continue,
+        };
+        if code.contains(&address) {
+            continue;
+        }
+        let Some(instruction) = Instruction::from_opcode_u8(rom[offset], rom[offset + 1]) else {
+            continue;
+        };
+        code.insert(address);
+        pending.extend(
+            successors(address, &instruction)
+                .into_iter()
+                .map(|(a, _)| a),
+        );
+        match instruction {
+            Instruction::Call(addr) => pending.push(addr.value() as usize),
+            // The offset in V0 is unknown, assume the base is code
+            Instruction::JumpOffset(addr) => pending.push(addr.value() as usize),
+            _ => {}
+        }
+    }
+    code
+}
+
+/// Where execution can continue after the instruction within the same
+/// subroutine, calls continue at the next instruction once returned
+pub fn successors(address: usize, instruction: &Instruction) -> Vec<(usize, EdgeKind)> {
+    match instruction {
+        Instruction::Jump(addr) => vec![(addr.value() as usize, EdgeKind::Jump)],
+        Instruction::SkipEqual(..)
+        | Instruction::SkipNotEqual(..)
+        | Instruction::SkipRegistersEqual(..)
+        | Instruction::SkipRegistersNotEqual(..)
+        | Instruction::SkipKeyPressed(..)
+        | Instruction::SkipKeyNotPressed(..) => vec![
+            (address + 2, EdgeKind::Fallthrough),
+            (address + 4, EdgeKind::Skip),
+        ],
+        // The target of jmpr depends on V0
+        Instruction::Return
+        | Instruction::Exit
+        | Instruction::Halt
+        | Instruction::JumpOffset(_) => Vec::new(),
+        _ => vec![(address + 2, EdgeKind::Fallthrough)],
+    }
+}
+
+/// The address the instruction jumps to, calls or loads into I
+fn target_address(instruction: &Instruction) -> Option<usize> {
+    instruction
+        .target_address()
+        .map(|addr| addr.value() as usize)
+}
+
+/// Names every address referenced by an instruction, when it's the start
+/// of a decoded line
+fn synthesize_labels(lines: &[Line]) -> HashMap<usize, String> {
+    let starts: HashSet<usize> = lines.iter().map(|line| line.address).collect();
+    lines
+        .iter()
+        .filter_map(|line| match &line.decoded {
+            Decoded::Instruction(i) => target_address(i),
+            Decoded::Data(_) => None,
+        })
+        .filter(|target| starts.contains(target))
+        .map(|target| (target, format!("L_{:04x}", target)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// call 0x208, ldi 0x20c, draw r1 r2 3, jmp 0x200, ldb r1 2, ret,
+    /// followed by a sprite
+    const ROM: [u8; 16] = [
+        0x22, 0x08, 0xA2, 0x0C, 0xD1, 0x23, 0x12, 0x00, 0x61, 0x02, 0x00, 0xEE, 0xF0, 0x90, 0xF0,
+        0x00,
+    ];
+
+    #[test]
+    fn test_linear() {
+        let disassembly = disassemble(&ROM, &Default::default());
+        assert_eq!(disassembly.unknown, vec![0x20C, 0x20E]);
+        assert_eq!(disassembly.lines.len(), 8);
+        assert_eq!(disassembly.data_regions(), vec![0x20C..0x210]);
+        assert_eq!(
+            disassembly.to_assembly(),
+            "L_0200:\ncall L_0208\nldi L_020c\ndraw r1 r2 3\njmp L_0200\n\
+             L_0208:\nldb r1 2\nret\nL_020c:\n; ####....\n; #..#....\n; ####....\n\
+             db 0xf0 0x90\ndb 0xf0 0x00\n"
+        );
+        // A trailing odd byte is kept as well
+        let disassembly = disassemble(&ROM[..13], &Default::default());
+        assert_eq!(
+            disassembly.lines.last().map(|l| &l.decoded),
+            Some(&Decoded::Data(vec![0xF0]))
+        );
+    }
+
+    #[test]
+    fn test_recursive() {
+        let options = DisassemblyOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let disassembly = disassemble(&ROM, &options);
+        assert!(disassembly.unknown.is_empty());
+        assert_eq!(disassembly.instructions().count(), 6);
+        assert_eq!(disassembly.data_regions(), vec![0x20C..0x210]);
+        assert_eq!(disassembly.labels.get(&0x20C), Some(&"L_020c".to_string()));
+        assert!(disassembly.to_assembly().ends_with(
+            "ret\nL_020c:\n; ####....\n; #..#....\n; ####....\ndb 0xf0 0x90\ndb 0xf0 0x00\n"
+        ));
+    }
+
+    #[test]
+    fn test_call_graph() {
+        // main: call a, jmp main; a: call b, jmp b; b: ret
+        let rom = [0x22, 0x04, 0x12, 0x00, 0x22, 0x08, 0x12, 0x08, 0x00, 0xEE];
+        let disassembly = disassemble(&rom, &Default::default());
+        let graph = disassembly.call_graph();
+        assert_eq!(graph.subroutines, vec![0x200, 0x204, 0x208]);
+        assert_eq!(
+            graph.edges,
+            vec![
+                CallEdge {
+                    from: 0x200,
+                    to: 0x204,
+                    kind: CallKind::Call
+                },
+                CallEdge {
+                    from: 0x204,
+                    to: 0x208,
+                    kind: CallKind::Call
+                },
+                CallEdge {
+                    from: 0x204,
+                    to: 0x208,
+                    kind: CallKind::Jump
+                },
+            ]
+        );
+        assert_eq!(
+            graph.to_dot(&disassembly.labels),
+            "digraph calls {\n    \"L_0200\";\n    \"L_0204\";\n    \"L_0208\";\n    \
+             \"L_0200\" -> \"L_0204\";\n    \"L_0204\" -> \"L_0208\";\n    \
+             \"L_0204\" -> \"L_0208\" [style=dashed];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_control_flow_graph() {
+        // 0x200: se r1 1, 0x202: jmp 0x208, 0x204: call 0x20c, 0x206: ldb r1 1,
+        // 0x208: jmp 0x200, 0x20a: exit, 0x20c: ret
+        let rom = [
+            0x31, 0x01, 0x12, 0x08, 0x22, 0x0C, 0x61, 0x01, 0x12, 0x00, 0xF1, 0xEE, 0x00, 0xEE,
+        ];
+        let disassembly = disassemble(&rom, &Default::default());
+        let cfg = disassembly.control_flow_graph();
+        let blocks: Vec<(usize, usize)> = cfg.blocks.values().map(|b| (b.start, b.end)).collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0x200, 0x202),
+                (0x202, 0x204),
+                (0x204, 0x208),
+                (0x208, 0x20a),
+                (0x20a, 0x20c),
+                (0x20c, 0x20e)
+            ]
+        );
+        assert_eq!(
+            cfg.blocks[&0x200].successors,
+            vec![(0x202, EdgeKind::Fallthrough), (0x204, EdgeKind::Skip)]
+        );
+        assert_eq!(cfg.blocks[&0x202].successors, vec![(0x208, EdgeKind::Jump)]);
+        assert_eq!(
+            cfg.blocks[&0x204].successors,
+            vec![(0x208, EdgeKind::Fallthrough)]
+        );
+        assert!(cfg.blocks[&0x20c].successors.is_empty());
+        assert_eq!(cfg.block_at(0x206).map(|b| b.start), Some(0x204));
+        assert_eq!(cfg.block_at(0x20e), None);
+        assert_eq!(
+            cfg.predecessors(0x208),
+            vec![(0x202, EdgeKind::Jump), (0x204, EdgeKind::Fallthrough)]
+        );
+    }
+
+    #[test]
+    fn test_cross_references() {
+        let disassembly = disassemble(&ROM, &Default::default());
+        let references = disassembly.cross_references();
+        assert_eq!(
+            references.keys().copied().collect::<Vec<_>>(),
+            vec![0x200, 0x208, 0x20C]
+        );
+        assert_eq!(
+            references[&0x20C],
+            vec![Reference {
+                address: 0x202,
+                kind: ReferenceKind::LoadI
+            }]
+        );
+        assert_eq!(references[&0x200][0].kind.to_string(), "jmp");
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = HashMap::from([
+            (0x200, "main".to_string()),
+            (0x20C, "sprite".to_string()),
+            (0x20D, "unaligned".to_string()),
+        ]);
+        let disassembly = disassemble(&ROM, &Default::default()).with_symbols(&symbols);
+        assert_eq!(disassembly.labels.get(&0x208), Some(&"L_0208".to_string()));
+        assert_eq!(disassembly.labels.get(&0x20C), Some(&"sprite".to_string()));
+        assert!(!disassembly.labels.contains_key(&0x20D));
+        let asm = disassembly.to_assembly();
+        assert!(
+            asm.starts_with("main:\ncall L_0208\nldi sprite\n"),
+            "{}",
+            asm
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        let options = DisassemblyOptions {
+            start: 8,
+            end: Some(12),
+            ..Default::default()
+        };
+        let disassembly = disassemble(&ROM, &options);
+        assert_eq!(
+            disassembly.instructions().collect::<Vec<_>>(),
+            vec![
+                (0x208, &Instruction::SetRegisterByte(1.into(), 2)),
+                (0x20A, &Instruction::Return),
+            ]
+        );
+        assert!(disassembly.unknown.is_empty());
+
+        // Recursive traversal still starts at the start address
+        let options = DisassemblyOptions {
+            recursive: true,
+            start: 10,
+            ..Default::default()
+        };
+        let disassembly = disassemble(&ROM, &options);
+        assert_eq!(disassembly.lines[0].address, 0x20A);
+        assert_eq!(disassembly.data_regions(), vec![0x20C..0x210]);
+
+        let options = DisassemblyOptions {
+            start: 20,
+            ..Default::default()
+        };
+        assert!(disassemble(&ROM, &options).lines.is_empty());
+    }
+
+    #[test]
+    fn test_sprites() {
+        let disassembly = disassemble(&ROM, &Default::default());
+        let sprites = disassembly.sprites();
+        assert_eq!(
+            sprites,
+            vec![Sprite {
+                address: 0x20C,
+                bytes: vec![0xF0, 0x90, 0xF0]
+            }]
+        );
+        assert_eq!(
+            sprites[0].preview(),
+            vec!["####....", "#..#....", "####...."]
+        );
+
+        // I is changed before the draw
+        let rom = [0xA2, 0x06, 0xF1, 0x1E, 0xD1, 0x21, 0xFF, 0x00];
+        assert!(disassemble(&rom, &Default::default()).sprites().is_empty());
+        // The sprite would run past the data
+        let rom = [0xA2, 0x06, 0xD1, 0x23, 0x12, 0x00, 0xFF, 0x00];
+        let disassembly = disassemble(&rom, &Default::default());
+        assert!(disassembly.sprites().is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = disassemble(&ROM, &Default::default());
+        let mut patched = ROM.to_vec();
+        patched[5] = 0x24;
+        patched.extend([0x00, 0xE0]);
+        let new = disassemble(&patched, &Default::default());
+
+        let differences = diff(&old, &new);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|d| (d.address, d.old.is_some(), d.new.is_some()))
+                .collect::<Vec<_>>(),
+            vec![(0x204, true, true), (0x210, false, true)]
+        );
+        assert_eq!(
+            differences[0].new.as_ref().map(|l| &l.decoded),
+            Some(&Decoded::Instruction(Instruction::Draw(
+                1.into(),
+                2.into(),
+                4.into()
+            )))
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_extensions() {
+        let disassembly = disassemble(&ROM, &Default::default());
+        assert!(disassembly.extensions().is_empty());
+        assert_eq!(disassembly.required_target(), Target::Chip8);
+
+        // hires, cls, plane 1
+        let rom = [0x00, 0xFF, 0x00, 0xE0, 0xF1, 0x01];
+        let disassembly = disassemble(&rom, &Default::default());
+        assert_eq!(
+            disassembly.extensions(),
+            vec![
+                Extension {
+                    address: 0x200,
+                    opcode: 0x00FF,
+                    target: Target::Schip,
+                    mnemonic: "hires"
+                },
+                Extension {
+                    address: 0x204,
+                    opcode: 0xF101,
+                    target: Target::XoChip,
+                    mnemonic: "plane"
+                },
+            ]
+        );
+        assert_eq!(disassembly.required_target(), Target::XoChip);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut rom = ROM.to_vec();
+        // write r2
+        rom[12..14].copy_from_slice(&[0xF2, 0x55]);
+        let statistics = disassemble(&rom, &Default::default()).statistics();
+        assert_eq!(
+            statistics.instructions,
+            BTreeMap::from([
+                ("call", 1),
+                ("draw", 1),
+                ("jmp", 1),
+                ("ldb", 1),
+                ("ldi", 1),
+                ("ret", 1),
+                ("write", 1),
+            ])
+        );
+        assert_eq!(
+            statistics.registers,
+            [1, 3, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            statistics.addresses,
+            BTreeMap::from([(0x200, 1), (0x208, 1), (0x20C, 1)])
+        );
+    }
+
+    #[test]
+    fn test_without_labels() {
+        let options = DisassemblyOptions {
+            labels: false,
+            ..Default::default()
+        };
+        let disassembly = disassemble(&ROM[..2], &options);
+        assert!(disassembly.labels.is_empty());
+        assert_eq!(disassembly.to_assembly(), "call 520\n");
+    }
+}
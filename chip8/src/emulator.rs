@@ -1,54 +1,167 @@
 //!
 //! Chip-8 emulator
 //!
+//! Without the `std` feature only the core builds, with `alloc`: the
+//! memory, registers, display and timers, executing instructions with
+//! [Emulator::tick] on a [Clock] of the platform. The system clock,
+//! message loop, file loading, tracing and debugging tools need `std`.
+//!
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, ops::Range, str::FromStr, time::Duration};
+#[cfg(feature = "std")]
 use std::{
-    error::Error,
-    fmt,
+    any::Any,
     fs::File,
-    io::{self, Read},
-    sync::mpsc::{Receiver, Sender},
-    thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, RecvError, RecvTimeoutError, SendError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use rand::{thread_rng, RngCore};
-use tracing::{debug, error, info, span, Level};
+#[cfg(feature = "std")]
+use rand::thread_rng;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use tracing::{debug, error, info, span, warn, Level};
 
-use crate::instructions::{u4, Instruction};
+use crate::instructions::{u4, Instruction, Target};
+#[cfg(feature = "std")]
+use crate::profile::{Profile, ProfileError};
+#[cfg(feature = "std")]
+use crate::rom::RomInfo;
 
-#[derive(Debug)]
-pub enum Chip8Error {
+#[derive(Debug, thiserror::Error)]
+pub enum EmulatorError {
+    #[error("unimplemented instruction")]
     UnimplementedInstruction,
+    /// The opcode doesn't decode to an instruction
+    #[error("invalid opcode {0}")]
     InvalidOpcode(String),
+    #[error("return with an empty stack")]
     StackEmpty,
-    StackFull,
-    IO(io::Error),
+    /// The return addresses on the stack, oldest first
+    #[error("call with a full stack, returning to {0:03x?}")]
+    StackFull(Vec<usize>),
+    /// A font has to be exactly 16 sprites of 5 bytes, or 10 bytes for a
+    /// large font, received size
+    #[error("invalid font of {0} bytes")]
+    InvalidFont(usize),
+    /// Packed frame buffers are whole planes of the resolution, received
+    /// size
+    #[error("invalid frame buffer of {0} bytes")]
+    InvalidFrameBuffer(usize),
+    /// The save state is malformed, with the reason
+    #[error("invalid save state: {0}")]
+    InvalidSaveState(String),
+    /// The save state was written by an incompatible version
+    #[error("unsupported save state version {0}")]
+    UnsupportedSaveStateVersion(u16),
+    /// The input recording is malformed, with the reason
+    #[error("invalid input recording: {0}")]
+    InvalidRecording(String),
+    /// An address outside of memory
+    #[error("invalid address 0x{0:04x}")]
+    InvalidAddress(usize),
+    /// The program doesn't fit in memory from its load address
+    #[error("program of {size} bytes exceeds the {max} bytes of memory")]
+    RomTooLarge { size: usize, max: usize },
+    /// A write to the address below [START_ADDR], see [MemoryProtection]
+    #[error("write to protected address 0x{0:04x}")]
+    ProtectedWrite(usize),
+    /// Execution at the address outside of the loaded program, see
+    /// [MemoryProtection]
+    #[error("execution out of bounds at 0x{0:04x}")]
+    ExecutionOutOfBounds(usize),
+    /// The profile next to the rom is malformed
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Profile(#[from] ProfileError),
+    /// The thread of a running emulator hung up its channel
+    #[error("emulator thread disconnected")]
+    Disconnected,
+    /// The emulator panicked, with the panic message
+    #[error("emulator panicked: {0}")]
+    Panicked(String),
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    IO(#[from] io::Error),
 }
 
-impl fmt::Display for Chip8Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "chip-8 failure: {:?}", self)
+#[cfg(feature = "std")]
+impl EmulatorError {
+    /// From the payload of a caught panic
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        Self::Panicked(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> From<SendError<T>> for EmulatorError {
+    fn from(_: SendError<T>) -> Self {
+        EmulatorError::Disconnected
     }
 }
-impl From<io::Error> for Chip8Error {
-    fn from(err: io::Error) -> Self {
-        Chip8Error::IO(err)
+#[cfg(feature = "std")]
+impl From<RecvError> for EmulatorError {
+    fn from(_: RecvError) -> Self {
+        EmulatorError::Disconnected
     }
 }
-impl Error for Chip8Error {}
 
+/// The default memory size
 pub const MEMSIZE: usize = 4096;
+/// The memory size of XO-CHIP, addressable by [LONG_LOAD_OPCODE]
+pub const XO_CHIP_MEMSIZE: usize = 0x10000;
+/// XO-CHIP `F000 nnnn`, setting I to the 16 bit address in the two bytes
+/// after the opcode. Spanning 4 bytes it's not an [Instruction], the
+/// emulator executes it as [Instruction::Raw].
+pub const LONG_LOAD_OPCODE: u16 = 0xF000;
 pub const START_ADDR: usize = 0x200;
 
 pub const REGISTRY_COUNT: usize = 16;
 pub const STACK_SIZE: usize = 32;
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
-pub const GRAPHICS_BUFFER_SIZE: usize = (DISPLAY_WIDTH * DISPLAY_HEIGHT) / 8;
+/// The SUPER-CHIP high resolution display
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+/// Large enough for the high resolution display
+pub const GRAPHICS_BUFFER_SIZE: usize = (HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT) / 8;
+/// XO-CHIP display planes, combined into 2 bit colors
+pub const PLANE_COUNT: usize = 2;
 pub const KEY_COUNT: usize = 16;
+/// Flag registers stored by `saveflags`, XO-CHIP has 16 and SUPER-CHIP
+/// the first 8
+pub const FLAG_COUNT: usize = 16;
+
+/// Leads every save state, followed by the big endian format version
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 1;
 
 pub const DEFAULT_SPRITE_START_ADDR: usize = 0x00;
-pub const DEFAULT_SPRITES: [[u8; 5]; 16] = [
+/// 16 sprites of 5 bytes, one for each hexadecimal digit
+pub type Font = [[u8; 5]; 16];
+
+pub const DEFAULT_SPRITES: Font = [
     // 0
     [0xF0, 0x90, 0x90, 0x90, 0xF0],
     // 1
@@ -83,985 +196,5774 @@ pub const DEFAULT_SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0x80],
 ];
 
-// 60 hz at microsecond scale
-const TIME_BETWEEN_DECREMENT: u128 = Duration::from_micros(1_000_000 / 60).as_micros();
+/// The large font follows the font
+pub const LARGE_SPRITE_START_ADDR: usize = DEFAULT_SPRITE_START_ADDR + 16 * 5;
+/// 16 sprites of 8x10 pixels for SCHIP's `ldhf`
+pub type LargeFont = [[u8; 10]; 16];
 
-// Control/read messages supported by the
-// chip-8 emulator
-pub enum Message {
-    Pause,
-    SendGraphics(Sender<[u8; GRAPHICS_BUFFER_SIZE]>),
-    KeyEvent(u4, KeyStatus),
+pub const DEFAULT_LARGE_SPRITES: LargeFont = [
+    // 0
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+    // 1
+    [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+    // 2
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    // 3
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    // 4
+    [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+    // 5
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    // 6
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    // 7
+    [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+    // 8
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    // 9
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    // A
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    // B
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    // C
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    // D
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    // E
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    // F
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+];
+
+/// Creates a font from 80 bytes, 5 bytes per sprite starting with '0'
+pub fn font_from_bytes(bytes: &[u8]) -> Result<Font, EmulatorError> {
+    if bytes.len() != 16 * 5 {
+        return Err(EmulatorError::InvalidFont(bytes.len()));
+    }
+    let mut font = [[0; 5]; 16];
+    for (sprite, chunk) in font.iter_mut().zip(bytes.chunks(5)) {
+        sprite.copy_from_slice(chunk);
+    }
+    Ok(font)
 }
 
-pub struct Builder {
-    hertz: usize,
-    timeboxes: usize,
+/// Creates a large font from 160 bytes, 10 bytes per sprite starting with
+/// '0'
+pub fn large_font_from_bytes(bytes: &[u8]) -> Result<LargeFont, EmulatorError> {
+    if bytes.len() != 16 * 10 {
+        return Err(EmulatorError::InvalidFont(bytes.len()));
+    }
+    let mut font = [[0; 10]; 16];
+    for (sprite, chunk) in font.iter_mut().zip(bytes.chunks(10)) {
+        sprite.copy_from_slice(chunk);
+    }
+    Ok(font)
 }
 
-impl Default for Builder {
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Resolution {
+    /// 64x32
+    #[default]
+    Low,
+    /// 128x64, SUPER-CHIP
+    High,
+}
+
+impl Resolution {
+    pub const fn width(&self) -> usize {
+        match self {
+            Self::Low => DISPLAY_WIDTH,
+            Self::High => HIRES_DISPLAY_WIDTH,
+        }
+    }
+
+    pub const fn height(&self) -> usize {
+        match self {
+            Self::Low => DISPLAY_HEIGHT,
+            Self::High => HIRES_DISPLAY_HEIGHT,
+        }
+    }
+}
+
+/// The display in either resolution, with a plane per bit of the pixel
+/// colors. Pixels are packed 8 per byte with the most significant bit
+/// leftmost, in rows of `width / 8` bytes, the buffer past the last row
+/// is unused.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameBuffer {
+    pub resolution: Resolution,
+    pub planes: [[u8; GRAPHICS_BUFFER_SIZE]; PLANE_COUNT],
+}
+
+impl Default for FrameBuffer {
     fn default() -> Self {
-        Self::new()
+        Self::new(Resolution::Low)
     }
 }
 
-impl Builder {
-    pub fn new() -> Self {
+impl FrameBuffer {
+    /// A cleared display
+    pub fn new(resolution: Resolution) -> Self {
         Self {
-            hertz: 400,
-            timeboxes: 100,
+            resolution,
+            planes: [[0; GRAPHICS_BUFFER_SIZE]; PLANE_COUNT],
         }
     }
 
-    pub fn with_hertz(mut self, hertz: usize) -> Self {
-        self.hertz = hertz;
-        self
+    pub fn width(&self) -> usize {
+        self.resolution.width()
     }
 
-    pub fn with_timeboxes(mut self, timeboxes: usize) -> Self {
-        self.timeboxes = timeboxes;
-        self
+    pub fn height(&self) -> usize {
+        self.resolution.height()
     }
 
-    pub fn load_program(self, filepath: &str) -> Result<Emulator, Chip8Error> {
-        let mut emulator = Emulator::new(self.hertz, self.timeboxes);
-        emulator.reset();
-        let file = File::open(filepath)?;
-        emulator.load(file)?;
-        Ok(emulator)
+    /// Whether the pixel is lit in any plane, pixels outside the display
+    /// never are
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.color(x, y) != 0
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Default)]
-pub enum KeyStatus {
-    #[default]
-    Up,
-    Pressed,
-}
+    /// The rows from the top, whether each pixel is lit in any plane
+    pub fn iter_rows(&self) -> impl Iterator<Item = Vec<bool>> + '_ {
+        (0..self.height()).map(move |y| (0..self.width()).map(|x| self.get(x, y)).collect())
+    }
 
-#[derive(Debug, Default, Clone)]
-pub struct Snapshot {
-    pub registries: [u8; REGISTRY_COUNT],
-    pub program_counter: usize,
-    pub stack_pointer: usize,
-    pub address_register: usize,
-    pub delay_timer: u8,
-    pub stack: [usize; STACK_SIZE],
-    pub key_status: [KeyStatus; KEY_COUNT],
-    pub instruction: Option<Instruction>,
-}
+    /// Bytes of a packed plane in the resolution
+    pub const fn plane_size(resolution: Resolution) -> usize {
+        resolution.width() * resolution.height() / 8
+    }
 
-pub struct Emulator {
-    // hardware
-    memory: [u8; MEMSIZE],
-    registries: [u8; REGISTRY_COUNT],
-    program_counter: usize,
-    stack_pointer: usize,
-    // I registry
-    address_register: usize,
-    delay_timer: u8,
-    sound_timer: u8,
-    stack: [usize; STACK_SIZE],
-    graphics_buffer: [u8; GRAPHICS_BUFFER_SIZE],
-    last_delay_decrement: Option<Instant>,
-    last_sound_decrement: Option<Instant>,
-    key_status: [KeyStatus; KEY_COUNT],
-    wait_for_key: Option<u8>,
+    /// Builds a frame buffer from packed planes following each other,
+    /// planes which aren't included are cleared
+    pub fn from_packed(resolution: Resolution, bytes: &[u8]) -> Result<Self, EmulatorError> {
+        let plane_size = Self::plane_size(resolution);
+        if bytes.is_empty()
+            || !bytes.len().is_multiple_of(plane_size)
+            || bytes.len() / plane_size > PLANE_COUNT
+        {
+            return Err(EmulatorError::InvalidFrameBuffer(bytes.len()));
+        }
+        let mut frame_buffer = Self::new(resolution);
+        for (plane, packed) in frame_buffer.planes.iter_mut().zip(bytes.chunks(plane_size)) {
+            plane[..plane_size].copy_from_slice(packed);
+        }
+        Ok(frame_buffer)
+    }
 
-    // configurations
-    hertz: usize,
-    timeboxes: usize,
+    /// FNV-1a of the resolution and the packed planes, stable across runs
+    /// and builds so it can be stored by tests
+    pub fn content_hash(&self) -> u64 {
+        let resolution = match self.resolution {
+            Resolution::Low => 0,
+            Resolution::High => 1,
+        };
+        fnv1a(core::iter::once(resolution).chain(self.to_packed()))
+    }
 
-    // thread communication
-    receiver: Option<Receiver<Message>>,
-}
+    /// The display as text, a line per row with `.` for unlit pixels and
+    /// `#`, `+` and `@` for colors 1 to 3
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                text.push(['.', '#', '+', '@'][self.color(x, y) as usize]);
+            }
+            text.push('\n');
+        }
+        text
+    }
 
-impl Emulator {
-    fn new(hertz: usize, timeboxes: usize) -> Self {
-        let mut ret = Self {
-            memory: [0; MEMSIZE],
-            registries: [0; REGISTRY_COUNT],
-            program_counter: START_ADDR,
-            stack_pointer: 0,
-            address_register: 0,
-            delay_timer: 0,
-            sound_timer: 0,
-            stack: [0; STACK_SIZE],
-            graphics_buffer: [0; GRAPHICS_BUFFER_SIZE],
-            last_delay_decrement: None,
-            last_sound_decrement: None,
-            key_status: [KeyStatus::Up; KEY_COUNT],
-            wait_for_key: None,
-            hertz,
-            timeboxes,
-            receiver: None,
-        };
-        ret.reset();
-        ret
+    /// The packed planes following each other, see
+    /// [FrameBuffer::from_packed]
+    pub fn to_packed(&self) -> Vec<u8> {
+        let plane_size = Self::plane_size(self.resolution);
+        self.planes
+            .iter()
+            .flat_map(|plane| plane[..plane_size].iter().copied())
+            .collect()
     }
 
-    /// Resets everything in the emulator
-    pub fn reset(&mut self) {
-        self.memory = [0; MEMSIZE];
-        self.registries = [0; REGISTRY_COUNT];
-        self.program_counter = START_ADDR;
-        self.stack_pointer = 0;
-        self.address_register = 0;
-        self.delay_timer = 0;
-        self.sound_timer = 0;
-        self.stack = [0; STACK_SIZE];
-        self.graphics_buffer = [0; GRAPHICS_BUFFER_SIZE];
-        self.last_delay_decrement = None;
-        self.last_sound_decrement = None;
-        self.key_status = [KeyStatus::Up; KEY_COUNT];
-        self.wait_for_key = None;
-        self.load_default_sprites().unwrap();
+    /// The color of the pixel, bit 0 from the first plane and bit 1 from
+    /// the second
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        (0..PLANE_COUNT)
+            .filter(|plane| self.plane_pixel(*plane, x, y))
+            .fold(0, |color, plane| color | (1 << plane))
     }
 
-    /// Loads the default sprites which should be available.
-    /// These are placed in the 0x00-0x1FF range
-    fn load_default_sprites(&mut self) -> std::io::Result<()> {
-        for (offset, sprite) in DEFAULT_SPRITES.iter().enumerate() {
-            for (i, item) in sprite.iter().enumerate() {
-                self.memory[DEFAULT_SPRITE_START_ADDR + (offset * 5) + i] = *item;
-            }
+    /// Whether the pixel is lit in the plane
+    pub fn plane_pixel(&self, plane: usize, x: usize, y: usize) -> bool {
+        if x >= self.width() || y >= self.height() {
+            return false;
         }
-        Ok(())
+        let (index, mask) = self.position(x, y);
+        self.planes[plane][index] & mask != 0
     }
 
-    pub fn copy_bytes(&self, start: usize, amount: usize) -> Vec<u8> {
-        let mut ret = Vec::new();
-        for index in start..(start + amount) {
-            ret.push(self.memory[index]);
+    fn set_pixel(&mut self, plane: usize, x: usize, y: usize, lit: bool) {
+        let (index, mask) = self.position(x, y);
+        if lit {
+            self.planes[plane][index] |= mask;
+        } else {
+            self.planes[plane][index] &= !mask;
         }
-        ret
     }
 
-    pub fn load<T: Read>(&mut self, mut reader: T) -> Result<(), Chip8Error> {
-        self.reset();
-        let bytes = match reader.read(&mut self.memory[START_ADDR..]) {
-            Ok(n) => n,
-            Err(e) => {
-                error!(error = ?e, "failed to load bytes into emulator memory");
-                return Err(e.into());
-            }
-        };
-        debug!(%bytes, "loaded bytes into emulator memory");
-        Ok(())
+    /// Flips the pixel, returns whether it was lit
+    fn toggle(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        let (index, mask) = self.position(x, y);
+        let lit = self.planes[plane][index] & mask != 0;
+        self.planes[plane][index] ^= mask;
+        lit
     }
 
-    fn instruction(&self) -> Result<Instruction, Box<dyn Error>> {
-        // Expecting big endian
-        let big = self.memory[self.program_counter];
-        let little = self.memory[self.program_counter + 1];
-        let instruction = Instruction::from_opcode_u8(big, little);
-        let instruction = if let Some(i) = instruction {
-            i
-        } else {
-            return Err(Chip8Error::InvalidOpcode(format!("0x{:02x}{:02x}", big, little)).into());
-        };
-        Ok(instruction)
+    /// Clears the planes selected by the bitmask
+    fn clear(&mut self, planes: u8) {
+        for plane in selected(planes) {
+            self.planes[plane] = [0; GRAPHICS_BUFFER_SIZE];
+        }
     }
 
-    pub fn tick(&mut self) -> Result<bool, Box<dyn Error>> {
-        let span = span!(Level::INFO, "emulator.tick");
-        let _guard = span.enter();
+    /// Byte index and bit mask of the pixel
+    fn position(&self, x: usize, y: usize) -> (usize, u8) {
+        ((y * self.width() + x) / 8, 0x80 >> (x % 8))
+    }
 
-        if let Some(regx) = self.wait_for_key {
-            for (i, key) in self.key_status.iter().enumerate() {
-                if *key == KeyStatus::Pressed {
-                    self.registries[regx as usize] = i as u8;
-                    self.wait_for_key = None;
-                    break;
+    /// Moves the selected planes down, clearing the top rows
+    fn scroll_down(&mut self, planes: u8, rows: usize) {
+        for plane in selected(planes) {
+            for y in (0..self.height()).rev() {
+                for x in 0..self.width() {
+                    let lit = y >= rows && self.plane_pixel(plane, x, y - rows);
+                    self.set_pixel(plane, x, y, lit);
                 }
             }
-            self.decrement_timers();
-            return Ok(true);
         }
+    }
 
-        let instruction = match self.instruction() {
-            Ok(i) => i,
-            Err(e) => {
-                debug!(
-                    pc = self.program_counter,
-                    error = ?e,
-                    "failed to parse instruction opcode"
-                );
-                return Err(e);
-            }
+    /// Moves the selected planes right by a positive amount of columns, or
+    /// left by a negative, clearing the uncovered columns
+    fn scroll_horizontal(&mut self, planes: u8, columns: isize) {
+        let width = self.width() as isize;
+        let xs: Vec<isize> = if columns > 0 {
+            (0..width).rev().collect()
+        } else {
+            (0..width).collect()
         };
-        self.program_counter += 2;
-
-        let ret = match self.execute(instruction) {
-            Err(e) => {
-                debug!(
-                    pc = self.program_counter,
-                    error = ?e,
-                    "failed to execute instruction"
-                );
-                Err(e)
+        for plane in selected(planes) {
+            for y in 0..self.height() {
+                for x in &xs {
+                    let from = x - columns;
+                    let lit =
+                        (0..width).contains(&from) && self.plane_pixel(plane, from as usize, y);
+                    self.set_pixel(plane, *x as usize, y, lit);
+                }
             }
-            res => res,
-        }?;
+        }
+    }
+}
 
-        self.decrement_timers();
-        Ok(ret)
+/// The graphics as of a [Emulator::graphics_frame]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    pub number: u64,
+    pub buffer: FrameBuffer,
+}
+
+/// The planes selected by the bitmask
+fn selected(planes: u8) -> impl Iterator<Item = usize> {
+    (0..PLANE_COUNT).filter(move |plane| planes & (1 << plane) != 0)
+}
+
+/// Source of time for the delay and sound timers
+pub trait Clock: Send {
+    /// Time passed since an arbitrary, fixed, starting point
+    fn now(&self) -> Duration;
+}
+
+/// Follows the system's monotonic clock
+#[cfg(feature = "std")]
+pub struct SystemClock {
+    start: Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn sub_regs(&mut self, regx: u4, regy: u4) {
-        let vx = self.registries[regx.value() as usize];
-        let vy = self.registries[regy.value() as usize];
-        // this is probably the wrong implementation
-        let (result, borrow) = vx.overflowing_sub(vy);
-        self.registries[0xF_usize] = if borrow { 1 } else { 0 };
-        self.registries[regx.value() as usize] = result;
+#[cfg(feature = "std")]
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
     }
+}
 
-    pub fn execute(&mut self, instruction: Instruction) -> Result<bool, Box<dyn Error>> {
-        debug!(instruction = ?instruction, "executing instruction");
-        match instruction {
-            Instruction::Exit => {
-                // Kill execution
-                return Ok(false);
-            }
-            Instruction::Clear => {
-                self.graphics_buffer = [0; GRAPHICS_BUFFER_SIZE];
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Only moves when advanced, for deterministic tests and replays. Clones
+/// share the time, so a clone can be kept to drive the emulator's clock.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+#[cfg(feature = "std")]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Stays at the time, while running a frame or stepping back. Without
+/// `std` it's the default clock, at zero, so the timers only count down with
+/// [Emulator::tick_frame] unless a clock of the platform is set with
+/// [Builder::with_clock].
+struct FixedClock(Duration);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Duration {
+        self.0
+    }
+}
+
+// 60 hz at microsecond scale
+const TIME_BETWEEN_DECREMENT: u128 = Duration::from_micros(1_000_000 / 60).as_micros();
+
+/// Lines kept by the debug output until taken, older lines are dropped,
+/// see [Emulator::take_debug_output]
+pub const DEBUG_OUTPUT_LINES: usize = 256;
+
+/// A frame at the 60 hz of the timers
+pub const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 60);
+/// Frames kept by the profiler, a second worth
+pub const PROFILE_FRAMES: usize = 60;
+
+// Control/read messages supported by the
+// chip-8 emulator
+#[cfg(feature = "std")]
+pub enum Message {
+    /// Suspends the execution, the thread keeps servicing messages
+    Pause,
+    Resume,
+    /// Ends the thread, which returns the emulator
+    Stop,
+    /// Sends whether the execution is suspended, by a [Message::Pause],
+    /// breakpoint, exit or failure
+    SendSuspended(Sender<bool>),
+    SendGraphics(Sender<FrameBuffer>),
+    /// Sends the graphics and their frame, see [Emulator::graphics_frame],
+    /// unless the frame is still the given one
+    SendGraphicsIfChanged(Option<u64>, Sender<Option<(u64, FrameBuffer)>>),
+    /// See [Emulator::subscribe]
+    Subscribe(Sender<Frame>),
+    KeyEvent(u4, KeyStatus),
+    /// Sends a snapshot, including the memory if requested
+    SendSnapshot(bool, Sender<Snapshot>),
+    /// Sends whether the breakpoint wasn't already set
+    SetBreakpoint(usize, Sender<bool>),
+    /// Sends whether the breakpoint was set
+    ClearBreakpoint(usize, Sender<bool>),
+    SendBreakpoints(Sender<Vec<usize>>),
+    SendTrace(Sender<Vec<TraceEntry>>),
+    SendProfile(Sender<Option<ProfileReport>>),
+    SendCoverage(Sender<Option<CoverageReport>>),
+    SendStats(Sender<Stats>),
+    /// See [Emulator::take_debug_output]
+    TakeDebugOutput(Sender<Vec<String>>),
+    ReadMemory(Range<usize>, Sender<Result<Vec<u8>, EmulatorError>>),
+    WriteMemory(usize, Vec<u8>, Sender<Result<(), EmulatorError>>),
+    SetRegister(u4, u8, Sender<()>),
+    SetAddressRegister(usize, Sender<Result<(), EmulatorError>>),
+    SetProgramCounter(usize, Sender<Result<(), EmulatorError>>),
+    /// Executes a single tick, regardless of the execution speed
+    Step(Sender<Result<TickOutcome, EmulatorError>>),
+    /// See [Emulator::tick_frame]
+    StepFrame(Sender<Result<TickOutcome, EmulatorError>>),
+    /// See [Emulator::reset]
+    Reset(Sender<()>),
+    /// See [Emulator::restart]
+    Restart(Sender<Result<(), EmulatorError>>),
+    /// Sends whether a step was taken, see [Emulator::step_back]
+    StepBack(Sender<bool>),
+    /// Sends the id of the cheat, see [Emulator::add_cheat]
+    AddCheat(Cheat, Sender<Result<usize, EmulatorError>>),
+    /// Sends whether a cheat had the id
+    RemoveCheat(usize, Sender<bool>),
+    SendCheats(Sender<Vec<(usize, Cheat)>>),
+    /// See [Emulator::start_recording]
+    StartRecording,
+    /// Sends the input recorded so far, see [Emulator::recording]
+    SendRecording(Sender<Option<InputRecording>>),
+    Replay(InputRecording),
+    /// Sends the path of the last crash report, see
+    /// [Emulator::crash_report_path]
+    SendCrashReport(Sender<Option<PathBuf>>),
+    /// See [Emulator::rom_info]
+    SendRomInfo(Sender<Option<RomInfo>>),
+}
+
+/// A breakpoint which stopped the execution
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The `break` instruction at the address, which has been executed
+    Opcode(usize),
+    /// A breakpoint set with [Emulator::set_breakpoint], the instruction at
+    /// the address hasn't been executed
+    Address(usize),
+    /// An instruction breakpoint set with
+    /// [Emulator::set_instruction_breakpoint], on the mnemonic at the
+    /// address which hasn't been executed
+    Instruction(usize, &'static str),
+}
+
+impl Breakpoint {
+    pub fn address(&self) -> usize {
+        match self {
+            Self::Opcode(address) | Self::Address(address) | Self::Instruction(address, _) => {
+                *address
             }
-            Instruction::Return => {
-                if self.stack_pointer == 0 {
-                    return Err(Chip8Error::StackEmpty.into());
-                }
-                self.stack_pointer -= 1;
-                self.program_counter = self.stack[self.stack_pointer];
-            }
-            Instruction::Call(addr) => {
-                if self.stack_pointer >= STACK_SIZE {
-                    return Err(Chip8Error::StackFull.into());
-                }
-                self.stack[self.stack_pointer] = self.program_counter;
-                self.stack_pointer += 1;
-                self.program_counter = addr.value() as usize;
-            }
-            Instruction::Jump(addr) => {
-                self.program_counter = addr.value() as usize;
-            }
-            Instruction::SkipEqual(register, value) => {
-                let index = register.value() as usize;
-                if self.registries[index] == value {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::SkipNotEqual(register, value) => {
-                let index = register.value() as usize;
-                if self.registries[index] != value {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::SkipRegistersEqual(regx, regy) => {
-                if self.registries[regx.value() as usize] == self.registries[regy.value() as usize]
-                {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::SetRegisterByte(register, value) => {
-                self.registries[register.value() as usize] = value;
-            }
-            Instruction::SetRegisterRegister(regx, regy) => {
-                self.registries[regx.value() as usize] = self.registries[regy.value() as usize];
-            }
-            Instruction::Add(register, value) => {
-                let current = self.registries[register.value() as usize];
-                let (new, _) = current.overflowing_add(value);
-                self.registries[register.value() as usize] = new;
-            }
-            Instruction::Or(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                self.registries[regx.value() as usize] = vx | vy;
-            }
-            Instruction::And(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                self.registries[regx.value() as usize] = vx & vy;
-            }
-            Instruction::Xor(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                self.registries[regx.value() as usize] = vx ^ vy;
-            }
-            Instruction::AddChecked(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                // u8::overflowing_add wraps the value, which isn't what I want
-                // here? 1111 1111 + 0000 0001 = 1111 1110 is what's expected,
-                // and according to rust docs overflowing_add == 0 in that case
-                let result = (vx as u16) + (vy as u16);
-                let vf = if result & 0xFF00 >= 1 { 1 } else { 0 };
-                self.registries[0xF_usize] = vf;
-                self.registries[regx.value() as usize] = (result & 0x00FF) as u8;
-            }
-            Instruction::SubChecked(regx, regy) => {
-                self.sub_regs(regx, regy);
-            }
-            Instruction::ShiftRight(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                let (result, overflow) = vx.overflowing_shr(vy as u32);
-                self.registries[0xF_usize] = if overflow { 1 } else { 0 };
-                self.registries[regx.value() as usize] = result;
-            }
-            Instruction::SubNChecked(regx, regy) => {
-                self.sub_regs(regy, regx);
+        }
+    }
+}
+
+/// The result of a single [Emulator::tick] or [Emulator::execute], failures
+/// are reported as a [EmulatorError]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TickOutcome {
+    Continue,
+    /// The program exited or halted
+    Exit,
+    Stopped(Breakpoint),
+    /// No instruction is executed until a key is pressed
+    WaitingForKey,
+}
+
+/// How sprites crossing the edge of the display are drawn. The starting
+/// position always wraps around the display.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum DrawMode {
+    /// Pixels outside the display are dropped, as on the original
+    /// interpreter
+    #[default]
+    Clip,
+    /// Pixels outside the display wrap around to the opposite edge
+    Wrap,
+}
+
+/// How writes below [START_ADDR], where the font lives, and execution
+/// outside of the loaded program are treated. Programs generating code past
+/// their end are valid, so this is meant for catching bugs.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum MemoryProtection {
+    #[default]
+    Off,
+    /// Log a warning and carry on
+    Warn,
+    /// Fail with [EmulatorError::ProtectedWrite] or
+    /// [EmulatorError::ExecutionOutOfBounds], before the write or execution
+    Error,
+}
+
+/// How calls with a full stack and returns with an empty stack are treated
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum StackFault {
+    /// Fail with [EmulatorError::StackFull] or [EmulatorError::StackEmpty]
+    #[default]
+    Error,
+    /// The stack pointer wraps around, overwriting the oldest return
+    /// address or returning to the newest
+    Wrap,
+    /// Notify the observers with [ExecutionObserver::on_stack_fault] and
+    /// carry on past the instruction, observers may change the state
+    Hook,
+}
+
+/// Behaviours differing between interpreters, which programs may depend on
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// `wkey` completes when the pressed key is released, as on the COSMAC
+    /// VIP, instead of when it's pressed
+    pub wait_for_release: bool,
+    /// Sprites are drawn at the start of the next 60 hz frame, as the
+    /// COSMAC VIP waited for the display interrupt, so at most one draw
+    /// happens per frame
+    pub display_wait: bool,
+    /// `shr` and `shl` shift X in place, as on SUPER-CHIP, instead of
+    /// storing the shifted Y in X
+    pub shift_in_place: bool,
+}
+
+impl Quirks {
+    /// The original interpreter, waiting for key releases and the display
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            wait_for_release: true,
+            display_wait: true,
+            shift_in_place: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP 48, drawing without waiting for the display
+    pub const fn schip_1_1() -> Self {
+        Self {
+            wait_for_release: false,
+            display_wait: false,
+            shift_in_place: true,
+        }
+    }
+
+    /// XO-CHIP as run by Octo, drawing without waiting for the display
+    pub const fn xo_chip() -> Self {
+        Self {
+            wait_for_release: false,
+            display_wait: false,
+            shift_in_place: false,
+        }
+    }
+
+    /// The preset of the platform
+    pub const fn for_target(target: Target) -> Self {
+        match target {
+            Target::Chip8 => Self::cosmac_vip(),
+            Target::Schip => Self::schip_1_1(),
+            Target::XoChip => Self::xo_chip(),
+        }
+    }
+}
+
+/// A value kept in place after every tick, see [Emulator::add_cheat]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cheat {
+    /// Keeps the V register at the value, e.g. `v3=9`
+    FreezeRegister(u4, u8),
+    /// Keeps the bytes at the address, e.g. `0x2f0=0x12,0x00`
+    Patch(usize, Vec<u8>),
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FreezeRegister(register, value) => {
+                write!(f, "v{:x}=0x{:02x}", register.value(), value)
             }
-            Instruction::ShiftLeft(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                let (result, overflow) = vx.overflowing_shl(vy as u32);
-                self.registries[0xF_usize] = if overflow { 1 } else { 0 };
-                self.registries[regx.value() as usize] = result;
+            Self::Patch(address, bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                write!(f, "0x{:03x}={}", address, bytes.join(","))
             }
-            Instruction::SkipRegistersNotEqual(regx, regy) => {
-                let vx = self.registries[regx.value() as usize];
-                let vy = self.registries[regy.value() as usize];
-                if vx != vy {
-                    self.program_counter += 2;
+        }
+    }
+}
+
+impl FromStr for Cheat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |text: &str| match text.trim().strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => text.trim().parse().ok(),
+        };
+        let invalid = || format!("invalid cheat '{}', expected vX=BYTE or ADDR=BYTE,...", s);
+        let (target, value) = s.split_once('=').ok_or_else(invalid)?;
+        let bytes = value
+            .split(',')
+            .map(|byte| number(byte).and_then(|b| u8::try_from(b).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let target = target.trim().to_ascii_lowercase();
+        match target.strip_prefix('v') {
+            Some(register) => match (u8::from_str_radix(register, 16), &bytes[..]) {
+                (Ok(register), [value]) if register < 16 => {
+                    Ok(Self::FreezeRegister(register.into(), *value))
                 }
+                _ => Err(invalid()),
+            },
+            None => Ok(Self::Patch(number(&target).ok_or_else(invalid)?, bytes)),
+        }
+    }
+}
+
+/// Receives the state of the beeper, so frontends can play sound without
+/// polling snapshots. Called from the thread running the emulator.
+/// XO-CHIP pattern audio will be delivered through this trait as well.
+pub trait AudioSink: Send {
+    /// The sound timer was set while silent
+    fn start(&mut self);
+    /// The sound timer reached zero, or the emulator was reset
+    fn stop(&mut self);
+}
+
+/// Hooks into the execution of [Emulator::tick], for tracing, coverage,
+/// cheats or custom peripherals. Every method does nothing by default and
+/// may change the emulator.
+pub trait ExecutionObserver: Send {
+    /// The instruction at the address is about to be executed, the program
+    /// counter has moved past it
+    fn before_execute(
+        &mut self,
+        _emulator: &mut Emulator,
+        _address: usize,
+        _instruction: Instruction,
+    ) {
+    }
+    /// The instruction at the address was executed without failing
+    fn after_execute(
+        &mut self,
+        _emulator: &mut Emulator,
+        _address: usize,
+        _instruction: Instruction,
+    ) {
+    }
+    /// A sprite was drawn at (x, y), before VF is set to the collision
+    fn on_draw(&mut self, _emulator: &mut Emulator, _x: usize, _y: usize, _collision: bool) {}
+    /// The execution waits for a key to store in the register
+    fn on_key_wait(&mut self, _emulator: &mut Emulator, _register: u4) {}
+    /// A call or return faulted with [StackFault::Hook], the error holds
+    /// the stack
+    fn on_stack_fault(&mut self, _emulator: &mut Emulator, _fault: &EmulatorError) {}
+}
+
+/// How long [Emulator::run_for] runs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunLimit {
+    Ticks(u64),
+    /// Wall clock time, or time of the configured [Clock] without `std`
+    Duration(Duration),
+}
+
+/// Why a headless run stopped
+#[derive(Debug)]
+pub enum StopReason {
+    /// The [RunLimit] was reached
+    Limit,
+    /// The condition of [Emulator::run_until] held
+    Condition,
+    Exit,
+    Breakpoint(Breakpoint),
+    /// Nothing can press a key in a headless run
+    WaitingForKey,
+    Error(EmulatorError),
+}
+
+/// The result of a headless run
+#[derive(Debug)]
+pub struct ExecutionReport {
+    pub instructions: u64,
+    pub ticks: u64,
+    pub elapsed: Duration,
+    pub stop_reason: StopReason,
+    pub snapshot: Snapshot,
+}
+
+/// A V register changed by an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterDelta {
+    pub register: u8,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// An executed instruction, recorded when tracing is enabled
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub program_counter: usize,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub registers: Vec<RegisterDelta>,
+    /// I before and after the instruction, if it changed
+    pub address_register: Option<(usize, usize)>,
+}
+
+impl TraceEntry {
+    /// The entry as a JSON object on a single line
+    #[cfg(feature = "std")]
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|d| {
+                format!(
+                    "{{\"register\":{},\"before\":{},\"after\":{}}}",
+                    d.register, d.before, d.after
+                )
+            })
+            .collect();
+        let address_register = match self.address_register {
+            Some((before, after)) => format!("{{\"before\":{},\"after\":{}}}", before, after),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"pc\":{},\"opcode\":\"{:04x}\",\"instruction\":{},\"registers\":[{}],\"i\":{}}}",
+            self.program_counter,
+            self.opcode,
+            json_string(&self.instruction.to_assembly()),
+            registers.join(","),
+            address_register
+        )
+    }
+}
+
+/// Writes the entries as JSON lines, oldest first
+#[cfg(feature = "std")]
+pub fn export_trace<W: Write>(entries: &[TraceEntry], mut writer: W) -> io::Result<()> {
+    for entry in entries {
+        writeln!(writer, "{}", entry.to_json())?;
+    }
+    Ok(())
+}
+
+/// Written by a running emulator failing with a crash directory
+/// configured, see [Builder::with_crash_dir]
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub error: String,
+    pub snapshot: Snapshot,
+    /// The last executed instructions, see [Builder::with_trace]
+    pub trace: Vec<TraceEntry>,
+    /// See [Emulator::rom_hash]
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub memory_protection: MemoryProtection,
+    pub stack_fault: StackFault,
+}
+
+impl CrashReport {
+    /// The report as a JSON object
+    #[cfg(feature = "std")]
+    pub fn to_json(&self) -> String {
+        let snapshot = &self.snapshot;
+        let instruction = match snapshot.instruction {
+            Some(instruction) => json_string(&instruction.to_assembly()),
+            None => "null".to_string(),
+        };
+        let trace: Vec<String> = self.trace.iter().map(TraceEntry::to_json).collect();
+        format!(
+            "{{\"error\":{},\"rom_hash\":\"{:016x}\",\
+             \"quirks\":{{\"wait_for_release\":{},\"display_wait\":{},\"shift_in_place\":{}}},\
+             \"memory_protection\":\"{:?}\",\"stack_fault\":\"{:?}\",\
+             \"pc\":{},\"instruction\":{},\"registers\":{:?},\"i\":{},\
+             \"dt\":{},\"st\":{},\"stack\":{:?},\"trace\":[{}]}}",
+            json_string(&self.error),
+            self.rom_hash,
+            self.quirks.wait_for_release,
+            self.quirks.display_wait,
+            self.quirks.shift_in_place,
+            self.memory_protection,
+            self.stack_fault,
+            snapshot.program_counter,
+            instruction,
+            snapshot.registries,
+            snapshot.address_register,
+            snapshot.delay_timer,
+            snapshot.sound_timer,
+            &snapshot.stack[..snapshot.stack_pointer.min(STACK_SIZE)],
+            trace.join(",")
+        )
+    }
+}
+
+/// The value as a JSON string, quoted and escaped
+#[cfg(feature = "std")]
+fn json_string(value: &str) -> String {
+    // Only fails for maps with non-string keys
+    serde_json::to_string(value).unwrap()
+}
+
+/// FNV-1a, stable across runs and builds
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Leads every input recording, followed by the format version
+pub const INPUT_RECORDING_MAGIC: &str = "chip8-input";
+pub const INPUT_RECORDING_VERSION: u16 = 1;
+
+/// A key event of an [InputRecording], applied before the tick
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InputEvent {
+    /// Ticks since the recording started
+    pub tick: u64,
+    pub key: u4,
+    pub status: KeyStatus,
+}
+
+/// Key events recorded with [Emulator::start_recording], replayed with
+/// [Emulator::replay]. Replays are deterministic when started from the
+/// same state as the recording, e.g. right after loading the program, with
+/// a clock advanced the same way, e.g. a [ManualClock].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InputRecording {
+    /// Seed of the random number generator, see [Builder::with_seed]
+    pub seed: u64,
+    /// Ordered by tick
+    pub events: Vec<InputEvent>,
+}
+
+impl InputRecording {
+    /// Writes the recording as lines of text, the header and seed followed
+    /// by a `<tick> <key> <pressed|up>` line per event
+    #[cfg(feature = "std")]
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{} {}",
+            INPUT_RECORDING_MAGIC, INPUT_RECORDING_VERSION
+        )?;
+        writeln!(writer, "seed {}", self.seed)?;
+        for event in &self.events {
+            let status = match event.status {
+                KeyStatus::Pressed => "pressed",
+                KeyStatus::Up => "up",
+            };
+            writeln!(writer, "{} {:x} {}", event.tick, event.key.value(), status)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a recording written by [InputRecording::save]
+    #[cfg(feature = "std")]
+    pub fn load<R: Read>(mut reader: R) -> Result<Self, EmulatorError> {
+        let invalid = |reason: &str| EmulatorError::InvalidRecording(reason.to_string());
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut lines = text.lines();
+
+        let header = format!("{} {}", INPUT_RECORDING_MAGIC, INPUT_RECORDING_VERSION);
+        if lines.next() != Some(header.as_str()) {
+            return Err(invalid("missing header"));
+        }
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .and_then(|seed| seed.parse().ok())
+            .ok_or_else(|| invalid("missing seed"))?;
+
+        let mut events: Vec<InputEvent> = Vec::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [tick, key, status] = fields[..] else {
+                return Err(invalid(line));
+            };
+            let tick: u64 = tick.parse().map_err(|_| invalid(line))?;
+            let key = match u8::from_str_radix(key, 16) {
+                Ok(key) if (key as usize) < KEY_COUNT => u4::from(key),
+                _ => return Err(invalid(line)),
+            };
+            let status = match status {
+                "pressed" => KeyStatus::Pressed,
+                "up" => KeyStatus::Up,
+                _ => return Err(invalid(line)),
+            };
+            if events.last().is_some_and(|last| last.tick > tick) {
+                return Err(invalid("events out of order"));
             }
-            Instruction::SetMemRegister(addr) => {
-                self.address_register = addr.value() as usize;
-            }
-            Instruction::JumpOffset(addr) => {
-                let v0 = self.registries[0] as usize;
-                self.program_counter = (addr.value() as usize) + v0;
+            events.push(InputEvent { tick, key, status });
+        }
+        Ok(Self { seed, events })
+    }
+}
+
+/// Instructions executed within a [FRAME_DURATION]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct FrameProfile {
+    pub instructions: usize,
+    /// Clock time spent executing the instructions
+    pub busy: Duration,
+}
+
+/// Execution statistics since profiling was enabled, see
+/// [Emulator::profile_report]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    /// Clock time between the first and last profiled instruction,
+    /// including any time spent paused
+    pub elapsed: Duration,
+    /// Executions per address, most executed first
+    pub addresses: Vec<(usize, u64)>,
+    /// The last [PROFILE_FRAMES] frames, oldest first
+    pub frames: Vec<FrameProfile>,
+}
+
+impl ProfileReport {
+    /// Executed instructions per second
+    pub fn hertz(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The `count` most executed addresses
+    pub fn hottest(&self, count: usize) -> &[(usize, u64)] {
+        &self.addresses[..count.min(self.addresses.len())]
+    }
+}
+
+struct Profiler {
+    counts: Vec<u64>,
+    instructions: u64,
+    /// Clock time of the first and last profiled instruction
+    start: Option<Duration>,
+    end: Duration,
+    /// Index of the last frame since the clock started
+    frame: u128,
+    frames: VecDeque<FrameProfile>,
+}
+
+impl Profiler {
+    fn new(memory_size: usize) -> Self {
+        Self {
+            counts: vec![0; memory_size],
+            instructions: 0,
+            start: None,
+            end: Duration::ZERO,
+            frame: 0,
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, address: usize, started: Duration, finished: Duration) {
+        self.counts[address] += 1;
+        self.instructions += 1;
+        self.start.get_or_insert(started);
+        self.end = finished;
+
+        let frame = started.as_micros() / FRAME_DURATION.as_micros();
+        if self.frames.is_empty() || frame != self.frame {
+            if self.frames.len() == PROFILE_FRAMES {
+                self.frames.pop_front();
             }
-            Instruction::Randomize(reg, value) => {
-                let rb = thread_rng()
-                    .next_u32()
-                    .to_be_bytes()
-                    .first()
-                    .map(|e| e.clone());
-                let rb = match rb {
-                    Some(b) => b,
-                    None => {
-                        error!("failed to randomize byte, using default value of 0xFF");
-                        0xFF
-                    }
-                };
-                self.registries[reg.value() as usize] = rb & value;
+            self.frames.push_back(FrameProfile::default());
+            self.frame = frame;
+        }
+        let current = self.frames.back_mut().unwrap();
+        current.instructions += 1;
+        current.busy += finished.saturating_sub(started);
+    }
+
+    fn report(&self) -> ProfileReport {
+        let mut addresses: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address, *count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ProfileReport {
+            instructions: self.instructions,
+            elapsed: self.end.saturating_sub(self.start.unwrap_or(self.end)),
+            addresses,
+            frames: self.frames.iter().copied().collect(),
+        }
+    }
+}
+
+/// The bytes of the program executed and read as data since coverage was
+/// enabled or the emulator reset, see [Emulator::coverage_report]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CoverageReport {
+    /// Address of the first byte of the program
+    pub start: usize,
+    /// Whether each byte of the program was executed as part of an
+    /// instruction
+    pub executed: Vec<bool>,
+    /// Whether each byte of the program was read as data, by draws and
+    /// `ldr`
+    pub read: Vec<bool>,
+}
+
+impl CoverageReport {
+    /// Share of the program's bytes executed or read, between 0 and 100
+    pub fn percentage(&self) -> f64 {
+        let covered = self
+            .executed
+            .iter()
+            .zip(&self.read)
+            .filter(|(e, r)| **e || **r);
+        percentage(covered.count(), self.executed.len())
+    }
+
+    /// Share of the program's bytes executed, between 0 and 100
+    pub fn executed_percentage(&self) -> f64 {
+        percentage(
+            self.executed.iter().filter(|e| **e).count(),
+            self.executed.len(),
+        )
+    }
+
+    /// Ranges of addresses neither executed nor read, ordered by address
+    pub fn uncovered(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let covered = self.executed.iter().zip(&self.read).map(|(e, r)| *e || *r);
+        for (address, covered) in (self.start..).zip(covered) {
+            match ranges.last_mut() {
+                _ if covered => {}
+                Some(range) if range.end == address => range.end += 1,
+                _ => ranges.push(address..address + 1),
             }
-            Instruction::Draw(regx, regy, n) => {
-                let mut vf = 0;
+        }
+        ranges
+    }
+}
 
-                let x = self.registries[regx.value() as usize] as usize;
-                let y = self.registries[regy.value() as usize] as usize;
-                let start = x / 8 + y * 8;
-                // render each line separetly
-                for i in 0..n.value() {
-                    let sprite = self.memory[self.address_register + (i as usize)];
-                    let sp1 = sprite >> (x % 8);
-                    let sp2 = ((sprite as u16) << (8 - (x % 8))) as u8;
+fn percentage(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    count as f64 * 100.0 / total as f64
+}
+
+/// Records the addresses executed and read, for the whole memory
+struct Coverage {
+    executed: Vec<bool>,
+    read: Vec<bool>,
+}
+
+impl Coverage {
+    fn new(memory_size: usize) -> Self {
+        Self {
+            executed: vec![false; memory_size],
+            read: vec![false; memory_size],
+        }
+    }
+
+    fn executed(&mut self, addresses: Range<usize>) {
+        mark(&mut self.executed, addresses);
+    }
+
+    fn read(&mut self, addresses: Range<usize>) {
+        mark(&mut self.read, addresses);
+    }
+
+    fn report(&self, program: Range<usize>) -> CoverageReport {
+        CoverageReport {
+            start: program.start,
+            executed: self.executed[program.clone()].to_vec(),
+            read: self.read[program].to_vec(),
+        }
+    }
+}
+
+fn mark(addresses: &mut [bool], range: Range<usize>) {
+    let end = range.end.min(addresses.len());
+    let start = range.start.min(end);
+    addresses[start..end].fill(true);
+}
+
+/// Pacing of the execution since the reset, always recorded, see
+/// [Emulator::stats]
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Stats {
+    pub instructions: u64,
+    /// 60 hz frames in which the display changed
+    pub frames_drawn: u64,
+    /// Clock time between the first and last tick, including any time
+    /// suspended
+    pub elapsed: Duration,
+    /// Clock time spent waiting for a key
+    pub key_wait: Duration,
+}
+
+impl Stats {
+    /// Executed instructions per second, outside of waits for keys
+    pub fn hertz(&self) -> f64 {
+        let executing = self.elapsed.saturating_sub(self.key_wait);
+        if executing.is_zero() {
+            return 0.0;
+        }
+        self.instructions as f64 / executing.as_secs_f64()
+    }
+}
+
+#[derive(Default, Clone)]
+struct StatsRecorder {
+    /// Clock time of the first and last tick
+    start: Option<Duration>,
+    last_tick: Duration,
+    key_wait: Duration,
+    frames_drawn: u64,
+    /// Index of the last frame the display changed in
+    drawn_frame: Option<u128>,
+}
+
+impl StatsRecorder {
+    fn tick(&mut self, now: Duration, waiting_for_key: bool) {
+        if waiting_for_key && self.start.is_some() {
+            self.key_wait += now.saturating_sub(self.last_tick);
+        }
+        self.start.get_or_insert(now);
+        self.last_tick = now;
+    }
+
+    fn drawn(&mut self, now: Duration) {
+        let frame = now.as_micros() / FRAME_DURATION.as_micros();
+        if self.drawn_frame != Some(frame) {
+            self.frames_drawn += 1;
+            self.drawn_frame = Some(frame);
+        }
+    }
+
+    fn report(&self, instructions: u64) -> Stats {
+        Stats {
+            instructions,
+            frames_drawn: self.frames_drawn,
+            elapsed: self
+                .last_tick
+                .saturating_sub(self.start.unwrap_or(self.last_tick)),
+            key_wait: self.key_wait,
+        }
+    }
+}
+
+/// The machine state before a tick, restored by [Emulator::step_back]
+#[derive(Clone)]
+struct Checkpoint {
+    /// Index of the tick taken before, see [Rewind::tick]
+    tick: u64,
+    memory: Vec<u8>,
+    registries: [u8; REGISTRY_COUNT],
+    program_counter: usize,
+    stack_pointer: usize,
+    address_register: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [usize; STACK_SIZE],
+    flags: [u8; FLAG_COUNT],
+    graphics: FrameBuffer,
+    selected_planes: u8,
+    last_delay_decrement: Option<Duration>,
+    last_sound_decrement: Option<Duration>,
+    key_status: [KeyStatus; KEY_COUNT],
+    wait_for_key: Option<u8>,
+    pressed_key: Option<u8>,
+    display_wait: Option<u64>,
+    instructions: u64,
+    rng: StdRng,
+    resume_address: Option<usize>,
+    stats: StatsRecorder,
+    input_tick: u64,
+}
+
+/// What re-executing a tick needs
+#[derive(Clone, Copy)]
+struct TickRecord {
+    /// Clock time of the tick
+    time: Duration,
+    keys: [KeyStatus; KEY_COUNT],
+    /// Whether the tick stopped at a breakpoint
+    stopped: bool,
+    /// Whether the tick executed an instruction
+    executed: bool,
+}
+
+/// Checkpoints taken every `interval` ticks and the ticks since the
+/// oldest, see [Builder::with_rewind]
+struct Rewind {
+    interval: u64,
+    capacity: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    /// The ticks from the oldest checkpoint on
+    ticks: VecDeque<TickRecord>,
+    /// Index of the next tick since the reset
+    tick: u64,
+}
+
+impl Rewind {
+    fn new(capacity: usize, interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity,
+            checkpoints: VecDeque::new(),
+            ticks: VecDeque::new(),
+            tick: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.ticks.clear();
+        self.tick = 0;
+    }
+
+    /// Whether the next tick needs a checkpoint
+    fn due(&self) -> bool {
+        self.tick.is_multiple_of(self.interval)
+            && self.checkpoints.back().map(|c| c.tick) != Some(self.tick)
+    }
+
+    fn push(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.push_back(checkpoint);
+        if self.checkpoints.len() > self.capacity {
+            let dropped = self.checkpoints.pop_front().unwrap();
+            let first = self.checkpoints.front().map_or(self.tick, |c| c.tick);
+            self.ticks.drain(..(first - dropped.tick) as usize);
+        }
+    }
+
+    fn record(&mut self, record: TickRecord) {
+        if !self.checkpoints.is_empty() {
+            self.ticks.push_back(record);
+        }
+        self.tick += 1;
+    }
+}
+
+pub struct Builder {
+    hertz: usize,
+    timeboxes: usize,
+    font: Font,
+    large_font: LargeFont,
+    draw_mode: DrawMode,
+    audio_sink: Option<Box<dyn AudioSink>>,
+    clock: Option<Box<dyn Clock>>,
+    trace_size: usize,
+    profiling: bool,
+    coverage: bool,
+    tick_tracing: bool,
+    memory_protection: MemoryProtection,
+    stack_fault: StackFault,
+    memory_size: usize,
+    #[cfg(feature = "std")]
+    crash_dir: Option<PathBuf>,
+    rewind: Option<(usize, u64)>,
+    #[cfg(feature = "std")]
+    profile: Option<Profile>,
+    #[cfg(feature = "std")]
+    sidecar_profiles: bool,
+    observers: Vec<Box<dyn ExecutionObserver>>,
+    quirks: Quirks,
+    start_addr: usize,
+    initial_registers: [u8; REGISTRY_COUNT],
+    seed: Option<u64>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            hertz: 400,
+            timeboxes: 100,
+            font: DEFAULT_SPRITES,
+            large_font: DEFAULT_LARGE_SPRITES,
+            draw_mode: DrawMode::Clip,
+            audio_sink: None,
+            clock: None,
+            trace_size: 0,
+            profiling: false,
+            coverage: false,
+            tick_tracing: false,
+            memory_protection: MemoryProtection::Off,
+            stack_fault: StackFault::Error,
+            memory_size: MEMSIZE,
+            #[cfg(feature = "std")]
+            crash_dir: None,
+            rewind: None,
+            #[cfg(feature = "std")]
+            profile: None,
+            #[cfg(feature = "std")]
+            sidecar_profiles: true,
+            observers: Vec::new(),
+            quirks: Quirks::default(),
+            start_addr: START_ADDR,
+            initial_registers: [0; REGISTRY_COUNT],
+            seed: None,
+        }
+    }
+
+    pub fn with_hertz(mut self, hertz: usize) -> Self {
+        self.hertz = hertz;
+        self
+    }
+
+    pub fn with_timeboxes(mut self, timeboxes: usize) -> Self {
+        self.timeboxes = timeboxes;
+        self
+    }
+
+    /// Use a custom font instead of [DEFAULT_SPRITES]
+    pub fn with_font(mut self, font: Font) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// Use a custom large font instead of [DEFAULT_LARGE_SPRITES]
+    pub fn with_large_font(mut self, large_font: LargeFont) -> Self {
+        self.large_font = large_font;
+        self
+    }
+
+    pub fn with_draw_mode(mut self, draw_mode: DrawMode) -> Self {
+        self.draw_mode = draw_mode;
+        self
+    }
+
+    pub fn with_audio_sink(mut self, audio_sink: Box<dyn AudioSink>) -> Self {
+        self.audio_sink = Some(audio_sink);
+        self
+    }
+
+    /// Drive the timers by the clock instead of a [SystemClock]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Records the last `trace_size` executed instructions, see
+    /// [Emulator::trace]
+    pub fn with_trace(mut self, trace_size: usize) -> Self {
+        self.trace_size = trace_size;
+        self
+    }
+
+    /// Profiles the execution, see [Emulator::profile_report]
+    pub fn with_profiling(mut self, profiling: bool) -> Self {
+        self.profiling = profiling;
+        self
+    }
+
+    /// Tracks the bytes of the program executed and read, see
+    /// [Emulator::coverage_report]
+    pub fn with_coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    /// Enters a span for every tick and logs every executed instruction
+    /// at the debug level. Off by default, as it slows down fast programs
+    /// whenever a subscriber listens, see `benches/core.rs`.
+    pub fn with_tick_tracing(mut self, tick_tracing: bool) -> Self {
+        self.tick_tracing = tick_tracing;
+        self
+    }
+
+    pub fn with_memory_protection(mut self, memory_protection: MemoryProtection) -> Self {
+        self.memory_protection = memory_protection;
+        self
+    }
+
+    pub fn with_stack_fault(mut self, stack_fault: StackFault) -> Self {
+        self.stack_fault = stack_fault;
+        self
+    }
+
+    /// Write a [CrashReport] to a new file in the directory whenever the
+    /// running emulator fails, see [Emulator::crash_report_path]
+    #[cfg(feature = "std")]
+    pub fn with_crash_dir(mut self, crash_dir: impl Into<PathBuf>) -> Self {
+        self.crash_dir = Some(crash_dir.into());
+        self
+    }
+
+    /// Keeps up to `checkpoints` states, taken every `interval` ticks, to
+    /// step back from, see [Emulator::step_back]. No checkpoints disables
+    /// stepping back.
+    pub fn with_rewind(mut self, checkpoints: usize, interval: u64) -> Self {
+        self.rewind = (checkpoints > 0).then_some((checkpoints, interval));
+        self
+    }
+
+    /// Bytes of memory, between [MEMSIZE] and [XO_CHIP_MEMSIZE]. Programs
+    /// larger than the default memory need [XO_CHIP_MEMSIZE].
+    pub fn with_memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size.clamp(MEMSIZE, XO_CHIP_MEMSIZE);
+        self
+    }
+
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Where programs are loaded and the execution starts, instead of
+    /// [START_ADDR]. The ETI 660 starts at 0x600.
+    pub fn with_start_addr(mut self, start_addr: usize) -> Self {
+        self.start_addr = start_addr;
+        self
+    }
+
+    /// Values of the V registers after a reset
+    pub fn with_initial_registers(mut self, registers: [u8; REGISTRY_COUNT]) -> Self {
+        self.initial_registers = registers;
+        self
+    }
+
+    /// Seeds the random number generator, which is reseeded on every
+    /// reset, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Applies the settings of the profile over the configured ones, see
+    /// [Emulator::profile]
+    #[cfg(feature = "std")]
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        if let Some(hertz) = profile.hertz {
+            self.hertz = hertz;
+        }
+        if let Some(timeboxes) = profile.timeboxes {
+            self.timeboxes = timeboxes;
+        }
+        if let Some(quirks) = profile.quirks {
+            self.quirks = quirks;
+        }
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Whether [Builder::load_program] applies the profile next to the
+    /// program, see [Profile::for_rom]. Enabled by default.
+    #[cfg(feature = "std")]
+    pub fn with_sidecar_profiles(mut self, sidecar_profiles: bool) -> Self {
+        self.sidecar_profiles = sidecar_profiles;
+        self
+    }
+
+    /// Attaches an observer, observers are notified in the order attached
+    pub fn with_observer(mut self, observer: Box<dyn ExecutionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Builds an emulator without any program loaded
+    pub fn build(self) -> Emulator {
+        let mut emulator = Emulator::new(self.hertz, self.timeboxes);
+        emulator.memory = vec![0; self.memory_size];
+        emulator.font = self.font;
+        emulator.large_font = self.large_font;
+        emulator.draw_mode = self.draw_mode;
+        emulator.audio_sink = self.audio_sink;
+        if let Some(clock) = self.clock {
+            emulator.clock = clock;
+        }
+        emulator.trace_size = self.trace_size;
+        #[cfg(feature = "std")]
+        {
+            emulator.crash_dir = self.crash_dir;
+            emulator.profile = self.profile;
+        }
+        emulator.rewind = self
+            .rewind
+            .map(|(checkpoints, interval)| Rewind::new(checkpoints, interval));
+        emulator.set_profiling(self.profiling);
+        emulator.set_coverage(self.coverage);
+        emulator.tick_tracing = self.tick_tracing;
+        emulator.memory_protection = self.memory_protection;
+        emulator.stack_fault = self.stack_fault;
+        emulator.observers = self.observers;
+        emulator.quirks = self.quirks;
+        emulator.start_addr = self.start_addr;
+        emulator.initial_registers = self.initial_registers;
+        emulator.seed = self.seed;
+        emulator.reset();
+        emulator
+    }
+
+    /// Builds an emulator with the program in the file loaded, applying
+    /// the profile next to it, see [Builder::with_sidecar_profiles]
+    #[cfg(feature = "std")]
+    pub fn load_program(mut self, filepath: &str) -> Result<Emulator, EmulatorError> {
+        if self.sidecar_profiles {
+            if let Some(profile) = Profile::for_rom(filepath)? {
+                info!(filepath, "applying the profile of the program");
+                self = self.with_profile(profile);
+            }
+        }
+        let file = File::open(filepath)?;
+        self.load_reader(file)
+    }
+
+    /// Builds an emulator with the program read from the reader loaded
+    #[cfg(feature = "std")]
+    pub fn load_reader<T: Read>(self, reader: T) -> Result<Emulator, EmulatorError> {
+        let mut emulator = self.build();
+        emulator.load(reader)?;
+        Ok(emulator)
+    }
+
+    /// Builds an emulator with the program loaded at the start address,
+    /// see [Builder::with_start_addr]
+    pub fn load_bytes(self, rom: &[u8]) -> Result<Emulator, EmulatorError> {
+        let mut emulator = self.build();
+        emulator.load_rom_at(rom, emulator.start_addr)?;
+        Ok(emulator)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum KeyStatus {
+    #[default]
+    Up,
+    Pressed,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    pub registries: [u8; REGISTRY_COUNT],
+    pub program_counter: usize,
+    pub stack_pointer: usize,
+    pub address_register: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: [usize; STACK_SIZE],
+    pub key_status: [KeyStatus; KEY_COUNT],
+    pub instruction: Option<Instruction>,
+    /// A copy of the memory, if requested
+    pub memory: Option<Vec<u8>>,
+    /// See [Emulator::graphics_frame]
+    pub graphics_frame: u64,
+}
+
+/// Everything restored by [Emulator::load_state], the configuration isn't
+/// included
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SaveState {
+    memory: Vec<u8>,
+    registries: [u8; REGISTRY_COUNT],
+    program_counter: usize,
+    stack_pointer: usize,
+    address_register: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [usize; STACK_SIZE],
+    #[serde(default)]
+    flags: [u8; FLAG_COUNT],
+    key_status: [KeyStatus; KEY_COUNT],
+    wait_for_key: Option<u8>,
+    #[serde(default)]
+    pressed_key: Option<u8>,
+    resolution: Resolution,
+    planes: Vec<Vec<u8>>,
+    selected_planes: u8,
+}
+
+pub struct Emulator {
+    // hardware
+    memory: Vec<u8>,
+    registries: [u8; REGISTRY_COUNT],
+    program_counter: usize,
+    stack_pointer: usize,
+    // I registry
+    address_register: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [usize; STACK_SIZE],
+    /// The HP48 RPL user flags, see [FLAG_COUNT]
+    flags: [u8; FLAG_COUNT],
+    graphics: FrameBuffer,
+    /// Counts the changes to the graphics
+    graphics_frame: u64,
+    /// Bitmask of the planes drawn to
+    selected_planes: u8,
+    /// Clock times of the last decrements
+    last_delay_decrement: Option<Duration>,
+    last_sound_decrement: Option<Duration>,
+    key_status: [KeyStatus; KEY_COUNT],
+    wait_for_key: Option<u8>,
+    /// The key pressed while waiting for its release
+    pressed_key: Option<u8>,
+    /// The timer frame a draw is waiting to pass, see [Quirks::display_wait]
+    display_wait: Option<u64>,
+    /// Virtual time of the next frame, see [Emulator::tick_frame]
+    frame_time: Duration,
+    /// Where the loaded program is in memory
+    program: Range<usize>,
+    /// See [Emulator::rom_hash]
+    rom_hash: u64,
+    /// See [Emulator::rom_info]
+    #[cfg(feature = "std")]
+    rom_info: Option<RomInfo>,
+    /// See [Emulator::profile]
+    #[cfg(feature = "std")]
+    profile: Option<Profile>,
+    /// The last loaded program and where, kept for [Emulator::restart]
+    rom: Vec<u8>,
+    rom_address: usize,
+    /// Written after loading programs, see [Emulator::load_segment]
+    segments: Vec<(usize, Vec<u8>)>,
+    /// Instructions executed since the reset
+    instructions: u64,
+    rng: StdRng,
+
+    // debugging
+    breakpoints: BTreeSet<usize>,
+    /// Mnemonics which stop the execution
+    instruction_breakpoints: BTreeSet<&'static str>,
+    /// See [Emulator::add_cheat]
+    cheats: BTreeMap<usize, Cheat>,
+    next_cheat: usize,
+    /// Address of the last breakpoint stop, which the next tick executes
+    /// past
+    resume_address: Option<usize>,
+    /// The last executed instructions, oldest first
+    trace: VecDeque<TraceEntry>,
+    trace_size: usize,
+    #[cfg(feature = "std")]
+    crash_dir: Option<PathBuf>,
+    /// See [Emulator::crash_report_path]
+    #[cfg(feature = "std")]
+    crash_report: Option<PathBuf>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    /// See [Builder::with_tick_tracing]
+    tick_tracing: bool,
+    stats: StatsRecorder,
+    /// See [Emulator::take_debug_output]
+    debug_output: VecDeque<String>,
+    observers: Vec<Box<dyn ExecutionObserver>>,
+    /// Receive the changed frames, see [Emulator::subscribe]
+    #[cfg(feature = "std")]
+    subscribers: Vec<Sender<Frame>>,
+    /// The frame last sent to the subscribers
+    #[cfg(feature = "std")]
+    broadcast_frame: u64,
+    /// See [Emulator::step_back]
+    rewind: Option<Rewind>,
+    /// Key events since [Emulator::start_recording]
+    recording: Option<InputRecording>,
+    /// Key events left to replay, see [Emulator::replay]
+    replay: VecDeque<InputEvent>,
+    /// Ticks since the recording or replay started
+    input_tick: u64,
+
+    // configurations
+    hertz: usize,
+    /// Only used when running in a thread
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    timeboxes: usize,
+    font: Font,
+    large_font: LargeFont,
+    draw_mode: DrawMode,
+    quirks: Quirks,
+    memory_protection: MemoryProtection,
+    stack_fault: StackFault,
+    /// Where [Emulator::load] places programs
+    start_addr: usize,
+    /// The V registers after a reset
+    initial_registers: [u8; REGISTRY_COUNT],
+    /// Reseeds the random number generator on reset
+    seed: Option<u64>,
+    audio_sink: Option<Box<dyn AudioSink>>,
+    /// Whether the audio sink was last started
+    sound_playing: bool,
+    clock: Box<dyn Clock>,
+
+    // thread communication
+    /// Whether the thread only services messages
+    #[cfg(feature = "std")]
+    suspended: bool,
+}
+
+impl Emulator {
+    fn new(hertz: usize, timeboxes: usize) -> Self {
+        let mut ret = Self {
+            memory: vec![0; MEMSIZE],
+            registries: [0; REGISTRY_COUNT],
+            program_counter: START_ADDR,
+            stack_pointer: 0,
+            address_register: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            stack: [0; STACK_SIZE],
+            flags: [0; FLAG_COUNT],
+            graphics: FrameBuffer::default(),
+            graphics_frame: 0,
+            selected_planes: 1,
+            last_delay_decrement: None,
+            last_sound_decrement: None,
+            key_status: [KeyStatus::Up; KEY_COUNT],
+            wait_for_key: None,
+            pressed_key: None,
+            display_wait: None,
+            frame_time: Duration::ZERO,
+            program: START_ADDR..START_ADDR,
+            rom_hash: fnv1a([]),
+            #[cfg(feature = "std")]
+            rom_info: None,
+            #[cfg(feature = "std")]
+            profile: None,
+            rom: Vec::new(),
+            rom_address: START_ADDR,
+            segments: Vec::new(),
+            instructions: 0,
+            #[cfg(feature = "std")]
+            rng: StdRng::from_entropy(),
+            #[cfg(not(feature = "std"))]
+            rng: StdRng::seed_from_u64(0),
+            breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            cheats: BTreeMap::new(),
+            next_cheat: 0,
+            resume_address: None,
+            trace: VecDeque::new(),
+            trace_size: 0,
+            #[cfg(feature = "std")]
+            crash_dir: None,
+            #[cfg(feature = "std")]
+            crash_report: None,
+            profiler: None,
+            coverage: None,
+            tick_tracing: false,
+            stats: StatsRecorder::default(),
+            debug_output: VecDeque::new(),
+            observers: Vec::new(),
+            #[cfg(feature = "std")]
+            subscribers: Vec::new(),
+            #[cfg(feature = "std")]
+            broadcast_frame: 0,
+            rewind: None,
+            recording: None,
+            replay: VecDeque::new(),
+            input_tick: 0,
+            hertz,
+            timeboxes,
+            font: DEFAULT_SPRITES,
+            large_font: DEFAULT_LARGE_SPRITES,
+            draw_mode: DrawMode::Clip,
+            quirks: Quirks::default(),
+            memory_protection: MemoryProtection::Off,
+            stack_fault: StackFault::Error,
+            start_addr: START_ADDR,
+            initial_registers: [0; REGISTRY_COUNT],
+            seed: None,
+            audio_sink: None,
+            sound_playing: false,
+            #[cfg(feature = "std")]
+            clock: Box::new(SystemClock::new()),
+            #[cfg(not(feature = "std"))]
+            clock: Box::new(FixedClock(Duration::ZERO)),
+            #[cfg(feature = "std")]
+            suspended: false,
+        };
+        ret.reset();
+        ret
+    }
+
+    /// Resets everything in the emulator
+    pub fn reset(&mut self) {
+        self.memory.fill(0);
+        self.registries = self.initial_registers;
+        self.program_counter = self.start_addr;
+        self.stack_pointer = 0;
+        self.address_register = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.stack = [0; STACK_SIZE];
+        self.flags = [0; FLAG_COUNT];
+        self.graphics = FrameBuffer::default();
+        self.graphics_frame = self.graphics_frame.wrapping_add(1);
+        self.selected_planes = 1;
+        self.last_delay_decrement = None;
+        self.last_sound_decrement = None;
+        self.key_status = [KeyStatus::Up; KEY_COUNT];
+        self.wait_for_key = None;
+        self.pressed_key = None;
+        self.display_wait = None;
+        self.frame_time = Duration::ZERO;
+        self.program = self.start_addr..self.start_addr;
+        self.rom_hash = fnv1a([]);
+        #[cfg(feature = "std")]
+        {
+            self.rom_info = None;
+            self.crash_report = None;
+        }
+        self.instructions = 0;
+        self.stats = StatsRecorder::default();
+        self.debug_output.clear();
+        if let Some(seed) = self.seed {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+        self.resume_address = None;
+        self.trace.clear();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new(self.memory.len()));
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new(self.memory.len()));
+        }
+        self.update_audio();
+        self.load_default_sprites();
+    }
+
+    /// Stops the execution before the instruction at the address. Returns
+    /// false if the breakpoint was already set
+    pub fn set_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns false if no breakpoint was set at the address
+    pub fn clear_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Applies the cheat now and after every tick until removed, returning
+    /// the id to remove it by. Fails if a patch reaches outside of memory.
+    /// Cheats are kept over resets.
+    pub fn add_cheat(&mut self, cheat: Cheat) -> Result<usize, EmulatorError> {
+        if let Cheat::Patch(address, bytes) = &cheat {
+            self.write_memory(*address, bytes)?;
+        }
+        let id = self.next_cheat;
+        self.next_cheat += 1;
+        info!(id, %cheat, "added cheat");
+        self.cheats.insert(id, cheat);
+        self.apply_cheats();
+        Ok(id)
+    }
+
+    /// Returns false if no cheat has the id
+    pub fn remove_cheat(&mut self, id: usize) -> bool {
+        self.cheats.remove(&id).is_some()
+    }
+
+    /// The cheats by id, in the order added
+    pub fn cheats(&self) -> Vec<(usize, Cheat)> {
+        self.cheats
+            .iter()
+            .map(|(id, cheat)| (*id, cheat.clone()))
+            .collect()
+    }
+
+    fn apply_cheats(&mut self) {
+        for cheat in self.cheats.values() {
+            match cheat {
+                Cheat::FreezeRegister(register, value) => {
+                    self.registries[register.value() as usize] = *value;
+                }
+                Cheat::Patch(address, bytes) => {
+                    // Checked when added, the memory size doesn't change
+                    self.memory[*address..*address + bytes.len()].copy_from_slice(bytes);
+                }
+            }
+        }
+    }
+
+    /// Stops the execution before any instruction with the mnemonic, e.g.
+    /// `draw`. Returns false if the mnemonic isn't an instruction or the
+    /// breakpoint was already set
+    pub fn set_instruction_breakpoint(&mut self, mnemonic: &str) -> bool {
+        match Instruction::iter().find(|i| i.info().mnemonic == mnemonic) {
+            Some(instruction) => self
+                .instruction_breakpoints
+                .insert(instruction.info().mnemonic),
+            None => false,
+        }
+    }
+
+    /// Returns false if no breakpoint was set on the mnemonic
+    pub fn clear_instruction_breakpoint(&mut self, mnemonic: &str) -> bool {
+        self.instruction_breakpoints.remove(mnemonic)
+    }
+
+    pub fn instruction_breakpoints(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.instruction_breakpoints.iter().copied()
+    }
+
+    /// Clears the address and instruction breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.instruction_breakpoints.clear();
+    }
+
+    /// Records the last `trace_size` executed instructions, 0 disables
+    /// tracing
+    pub fn set_trace_size(&mut self, trace_size: usize) {
+        self.trace_size = trace_size;
+        while self.trace.len() > trace_size {
+            self.trace.pop_front();
+        }
+    }
+
+    /// The last executed instructions, oldest first. Instructions failing
+    /// to execute are included.
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        self.trace.iter().cloned().collect()
+    }
+
+    fn record_trace(
+        &mut self,
+        program_counter: usize,
+        instruction: Instruction,
+        registries: [u8; REGISTRY_COUNT],
+        address_register: usize,
+    ) {
+        if self.trace_size == 0 {
+            return;
+        }
+        let registers = registries
+            .iter()
+            .zip(self.registries)
+            .enumerate()
+            .filter(|(_, (before, after))| *before != after)
+            .map(|(register, (before, after))| RegisterDelta {
+                register: register as u8,
+                before: *before,
+                after,
+            })
+            .collect();
+        if self.trace.len() == self.trace_size {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(TraceEntry {
+            program_counter,
+            opcode: u16::from_be_bytes([
+                self.memory[program_counter],
+                self.memory[program_counter + 1],
+            ]),
+            instruction,
+            registers,
+            address_register: (address_register != self.address_register)
+                .then_some((address_register, self.address_register)),
+        });
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn ExecutionObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    /// Calls every observer with the emulator. Observers attached while
+    /// notifying are kept, but not notified.
+    fn notify<F: FnMut(&mut dyn ExecutionObserver, &mut Emulator)>(&mut self, mut notify: F) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let mut observers = core::mem::take(&mut self.observers);
+        for observer in observers.iter_mut() {
+            notify(observer.as_mut(), self);
+        }
+        observers.append(&mut self.observers);
+        self.observers = observers;
+    }
+
+    /// Enables or disables profiling, enabling restarts the profile
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiler = profiling.then(|| Profiler::new(self.memory.len()));
+    }
+
+    /// Enables or disables coverage tracking, enabling restarts it
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = coverage.then(|| Coverage::new(self.memory.len()));
+    }
+
+    /// Coverage of the loaded program since tracking was enabled or the
+    /// emulator reset, none if tracking is disabled
+    pub fn coverage_report(&self) -> Option<CoverageReport> {
+        let program = self.program.start..self.program.end.min(self.memory.len());
+        self.coverage.as_ref().map(|c| c.report(program))
+    }
+
+    /// Takes the lines printed by the `debug` instruction so far: the
+    /// registers with `debug 1`, the zero terminated string at I with
+    /// `debug 2` and V0 with `debug 3`
+    pub fn take_debug_output(&mut self) -> Vec<String> {
+        self.debug_output.drain(..).collect()
+    }
+
+    /// The pacing since the reset
+    pub fn stats(&self) -> Stats {
+        self.stats.report(self.instructions)
+    }
+
+    /// The statistics since profiling was enabled or the emulator reset,
+    /// none if profiling is disabled
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profiler.as_ref().map(Profiler::report)
+    }
+
+    /// The breakpoint stopping the instruction at the program counter
+    fn breakpoint(&self, instruction: &Instruction) -> Option<Breakpoint> {
+        let address = self.program_counter;
+        if self.breakpoints.contains(&address) {
+            return Some(Breakpoint::Address(address));
+        }
+        let mnemonic = instruction.info().mnemonic;
+        self.instruction_breakpoints
+            .get(mnemonic)
+            .map(|mnemonic| Breakpoint::Instruction(address, mnemonic))
+    }
+
+    /// Replaces the audio sink, the new sink is started if the sound timer
+    /// is running
+    pub fn set_audio_sink(&mut self, audio_sink: Option<Box<dyn AudioSink>>) {
+        self.audio_sink = audio_sink;
+        self.sound_playing = false;
+        self.update_audio();
+    }
+
+    /// Starts or stops the audio sink when the sound timer changed between
+    /// running and zero
+    fn update_audio(&mut self) {
+        let playing = self.sound_timer > 0;
+        if playing == self.sound_playing {
+            return;
+        }
+        self.sound_playing = playing;
+        if let Some(sink) = &mut self.audio_sink {
+            if playing {
+                sink.start();
+            } else {
+                sink.stop();
+            }
+        }
+    }
+
+    /// Loads the font sprites which should be available, the default
+    /// sprites unless a custom font is configured, followed by the large
+    /// font.
+    /// These are placed in the 0x00-0x1FF range
+    fn load_default_sprites(&mut self) {
+        for (offset, sprite) in self.font.iter().enumerate() {
+            for (i, item) in sprite.iter().enumerate() {
+                self.memory[DEFAULT_SPRITE_START_ADDR + (offset * 5) + i] = *item;
+            }
+        }
+        for (offset, sprite) in self.large_font.iter().enumerate() {
+            let start = LARGE_SPRITE_START_ADDR + offset * 10;
+            self.memory[start..start + 10].copy_from_slice(sprite);
+        }
+    }
+
+    /// A copy of the bytes, failing if they reach outside of memory
+    pub fn copy_bytes(&self, start: usize, amount: usize) -> Result<Vec<u8>, EmulatorError> {
+        Ok(self.read_memory(start..start + amount)?.to_vec())
+    }
+
+    /// Resets the emulator and loads the whole program read from the
+    /// reader at the start address, [START_ADDR] unless configured with
+    /// [Builder::with_start_addr]
+    #[cfg(feature = "std")]
+    pub fn load<T: Read>(&mut self, mut reader: T) -> Result<(), EmulatorError> {
+        let mut rom = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut rom) {
+            error!(error = ?e, "failed to load bytes into emulator memory");
+            return Err(e.into());
+        }
+        self.load_rom_at(&rom, self.start_addr)
+    }
+
+    /// Resets the emulator and loads the program at the address, where the
+    /// execution starts. Some programs expect another start address than
+    /// [START_ADDR], e.g. 0x600 on the ETI 660.
+    pub fn load_rom_at(&mut self, rom: &[u8], address: usize) -> Result<(), EmulatorError> {
+        let max = self.memory.len().saturating_sub(address);
+        if rom.len() > max {
+            error!(size = rom.len(), max, "program doesn't fit in memory");
+            return Err(EmulatorError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.reset();
+        self.write_memory(address, rom)?;
+        self.set_pc(address)?;
+        self.program = address..address + rom.len();
+        self.rom = rom.to_vec();
+        self.rom_address = address;
+        for (address, bytes) in core::mem::take(&mut self.segments) {
+            self.load_segment(address, bytes)?;
+        }
+        self.rom_hash = fnv1a(rom.iter().copied());
+        #[cfg(feature = "std")]
+        {
+            self.rom_info = Some(RomInfo::new(rom));
+        }
+        debug!(
+            bytes = rom.len(),
+            address, "loaded bytes into emulator memory"
+        );
+        Ok(())
+    }
+
+    /// The bytes of memory in the range, failing if it reaches outside of
+    /// memory
+    pub fn read_memory(&self, range: Range<usize>) -> Result<&[u8], EmulatorError> {
+        if range.end > self.memory.len() {
+            return Err(EmulatorError::InvalidAddress(range.end));
+        }
+        self.memory
+            .get(range.clone())
+            .ok_or(EmulatorError::InvalidAddress(range.start))
+    }
+
+    /// Resets the emulator and loads the last loaded program and segments
+    /// again, as they were before executing
+    pub fn restart(&mut self) -> Result<(), EmulatorError> {
+        let rom = core::mem::take(&mut self.rom);
+        self.load_rom_at(&rom, self.rom_address)
+    }
+
+    /// Writes the bytes at the address, anywhere in memory, and again after
+    /// every program loaded later, so they overlay the program. Execution in
+    /// segments isn't treated as outside of the program, see
+    /// [MemoryProtection].
+    pub fn load_segment(
+        &mut self,
+        address: usize,
+        bytes: impl Into<Vec<u8>>,
+    ) -> Result<(), EmulatorError> {
+        let bytes = bytes.into();
+        self.write_memory(address, &bytes)?;
+        debug!(bytes = bytes.len(), address, "loaded segment into memory");
+        self.segments.push((address, bytes));
+        Ok(())
+    }
+
+    /// Removes the segments from later loads, memory is left as is
+    pub fn clear_segments(&mut self) {
+        self.segments.clear();
+    }
+
+    /// Whether the address is in the loaded program or a segment
+    fn in_program(&self, address: usize) -> bool {
+        self.program.contains(&address)
+            || self
+                .segments
+                .iter()
+                .any(|(start, bytes)| (*start..start + bytes.len()).contains(&address))
+    }
+
+    /// Writes the bytes starting at the address, nothing is written if they
+    /// don't fit in memory
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) -> Result<(), EmulatorError> {
+        let end = address + bytes.len();
+        if end > self.memory.len() {
+            return Err(EmulatorError::InvalidAddress(end));
+        }
+        self.memory[address..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Sets the V register
+    pub fn set_register(&mut self, register: u4, value: u8) {
+        self.registries[register.value() as usize] = value;
+    }
+
+    /// Sets the I register
+    pub fn set_address_register(&mut self, address: usize) -> Result<(), EmulatorError> {
+        if address >= self.memory.len() {
+            return Err(EmulatorError::InvalidAddress(address));
+        }
+        self.address_register = address;
+        Ok(())
+    }
+
+    /// Continues the execution at the address, which must hold a whole
+    /// opcode
+    pub fn set_pc(&mut self, address: usize) -> Result<(), EmulatorError> {
+        if address + 1 >= self.memory.len() {
+            return Err(EmulatorError::InvalidAddress(address));
+        }
+        self.program_counter = address;
+        self.wait_for_key = None;
+        self.pressed_key = None;
+        self.display_wait = None;
+        self.resume_address = None;
+        Ok(())
+    }
+
+    /// Applies the memory protection to a violation
+    fn protect(&self, violation: EmulatorError) -> Result<(), EmulatorError> {
+        match self.memory_protection {
+            MemoryProtection::Off => Ok(()),
+            MemoryProtection::Warn => {
+                warn!(%violation, pc = self.program_counter, "memory protection");
+                Ok(())
+            }
+            MemoryProtection::Error => Err(violation),
+        }
+    }
+
+    /// Applies the [StackFault] policy, whether the stack pointer wraps
+    fn stack_fault(&mut self, fault: EmulatorError) -> Result<bool, EmulatorError> {
+        match self.stack_fault {
+            StackFault::Error => Err(fault),
+            StackFault::Wrap => {
+                warn!(%fault, pc = self.program_counter, "wrapping the stack");
+                Ok(true)
+            }
+            StackFault::Hook => {
+                self.notify(|o, e| o.on_stack_fault(e, &fault));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Checks a write of `length` bytes at I
+    fn protect_write(&self, length: usize) -> Result<(), EmulatorError> {
+        if length > 0 && self.address_register < START_ADDR {
+            return self.protect(EmulatorError::ProtectedWrite(self.address_register));
+        }
+        Ok(())
+    }
+
+    /// The bytes of memory
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Skips the next instruction, a long load is skipped along with its
+    /// address
+    fn skip(&mut self) {
+        let long = LONG_LOAD_OPCODE.to_be_bytes();
+        if self
+            .memory
+            .get(self.program_counter..self.program_counter + 2)
+            == Some(&long)
+        {
+            self.program_counter += 4;
+        } else {
+            self.program_counter += 2;
+        }
+    }
+
+    fn instruction(&self) -> Result<Instruction, EmulatorError> {
+        // Expecting big endian
+        let opcode = self.read_memory(self.program_counter..self.program_counter + 2)?;
+        let (big, little) = (opcode[0], opcode[1]);
+        let instruction = Instruction::from_opcode_u8(big, little);
+        let instruction = if let Some(i) = instruction {
+            i
+        } else if u16::from_be_bytes([big, little]) == LONG_LOAD_OPCODE {
+            Instruction::Raw(LONG_LOAD_OPCODE)
+        } else {
+            return Err(EmulatorError::InvalidOpcode(format!(
+                "0x{:02x}{:02x}",
+                big, little
+            )));
+        };
+        Ok(instruction)
+    }
+
+    /// Executes the next instruction, unless a breakpoint stops before it.
+    /// The tick after a stop executes the instruction.
+    pub fn tick(&mut self) -> Result<TickOutcome, EmulatorError> {
+        let span = self
+            .tick_tracing
+            .then(|| span!(Level::INFO, "emulator.tick"));
+        let _guard = span.as_ref().map(|span| span.enter());
+
+        if self.rewind.as_ref().is_some_and(Rewind::due) {
+            let checkpoint = self.checkpoint();
+            self.rewind.as_mut().unwrap().push(checkpoint);
+        }
+        self.replay_input();
+        let (time, keys, instructions) = (self.clock.now(), self.key_status, self.instructions);
+        let outcome = self.execute_tick();
+        self.apply_cheats();
+        if let Some(rewind) = &mut self.rewind {
+            rewind.record(TickRecord {
+                time,
+                keys,
+                stopped: matches!(outcome, Ok(TickOutcome::Stopped(_))),
+                executed: self.instructions != instructions,
+            });
+        }
+        outcome
+    }
+
+    /// The instructions executed by [Emulator::tick_frame], the hertz over
+    /// 60 rounded, at least one
+    pub fn instructions_per_frame(&self) -> usize {
+        ((self.hertz + 30) / 60).max(1)
+    }
+
+    /// Ticks through one 60 Hz frame, followed by exactly one decrement of
+    /// the timers. The ticks run on a virtual clock standing still within
+    /// the frame and advanced by a [FRAME_DURATION] after it, regardless of
+    /// the configured clock. Ends the frame early when a tick exits, stops
+    /// or fails, without decrementing the timers.
+    pub fn tick_frame(&mut self) -> Result<TickOutcome, EmulatorError> {
+        // Never behind the configured clock, so time only moves forward
+        let now = self.frame_time.max(self.clock.now());
+        let configured_clock = core::mem::replace(&mut self.clock, Box::new(FixedClock(now)));
+        // Timers only decrement at the end of the frame
+        for last in [
+            &mut self.last_delay_decrement,
+            &mut self.last_sound_decrement,
+        ] {
+            if last.is_some() {
+                *last = Some(now);
+            }
+        }
+        let mut outcome = Ok(TickOutcome::Continue);
+        for _ in 0..self.instructions_per_frame() {
+            outcome = self.tick();
+            if !matches!(
+                outcome,
+                Ok(TickOutcome::Continue | TickOutcome::WaitingForKey)
+            ) {
+                break;
+            }
+        }
+        self.clock = configured_clock;
+        if matches!(
+            outcome,
+            Ok(TickOutcome::Continue | TickOutcome::WaitingForKey)
+        ) {
+            self.decrement_frame_timers(now);
+        }
+        self.frame_time = now + FRAME_DURATION;
+        outcome
+    }
+
+    /// A tick after the input was replayed
+    fn execute_tick(&mut self) -> Result<TickOutcome, EmulatorError> {
+        self.stats
+            .tick(self.clock.now(), self.wait_for_key.is_some());
+        if let Some(regx) = self.wait_for_key {
+            match self.pressed_key {
+                Some(key) if self.key_status[key as usize] == KeyStatus::Up => {
+                    self.registries[regx as usize] = key;
+                    self.wait_for_key = None;
+                    self.pressed_key = None;
+                }
+                Some(_) => {}
+                None => {
+                    let pressed = self
+                        .key_status
+                        .iter()
+                        .position(|key| *key == KeyStatus::Pressed);
+                    match pressed {
+                        Some(key) if self.quirks.wait_for_release => {
+                            self.pressed_key = Some(key as u8)
+                        }
+                        Some(key) => {
+                            self.registries[regx as usize] = key as u8;
+                            self.wait_for_key = None;
+                        }
+                        None => {}
+                    }
+                }
+            }
+            self.decrement_timers();
+            return Ok(match self.wait_for_key {
+                Some(_) => TickOutcome::WaitingForKey,
+                None => TickOutcome::Continue,
+            });
+        }
+
+        if !self.in_program(self.program_counter) {
+            self.protect(EmulatorError::ExecutionOutOfBounds(self.program_counter))?;
+        }
+        let instruction = match self.instruction() {
+            Ok(i) => i,
+            Err(e) => {
+                debug!(
+                    pc = self.program_counter,
+                    error = ?e,
+                    "failed to parse instruction opcode"
+                );
+                return Err(e);
+            }
+        };
+        if self.resume_address.take() != Some(self.program_counter) {
+            if let Some(breakpoint) = self.breakpoint(&instruction) {
+                info!(?breakpoint, "stopped at breakpoint");
+                self.resume_address = Some(self.program_counter);
+                return Ok(TickOutcome::Stopped(breakpoint));
+            }
+        }
+        if self.waits_for_display(&instruction) {
+            // The breakpoint was already passed for this instruction
+            self.resume_address = Some(self.program_counter);
+            self.decrement_timers();
+            return Ok(TickOutcome::Continue);
+        }
+        let address = self.program_counter;
+        let (registries, address_register) = (self.registries, self.address_register);
+        self.program_counter += 2;
+
+        self.notify(|o, e| o.before_execute(e, address, instruction));
+        let started = self.profiler.as_ref().map(|_| self.clock.now());
+        let result = self.execute(instruction);
+        self.instructions += 1;
+        if let (Some(profiler), Some(started)) = (&mut self.profiler, started) {
+            profiler.record(address, started, self.clock.now());
+        }
+        if let Some(coverage) = &mut self.coverage {
+            let size = match instruction {
+                Instruction::Raw(LONG_LOAD_OPCODE) => 4,
+                _ => 2,
+            };
+            coverage.executed(address..address + size);
+        }
+        self.record_trace(address, instruction, registries, address_register);
+        let outcome = match result {
+            Err(e) => {
+                debug!(
+                    pc = self.program_counter,
+                    error = ?e,
+                    "failed to execute instruction"
+                );
+                Err(e)
+            }
+            res => res,
+        }?;
+        self.notify(|o, e| o.after_execute(e, address, instruction));
+
+        self.decrement_timers();
+        Ok(outcome)
+    }
+
+    /// Whether the draw has to wait for the next timer frame, see
+    /// [Quirks::display_wait]
+    fn waits_for_display(&mut self, instruction: &Instruction) -> bool {
+        if !self.quirks.display_wait
+            || !matches!(
+                instruction,
+                Instruction::Draw(..) | Instruction::DrawLarge(..)
+            )
+        {
+            return false;
+        }
+        let frame = (self.clock.now().as_micros() / FRAME_DURATION.as_micros()) as u64;
+        match self.display_wait {
+            None => {
+                self.display_wait = Some(frame);
+                true
+            }
+            Some(waiting) if frame <= waiting => true,
+            Some(_) => {
+                self.display_wait = None;
+                false
+            }
+        }
+    }
+
+    /// Ticks without a thread until the limit is reached or the execution
+    /// stops
+    pub fn run_for(&mut self, limit: RunLimit) -> ExecutionReport {
+        self.run_headless(Some(limit), |_| false)
+    }
+
+    /// Ticks without a thread until the condition holds after a tick, or
+    /// the execution stops. A program looping forever never returns, see
+    /// [Emulator::run_for].
+    pub fn run_until<F: FnMut(&Emulator) -> bool>(&mut self, condition: F) -> ExecutionReport {
+        self.run_headless(None, condition)
+    }
+
+    fn run_headless<F: FnMut(&Emulator) -> bool>(
+        &mut self,
+        limit: Option<RunLimit>,
+        mut condition: F,
+    ) -> ExecutionReport {
+        #[cfg(feature = "std")]
+        let elapsed = {
+            let start = Instant::now();
+            move |_: &Self| start.elapsed()
+        };
+        #[cfg(not(feature = "std"))]
+        let elapsed = {
+            let start = self.clock.now();
+            move |emulator: &Self| emulator.clock.now().saturating_sub(start)
+        };
+        let instructions = self.instructions;
+        let mut ticks = 0;
+        let stop_reason = loop {
+            let reached = match limit {
+                Some(RunLimit::Ticks(limit)) => ticks >= limit,
+                Some(RunLimit::Duration(limit)) => elapsed(self) >= limit,
+                None => false,
+            };
+            if reached {
+                break StopReason::Limit;
+            }
+            ticks += 1;
+            match self.tick() {
+                Ok(TickOutcome::Continue) => {}
+                Ok(TickOutcome::Exit) => break StopReason::Exit,
+                Ok(TickOutcome::Stopped(breakpoint)) => break StopReason::Breakpoint(breakpoint),
+                Ok(TickOutcome::WaitingForKey) => break StopReason::WaitingForKey,
+                Err(error) => break StopReason::Error(error),
+            }
+            if condition(self) {
+                break StopReason::Condition;
+            }
+        };
+        ExecutionReport {
+            instructions: self.instructions - instructions,
+            ticks,
+            elapsed: elapsed(self),
+            stop_reason,
+            snapshot: self.create_snapshot(),
+        }
+    }
+
+    /// Stores X - Y in the target, the flag is 1 unless it borrowed
+    fn sub_regs(&mut self, target: u4, regx: u4, regy: u4) {
+        let vx = self.registries[regx.value() as usize];
+        let vy = self.registries[regy.value() as usize];
+        self.registries[target.value() as usize] = vx.wrapping_sub(vy);
+        self.registries[0xF_usize] = if vx >= vy { 1 } else { 0 };
+    }
+
+    /// The value shifted by `shr` and `shl`, see [Quirks::shift_in_place]
+    fn shifted(&self, regx: u4, regy: u4) -> u8 {
+        let source = if self.quirks.shift_in_place {
+            regx
+        } else {
+            regy
+        };
+        self.registries[source.value() as usize]
+    }
+
+    /// Executes the instruction, the program counter is expected to have
+    /// moved past it
+    pub fn execute(&mut self, instruction: Instruction) -> Result<TickOutcome, EmulatorError> {
+        if self.tick_tracing {
+            debug!(instruction = ?instruction, "executing instruction");
+        }
+        if matches!(
+            instruction,
+            Instruction::Clear
+                | Instruction::Draw(..)
+                | Instruction::DrawLarge(..)
+                | Instruction::LowRes
+                | Instruction::HighRes
+                | Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+        ) {
+            self.graphics_frame = self.graphics_frame.wrapping_add(1);
+            self.stats.drawn(self.clock.now());
+        }
+        match instruction {
+            Instruction::Exit => {
+                // Kill execution
+                return Ok(TickOutcome::Exit);
+            }
+            Instruction::Clear => {
+                self.graphics.clear(self.selected_planes);
+            }
+            Instruction::Return => {
+                if self.stack_pointer == 0 {
+                    match self.stack_fault(EmulatorError::StackEmpty)? {
+                        true => self.stack_pointer = STACK_SIZE,
+                        false => return Ok(TickOutcome::Continue),
+                    }
+                }
+                self.stack_pointer -= 1;
+                self.program_counter = self.stack[self.stack_pointer];
+            }
+            Instruction::Call(addr) => {
+                if self.stack_pointer >= STACK_SIZE {
+                    let stack = self.stack[..self.stack_pointer].to_vec();
+                    match self.stack_fault(EmulatorError::StackFull(stack))? {
+                        true => self.stack_pointer = 0,
+                        false => return Ok(TickOutcome::Continue),
+                    }
+                }
+                self.stack[self.stack_pointer] = self.program_counter;
+                self.stack_pointer += 1;
+                self.program_counter = addr.value() as usize;
+            }
+            Instruction::Jump(addr) => {
+                self.program_counter = addr.value() as usize;
+            }
+            Instruction::SkipEqual(register, value) => {
+                let index = register.value() as usize;
+                if self.registries[index] == value {
+                    self.skip();
+                }
+            }
+            Instruction::SkipNotEqual(register, value) => {
+                let index = register.value() as usize;
+                if self.registries[index] != value {
+                    self.skip();
+                }
+            }
+            Instruction::SkipRegistersEqual(regx, regy) => {
+                if self.registries[regx.value() as usize] == self.registries[regy.value() as usize]
+                {
+                    self.skip();
+                }
+            }
+            Instruction::SetRegisterByte(register, value) => {
+                self.registries[register.value() as usize] = value;
+            }
+            Instruction::SetRegisterRegister(regx, regy) => {
+                self.registries[regx.value() as usize] = self.registries[regy.value() as usize];
+            }
+            Instruction::Add(register, value) => {
+                // Wraps without touching VF, unlike the register addition
+                let current = self.registries[register.value() as usize];
+                self.registries[register.value() as usize] = current.wrapping_add(value);
+            }
+            Instruction::Or(regx, regy) => {
+                let vx = self.registries[regx.value() as usize];
+                let vy = self.registries[regy.value() as usize];
+                self.registries[regx.value() as usize] = vx | vy;
+            }
+            Instruction::And(regx, regy) => {
+                let vx = self.registries[regx.value() as usize];
+                let vy = self.registries[regy.value() as usize];
+                self.registries[regx.value() as usize] = vx & vy;
+            }
+            Instruction::Xor(regx, regy) => {
+                let vx = self.registries[regx.value() as usize];
+                let vy = self.registries[regy.value() as usize];
+                self.registries[regx.value() as usize] = vx ^ vy;
+            }
+            Instruction::AddChecked(regx, regy) => {
+                let vx = self.registries[regx.value() as usize];
+                let vy = self.registries[regy.value() as usize];
+                // u8::overflowing_add wraps the value, which isn't what I want
+                // here? 1111 1111 + 0000 0001 = 1111 1110 is what's expected,
+                // and according to rust docs overflowing_add == 0 in that case
+                let result = (vx as u16) + (vy as u16);
+                let vf = if result & 0xFF00 >= 1 { 1 } else { 0 };
+                self.registries[0xF_usize] = vf;
+                self.registries[regx.value() as usize] = (result & 0x00FF) as u8;
+            }
+            Instruction::SubChecked(regx, regy) => {
+                self.sub_regs(regx, regx, regy);
+            }
+            Instruction::ShiftRight(regx, regy) => {
+                let value = self.shifted(regx, regy);
+                self.registries[regx.value() as usize] = value >> 1;
+                self.registries[0xF_usize] = value & 0x01;
+            }
+            Instruction::SubNChecked(regx, regy) => {
+                self.sub_regs(regx, regy, regx);
+            }
+            Instruction::ShiftLeft(regx, regy) => {
+                let value = self.shifted(regx, regy);
+                self.registries[regx.value() as usize] = value << 1;
+                self.registries[0xF_usize] = value >> 7;
+            }
+            Instruction::SkipRegistersNotEqual(regx, regy) => {
+                let vx = self.registries[regx.value() as usize];
+                let vy = self.registries[regy.value() as usize];
+                if vx != vy {
+                    self.skip();
+                }
+            }
+            Instruction::SetMemRegister(addr) => {
+                self.address_register = addr.value() as usize;
+            }
+            Instruction::Raw(LONG_LOAD_OPCODE) => {
+                let address = self.read_memory(self.program_counter..self.program_counter + 2)?;
+                let address = u16::from_be_bytes([address[0], address[1]]) as usize;
+                self.set_address_register(address)?;
+                self.program_counter += 2;
+            }
+            Instruction::JumpOffset(addr) => {
+                let v0 = self.registries[0] as usize;
+                self.program_counter = (addr.value() as usize) + v0;
+            }
+            Instruction::Randomize(reg, value) => {
+                let rb = self.rng.next_u32().to_be_bytes().first().copied();
+                let rb = match rb {
+                    Some(b) => b,
+                    None => {
+                        error!("failed to randomize byte, using default value of 0xFF");
+                        0xFF
+                    }
+                };
+                self.registries[reg.value() as usize] = rb & value;
+            }
+            Instruction::Draw(regx, regy, n) => {
+                let x = self.registries[regx.value() as usize] as usize;
+                let y = self.registries[regy.value() as usize] as usize;
+                let rows = n.value() as usize;
+                let collision = self.draw_planes(x, y, rows, 8)?;
+                self.registries[0x0F_usize] = collision as u8;
+            }
+            Instruction::SkipKeyPressed(regx) => {
+                if self.key_status[regx.value() as usize] == KeyStatus::Pressed {
+                    self.skip();
+                }
+            }
+            Instruction::SkipKeyNotPressed(regx) => {
+                if self.key_status[regx.value() as usize] == KeyStatus::Up {
+                    self.skip();
+                }
+            }
+            Instruction::WaitForKey(regx) => {
+                self.wait_for_key = Some(regx.value());
+                self.notify(|o, e| o.on_key_wait(e, regx));
+                return Ok(TickOutcome::WaitingForKey);
+            }
+            Instruction::SetMemRegisterDefaultSprit(regx) => {
+                // Only the low nibble selects a digit, as with ldhf
+                let hex_digit = self.registries[regx.value() as usize] & 0x0F;
+                // sprites are sequential, 0 -> F, and always 5 bytes. Just calculate
+                // the offset from the start location
+                self.address_register = DEFAULT_SPRITE_START_ADDR + ((hex_digit as usize) * 5);
+            }
+            Instruction::SetMemRegisterLargeSprite(regx) => {
+                let hex_digit = self.registries[regx.value() as usize] & 0x0F;
+                self.address_register = LARGE_SPRITE_START_ADDR + (hex_digit as usize) * 10;
+            }
+            Instruction::SetRegisterDelayTimer(regx) => {
+                self.registries[regx.value() as usize] = self.delay_timer;
+            }
+            Instruction::SetDelayTimer(regx) => {
+                self.delay_timer = self.registries[regx.value() as usize];
+            }
+            Instruction::SetSoundTimer(regx) => {
+                self.sound_timer = self.registries[regx.value() as usize];
+                self.update_audio();
+            }
+            Instruction::AddMemReg(regx) => {
+                self.address_register += self.registries[regx.value() as usize] as usize;
+            }
+            Instruction::SetBcd(regx) => {
+                let value = self.registries[regx.value() as usize];
+                let hundred = value / 100_u8;
+                let ten = (value % 100) / 10;
+                let one = value % 10;
+                self.protect_write(3)?;
+                self.write_memory(self.address_register, &[hundred, ten, one])?;
+            }
+            Instruction::MemWrite(regx) => {
+                let count = regx.value() as usize + 1;
+                self.protect_write(count)?;
+                let registries = self.registries;
+                self.write_memory(self.address_register, &registries[..count])?;
+            }
+            Instruction::MemRead(regx) => {
+                let range =
+                    self.address_register..self.address_register + regx.value() as usize + 1;
+                let bytes = self.read_memory(range.clone())?.to_vec();
+                self.registries[..bytes.len()].copy_from_slice(&bytes);
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.read(range);
+                }
+            }
+            Instruction::Debug(value) => {
+                let msg = match value.value() {
+                    1 => format!("{:?}", self.registries),
+                    // A zero terminated string at I
+                    2 => {
+                        let start = self.address_register.min(self.memory.len());
+                        let bytes = &self.memory[start..];
+                        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                        String::from_utf8_lossy(&bytes[..end]).into_owned()
+                    }
+                    3 => self.registries[0].to_string(),
+                    _ => return Ok(TickOutcome::Continue),
+                };
+                info!(source = "debug-instruction", msg);
+                if self.debug_output.len() == DEBUG_OUTPUT_LINES {
+                    self.debug_output.pop_front();
+                }
+                self.debug_output.push_back(msg);
+            }
+            Instruction::Breakpoint => {
+                let address = self.program_counter.saturating_sub(2);
+                return Ok(TickOutcome::Stopped(Breakpoint::Opcode(address)));
+            }
+            Instruction::Raw(opcode) => {
+                return Err(EmulatorError::InvalidOpcode(format!("0x{:04x}", opcode)));
+            }
+            Instruction::Custom(_) => return Err(EmulatorError::UnimplementedInstruction),
+            Instruction::Halt => return Ok(TickOutcome::Exit),
+            // A 16x16 sprite of 2 bytes per row in high resolution. In low
+            // resolution this draws 0 rows, as on the original interpreter
+            Instruction::DrawLarge(regx, regy) => {
+                if self.graphics.resolution == Resolution::Low {
+                    return self.execute(Instruction::Draw(regx, regy, 0.into()));
+                }
+                let x = self.registries[regx.value() as usize] as usize;
+                let y = self.registries[regy.value() as usize] as usize;
+                let collision = self.draw_planes(x, y, 16, 16)?;
+                self.registries[0x0F_usize] = collision as u8;
+            }
+            // Switching resolution clears the display
+            Instruction::LowRes => self.graphics = FrameBuffer::new(Resolution::Low),
+            Instruction::HighRes => self.graphics = FrameBuffer::new(Resolution::High),
+            Instruction::ScrollDown(rows) => {
+                let rows = rows.value() as usize;
+                self.graphics.scroll_down(self.selected_planes, rows);
+            }
+            Instruction::ScrollRight => self.graphics.scroll_horizontal(self.selected_planes, 4),
+            Instruction::ScrollLeft => self.graphics.scroll_horizontal(self.selected_planes, -4),
+            Instruction::SelectPlane(planes) => self.selected_planes = planes.value(),
+            Instruction::SaveFlags(regx) => {
+                let count = regx.value() as usize + 1;
+                self.flags[..count].copy_from_slice(&self.registries[..count]);
+            }
+            Instruction::LoadFlags(regx) => {
+                let count = regx.value() as usize + 1;
+                self.registries[..count].copy_from_slice(&self.flags[..count]);
+            }
+        };
+        Ok(TickOutcome::Continue)
+    }
+
+    /// Draws a sprite at I onto each selected plane, the sprites of the
+    /// planes follow each other in memory. Returns whether any pixel was
+    /// turned off, failing if a sprite reaches outside of memory.
+    fn draw_planes(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: usize,
+        width: usize,
+    ) -> Result<bool, EmulatorError> {
+        let bytes_per_row = width / 8;
+        let size = rows * bytes_per_row;
+        let planes: Vec<usize> = selected(self.selected_planes).collect();
+        // Checked before drawing, so a failing draw changes nothing
+        let start = self.address_register;
+        let range = start..start + size * planes.len();
+        let sprites = self.read_memory(range.clone())?.to_vec();
+        if let Some(coverage) = &mut self.coverage {
+            coverage.read(range);
+        }
+        let mut collision = false;
+        for (plane, bytes) in planes.into_iter().zip(sprites.chunks(size.max(1))) {
+            let sprite: Vec<u16> = bytes
+                .chunks(bytes_per_row)
+                .map(|row| match row {
+                    [big, little] => u16::from_be_bytes([*big, *little]),
+                    [big] => (*big as u16) << 8,
+                    _ => 0,
+                })
+                .collect();
+            collision |= self.draw_sprite(plane, x, y, &sprite, width);
+        }
+        self.notify(|o, e| o.on_draw(e, x, y, collision));
+        Ok(collision)
+    }
+
+    /// XORs the sprite onto the plane, the rows are `width` pixels wide
+    /// with the most significant bit leftmost. Returns whether any pixel
+    /// was turned off.
+    fn draw_sprite(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        sprite: &[u16],
+        width: usize,
+    ) -> bool {
+        let mut collision = false;
+        let (display_width, display_height) = (self.graphics.width(), self.graphics.height());
+        let x = x % display_width;
+        let y = y % display_height;
+        for (row, bits) in sprite.iter().enumerate() {
+            let py = match (y + row, self.draw_mode) {
+                (py, DrawMode::Clip) if py >= display_height => break,
+                (py, _) => py % display_height,
+            };
+            for column in 0..width {
+                if bits & (0x8000 >> column) == 0 {
+                    continue;
+                }
+                let px = match (x + column, self.draw_mode) {
+                    (px, DrawMode::Clip) if px >= display_width => break,
+                    (px, _) => px % display_width,
+                };
+                collision |= self.graphics.toggle(plane, px, py);
+            }
+        }
+        collision
+    }
+
+    /// Decrements the timers once, see [Emulator::tick_frame]
+    fn decrement_frame_timers(&mut self, now: Duration) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+            self.last_delay_decrement = (self.delay_timer > 0).then_some(now);
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+            self.last_sound_decrement = (self.sound_timer > 0).then_some(now);
+            self.update_audio();
+        }
+    }
+
+    /// Decrement timers at a rate of 60hz, when a timer reaches
+    /// zero this does nothing. The upper bound is 1 decrement per instruction
+    /// execution
+    fn decrement_timers(&mut self) {
+        let now = self.clock.now();
+        if self.delay_timer > 0 {
+            if let Some(last_delay_decrement) = self.last_delay_decrement {
+                // Saturating, a frame may have run ahead of the clock
+                if now.saturating_sub(last_delay_decrement).as_micros() > TIME_BETWEEN_DECREMENT {
+                    self.delay_timer -= 1;
+                    self.last_delay_decrement = Some(now);
+                }
+
+                if self.delay_timer == 0 {
+                    // Reached zero now, remove last delay decrement
+                    self.last_delay_decrement = None;
+                }
+            } else {
+                // Just started a delay timer, start keeping track of time
+                self.last_delay_decrement = Some(now);
+            }
+        }
+        if self.sound_timer > 0 {
+            if let Some(last_sound_decrement) = self.last_sound_decrement {
+                if now.saturating_sub(last_sound_decrement).as_micros() > TIME_BETWEEN_DECREMENT {
+                    self.sound_timer -= 1;
+                    self.last_sound_decrement = Some(now);
+                }
+
+                if self.sound_timer == 0 {
+                    self.last_sound_decrement = None;
+                    self.update_audio();
+                }
+            } else {
+                self.last_sound_decrement = Some(now);
+            }
+        }
+    }
+
+    pub fn copy_graphics_buffer(&self) -> FrameBuffer {
+        self.graphics
+    }
+
+    /// See [FrameBuffer::content_hash]
+    pub fn framebuffer_hash(&self) -> u64 {
+        self.graphics.content_hash()
+    }
+
+    /// Changes whenever an instruction, reset or restored state may have
+    /// changed the graphics, so frontends can skip copying unchanged frames
+    pub fn graphics_frame(&self) -> u64 {
+        self.graphics_frame
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.graphics.resolution
+    }
+
+    /// Serializes the machine state, behind [SAVE_STATE_MAGIC] and the
+    /// format version
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let state = SaveState {
+            memory: self.memory.to_vec(),
+            registries: self.registries,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            address_register: self.address_register,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack,
+            flags: self.flags,
+            key_status: self.key_status,
+            wait_for_key: self.wait_for_key,
+            pressed_key: self.pressed_key,
+            resolution: self.graphics.resolution,
+            planes: self.graphics.planes.iter().map(|p| p.to_vec()).collect(),
+            selected_planes: self.selected_planes,
+        };
+        let mut bytes = SAVE_STATE_MAGIC.to_vec();
+        bytes.extend(SAVE_STATE_VERSION.to_be_bytes());
+        // Only fails for maps with non-string keys
+        bytes.extend(serde_json::to_vec(&state).unwrap());
+        bytes
+    }
+
+    /// Restores a state written by [Emulator::save_state]. The timers start
+    /// counting down from their restored values.
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), EmulatorError> {
+        let invalid = |reason: &str| EmulatorError::InvalidSaveState(reason.to_string());
+        let payload = bytes
+            .strip_prefix(SAVE_STATE_MAGIC)
+            .ok_or_else(|| invalid("missing header"))?;
+        let (version, payload) = match payload {
+            [big, little, payload @ ..] => (u16::from_be_bytes([*big, *little]), payload),
+            _ => return Err(invalid("missing version")),
+        };
+        if version != SAVE_STATE_VERSION {
+            return Err(EmulatorError::UnsupportedSaveStateVersion(version));
+        }
+        let state: SaveState =
+            serde_json::from_slice(payload).map_err(|e| invalid(&e.to_string()))?;
+        if state.memory.len() != self.memory.len() {
+            return Err(invalid("memory size"));
+        }
+        if state.planes.len() != PLANE_COUNT
+            || state.planes.iter().any(|p| p.len() != GRAPHICS_BUFFER_SIZE)
+        {
+            return Err(invalid("display size"));
+        }
+        if state.stack_pointer > STACK_SIZE {
+            return Err(invalid("stack pointer"));
+        }
+        if state.program_counter >= self.memory.len() {
+            return Err(invalid("program counter"));
+        }
+        if state.address_register >= self.memory.len() {
+            return Err(invalid("address register"));
+        }
+        if state
+            .wait_for_key
+            .is_some_and(|regx| regx as usize >= REGISTRY_COUNT)
+        {
+            return Err(invalid("register waiting for a key"));
+        }
+        if state
+            .pressed_key
+            .is_some_and(|key| key as usize >= KEY_COUNT)
+        {
+            return Err(invalid("pressed key"));
+        }
+
+        self.memory.copy_from_slice(&state.memory);
+        self.registries = state.registries;
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.address_register = state.address_register;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.stack = state.stack;
+        self.flags = state.flags;
+        self.key_status = state.key_status;
+        self.wait_for_key = state.wait_for_key;
+        self.pressed_key = state.pressed_key;
+        self.resume_address = None;
+        self.graphics.resolution = state.resolution;
+        self.graphics_frame = self.graphics_frame.wrapping_add(1);
+        for (plane, bytes) in self.graphics.planes.iter_mut().zip(&state.planes) {
+            plane.copy_from_slice(bytes);
+        }
+        self.selected_planes = state.selected_planes;
+        self.last_delay_decrement = None;
+        self.last_sound_decrement = None;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        self.update_audio();
+        Ok(())
+    }
+
+    /// Undoes the last executed instruction, by restoring the nearest
+    /// checkpoint and executing the ticks since again with their clock
+    /// times and keys. Returns false without history to step back into,
+    /// see [Builder::with_rewind].
+    ///
+    /// The observers and profiler don't see the executed ticks again, and
+    /// changes made outside of the ticks, e.g. [Emulator::write_memory],
+    /// aren't replayed.
+    pub fn step_back(&mut self) -> bool {
+        let Some(mut rewind) = self.rewind.take() else {
+            return false;
+        };
+        let stepped = self.rewind(&mut rewind);
+        self.rewind = Some(rewind);
+        stepped
+    }
+
+    fn rewind(&mut self, rewind: &mut Rewind) -> bool {
+        let Some(first) = rewind.checkpoints.front().map(|c| c.tick) else {
+            return false;
+        };
+        let Some(target) = rewind.ticks.iter().rposition(|t| t.executed) else {
+            return false;
+        };
+        let target = first + target as u64;
+        while rewind.checkpoints.back().is_some_and(|c| c.tick > target) {
+            rewind.checkpoints.pop_back();
+        }
+        let checkpoint = rewind.checkpoints.back().unwrap().clone();
+        let start = checkpoint.tick;
+        self.restore(checkpoint);
+
+        let system_clock =
+            core::mem::replace(&mut self.clock, Box::new(FixedClock(Duration::ZERO)));
+        let breakpoints = core::mem::take(&mut self.breakpoints);
+        let instruction_breakpoints = core::mem::take(&mut self.instruction_breakpoints);
+        let observers = core::mem::take(&mut self.observers);
+        let profiler = self.profiler.take();
+        let trace = core::mem::take(&mut self.trace);
+        let debug_output = core::mem::take(&mut self.debug_output);
+        for record in rewind
+            .ticks
+            .range((start - first) as usize..(target - first) as usize)
+        {
+            let now = self.clock.now().max(record.time);
+            self.clock = Box::new(FixedClock(now));
+            self.key_status = record.keys;
+            self.input_tick += 1;
+            // Stops where the tick stopped before, by the same resume logic
+            if record.stopped {
+                self.breakpoints.insert(self.program_counter);
+            }
+            // Failed before as well
+            let _ = self.execute_tick();
+            self.apply_cheats();
+            self.breakpoints.clear();
+        }
+        self.clock = system_clock;
+        self.breakpoints = breakpoints;
+        self.instruction_breakpoints = instruction_breakpoints;
+        self.observers = observers;
+        self.profiler = profiler;
+        self.trace = trace;
+        self.trace.pop_back();
+        self.debug_output = debug_output;
+
+        rewind.ticks.truncate((target - first) as usize);
+        rewind.tick = target;
+        self.graphics_frame = self.graphics_frame.wrapping_add(1);
+        self.update_audio();
+        info!(pc = self.program_counter, "stepped back");
+        true
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            tick: self.rewind.as_ref().map_or(0, |r| r.tick),
+            memory: self.memory.clone(),
+            registries: self.registries,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            address_register: self.address_register,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack,
+            flags: self.flags,
+            graphics: self.graphics,
+            selected_planes: self.selected_planes,
+            last_delay_decrement: self.last_delay_decrement,
+            last_sound_decrement: self.last_sound_decrement,
+            key_status: self.key_status,
+            wait_for_key: self.wait_for_key,
+            pressed_key: self.pressed_key,
+            display_wait: self.display_wait,
+            instructions: self.instructions,
+            rng: self.rng.clone(),
+            resume_address: self.resume_address,
+            stats: self.stats.clone(),
+            input_tick: self.input_tick,
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.memory = checkpoint.memory;
+        self.registries = checkpoint.registries;
+        self.program_counter = checkpoint.program_counter;
+        self.stack_pointer = checkpoint.stack_pointer;
+        self.address_register = checkpoint.address_register;
+        self.delay_timer = checkpoint.delay_timer;
+        self.sound_timer = checkpoint.sound_timer;
+        self.stack = checkpoint.stack;
+        self.flags = checkpoint.flags;
+        self.graphics = checkpoint.graphics;
+        self.selected_planes = checkpoint.selected_planes;
+        self.last_delay_decrement = checkpoint.last_delay_decrement;
+        self.last_sound_decrement = checkpoint.last_sound_decrement;
+        self.key_status = checkpoint.key_status;
+        self.wait_for_key = checkpoint.wait_for_key;
+        self.pressed_key = checkpoint.pressed_key;
+        self.display_wait = checkpoint.display_wait;
+        self.instructions = checkpoint.instructions;
+        self.rng = checkpoint.rng;
+        self.resume_address = checkpoint.resume_address;
+        self.stats = checkpoint.stats;
+        self.input_tick = checkpoint.input_tick;
+    }
+
+    /// A snapshot without the memory
+    pub fn create_snapshot(&self) -> Snapshot {
+        self.snapshot(false)
+    }
+
+    /// The state of the emulator failing with the error
+    pub fn crash_report(&self, error: &EmulatorError) -> CrashReport {
+        CrashReport {
+            error: error.to_string(),
+            snapshot: self.create_snapshot(),
+            trace: self.trace(),
+            rom_hash: self.rom_hash,
+            quirks: self.quirks,
+            memory_protection: self.memory_protection,
+            stack_fault: self.stack_fault,
+        }
+    }
+
+    /// Where the last crash report since the reset was written
+    #[cfg(feature = "std")]
+    pub fn crash_report_path(&self) -> Option<&Path> {
+        self.crash_report.as_deref()
+    }
+
+    /// Writes the report to a new file in the crash directory, if one is
+    /// configured
+    #[cfg(feature = "std")]
+    fn write_crash_report(&mut self, error: &EmulatorError) -> Option<PathBuf> {
+        let dir = self.crash_dir.as_ref()?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = dir.join(format!("chip8-crash-{}.json", millis));
+        let report = self.crash_report(error).to_json();
+        if let Err(error) = std::fs::write(&path, report + "\n") {
+            error!(%error, ?path, "failed to write crash report");
+            return None;
+        }
+        self.crash_report = Some(path.clone());
+        Some(path)
+    }
+
+    /// FNV-1a of the loaded program, as it was loaded
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Size, SHA-1 and likely platform of the loaded program, none until
+    /// a program is loaded
+    #[cfg(feature = "std")]
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    /// The profile applied when building, including the keymap and colors
+    /// left to frontends
+    #[cfg(feature = "std")]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// A snapshot of the state, copying the memory if included
+    pub fn snapshot(&self, include_memory: bool) -> Snapshot {
+        Snapshot {
+            registries: self.registries,
+            program_counter: self.program_counter,
+            address_register: self.address_register,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            key_status: self.key_status,
+            instruction: self.instruction().ok(),
+            memory: include_memory.then(|| self.memory.clone()),
+            graphics_frame: self.graphics_frame,
+        }
+    }
+
+    pub fn set_key(&mut self, key: u4, status: KeyStatus) {
+        info!(?key, ?status, "key event");
+        self.key_status[key.value() as usize] = status;
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(InputEvent {
+                tick: self.input_tick,
+                key,
+                status,
+            });
+        }
+    }
+
+    /// Records the key events from now on, replacing any earlier
+    /// recording. Without a seed the random number generator is reseeded
+    /// with a random one, which the recording keeps.
+    pub fn start_recording(&mut self) {
+        let seed = match self.seed {
+            Some(seed) => seed,
+            #[cfg(feature = "std")]
+            None => thread_rng().next_u64(),
+            #[cfg(not(feature = "std"))]
+            None => self.rng.next_u64(),
+        };
+        self.seed = Some(seed);
+        self.rng = StdRng::seed_from_u64(seed);
+        self.input_tick = 0;
+        self.recording = Some(InputRecording {
+            seed,
+            events: Vec::new(),
+        });
+    }
+
+    /// The key events recorded so far
+    pub fn recording(&self) -> Option<InputRecording> {
+        self.recording.clone()
+    }
+
+    pub fn stop_recording(&mut self) -> Option<InputRecording> {
+        self.recording.take()
+    }
+
+    /// Replays the key events of the recording from now on, with the
+    /// random number generator reseeded by its seed
+    pub fn replay(&mut self, recording: InputRecording) {
+        self.seed = Some(recording.seed);
+        self.rng = StdRng::seed_from_u64(recording.seed);
+        self.input_tick = 0;
+        self.replay = recording.events.into();
+    }
+
+    /// Whether key events are left to replay
+    pub fn is_replaying(&self) -> bool {
+        !self.replay.is_empty()
+    }
+
+    /// Applies the replayed key events of the current tick
+    fn replay_input(&mut self) {
+        while let Some(event) = self.replay.front().copied() {
+            if event.tick > self.input_tick {
+                break;
+            }
+            self.replay.pop_front();
+            self.set_key(event.key, event.status);
+        }
+        self.input_tick += 1;
+    }
+
+    pub fn key_pressed(&mut self, key: u4) {
+        self.set_key(key, KeyStatus::Pressed);
+    }
+
+    pub fn key_up(&mut self, key: u4) {
+        self.set_key(key, KeyStatus::Up);
+    }
+
+    /// Sends the current frame, followed by every changed frame while
+    /// running in a thread, until the receiver hangs up
+    #[cfg(feature = "std")]
+    pub fn subscribe(&mut self, subscriber: Sender<Frame>) {
+        if subscriber.send(self.frame()).is_ok() {
+            self.subscribers.push(subscriber);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn frame(&self) -> Frame {
+        Frame {
+            number: self.graphics_frame,
+            buffer: self.graphics,
+        }
+    }
+
+    /// Sends the frame to the subscribers if it changed since the last
+    /// broadcast
+    #[cfg(feature = "std")]
+    fn broadcast(&mut self) {
+        if self.broadcast_frame == self.graphics_frame {
+            return;
+        }
+        self.broadcast_frame = self.graphics_frame;
+        let frame = self.frame();
+        self.subscribers
+            .retain(|subscriber| subscriber.send(frame).is_ok());
+    }
+
+    /// The main loop of the emulator when executing in a thread, see
+    /// [crate::machine::Machine]. Executes at the configured speed until a
+    /// [Message::Stop] or the sender hangs up.
+    #[cfg(feature = "std")]
+    pub(crate) fn run_thread(&mut self, receiver: &Receiver<Message>, suspended: bool) {
+        let delay_per_second = 1_000_000_000;
+        let delay_per_timebox = (delay_per_second / self.timeboxes) as u128;
+        let ticks_per_timebox = self.hertz / self.timeboxes;
+
+        info!(%ticks_per_timebox, %delay_per_timebox, "starting chip-8 machine");
+        self.suspended = suspended;
+        let mut ticks = 0;
+        let mut last_tick = Instant::now();
+        loop {
+            if self.suspended {
+                // only service messages until resumed
+                let should_abort = match receiver.recv() {
+                    Ok(message) => self.process_message(message),
+                    Err(_) => true,
+                };
+                if should_abort {
+                    break;
+                }
+                // steps may have changed the graphics
+                self.broadcast();
+                ticks = 0;
+                last_tick = Instant::now();
+            } else if ticks < ticks_per_timebox {
+                // keep ticking while we're allowed in the timebox, handling
+                // any message requests
+                let should_abort = match receiver.try_recv() {
+                    Ok(message) => self.process_message(message),
+                    Err(TryRecvError::Empty) => false,
+                    Err(TryRecvError::Disconnected) => true,
+                };
+                if should_abort {
+                    break;
+                }
+                if self.suspended {
+                    continue;
+                }
+
+                match self.tick() {
+                    Ok(TickOutcome::Continue | TickOutcome::WaitingForKey) => {}
+                    Ok(TickOutcome::Stopped(breakpoint)) => {
+                        info!(?breakpoint, "breakpoint hit, suspending execution");
+                        self.suspended = true;
+                    }
+                    Ok(TickOutcome::Exit) => {
+                        info!("program exited, suspending execution");
+                        self.suspended = true;
+                    }
+                    Err(error) => {
+                        match self.write_crash_report(&error) {
+                            Some(path) => {
+                                error!(%error, ?path, "suspending emulator execution")
+                            }
+                            None => error!(%error, "suspending emulator execution"),
+                        }
+                        self.suspended = true;
+                    }
+                }
+                ticks += 1;
+            } else {
+                if last_tick.elapsed().as_nanos() < delay_per_timebox {
+                    // listen for message requests until we can execute more
+                    // ticks
+                    let timeout = delay_per_timebox - last_tick.elapsed().as_nanos();
+                    let should_abort =
+                        match receiver.recv_timeout(Duration::from_nanos(timeout as u64)) {
+                            Ok(message) => self.process_message(message),
+                            Err(RecvTimeoutError::Timeout) => false,
+                            Err(RecvTimeoutError::Disconnected) => true,
+                        };
+                    if should_abort {
+                        break;
+                    }
+                }
+                self.broadcast();
+                ticks = 0;
+                last_tick = Instant::now();
+            }
+        }
+        self.broadcast();
+        info!("stopping chip-8 machine");
+    }
+
+    #[cfg(feature = "std")]
+    fn process_message(&mut self, message: Message) -> bool {
+        match message {
+            Message::Pause => {
+                info!("received pause");
+                self.suspended = true;
+            }
+            Message::Resume => {
+                info!("received resume");
+                self.suspended = false;
+            }
+            Message::Stop => {
+                info!("received stop");
+                return true;
+            }
+            Message::SendSuspended(channel) => {
+                return respond(channel, self.suspended, "suspended state");
+            }
+            Message::SendGraphics(channel) => {
+                match channel.send(self.copy_graphics_buffer()) {
+                    Ok(_) => {}
+                    Err(_) => {
+                        info!("failed to send graphics buffer, terminating");
+                        return true;
+                    }
+                };
+            }
+            Message::SendGraphicsIfChanged(since_frame, channel) => {
+                let graphics = (since_frame != Some(self.graphics_frame))
+                    .then(|| (self.graphics_frame, self.copy_graphics_buffer()));
+                if channel.send(graphics).is_err() {
+                    info!("failed to send graphics buffer, terminating");
+                    return true;
+                }
+            }
+            Message::KeyEvent(key, status) => {
+                info!(key = ?key, status = ?status, "received key event");
+                self.set_key(key, status);
+            }
+            Message::Subscribe(subscriber) => self.subscribe(subscriber),
+            Message::SendSnapshot(include_memory, channel) => {
+                return respond(channel, self.snapshot(include_memory), "snapshot");
+            }
+            Message::SetBreakpoint(address, channel) => {
+                return respond(channel, self.set_breakpoint(address), "breakpoint");
+            }
+            Message::ClearBreakpoint(address, channel) => {
+                return respond(channel, self.clear_breakpoint(address), "breakpoint");
+            }
+            Message::SendBreakpoints(channel) => {
+                return respond(channel, self.breakpoints().collect(), "breakpoints");
+            }
+            Message::SendTrace(channel) => return respond(channel, self.trace(), "trace"),
+            Message::SendProfile(channel) => {
+                return respond(channel, self.profile_report(), "profile report");
+            }
+            Message::SendCoverage(channel) => {
+                return respond(channel, self.coverage_report(), "coverage report");
+            }
+            Message::SendStats(channel) => return respond(channel, self.stats(), "stats"),
+            Message::TakeDebugOutput(channel) => {
+                return respond(channel, self.take_debug_output(), "debug output");
+            }
+            Message::ReadMemory(range, channel) => {
+                let bytes = self.read_memory(range).map(|bytes| bytes.to_vec());
+                return respond(channel, bytes, "memory");
+            }
+            Message::WriteMemory(address, bytes, channel) => {
+                let result = self.write_memory(address, &bytes);
+                return respond(channel, result, "memory write");
+            }
+            Message::SetRegister(register, value, channel) => {
+                self.set_register(register, value);
+                return respond(channel, (), "register");
+            }
+            Message::SetAddressRegister(address, channel) => {
+                let result = self.set_address_register(address);
+                return respond(channel, result, "address register");
+            }
+            Message::SetProgramCounter(address, channel) => {
+                let result = self.set_pc(address);
+                return respond(channel, result, "program counter");
+            }
+            Message::Step(channel) => return respond(channel, self.tick(), "step"),
+            Message::StepFrame(channel) => {
+                return respond(channel, self.tick_frame(), "frame step");
+            }
+            Message::Reset(channel) => {
+                self.reset();
+                return respond(channel, (), "reset");
+            }
+            Message::Restart(channel) => {
+                let result = self.restart();
+                return respond(channel, result, "restart");
+            }
+            Message::StepBack(channel) => {
+                return respond(channel, self.step_back(), "step back");
+            }
+            Message::AddCheat(cheat, channel) => {
+                let result = self.add_cheat(cheat);
+                return respond(channel, result, "cheat");
+            }
+            Message::RemoveCheat(id, channel) => {
+                return respond(channel, self.remove_cheat(id), "cheat");
+            }
+            Message::SendCheats(channel) => return respond(channel, self.cheats(), "cheats"),
+            Message::StartRecording => self.start_recording(),
+            Message::SendRecording(channel) => {
+                return respond(channel, self.recording(), "input recording");
+            }
+            Message::Replay(recording) => self.replay(recording),
+            Message::SendCrashReport(channel) => {
+                return respond(channel, self.crash_report.clone(), "crash report");
+            }
+            Message::SendRomInfo(channel) => {
+                return respond(channel, self.rom_info.clone(), "rom info");
+            }
+        };
+        false
+    }
+}
+
+/// Sends the response of a message, returns whether the requester hung up
+/// and the execution should stop
+#[cfg(feature = "std")]
+fn respond<T>(channel: Sender<T>, response: T, name: &str) -> bool {
+    if channel.send(response).is_err() {
+        info!("failed to send {}, terminating", name);
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::assembly::lexer::StreamLexer;
+    use crate::assembly::parser::Parser;
+    use crate::machine::Machine;
+    use std::io::{BufReader, Cursor};
+
+    fn create_execute(input: &'static str) -> Emulator {
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let binary = parser.parse().unwrap().binary().unwrap();
+        let mut emulator = Emulator::new(400, 100);
+        let cursor = Cursor::new(binary);
+        emulator.load(cursor).unwrap();
+        while let Ok(TickOutcome::Continue) = emulator.tick() {}
+        emulator
+    }
+
+    #[test]
+    fn test_custom_font() {
+        let mut bytes: Vec<u8> = (0..80).collect();
+        let font = font_from_bytes(&bytes).unwrap();
+        assert_eq!(font[1], [5, 6, 7, 8, 9]);
+
+        let mut emulator = Builder::new().with_font(font).build();
+        assert_eq!(
+            emulator.copy_bytes(DEFAULT_SPRITE_START_ADDR, 80).unwrap(),
+            bytes
+        );
+        // The font survives loading a program
+        emulator.load(Cursor::new(vec![0x00, 0xE0])).unwrap();
+        assert_eq!(
+            emulator.copy_bytes(DEFAULT_SPRITE_START_ADDR, 80).unwrap(),
+            bytes
+        );
+
+        bytes.pop();
+        assert!(matches!(
+            font_from_bytes(&bytes),
+            Err(EmulatorError::InvalidFont(79))
+        ));
+    }
+
+    #[test]
+    fn test_large_font() {
+        let mut e = Builder::new().build();
+        assert_eq!(
+            e.copy_bytes(LARGE_SPRITE_START_ADDR + 10, 10).unwrap(),
+            DEFAULT_LARGE_SPRITES[1]
+        );
+        e.registries[2] = 0xB;
+        e.execute(Instruction::SetMemRegisterLargeSprite(2.into()))
+            .unwrap();
+        assert_eq!(e.address_register, LARGE_SPRITE_START_ADDR + 0xB * 10);
+
+        let bytes: Vec<u8> = (0..160).collect();
+        let large_font = large_font_from_bytes(&bytes).unwrap();
+        assert_eq!(large_font[1], [10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        let e = Builder::new()
+            .with_large_font(large_font)
+            .load_bytes(&[0x00, 0xE0])
+            .unwrap();
+        assert_eq!(e.copy_bytes(LARGE_SPRITE_START_ADDR, 160).unwrap(), bytes);
+        // The small font is untouched
+        assert_eq!(
+            e.copy_bytes(DEFAULT_SPRITE_START_ADDR, 5).unwrap(),
+            DEFAULT_SPRITES[0]
+        );
+        assert!(matches!(
+            large_font_from_bytes(&bytes[..80]),
+            Err(EmulatorError::InvalidFont(80))
+        ));
+    }
+
+    fn reg_value(emu: &Emulator, index: usize) -> u8 {
+        emu.registries[index]
+    }
+
+    #[test]
+    fn test_add() {
+        let e = create_execute(
+            "
+            ldb r2 1
+            ldb r1 0
+            add r1 2
+            add r1 10
+            add r2 4
+            exit",
+        );
+        assert_eq!(reg_value(&e, 1), 12);
+        assert_eq!(reg_value(&e, 2), 5);
+    }
+
+    #[test]
+    fn test_add_wrapping() {
+        let e = create_execute(
+            "
+            ldb r1 0xFE
+            ldb r15 7
+            add r1 3
+            ldb r2 0xFF
+            add r2 0xFF
+            exit",
+        );
+        assert_eq!(reg_value(&e, 1), 1);
+        assert_eq!(reg_value(&e, 2), 0xFE);
+        // VF isn't used as carry
+        assert_eq!(reg_value(&e, 15), 7);
+
+        // A counter overflowing repeatedly
+        let e = create_execute(
+            "
+            ldb r1 0
+            ldb r2 0
+            loop:
+            add r1 0x40
+            add r2 1
+            se r2 10
+            jmp loop
+            exit",
+        );
+        assert_eq!(reg_value(&e, 1), 0x80);
+    }
+
+    #[test]
+    fn test_branch_jmp_sne() {
+        let e = create_execute(
+            "
+            ldb r1 0
+            add r2 0
+        loop:
+            sne r1 4
+            jmp exit
+            add r1 1
+            add r2 4
+            jmp loop
+        exit:
+            exit",
+        );
+        assert_eq!(reg_value(&e, 1), 4);
+        assert_eq!(reg_value(&e, 2), 16);
+    }
+
+    #[test]
+    fn test_call_ret() {
+        let e = create_execute(
+            "
+        main:
+            ldb r1 0
+            add r1 2
+            call func1
+            call func2
+            call func1
+            exit
+
+        func1:
+            add r1 4
+            call func2
+            ret
+        
+        func2:
+            add r1 2
+            ret",
+        );
+        assert_eq!(reg_value(&e, 1), 16);
+    }
+
+    #[test]
+    fn test_default_sprites() {
+        // address registry should point at default sprite "1"
+        let e = create_execute(
+            "
+        main:
+            ldb r1 1
+            ldf r1
+            ",
+        );
+
+        let bytes = e.copy_bytes(e.address_register, 5).unwrap();
+        assert_eq!(bytes[0], 0x20, "byte {} is invalid", 0);
+        assert_eq!(bytes[1], 0x60, "byte {} is invalid", 1);
+        assert_eq!(bytes[2], 0x20, "byte {} is invalid", 2);
+        assert_eq!(bytes[3], 0x20, "byte {} is invalid", 3);
+        assert_eq!(bytes[4], 0x70, "byte {} is invalid", 4);
+    }
+
+    #[test]
+    fn test_draw_simple() {
+        // point address registry to 1 and render it on (0, 0)
+        let e = create_execute(
+            "
+        main:
+            ldb r1 1
+            ldf r1
+            ldb r1 0
+            ldb r2 0
+            draw r1 r2 5
+            ",
+        );
+
+        assert_eq!(e.graphics.planes[0][0], 0x20, "byte {} is invalid", 0);
+        assert_eq!(e.graphics.planes[0][8], 0x60, "byte {} is invalid", 8);
+        assert_eq!(e.graphics.planes[0][16], 0x20, "byte {} is invalid", 16);
+        assert_eq!(e.graphics.planes[0][24], 0x20, "byte {} is invalid", 24);
+        assert_eq!(e.graphics.planes[0][32], 0x70, "byte {} is invalid", 32);
+        for i in 0..GRAPHICS_BUFFER_SIZE {
+            match i {
+                0 | 8 | 16 | 24 | 32 => continue,
+                x => assert_eq!(
+                    e.graphics.planes[0][i], 0x00,
+                    "byte {} is invalid (0x{:02x})",
+                    x, e.graphics.planes[0][i]
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_wrapping() {
+        // point address registry to 1 and render it on (0, 0)
+        let e = create_execute(
+            "
+        main:
+            ldb r1 1
+            ldf r1
+            ldb r1 60
+            ldb r2 0
+            draw r1 r2 5
+            ",
+        );
+
+        assert_eq!(e.graphics.planes[0][7], 0x02, "byte {} is invalid", 7);
+        assert_eq!(e.graphics.planes[0][8], 0x00, "byte {} is invalid", 8);
+        assert_eq!(e.graphics.planes[0][15], 0x06, "byte {} is invalid", 15);
+        assert_eq!(e.graphics.planes[0][16], 0x00, "byte {} is invalid", 16);
+        assert_eq!(e.graphics.planes[0][23], 0x02, "byte {} is invalid", 23);
+        assert_eq!(e.graphics.planes[0][24], 0x00, "byte {} is invalid", 24);
+        assert_eq!(e.graphics.planes[0][31], 0x02, "byte {} is invalid", 31);
+        assert_eq!(e.graphics.planes[0][32], 0x00, "byte {} is invalid", 32);
+        assert_eq!(e.graphics.planes[0][39], 0x07, "byte {} is invalid", 39);
+        assert_eq!(e.graphics.planes[0][40], 0x00, "byte {} is invalid", 40);
+        for i in 0..GRAPHICS_BUFFER_SIZE {
+            match i {
+                7 | 8 | 15 | 16 | 23 | 24 | 31 | 32 | 39 | 40 => continue,
+                x => assert_eq!(
+                    e.graphics.planes[0][i], 0x00,
+                    "byte {} is invalid (0x{:02x})",
+                    x, e.graphics.planes[0][i]
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_no_collision() {
+        // Render 1 twice on the same location, should give a collision
+        let e = create_execute(
+            "
+        main:
+            ldb r1 1
+            ldf r1
+            ldb r1 0
+            ldb r2 0
+            draw r1 r2 5
+            ",
+        );
+        assert_eq!(e.registries[0x0F_usize], 0);
+    }
+
+    #[test]
+    fn test_draw_collision() {
+        // Render 1 twice on the same location, should give a collision
+        let e = create_execute(
+            "
+        main:
+            ldb r1 1
+            ldf r1
+            ldb r1 0
+            ldb r2 0
+            draw r1 r2 5
+            ldb r1 1
+            ldf r1
+            ldb r1 0
+            ldb r2 0
+            draw r1 r2 5
+            ",
+        );
+        assert_eq!(e.registries[0x0F_usize], 1);
+    }
+
+    /// Draws the sprite from a cleared display, returning the emulator
+    fn draw(draw_mode: DrawMode, x: u8, y: u8, sprite: &[u8]) -> Emulator {
+        let mut e = Builder::new().with_draw_mode(draw_mode).build();
+        e.memory[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+        e.address_register = 0x300;
+        e.registries[1] = x;
+        e.registries[2] = y;
+        e.execute(Instruction::Draw(
+            1.into(),
+            2.into(),
+            (sprite.len() as u8).into(),
+        ))
+        .unwrap();
+        e
+    }
+
+    /// The lit pixels of the display as (x, y)
+    fn pixels(e: &Emulator) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..e.graphics.height() {
+            for x in 0..e.graphics.width() {
+                if e.graphics.get(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_draw_edges() {
+        use DrawMode::*;
+        // Lights (0, 0), (1, 0) and (0, 1) relative to the position
+        let sprite = [0xC0, 0x80];
+        let cases = [
+            (Clip, 0, 0, vec![(0, 0), (1, 0), (0, 1)]),
+            (Wrap, 0, 0, vec![(0, 0), (1, 0), (0, 1)]),
+            (Clip, 63, 0, vec![(63, 0), (63, 1)]),
+            (Wrap, 63, 0, vec![(0, 0), (63, 0), (63, 1)]),
+            (Clip, 0, 31, vec![(0, 31), (1, 31)]),
+            (Wrap, 0, 31, vec![(0, 0), (0, 31), (1, 31)]),
+            (Clip, 63, 31, vec![(63, 31)]),
+            (Wrap, 63, 31, vec![(63, 0), (0, 31), (63, 31)]),
+            // The starting position wraps in both modes
+            (Clip, 66, 35, vec![(2, 3), (3, 3), (2, 4)]),
+            (Wrap, 66, 35, vec![(2, 3), (3, 3), (2, 4)]),
+            (Clip, 255, 255, vec![(63, 31)]),
+            (Wrap, 255, 255, vec![(63, 0), (0, 31), (63, 31)]),
+        ];
+        for (mode, x, y, expected) in cases {
+            let e = draw(mode, x, y, &sprite);
+            assert_eq!(pixels(&e), expected, "{:?} at ({}, {})", mode, x, y);
+            assert_eq!(e.registries[0xF], 0, "{:?} at ({}, {})", mode, x, y);
+        }
+    }
+
+    #[test]
+    fn test_draw_collision_flag() {
+        use DrawMode::*;
+        for mode in [Clip, Wrap] {
+            let mut e = draw(mode, 62, 0, &[0xFF]);
+            // Only the pixels wrapped to the left edge are overlapped
+            e.registries[1] = 0;
+            e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+                .unwrap();
+            let expected = if mode == Wrap { 1 } else { 0 };
+            assert_eq!(e.registries[0xF], expected, "{:?}", mode);
+
+            // VF is cleared by a draw without collision
+            e.registries[0xF] = 1;
+            e.registries[2] = 10;
+            e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+                .unwrap();
+            assert_eq!(e.registries[0xF], 0, "{:?}", mode);
+
+            // Drawing twice erases the sprite
+            e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+                .unwrap();
+            assert_eq!(e.registries[0xF], 1, "{:?}", mode);
+            assert!(pixels(&e).iter().all(|(_, y)| *y == 0), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_frame_buffer() {
+        let mut frame_buffer = FrameBuffer::new(Resolution::Low);
+        frame_buffer.set_pixel(0, 1, 0, true);
+        frame_buffer.set_pixel(1, 63, 31, true);
+        assert!(frame_buffer.get(1, 0));
+        assert!(!frame_buffer.get(64, 0));
+        let rows: Vec<Vec<bool>> = frame_buffer.iter_rows().collect();
+        assert_eq!(rows.len(), 32);
+        assert!(rows.iter().all(|row| row.len() == 64));
+        assert!(rows[0][1] && rows[31][63]);
+        assert_eq!(rows.iter().flatten().filter(|lit| **lit).count(), 2);
+
+        let packed = frame_buffer.to_packed();
+        assert_eq!(packed.len(), 2 * 256);
+        assert_eq!(packed[0], 0x40);
+        assert_eq!(
+            FrameBuffer::from_packed(Resolution::Low, &packed).unwrap(),
+            frame_buffer
+        );
+        // A single plane
+        let single = FrameBuffer::from_packed(Resolution::Low, &packed[..256]).unwrap();
+        assert!(single.get(1, 0) && !single.get(63, 31));
+        assert!(matches!(
+            FrameBuffer::from_packed(Resolution::High, &packed),
+            Err(EmulatorError::InvalidFrameBuffer(512))
+        ));
+    }
+
+    #[test]
+    fn test_framebuffer_hash() {
+        let mut e = Builder::new().build();
+        let cleared = e.framebuffer_hash();
+        assert_eq!(cleared, FrameBuffer::new(Resolution::Low).content_hash());
+        assert_ne!(cleared, FrameBuffer::new(Resolution::High).content_hash());
+
+        // The font sprite of 1 at 2,1
+        e.registries[0] = 2;
+        e.registries[1] = 1;
+        e.execute(Instruction::SetMemRegisterDefaultSprit(1.into()))
+            .unwrap();
+        e.execute(Instruction::Draw(0.into(), 1.into(), 5.into()))
+            .unwrap();
+        assert_ne!(e.framebuffer_hash(), cleared);
+        let ascii = e.copy_graphics_buffer().to_ascii();
+        let rows: Vec<&str> = ascii.lines().collect();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(
+            rows[..7].iter().map(|row| &row[..8]).collect::<Vec<_>>(),
+            vec![
+                "........", "....#...", "...##...", "....#...", "....#...", "...###..", "........",
+            ]
+        );
+        assert!(rows.iter().all(|row| row.len() == 64));
+
+        let mut colored = FrameBuffer::new(Resolution::Low);
+        colored.set_pixel(1, 0, 0, true);
+        colored.set_pixel(0, 1, 0, true);
+        colored.set_pixel(1, 1, 0, true);
+        colored.set_pixel(0, 2, 0, true);
+        assert!(colored.to_ascii().starts_with("+@#."));
+    }
+
+    #[test]
+    fn test_hires() {
+        let mut e = Builder::new().build();
+        e.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        e.address_register = 0x300;
+        e.registries[1] = 120;
+        e.registries[2] = 60;
+
+        // Nothing is drawn by large sprites in low resolution
+        e.execute(Instruction::DrawLarge(1.into(), 2.into()))
+            .unwrap();
+        assert_eq!(pixels(&e), vec![]);
+
+        e.execute(Instruction::HighRes).unwrap();
+        assert_eq!(e.resolution(), Resolution::High);
+        assert_eq!((e.graphics.width(), e.graphics.height()), (128, 64));
+        e.execute(Instruction::DrawLarge(1.into(), 2.into()))
+            .unwrap();
+        let expected: Vec<(usize, usize)> = (60..64)
+            .flat_map(|y| (120..128).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(pixels(&e), expected);
+        assert_eq!(e.registries[0xF], 0);
+        e.execute(Instruction::DrawLarge(1.into(), 2.into()))
+            .unwrap();
+        assert_eq!(pixels(&e), vec![]);
+        assert_eq!(e.registries[0xF], 1);
+
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        e.execute(Instruction::LowRes).unwrap();
+        assert_eq!(e.copy_graphics_buffer(), FrameBuffer::new(Resolution::Low));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut e = Builder::new().build();
+        e.execute(Instruction::HighRes).unwrap();
+        e.memory[0x300] = 0x80;
+        e.address_register = 0x300;
+        e.registries[1] = 124;
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        e.registries[1] = 0;
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        assert_eq!(pixels(&e), vec![(0, 0), (124, 0)]);
+
+        e.execute(Instruction::ScrollDown(3.into())).unwrap();
+        assert_eq!(pixels(&e), vec![(0, 3), (124, 3)]);
+        // Pixels scrolled off the display are lost
+        e.execute(Instruction::ScrollRight).unwrap();
+        assert_eq!(pixels(&e), vec![(4, 3)]);
+        e.execute(Instruction::ScrollLeft).unwrap();
+        assert_eq!(pixels(&e), vec![(0, 3)]);
+        e.execute(Instruction::ScrollLeft).unwrap();
+        assert_eq!(pixels(&e), vec![]);
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut e = Builder::new().build();
+        e.registries = core::array::from_fn(|i| i as u8 + 1);
+        e.execute(Instruction::SaveFlags(2.into())).unwrap();
+        e.registries = [0; REGISTRY_COUNT];
+        e.execute(Instruction::LoadFlags(0xF.into())).unwrap();
+        assert_eq!(e.registries[..4], [1, 2, 3, 0]);
+
+        #[cfg(feature = "serde")]
+        {
+            let mut restored = Builder::new().build();
+            restored.load_state(&e.save_state()).unwrap();
+            restored.execute(Instruction::LoadFlags(2.into())).unwrap();
+            assert_eq!(restored.registries[..3], [1, 2, 3]);
+        }
+
+        e.reset();
+        e.execute(Instruction::LoadFlags(0.into())).unwrap();
+        assert_eq!(e.registries[0], 0);
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut e = Builder::new().build();
+        e.memory[0x300..0x302].copy_from_slice(&[0xC0, 0x60]);
+        e.address_register = 0x300;
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        assert_eq!(e.graphics.color(0, 0), 1);
+
+        // Both planes read consecutive sprites
+        e.execute(Instruction::SelectPlane(3.into())).unwrap();
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        assert_eq!(e.registries[0xF], 1);
+        let colors: Vec<u8> = (0..4).map(|x| e.graphics.color(x, 0)).collect();
+        assert_eq!(colors, vec![0, 2, 2, 0]);
+
+        e.execute(Instruction::SelectPlane(1.into())).unwrap();
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        let colors: Vec<u8> = (0..4).map(|x| e.graphics.color(x, 0)).collect();
+        assert_eq!(colors, vec![1, 3, 2, 0]);
+
+        // Only the selected planes are scrolled and cleared
+        e.execute(Instruction::SelectPlane(2.into())).unwrap();
+        e.execute(Instruction::ScrollDown(1.into())).unwrap();
+        assert_eq!(e.graphics.color(1, 0), 1);
+        assert_eq!(e.graphics.color(1, 1), 2);
+        e.execute(Instruction::Clear).unwrap();
+        assert_eq!(pixels(&e), vec![(0, 0), (1, 0)]);
+
+        // Nothing is drawn without a selected plane
+        e.execute(Instruction::SelectPlane(0.into())).unwrap();
+        e.execute(Instruction::Draw(1.into(), 2.into(), 1.into()))
+            .unwrap();
+        assert_eq!(e.registries[0xF], 0);
+        assert_eq!(pixels(&e), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn test_delay_timer_start() {
+        let input = "
+        main:
+            ldb r1 3
+            ldb r3 0
+            delay r1
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let binary = parser.parse().unwrap().binary().unwrap();
+        let mut emulator = Emulator::new(400, 100);
+        let cursor = Cursor::new(binary);
+        emulator.load(cursor).unwrap();
+
+        // 3 ticks to start delay timer
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+
+        assert!(emulator.delay_timer > 0);
+    }
+
+    #[test]
+    fn test_delay_timer_tick() {
+        let input = "
+        main:
+            ldb r1 3
+            ldb r3 0
+            delay r1
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let binary = parser.parse().unwrap().binary().unwrap();
+        let clock = ManualClock::new();
+        let mut emulator = Builder::new().with_clock(Box::new(clock.clone())).build();
+        let cursor = Cursor::new(binary);
+        emulator.load(cursor).unwrap();
+
+        // 3 ticks to start delay timer
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+
+        // Advance past a 60hz period before each tick, we're limited to
+        // one decrement per tick
+        clock.advance(Duration::from_millis(20));
+        emulator.tick().unwrap();
+        clock.advance(Duration::from_millis(20));
+        emulator.tick().unwrap();
+        // Two decrements should've occured
+        assert_eq!(emulator.delay_timer, 1);
+    }
+
+    #[test]
+    fn test_sound_timer_start() {
+        let input = "
+        main:
+            ldb r1 3
+            ldb r3 0
+            sound r1
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let binary = parser.parse().unwrap().binary().unwrap();
+        let mut emulator = Emulator::new(400, 100);
+        let cursor = Cursor::new(binary);
+        emulator.load(cursor).unwrap();
+        //
+        // 3 ticks to start delay timer
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+
+        assert!(emulator.sound_timer > 0);
+    }
+
+    #[test]
+    fn test_sound_timer_tick() {
+        let input = "
+        main:
+            ldb r1 3
+            ldb r3 0
+            sound r1
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ldb r4 0
+            ";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let mut parser = Parser::new(Box::new(lexer));
+        let binary = parser.parse().unwrap().binary().unwrap();
+        let clock = ManualClock::new();
+        let mut emulator = Builder::new().with_clock(Box::new(clock.clone())).build();
+        let cursor = Cursor::new(binary);
+        emulator.load(cursor).unwrap();
+
+        // 3 ticks to start sound timer
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+        emulator.tick().unwrap();
+
+        // Advance past a 60hz period before each tick, we're limited to
+        // one decrement per tick
+        clock.advance(Duration::from_millis(20));
+        emulator.tick().unwrap();
+        clock.advance(Duration::from_millis(20));
+        emulator.tick().unwrap();
+        // Two decrements should've occured
+        assert_eq!(emulator.sound_timer, 1);
+    }
+
+    struct RecordingSink(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    impl AudioSink for RecordingSink {
+        fn start(&mut self) {
+            self.0.lock().unwrap().push("start");
+        }
+
+        fn stop(&mut self) {
+            self.0.lock().unwrap().push("stop");
+        }
+    }
+
+    #[test]
+    fn test_graphics_frame() {
+        // ldb r1 1, draw r0 r0 1, ldb r1 2
+        let rom = [0x61, 0x01, 0xD0, 0x01, 0x61, 0x02];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        let frame = e.graphics_frame();
+        e.tick().unwrap();
+        assert_eq!(e.graphics_frame(), frame);
+        e.tick().unwrap();
+        let drawn = e.graphics_frame();
+        assert_ne!(drawn, frame);
+        e.tick().unwrap();
+        assert_eq!(e.graphics_frame(), drawn);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        e.process_message(Message::SendGraphicsIfChanged(Some(drawn), sender.clone()));
+        assert_eq!(receiver.recv().unwrap(), None);
+        e.process_message(Message::SendGraphicsIfChanged(Some(frame), sender));
+        assert_eq!(receiver.recv().unwrap(), Some((drawn, e.graphics)));
+    }
+
+    struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+    impl ExecutionObserver for RecordingObserver {
+        fn before_execute(&mut self, _: &mut Emulator, address: usize, _: Instruction) {
+            self.0.lock().unwrap().push(format!("before {:x}", address));
+        }
+
+        fn after_execute(&mut self, e: &mut Emulator, address: usize, _: Instruction) {
+            self.0.lock().unwrap().push(format!("after {:x}", address));
+            // A cheat keeping V3 at 9
+            e.set_register(3.into(), 9);
+        }
+
+        fn on_draw(&mut self, _: &mut Emulator, x: usize, y: usize, collision: bool) {
+            let event = format!("draw {} {} {}", x, y, collision);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_key_wait(&mut self, _: &mut Emulator, register: u4) {
+            let event = format!("wait r{}", register.value());
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_stack_fault(&mut self, _: &mut Emulator, fault: &EmulatorError) {
+            self.0.lock().unwrap().push(format!("fault {}", fault));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        // ldb r3 1, draw r0 r0 1, wkey r2
+        let rom = [0x63, 0x01, 0xD0, 0x01, 0xF2, 0x0A];
+        let mut e = Builder::new()
+            .with_observer(Box::new(RecordingObserver(events.clone())))
+            .load_bytes(&rom)
+            .unwrap();
+        e.run_for(RunLimit::Ticks(4));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "before 200",
+                "after 200",
+                "before 202",
+                "draw 0 0 false",
+                "after 202",
+                "before 204",
+                "wait r2",
+                "after 204",
+            ]
+        );
+        assert_eq!(e.registries[3], 9);
+
+        e.clear_observers();
+        e.key_pressed(1.into());
+        e.tick().unwrap();
+        assert_eq!(events.lock().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let input = "
+            ldb r1 1
+            ldb r2 2
+            draw r1 r2 1
+            break
+            ldb r3 3
+            exit";
+        let reader = BufReader::new(input.as_bytes());
+        let lexer = StreamLexer::new(reader);
+        let binary = Parser::new(Box::new(lexer))
+            .parse()
+            .unwrap()
+            .binary()
+            .unwrap();
+        let mut e = Builder::new().build();
+        e.load(Cursor::new(binary)).unwrap();
+
+        assert!(e.set_breakpoint(START_ADDR + 2));
+        assert!(!e.set_breakpoint(START_ADDR + 2));
+        assert!(e.set_instruction_breakpoint("draw"));
+        assert!(!e.set_instruction_breakpoint("nope"));
+        assert_eq!(e.breakpoints().collect::<Vec<_>>(), vec![START_ADDR + 2]);
+
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(
+            e.tick().unwrap(),
+            TickOutcome::Stopped(Breakpoint::Address(START_ADDR + 2))
+        );
+        assert_eq!(e.registries[2], 0);
+        // Resuming executes the instruction at the breakpoint
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.registries[2], 2);
+        assert_eq!(
+            e.tick().unwrap(),
+            TickOutcome::Stopped(Breakpoint::Instruction(START_ADDR + 4, "draw"))
+        );
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(
+            e.tick().unwrap(),
+            TickOutcome::Stopped(Breakpoint::Opcode(START_ADDR + 6))
+        );
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.tick().unwrap(), TickOutcome::Exit);
+
+        // Breakpoints are kept when loading a program
+        e.load(Cursor::new(e.copy_bytes(START_ADDR, 12).unwrap()))
+            .unwrap();
+        assert!(e.clear_instruction_breakpoint("draw"));
+        assert!(e.clear_breakpoint(START_ADDR + 2));
+        assert!(!e.clear_breakpoint(START_ADDR + 2));
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let quirks = Quirks {
+            wait_for_release: true,
+            ..Default::default()
+        };
+        let mut e = Builder::new()
+            .with_quirks(quirks)
+            .load_bytes(&[0xF1, 0x0A, 0x62, 0x01])
+            .unwrap();
+        assert_eq!(e.tick().unwrap(), TickOutcome::WaitingForKey);
+        e.key_pressed(3.into());
+        assert_eq!(e.tick().unwrap(), TickOutcome::WaitingForKey);
+        // Other keys don't matter once one is pressed
+        e.key_pressed(4.into());
+        assert_eq!(e.tick().unwrap(), TickOutcome::WaitingForKey);
+        e.key_up(3.into());
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.registries[1], 3);
+        e.tick().unwrap();
+        assert_eq!(e.registries[2], 1);
+    }
+
+    #[test]
+    fn test_coverage() {
+        // ldi 0x20a, draw r0 r0 2, se r0 0, jmp 0x206, jmp 0x208, sprite
+        // and an unused byte
+        let rom = [
+            0xA2, 0x0A, 0xD0, 0x02, 0x30, 0x00, 0x12, 0x06, 0x12, 0x08, 0xF0, 0x90, 0xFF,
+        ];
+        assert!(Builder::new()
+            .load_bytes(&rom)
+            .unwrap()
+            .coverage_report()
+            .is_none());
+        let mut e = Builder::new().with_coverage(true).load_bytes(&rom).unwrap();
+        e.run_for(RunLimit::Ticks(5));
+        let report = e.coverage_report().unwrap();
+        assert_eq!(report.start, START_ADDR);
+        let executed: Vec<usize> = (START_ADDR..)
+            .zip(&report.executed)
+            .filter(|(_, e)| **e)
+            .map(|(address, _)| address)
+            .collect();
+        assert_eq!(
+            executed,
+            vec![0x200, 0x201, 0x202, 0x203, 0x204, 0x205, 0x208, 0x209]
+        );
+        assert_eq!(report.uncovered(), vec![0x206..0x208, 0x20C..0x20D]);
+        assert!((report.percentage() - 1000.0 / 13.0).abs() < 1e-9);
+        assert!((report.executed_percentage() - 800.0 / 13.0).abs() < 1e-9);
+
+        // ldr reads as well
+        e.execute(Instruction::SetMemRegister(0x20B.into()))
+            .unwrap();
+        e.execute(Instruction::MemRead(2.into())).unwrap();
+        let report = e.coverage_report().unwrap();
+        assert_eq!(report.uncovered(), vec![0x206..0x208]);
+
+        let machine = Machine::start_paused(e);
+        assert_eq!(machine.coverage_report().unwrap(), Some(report));
+        let mut e = machine.stop().unwrap();
+        e.reset();
+        assert_eq!(e.coverage_report().unwrap().percentage(), 0.0);
+    }
+
+    #[test]
+    fn test_tick_frame() {
+        // ldb r0 5, sdelay r0, add r1 1, jmp 0x204
+        let rom = [0x60, 0x05, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04];
+        // The configured clock doesn't matter
+        let clock = ManualClock::new();
+        let mut e = Builder::new()
+            .with_hertz(600)
+            .with_clock(Box::new(clock.clone()))
+            .load_bytes(&rom)
+            .unwrap();
+        assert_eq!(e.instructions_per_frame(), 10);
+        assert_eq!(e.tick_frame().unwrap(), TickOutcome::Continue);
+        assert_eq!((e.instructions, e.registries[1], e.delay_timer), (10, 4, 4));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(e.tick_frame().unwrap(), TickOutcome::Continue);
+        assert_eq!((e.instructions, e.registries[1], e.delay_timer), (20, 9, 3));
+
+        // Ticking on the configured clock afterwards doesn't decrement
+        // twice
+        e.tick().unwrap();
+        assert_eq!(e.delay_timer, 3);
+        clock.advance(FRAME_DURATION * 2);
+        e.tick().unwrap();
+        assert_eq!(e.delay_timer, 2);
+
+        // A stop ends the frame without decrementing
+        e.set_breakpoint(START_ADDR + 6);
+        assert!(matches!(e.tick_frame().unwrap(), TickOutcome::Stopped(_)));
+        assert_eq!(e.delay_timer, 2);
+        assert_eq!(
+            Builder::new()
+                .with_hertz(10)
+                .load_bytes(&rom)
+                .unwrap()
+                .instructions_per_frame(),
+            1
+        );
+
+        // One draw per frame with the display wait quirk
+        // draw r0 r0 1, jmp 0x200
+        let quirks = Quirks {
+            display_wait: true,
+            ..Default::default()
+        };
+        let e = Builder::new()
+            .with_hertz(600)
+            .with_quirks(quirks)
+            .load_bytes(&[0xD0, 0x01, 0x12, 0x00])
+            .unwrap();
+        let machine = Machine::start_paused(e);
+        for _ in 0..3 {
+            assert_eq!(machine.step_frame().unwrap(), TickOutcome::Continue);
+        }
+        let e = machine.stop().unwrap();
+        // The first draw waits for the second frame
+        assert_eq!(e.instructions, 4);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let cheat: Cheat = "v3=9".parse().unwrap();
+        assert_eq!(cheat, Cheat::FreezeRegister(3.into(), 9));
+        assert_eq!(cheat.to_string(), "v3=0x09");
+        let patch: Cheat = "0x208=0x42, 1".parse().unwrap();
+        assert_eq!(patch, Cheat::Patch(0x208, vec![0x42, 1]));
+        assert_eq!(patch.to_string(), "0x208=0x42,0x01");
+        for invalid in ["v3", "vg=1", "v3=1,2", "v3=256", "0x200=", "x=1"] {
+            assert!(invalid.parse::<Cheat>().is_err(), "{}", invalid);
+        }
+
+        // add r3 1, ldi 0x208, str r1, jmp 0x200
+        let rom = [0x73, 0x01, 0xA2, 0x08, 0xF1, 0x55, 0x12, 0x00];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        let freeze = e.add_cheat(cheat.clone()).unwrap();
+        assert_eq!(e.registries[3], 9);
+        let patch_id = e.add_cheat(Cheat::Patch(0x208, vec![0x42])).unwrap();
+        assert_eq!(e.memory[0x208], 0x42);
+        assert!(matches!(
+            e.add_cheat(Cheat::Patch(MEMSIZE - 1, vec![1, 2])),
+            Err(EmulatorError::InvalidAddress(_))
+        ));
+        assert_eq!(
+            e.cheats(),
+            vec![(freeze, cheat), (patch_id, Cheat::Patch(0x208, vec![0x42]))]
+        );
+
+        e.run_for(RunLimit::Ticks(8));
+        assert_eq!(e.registries[3], 9);
+        // Stored over by str, then patched back
+        assert_eq!(e.memory[0x208], 0x42);
 
-                    let i1 = start + (i as usize) * 8;
-                    let i2 = start + 1 + (i as usize) * 8;
+        // Kept over restarts
+        e.restart().unwrap();
+        assert_eq!(e.cheats().len(), 2);
+        assert!(e.remove_cheat(freeze));
+        assert!(!e.remove_cheat(freeze));
+        e.run_for(RunLimit::Ticks(4));
+        assert_eq!(e.registries[3], 1);
+        assert_eq!(e.memory[0x208], 0x42);
 
-                    if i1 >= GRAPHICS_BUFFER_SIZE {
-                        break;
-                    }
-                    let byte1 = self.graphics_buffer[i1];
-                    self.graphics_buffer[i1] = byte1 ^ sp1;
+        let machine = Machine::start_paused(e);
+        let id = machine.add_cheat("v3=0".parse().unwrap()).unwrap();
+        assert!(machine.add_cheat(Cheat::Patch(MEMSIZE, vec![1])).is_err());
+        assert_eq!(machine.cheats().unwrap().len(), 2);
+        assert!(machine.remove_cheat(id).unwrap());
+        assert!(!machine.remove_cheat(id).unwrap());
+        let e = machine.stop().unwrap();
+        assert_eq!(e.registries[3], 0);
+    }
 
-                    if i2 >= GRAPHICS_BUFFER_SIZE {
-                        break;
-                    }
-                    let byte2 = self.graphics_buffer[i2];
-                    self.graphics_buffer[i2] = byte2 ^ sp2;
+    #[test]
+    fn test_quirk_presets() {
+        assert_eq!(Quirks::for_target(Target::Chip8), Quirks::cosmac_vip());
+        assert_eq!(Quirks::for_target(Target::Schip), Quirks::schip_1_1());
+        assert_eq!(Quirks::for_target(Target::XoChip), Quirks::xo_chip());
+        assert!(Quirks::cosmac_vip().display_wait);
+        assert!(!Quirks::schip_1_1().display_wait);
 
-                    vf |= (byte1 ^ sp1) ^ (byte1 | sp1);
-                    vf |= (byte2 ^ sp2) ^ (byte2 | sp2);
-                }
+        // wkey r0, wkey r1
+        let rom = [0xF0, 0x0A, 0xF1, 0x0A];
+        let mut vip = Builder::new()
+            .with_quirks(Quirks::cosmac_vip())
+            .load_bytes(&rom)
+            .unwrap();
+        let mut schip = Builder::new()
+            .with_quirks(Quirks::schip_1_1())
+            .load_bytes(&rom)
+            .unwrap();
+        for e in [&mut vip, &mut schip] {
+            e.tick().unwrap();
+            e.key_pressed(5.into());
+            e.tick().unwrap();
+        }
+        // The key wait completes on the press, or waits for the release
+        assert_eq!(schip.registries[0], 5);
+        assert_eq!(vip.wait_for_key, Some(0));
+        vip.key_up(5.into());
+        vip.tick().unwrap();
+        assert_eq!(vip.registries[0], 5);
+    }
 
-                if vf > 0 {
-                    self.registries[0x0F_usize] = 1;
-                }
-            }
-            Instruction::SkipKeyPressed(regx) => {
-                if self.key_status[regx.value() as usize] == KeyStatus::Pressed {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::SkipKeyNotPressed(regx) => {
-                if self.key_status[regx.value() as usize] == KeyStatus::Up {
-                    self.program_counter += 2;
-                }
-            }
-            Instruction::WaitForKey(regx) => {
-                self.wait_for_key = Some(regx.value());
-            }
-            Instruction::SetMemRegisterDefaultSprit(regx) => {
-                let hex_digit = self.registries[regx.value() as usize];
-                if hex_digit > 0x0F {
-                    // Panic? Fail?
-                }
-                // sprites are sequential, 0 -> F, and always 5 bytes. Just calculate
-                // the offset from the start location
-                self.address_register = DEFAULT_SPRITE_START_ADDR + ((hex_digit as usize) * 5);
-            }
-            Instruction::SetRegisterDelayTimer(regx) => {
-                self.registries[regx.value() as usize] = self.delay_timer;
-            }
-            Instruction::SetDelayTimer(regx) => {
-                self.delay_timer = self.registries[regx.value() as usize];
-            }
-            Instruction::SetSoundTimer(regx) => {
-                self.sound_timer = self.registries[regx.value() as usize];
-            }
-            Instruction::AddMemReg(regx) => {
-                self.address_register += self.registries[regx.value() as usize] as usize;
-            }
-            Instruction::SetBcd(regx) => {
-                let value = self.registries[regx.value() as usize];
-                let hundred = value / 100_u8;
-                let ten = (value % 100) / 10;
-                let one = value % 10;
-                self.memory[self.address_register] = hundred;
-                self.memory[self.address_register + 1] = ten;
-                self.memory[self.address_register + 2] = one;
-            }
-            Instruction::MemWrite(regx) => {
-                for i in 0..regx.value() {
-                    self.memory[self.address_register + i as usize] = self.registries[i as usize];
-                }
-            }
-            Instruction::MemRead(regx) => {
-                for i in 0..regx.value() {
-                    self.registries[i as usize] = self.memory[self.address_register + i as usize];
-                }
-            }
-            Instruction::Debug(value) => {
-                let msg = match value {
-                    x if x == u4::little(1) => {
-                        format!("{:?}", self.registries)
-                    }
-                    _ => return Ok(true),
-                };
-                info!(source = "debug-instruction", msg);
-            }
-            Instruction::Breakpoint => return Ok(false),
+    #[test]
+    fn test_display_wait() {
+        // draw r0 r0 1, draw r0 r0 1, ldb r1 1
+        let rom = [0xD0, 0x01, 0xD0, 0x01, 0x61, 0x01];
+        let clock = ManualClock::new();
+        let quirks = Quirks {
+            display_wait: true,
+            ..Default::default()
         };
-        Ok(true)
+        let mut e = Builder::new()
+            .with_clock(Box::new(clock.clone()))
+            .with_quirks(quirks)
+            .load_bytes(&rom)
+            .unwrap();
+        e.set_breakpoint(START_ADDR + 2);
+        let frame = e.graphics_frame();
+        // The first draw waits for the next frame
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!((e.program_counter, e.graphics_frame()), (START_ADDR, frame));
+        clock.advance(FRAME_DURATION);
+        e.tick().unwrap();
+        assert_eq!(e.program_counter, START_ADDR + 2);
+        assert_ne!(e.graphics_frame(), frame);
+
+        // The second draw stops at the breakpoint once and then waits
+        assert!(matches!(e.tick().unwrap(), TickOutcome::Stopped(_)));
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.program_counter, START_ADDR + 2);
+        clock.advance(FRAME_DURATION);
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert_eq!((e.program_counter, e.registries[1]), (START_ADDR + 6, 1));
+
+        // Without the quirk draws don't wait
+        let mut e = Builder::new()
+            .with_clock(Box::new(clock.clone()))
+            .load_bytes(&rom)
+            .unwrap();
+        e.run_for(RunLimit::Ticks(3));
+        assert_eq!(e.registries[1], 1);
     }
 
-    /// Decrement timers at a rate of 60hz, when a timer reaches
-    /// zero this does nothing. The upper bound is 1 decrement per instruction
-    /// execution
-    fn decrement_timers(&mut self) {
-        if self.delay_timer > 0 {
-            if let Some(last_delay_decrement) = self.last_delay_decrement {
-                if last_delay_decrement.elapsed().as_micros() > TIME_BETWEEN_DECREMENT {
-                    self.delay_timer -= 1;
-                    self.last_delay_decrement = Some(Instant::now());
-                }
+    #[test]
+    fn test_snapshot() {
+        // ldb r1 9, sound r1, draw r0 r0 1
+        let rom = [0x61, 0x09, 0xF1, 0x18, 0xD0, 0x01];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        e.run_for(RunLimit::Ticks(3));
+        let snapshot = e.create_snapshot();
+        assert_eq!(snapshot.sound_timer, 9);
+        assert_eq!(snapshot.memory, None);
+        assert_eq!(snapshot.graphics_frame, e.graphics_frame());
 
-                if self.delay_timer == 0 {
-                    // Reached zero now, remove last delay decrement
-                    self.last_delay_decrement = None;
-                }
-            } else {
-                // Just started a delay timer, start keeping track of time
-                self.last_delay_decrement = Some(Instant::now());
-            }
+        let memory = e.snapshot(true).memory.unwrap();
+        assert_eq!(memory.len(), MEMSIZE);
+        assert_eq!(&memory[START_ADDR..START_ADDR + 6], &rom);
+    }
+
+    #[test]
+    fn test_tick_outcome() {
+        let mut e = Builder::new().build();
+        // wkey r1, halt, ret
+        e.load(Cursor::new(vec![0xF1, 0x0A, 0x00, 0xFD, 0x00, 0xEE]))
+            .unwrap();
+        assert_eq!(e.tick().unwrap(), TickOutcome::WaitingForKey);
+        assert_eq!(e.tick().unwrap(), TickOutcome::WaitingForKey);
+        e.key_pressed(5.into());
+        assert_eq!(e.tick().unwrap(), TickOutcome::Continue);
+        assert_eq!(e.registries[1], 5);
+        assert_eq!(e.tick().unwrap(), TickOutcome::Exit);
+        assert!(matches!(e.tick(), Err(EmulatorError::StackEmpty)));
+        assert!(matches!(
+            e.execute(Instruction::Raw(0x5001)),
+            Err(EmulatorError::InvalidOpcode(_))
+        ));
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(
+            EmulatorError::InvalidAddress(0x1000).to_string(),
+            "invalid address 0x1000"
+        );
+        let (sender, receiver) = std::sync::mpsc::channel::<Message>();
+        drop(receiver);
+        let error: EmulatorError = sender.send(Message::Pause).unwrap_err().into();
+        assert!(matches!(error, EmulatorError::Disconnected));
+        let io = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert_eq!(EmulatorError::from(io).to_string(), "missing");
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut e = Builder::new().with_trace(2).build();
+        // ldb r1 5, ldi 0x300, add r1 1, ret
+        e.load(Cursor::new(vec![
+            0x61, 0x05, 0xA3, 0x00, 0x71, 0x01, 0x00, 0xEE,
+        ]))
+        .unwrap();
+        while let Ok(TickOutcome::Continue) = e.tick() {}
+
+        let trace = e.trace();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0].program_counter, START_ADDR + 4);
+        assert_eq!(
+            trace[0].registers,
+            vec![RegisterDelta {
+                register: 1,
+                before: 5,
+                after: 6
+            }]
+        );
+        assert_eq!(trace[0].address_register, None);
+        // The failing return is recorded
+        assert_eq!(trace[1].instruction, Instruction::Return);
+
+        let mut lines = Vec::new();
+        export_trace(&trace, &mut lines).unwrap();
+        assert_eq!(
+            String::from_utf8(lines).unwrap(),
+            "{\"pc\":516,\"opcode\":\"7101\",\"instruction\":\"add r1 1\",\"registers\":[{\"register\":1,\"before\":5,\"after\":6}],\"i\":null}
+{\"pc\":518,\"opcode\":\"00ee\",\"instruction\":\"ret\",\"registers\":[],\"i\":null}
+"
+        );
+        for entry in &trace {
+            serde_json::from_str::<serde_json::Value>(&entry.to_json()).unwrap();
         }
-        if self.sound_timer > 0 {
-            if let Some(last_sound_decrement) = self.last_sound_decrement {
-                if last_sound_decrement.elapsed().as_micros() > TIME_BETWEEN_DECREMENT {
-                    self.sound_timer -= 1;
-                    self.last_sound_decrement = Some(Instant::now());
-                }
 
-                if self.sound_timer == 0 {
-                    self.last_sound_decrement = None;
-                }
-            } else {
-                self.last_sound_decrement = Some(Instant::now());
-            }
+        e.set_trace_size(0);
+        assert!(e.trace().is_empty());
+    }
+
+    #[test]
+    fn test_json_string() {
+        let value = "\u{1b}[31m it's a \"quote\" \\ \n\u{0}";
+        let json = json_string(value);
+        assert_eq!(json, "\"\\u001b[31m it's a \\\"quote\\\" \\\\ \\n\\u0000\"");
+        assert_eq!(serde_json::from_str::<String>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn test_debug_output() {
+        let mut e = Builder::new().build();
+        e.write_memory(0x300, b"hello\0world").unwrap();
+        e.address_register = 0x300;
+        e.registries[0] = 42;
+        for value in [2, 3, 4, 1] {
+            e.execute(Instruction::Debug(value.into())).unwrap();
+        }
+        assert_eq!(
+            e.take_debug_output(),
+            vec![
+                "hello".to_string(),
+                "42".to_string(),
+                format!("{:?}", e.registries)
+            ]
+        );
+        assert!(e.take_debug_output().is_empty());
+
+        // Strings end at the end of memory, old lines are dropped
+        e.write_memory(MEMSIZE - 2, b"ab").unwrap();
+        e.address_register = MEMSIZE - 2;
+        for _ in 0..DEBUG_OUTPUT_LINES + 1 {
+            e.execute(Instruction::Debug(2.into())).unwrap();
         }
+        let output = e.take_debug_output();
+        assert_eq!(output.len(), DEBUG_OUTPUT_LINES);
+        assert_eq!(output[0], "ab");
     }
 
-    pub fn copy_graphics_buffer(&self) -> [u8; GRAPHICS_BUFFER_SIZE] {
-        self.graphics_buffer
+    #[test]
+    fn test_stats() {
+        // draw r0 r0 1, wkey r1, add r0 1, jmp 0x204
+        let rom = [0xD0, 0x01, 0xF1, 0x0A, 0x70, 0x01, 0x12, 0x04];
+        let clock = ManualClock::new();
+        let mut e = Builder::new()
+            .with_clock(Box::new(clock.clone()))
+            .load_bytes(&rom)
+            .unwrap();
+        assert_eq!(e.stats(), Stats::default());
+        let tick = |e: &mut Emulator| {
+            clock.advance(Duration::from_millis(1));
+            e.tick().unwrap()
+        };
+        for _ in 0..4 {
+            tick(&mut e);
+        }
+        e.key_pressed(2.into());
+        assert_eq!(tick(&mut e), TickOutcome::Continue);
+        for _ in 0..5 {
+            tick(&mut e);
+        }
+        let stats = e.stats();
+        assert_eq!(stats.instructions, 7);
+        assert_eq!(stats.frames_drawn, 1);
+        assert_eq!(stats.elapsed, Duration::from_millis(9));
+        assert_eq!(stats.key_wait, Duration::from_millis(3));
+        assert!((stats.hertz() - 7.0 / 0.006).abs() < 0.001);
+
+        // Draws within a frame count once
+        e.execute(Instruction::Clear).unwrap();
+        assert_eq!(e.stats().frames_drawn, 1);
+        clock.advance(FRAME_DURATION);
+        e.execute(Instruction::Clear).unwrap();
+        e.execute(Instruction::Clear).unwrap();
+        assert_eq!(e.stats().frames_drawn, 2);
+
+        e.reset();
+        assert_eq!(e.stats(), Stats::default());
     }
 
-    pub fn create_snapshot(&self) -> Snapshot {
-        Snapshot {
-            registries: self.registries.clone(),
-            program_counter: self.program_counter.clone(),
-            address_register: self.address_register.clone(),
-            delay_timer: self.delay_timer.clone(),
-            stack: self.stack.clone(),
-            stack_pointer: self.stack_pointer.clone(),
-            key_status: self.key_status.clone(),
-            instruction: match self.instruction() {
-                Ok(i) => Some(i),
-                Err(_) => None,
-            },
+    #[test]
+    fn test_profile() {
+        let clock = ManualClock::new();
+        let mut e = Builder::new()
+            .with_clock(Box::new(clock.clone()))
+            .with_profiling(true)
+            .build();
+        // ldb r1 1, jmp 0x202
+        e.load(Cursor::new(vec![0x61, 0x01, 0x12, 0x02])).unwrap();
+        for _ in 0..3 {
+            e.tick().unwrap();
         }
+        clock.advance(Duration::from_millis(20));
+        for _ in 0..2 {
+            e.tick().unwrap();
+        }
+
+        let report = e.profile_report().unwrap();
+        assert_eq!(report.instructions, 5);
+        assert_eq!(report.elapsed, Duration::from_millis(20));
+        assert_eq!(report.hertz(), 250.0);
+        assert_eq!(report.addresses, vec![(0x202, 4), (0x200, 1)]);
+        assert_eq!(report.hottest(1), &[(0x202, 4)]);
+        let frames: Vec<usize> = report.frames.iter().map(|f| f.instructions).collect();
+        assert_eq!(frames, vec![3, 2]);
+
+        e.set_profiling(false);
+        assert_eq!(e.profile_report(), None);
     }
 
-    pub fn set_key(&mut self, key: u4, status: KeyStatus) {
-        info!(?key, ?status, "key event");
-        self.key_status[key.value() as usize] = status;
+    #[test]
+    fn test_peek_poke() {
+        let mut e = Builder::new().build();
+        e.write_memory(0x300, &[0x61, 0x2a]).unwrap();
+        assert_eq!(e.read_memory(0x300..0x302).unwrap(), &[0x61, 0x2a]);
+        assert!(matches!(
+            e.write_memory(MEMSIZE - 1, &[1, 2]),
+            Err(EmulatorError::InvalidAddress(_))
+        ));
+        assert_eq!(e.memory[MEMSIZE - 1], 0);
+        assert!(matches!(
+            e.read_memory(MEMSIZE - 1..MEMSIZE + 1),
+            Err(EmulatorError::InvalidAddress(_))
+        ));
+
+        e.set_register(2.into(), 7);
+        e.set_pc(0x300).unwrap();
+        assert!(e.set_pc(MEMSIZE - 1).is_err());
+        e.tick().unwrap();
+        assert_eq!(e.registries[1], 0x2a);
+        assert_eq!(e.registries[2], 7);
+        assert_eq!(e.program_counter, 0x302);
     }
 
-    pub fn key_pressed(&mut self, key: u4) {
-        self.set_key(key, KeyStatus::Pressed);
+    #[test]
+    fn test_messages() {
+        // jmp 0x200
+        let e = Builder::new().load_bytes(&[0x12, 0x00]).unwrap();
+        let machine = Machine::start(e);
+
+        assert!(machine
+            .request(|s| Message::SetBreakpoint(0x400, s))
+            .unwrap());
+        assert!(!machine
+            .request(|s| Message::SetBreakpoint(0x400, s))
+            .unwrap());
+        assert_eq!(
+            machine.request(Message::SendBreakpoints).unwrap(),
+            vec![0x400]
+        );
+        assert!(machine
+            .request(|s| Message::ClearBreakpoint(0x400, s))
+            .unwrap());
+
+        // ldb r1 0x2a, jmp 0x302
+        let program = vec![0x61, 0x2a, 0x13, 0x02];
+        machine
+            .request(|s| Message::WriteMemory(0x300, program, s))
+            .unwrap()
+            .unwrap();
+        machine
+            .request(|s| Message::SetProgramCounter(0x300, s))
+            .unwrap()
+            .unwrap();
+        assert!(machine
+            .request(|s| Message::SetAddressRegister(MEMSIZE, s))
+            .unwrap()
+            .is_err());
+        machine
+            .request(|s| Message::SetRegister(2.into(), 7, s))
+            .unwrap();
+        assert_eq!(
+            machine.request(Message::Step).unwrap().unwrap(),
+            TickOutcome::Continue
+        );
+
+        let snapshot = machine.request(|s| Message::SendSnapshot(true, s)).unwrap();
+        assert_eq!(snapshot.registries[1], 0x2a);
+        assert_eq!(snapshot.registries[2], 7);
+        assert_eq!(snapshot.program_counter, 0x302);
+        assert_eq!(snapshot.memory.unwrap()[0x301], 0x2a);
+
+        // The reset program is zeroed memory, failing and suspending the thread
+        machine.request(Message::Reset).unwrap();
+        let e = machine.stop().unwrap();
+        assert_eq!(e.registries, [0; REGISTRY_COUNT]);
+        assert_eq!(e.read_memory(0x300..0x302).unwrap(), &[0, 0]);
     }
 
-    pub fn key_up(&mut self, key: u4) {
-        self.set_key(key, KeyStatus::Up);
+    #[test]
+    fn test_subscribe() {
+        // ldi 0, drw r0 r0 5, jmp 0x204
+        let rom = [0xA0, 0x00, 0xD0, 0x05, 0x12, 0x04];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        let (first, first_frames) = std::sync::mpsc::channel();
+        let (second, second_frames) = std::sync::mpsc::channel();
+        e.subscribe(first);
+        let initial = first_frames.recv().unwrap();
+        assert!(!initial.buffer.get(0, 0));
+        let (dropped, dropped_frames) = std::sync::mpsc::channel();
+        e.subscribe(dropped);
+        drop(dropped_frames);
+
+        let machine = Machine::start(e);
+        machine.send(Message::Subscribe(second)).unwrap();
+        assert!(second_frames.recv().is_ok());
+        let drawn = first_frames.recv().unwrap();
+        assert!(drawn.number > initial.number);
+        assert!(drawn.buffer.get(0, 0));
+
+        let e = machine.stop().unwrap();
+        // The hung up subscriber was dropped
+        assert_eq!(e.subscribers.len(), 2);
     }
 
-    /// runs the emulator in a separate thread
-    pub fn run(self, receiver: Option<Receiver<Message>>) -> JoinHandle<Emulator> {
-        thread::spawn(move || {
-            let mut owned = self;
-            owned.receiver = receiver;
-            owned.threaded_run();
-            if let Some(recv) = owned.receiver.take() {
-                // Deallocating receiver allows the blocked send to unblock
-                drop(recv);
-            }
-            owned
-        })
+    #[test]
+    fn test_crash_report() {
+        let dir = std::env::temp_dir().join(format!("chip8-crash-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // ldb r1 7, ret
+        let rom = [0x61, 0x07, 0x00, 0xEE];
+        let e = Builder::new()
+            .with_trace(4)
+            .with_crash_dir(&dir)
+            .load_bytes(&rom)
+            .unwrap();
+        assert_eq!(e.rom_hash(), fnv1a(rom));
+        let machine = Machine::start(e);
+        while !machine.is_suspended().unwrap() {}
+        let path = machine.crash_report().unwrap().unwrap();
+        assert!(path.starts_with(&dir));
+        let report = std::fs::read_to_string(&path).unwrap();
+        assert!(report.contains("\"error\":\"return with an empty stack\""));
+        assert!(report.contains(&format!("\"rom_hash\":\"{:016x}\"", fnv1a(rom))));
+        assert!(report.contains("\"stack_fault\":\"Error\""));
+        assert!(report.contains("\"instruction\":\"ldb r1 7\""));
+        serde_json::from_str::<serde_json::Value>(&report).unwrap();
+
+        let e = machine.stop().unwrap();
+        assert_eq!(e.crash_report_path(), Some(path.as_path()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Arbitrary panic messages stay valid JSON
+        let error = EmulatorError::Panicked("\u{1b}[1m'bold' \"text\"\n".to_string());
+        let report = e.crash_report(&error).to_json();
+        let json: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(json["error"], error.to_string());
+        assert_eq!(json["trace"][0]["instruction"], "ldb r1 7");
+
+        // Without a directory nothing is written
+        let machine = Machine::start(Builder::new().load_bytes(&rom).unwrap());
+        while !machine.is_suspended().unwrap() {}
+        assert_eq!(machine.crash_report().unwrap(), None);
+        machine.stop().unwrap();
     }
 
-    // the main loop of the emulator when executing in a thread
-    fn threaded_run(&mut self) {
-        let delay_per_second = 1_000_000_000;
-        let delay_per_timebox = (delay_per_second / self.timeboxes) as u128;
-        let ticks_per_timebox = self.hertz / self.timeboxes;
+    #[test]
+    fn test_suspend() {
+        // add r0 1, jmp 0x200
+        let e = Builder::new()
+            .load_bytes(&[0x70, 0x01, 0x12, 0x00])
+            .unwrap();
+        let machine = Machine::start(e);
+        assert!(!machine.request(Message::SendSuspended).unwrap());
 
-        info!(%ticks_per_timebox, %delay_per_timebox, "starting chip-8 machine");
-        let mut ticks = 0;
-        let mut last_tick = Instant::now();
-        loop {
-            if ticks < ticks_per_timebox {
-                // keep ticking while we're allowed in the timebox
-                // check and handle any message requests if a receiver
-                // exists
-                if let Some(receiver) = &self.receiver {
-                    let should_abort = match receiver.try_recv() {
-                        Ok(message) => self.process_message(message),
-                        Err(_) => {
-                            // error!(%error, "failed polling receiver for message");
-                            false
-                        }
-                    };
-                    if should_abort {
-                        break;
-                    }
-                }
+        machine.send(Message::Pause).unwrap();
+        assert!(machine.request(Message::SendSuspended).unwrap());
+        let paused = machine
+            .request(|s| Message::SendSnapshot(false, s))
+            .unwrap();
+        // Suspended threads still step
+        machine.request(Message::Step).unwrap().unwrap();
+        let stepped = machine
+            .request(|s| Message::SendSnapshot(false, s))
+            .unwrap();
+        assert_ne!(stepped.program_counter, paused.program_counter);
+        std::thread::sleep(Duration::from_millis(20));
+        let snapshot = machine
+            .request(|s| Message::SendSnapshot(false, s))
+            .unwrap();
+        assert_eq!(snapshot.program_counter, stepped.program_counter);
+        assert_eq!(snapshot.registries, stepped.registries);
 
-                match self.tick() {
-                    Ok(true) => {}
-                    Ok(false) => {
-                        // Breakpoint hit, pause execution
-                        break;
-                    }
-                    Err(error) => {
-                        error!(%error, "pausing emulator execution");
-                        break;
-                    }
-                }
-                ticks += 1;
-            } else {
-                if last_tick.elapsed().as_nanos() < delay_per_timebox {
-                    // listen for message requests, or if no receiver is configured sleep,
-                    // until we can execute more ticks
-                    let timeout = delay_per_timebox - last_tick.elapsed().as_nanos();
-                    if let Some(receiver) = &self.receiver {
-                        let should_abort =
-                            match receiver.recv_timeout(Duration::from_nanos(timeout as u64)) {
-                                Ok(message) => self.process_message(message),
-                                Err(_) => {
-                                    // error!(%error, "failed polling receiver for message");
-                                    false
-                                }
-                            };
-                        if should_abort {
-                            break;
-                        }
-                    } else {
-                        thread::sleep(Duration::from_nanos(timeout as u64));
-                    }
-                }
-                ticks = 0;
-                last_tick = Instant::now();
+        // Breakpoints suspend the execution, resuming executes past them
+        machine.send(Message::Resume).unwrap();
+        assert!(machine
+            .request(|s| Message::SetBreakpoint(0x202, s))
+            .unwrap());
+        while !machine.request(Message::SendSuspended).unwrap() {}
+        let snapshot = machine
+            .request(|s| Message::SendSnapshot(false, s))
+            .unwrap();
+        assert_eq!(snapshot.program_counter, 0x202);
+        machine.send(Message::Resume).unwrap();
+        while machine
+            .request(|s| Message::SendSnapshot(false, s))
+            .unwrap()
+            .registries[0]
+            == snapshot.registries[0]
+        {}
+
+        machine.stop().unwrap();
+    }
+
+    #[test]
+    fn test_stack_fault() {
+        // call 0x200
+        let rom = [0x22, 0x00];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        let report = e.run_until(|_| false);
+        match report.stop_reason {
+            StopReason::Error(EmulatorError::StackFull(stack)) => {
+                assert_eq!(stack, vec![START_ADDR + 2; STACK_SIZE]);
             }
+            reason => panic!("unexpected stop {:?}", reason),
         }
-        info!("pausing chip-8 machine");
+
+        let mut e = Builder::new()
+            .with_stack_fault(StackFault::Wrap)
+            .load_bytes(&rom)
+            .unwrap();
+        let report = e.run_for(RunLimit::Ticks(STACK_SIZE as u64 + 2));
+        assert!(matches!(report.stop_reason, StopReason::Limit));
+        assert_eq!(e.stack_pointer, 2);
+        // ret
+        let mut e = Builder::new()
+            .with_stack_fault(StackFault::Wrap)
+            .load_bytes(&[0x00, 0xEE])
+            .unwrap();
+        e.stack[STACK_SIZE - 1] = 0x300;
+        e.tick().unwrap();
+        assert_eq!(
+            (e.program_counter, e.stack_pointer),
+            (0x300, STACK_SIZE - 1)
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut e = Builder::new()
+            .with_stack_fault(StackFault::Hook)
+            .with_observer(Box::new(RecordingObserver(events.clone())))
+            .load_bytes(&rom)
+            .unwrap();
+        e.run_for(RunLimit::Ticks(STACK_SIZE as u64 + 1));
+        // The faulting call is skipped
+        assert_eq!(e.program_counter, START_ADDR + 2);
+        assert_eq!(e.stack_pointer, STACK_SIZE);
+        let faults: Vec<String> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.starts_with("fault"))
+            .cloned()
+            .collect();
+        assert_eq!(faults.len(), 1);
+        assert!(faults[0].contains("202"), "{}", faults[0]);
     }
 
-    fn process_message(&mut self, message: Message) -> bool {
-        match message {
-            Message::Pause => {
-                info!("received pause");
-                return true;
-            }
-            Message::SendGraphics(channel) => {
-                match channel.send(self.copy_graphics_buffer()) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        info!("failed to send graphics buffer, terminating");
-                        return true;
-                    }
-                };
-            }
-            Message::KeyEvent(key, status) => {
-                info!(key = ?key, status = ?status, "received key event");
-                self.set_key(key, status);
+    #[test]
+    fn test_memory_protection() {
+        // ldi 0x1ff, bcd r1, ldi 0x300, str r2, jmp 0x208
+        let rom = vec![
+            0xA1, 0xFF, 0xF1, 0x33, 0xA3, 0x00, 0xF2, 0x55, 0x12, 0x08, 0x00, 0x00,
+        ];
+        let run = |protection| {
+            let mut e = Builder::new().with_memory_protection(protection).build();
+            e.load(Cursor::new(&rom)).unwrap();
+            let mut results = Vec::new();
+            for _ in 0..5 {
+                results.push(e.tick());
             }
+            (e, results)
         };
-        false
-    }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let (e, results) = run(MemoryProtection::Error);
+        assert!(matches!(
+            results[1],
+            Err(EmulatorError::ProtectedWrite(0x1ff))
+        ));
+        assert_eq!(e.memory[0x1ff], 0);
 
-    use crate::assembly::lexer::StreamLexer;
-    use crate::assembly::parser::Parser;
-    use std::io::{BufReader, Cursor};
+        let (e, results) = run(MemoryProtection::Warn);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(e.program_counter, 0x208);
 
-    fn create_execute(input: &'static str) -> Emulator {
-        let reader = BufReader::new(input.as_bytes());
-        let lexer = StreamLexer::new(reader);
-        let mut parser = Parser::new(Box::new(lexer));
-        let binary = parser.parse().unwrap().binary().unwrap();
-        let mut emulator = Emulator::new(400, 100);
-        let cursor = Cursor::new(binary);
-        emulator.load(cursor).unwrap();
-        loop {
-            match emulator.tick() {
-                Ok(false) => break,
-                Err(_) => break,
-                _ => {}
-            }
-        }
-        emulator
+        let mut e = Builder::new()
+            .with_memory_protection(MemoryProtection::Error)
+            .build();
+        e.load(Cursor::new(&rom[4..8])).unwrap();
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert!(matches!(
+            e.tick(),
+            Err(EmulatorError::ExecutionOutOfBounds(0x204))
+        ));
     }
 
-    fn reg_value(emu: &Emulator, index: usize) -> u8 {
-        emu.registries[index]
+    #[test]
+    fn test_register_transfer() {
+        let mut e = Builder::new().build();
+        e.registries[..3].copy_from_slice(&[1, 2, 3]);
+        e.address_register = 0x300;
+        // str and ldr include the last register
+        e.execute(Instruction::MemWrite(2.into())).unwrap();
+        assert_eq!(e.read_memory(0x300..0x304).unwrap(), &[1, 2, 3, 0]);
+        e.registries = [0; REGISTRY_COUNT];
+        e.execute(Instruction::MemRead(0.into())).unwrap();
+        assert_eq!(e.registries[..2], [1, 0]);
+        e.execute(Instruction::MemRead(2.into())).unwrap();
+        assert_eq!(e.registries[..4], [1, 2, 3, 0]);
+
+        // ldf only uses the low nibble
+        e.registries[0] = 0x32;
+        e.execute(Instruction::SetMemRegisterDefaultSprit(0.into()))
+            .unwrap();
+        assert_eq!(e.address_register, DEFAULT_SPRITE_START_ADDR + 2 * 5);
     }
 
     #[test]
-    fn test_add() {
-        let e = create_execute(
-            "
-            ldb r2 1
-            ldb r1 0
-            add r1 2
-            add r1 10
-            add r2 4
-            exit",
+    fn test_subtract_and_shift() {
+        let mut e = Builder::new().build();
+        // The flag is 1 unless the subtraction borrowed
+        e.registries[..2].copy_from_slice(&[5, 3]);
+        e.execute(Instruction::SubChecked(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!((e.registries[0], e.registries[0xF]), (2, 1));
+        e.execute(Instruction::SubChecked(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!((e.registries[0], e.registries[0xF]), (0xFF, 0));
+        // subn stores Y - X in X
+        e.registries[..2].copy_from_slice(&[3, 5]);
+        e.execute(Instruction::SubNChecked(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!(
+            (e.registries[0], e.registries[1], e.registries[0xF]),
+            (2, 5, 1)
         );
-        assert_eq!(reg_value(&e, 1), 12);
-        assert_eq!(reg_value(&e, 2), 5);
+
+        // Shifts store the shifted Y in X, with the shifted out bit in the
+        // flag
+        e.registries[..2].copy_from_slice(&[0, 0x81]);
+        e.execute(Instruction::ShiftRight(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!((e.registries[0], e.registries[0xF]), (0x40, 1));
+        e.execute(Instruction::ShiftLeft(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!((e.registries[0], e.registries[0xF]), (0x02, 1));
+
+        let mut e = Builder::new().with_quirks(Quirks::schip_1_1()).build();
+        e.registries[..2].copy_from_slice(&[0x40, 0x81]);
+        e.execute(Instruction::ShiftLeft(0.into(), 1.into()))
+            .unwrap();
+        assert_eq!((e.registries[0], e.registries[0xF]), (0x80, 0));
     }
 
     #[test]
-    fn test_branch_jmp_sne() {
-        let e = create_execute(
-            "
-            ldb r1 0
-            add r2 0
-        loop:
-            sne r1 4
-            jmp exit
-            add r1 1
-            add r2 4
-            jmp loop
-        exit:
-            exit",
-        );
-        assert_eq!(reg_value(&e, 1), 4);
-        assert_eq!(reg_value(&e, 2), 16);
+    fn test_access_past_memory() {
+        // ldi 0xfff, followed by an access reaching past the memory
+        for access in [
+            [0xD0, 0x0F], // draw r0 r0 15
+            [0xF0, 0x33], // bcd r0
+            [0xF1, 0x55], // str r1
+            [0xF1, 0x65], // ldr r1
+            [0x1F, 0xFF], // jmp 0xfff, the opcode is cut off
+        ] {
+            let mut e = Builder::new()
+                .load_bytes(&[0xAF, 0xFF, access[0], access[1]])
+                .unwrap();
+            e.tick().unwrap();
+            let result = e.tick().and_then(|_| e.tick());
+            assert!(
+                matches!(result, Err(EmulatorError::InvalidAddress(_))),
+                "{:02x?}: {:?}",
+                access,
+                result
+            );
+            assert!(e.copy_graphics_buffer().iter_rows().flatten().all(|p| !p));
+        }
+        // The last byte is still in reach
+        let mut e = Builder::new()
+            .load_bytes(&[0xAF, 0xFF, 0xF0, 0x55, 0xF0, 0x65])
+            .unwrap();
+        e.run_for(RunLimit::Ticks(3));
+        assert_eq!(e.program_counter, START_ADDR + 6);
     }
 
     #[test]
-    fn test_call_ret() {
-        let e = create_execute(
-            "
-        main:
-            ldb r1 0
-            add r1 2
-            call func1
-            call func2
-            call func1
-            exit
+    fn test_segments() {
+        // jmp 0x400
+        let mut e = Builder::new()
+            .with_memory_protection(MemoryProtection::Error)
+            .load_bytes(&[0x14, 0x00])
+            .unwrap();
+        // ldb r1 5, jmp 0x200
+        e.load_segment(0x400, [0x61, 0x05, 0x12, 0x00]).unwrap();
+        for _ in 0..4 {
+            e.tick().unwrap();
+        }
+        assert_eq!((e.registries[1], e.program_counter), (5, 0x400));
 
-        func1:
-            add r1 4
-            call func2
-            ret
-        
-        func2:
-            add r1 2
-            ret",
+        // Segments are loaded again over later programs
+        e.load_segment(0x202, [0x62, 0x07]).unwrap();
+        e.load(Cursor::new(vec![0x00, 0xE0, 0x62, 0x01])).unwrap();
+        assert_eq!(
+            e.read_memory(0x200..0x204).unwrap(),
+            &[0x00, 0xE0, 0x62, 0x07]
         );
-        assert_eq!(reg_value(&e, 1), 16);
+        assert_eq!(e.read_memory(0x400..0x402).unwrap(), &[0x61, 0x05]);
+
+        assert!(matches!(
+            e.load_segment(MEMSIZE - 1, [1, 2]),
+            Err(EmulatorError::InvalidAddress(_))
+        ));
+        e.clear_segments();
+        e.load(Cursor::new(vec![0x00, 0xE0, 0x62, 0x01])).unwrap();
+        assert_eq!(e.read_memory(0x202..0x204).unwrap(), &[0x62, 0x01]);
+        assert_eq!(e.read_memory(0x400..0x402).unwrap(), &[0, 0]);
     }
 
     #[test]
-    fn test_default_sprites() {
-        // address registry should point at default sprite "1"
-        let e = create_execute(
-            "
-        main:
-            ldb r1 1
-            ldf r1
-            ",
+    fn test_memory_size() {
+        let mut e = Builder::new().with_memory_size(XO_CHIP_MEMSIZE).build();
+        assert_eq!(e.memory_size(), XO_CHIP_MEMSIZE);
+        // i := long 0x8000, se r0 0, i := long 0x1234, ldb r1 5
+        let mut rom = vec![0xF0, 0x00, 0x80, 0x00, 0x30, 0x00];
+        rom.extend([0xF0, 0x00, 0x12, 0x34, 0x61, 0x05]);
+        // A ROM larger than the default memory loads completely
+        rom.resize(MEMSIZE, 0);
+        rom.push(0xAB);
+        e.load(Cursor::new(&rom)).unwrap();
+        assert_eq!(
+            e.read_memory(START_ADDR + MEMSIZE..START_ADDR + MEMSIZE + 1)
+                .unwrap(),
+            &[0xAB]
         );
 
-        let bytes = e.copy_bytes(e.address_register, 5);
-        assert_eq!(bytes[0], 0x20, "byte {} is invalid", 0);
-        assert_eq!(bytes[1], 0x60, "byte {} is invalid", 1);
-        assert_eq!(bytes[2], 0x20, "byte {} is invalid", 2);
-        assert_eq!(bytes[3], 0x20, "byte {} is invalid", 3);
-        assert_eq!(bytes[4], 0x70, "byte {} is invalid", 4);
+        e.tick().unwrap();
+        assert_eq!(e.address_register, 0x8000);
+        assert_eq!(e.program_counter, START_ADDR + 4);
+        // The skip steps over the address as well
+        e.tick().unwrap();
+        e.tick().unwrap();
+        assert_eq!(e.registries[1], 5);
+        assert_eq!(e.address_register, 0x8000);
+
+        // An address outside of the default memory
+        let mut e = Builder::new().build();
+        e.load(Cursor::new(vec![0xF0, 0x00, 0x80, 0x00])).unwrap();
+        assert!(matches!(
+            e.tick(),
+            Err(EmulatorError::InvalidAddress(0x8000))
+        ));
+        assert_eq!(
+            Builder::new().with_memory_size(1).build().memory_size(),
+            MEMSIZE
+        );
     }
 
     #[test]
-    fn test_draw_simple() {
-        // point address registry to 1 and render it on (0, 0)
-        let e = create_execute(
-            "
-        main:
-            ldb r1 1
-            ldf r1
-            ldb r1 0
-            ldb r2 0
-            draw r1 r2 5
-            ",
-        );
+    fn test_load_bytes() {
+        // ldb r1 5
+        let rom = [0x61, 0x05];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        e.tick().unwrap();
+        assert_eq!(e.registries[1], 5);
+        let e = Builder::new().load_reader(&rom[..]).unwrap();
+        assert_eq!(e.read_memory(START_ADDR..START_ADDR + 2).unwrap(), &rom);
 
-        assert_eq!(e.graphics_buffer[0], 0x20, "byte {} is invalid", 0);
-        assert_eq!(e.graphics_buffer[8], 0x60, "byte {} is invalid", 8);
-        assert_eq!(e.graphics_buffer[16], 0x20, "byte {} is invalid", 16);
-        assert_eq!(e.graphics_buffer[24], 0x20, "byte {} is invalid", 24);
-        assert_eq!(e.graphics_buffer[32], 0x70, "byte {} is invalid", 32);
-        for i in 0..GRAPHICS_BUFFER_SIZE {
-            match i {
-                0 | 8 | 16 | 24 | 32 => continue,
-                x => assert_eq!(
-                    e.graphics_buffer[i], 0x00,
-                    "byte {} is invalid (0x{:02x})",
-                    x, e.graphics_buffer[i]
-                ),
+        let mut e = Builder::new()
+            .with_memory_protection(MemoryProtection::Error)
+            .build();
+        e.load_rom_at(&rom, 0x600).unwrap();
+        assert_eq!(e.program_counter, 0x600);
+        e.tick().unwrap();
+        assert_eq!(e.registries[1], 5);
+        assert!(matches!(
+            e.load_rom_at(&rom, MEMSIZE - 1),
+            Err(EmulatorError::RomTooLarge { size: 2, max: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_input_recording() {
+        // wkey r1, rnd r2 0xff, jmp 0x202
+        let rom = [0xF1, 0x0A, 0xC2, 0xFF, 0x12, 0x02];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        e.start_recording();
+        for tick in 0..12 {
+            match tick {
+                3 => e.key_pressed(7.into()),
+                5 => e.key_up(7.into()),
+                _ => {}
             }
+            e.tick().unwrap();
+        }
+        let recording = e.stop_recording().unwrap();
+        assert_eq!(recording.events.len(), 2);
+        assert_eq!(recording.events[0].tick, 3);
+
+        let mut bytes = Vec::new();
+        recording.save(&mut bytes).unwrap();
+        let loaded = InputRecording::load(&bytes[..]).unwrap();
+        assert_eq!(loaded, recording);
+
+        let mut replayed = Builder::new().load_bytes(&rom).unwrap();
+        replayed.replay(loaded);
+        for _ in 0..12 {
+            replayed.tick().unwrap();
         }
+        assert!(!replayed.is_replaying());
+        assert_eq!(replayed.registries, e.registries);
+        assert_eq!(replayed.registries[1], 7);
+        assert_eq!(replayed.key_status, e.key_status);
+
+        assert!(matches!(
+            InputRecording::load(&b"chip8-input 1\nseed 1\n3 g up\n"[..]),
+            Err(EmulatorError::InvalidRecording(_))
+        ));
     }
 
     #[test]
-    fn test_draw_wrapping() {
-        // point address registry to 1 and render it on (0, 0)
-        let e = create_execute(
-            "
-        main:
-            ldb r1 1
-            ldf r1
-            ldb r1 60
-            ldb r2 0
-            draw r1 r2 5
-            ",
+    fn test_seed() {
+        // rnd r0 0xff, rnd r1 0xff
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF];
+        let run = || {
+            let mut e = Builder::new().with_seed(3).load_bytes(&rom).unwrap();
+            e.run_for(RunLimit::Ticks(2));
+            e.registries
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_restart() {
+        // ldb r0 0xff, ldi 0x600, str r1, draw r1 r1 1, jmp 0x608
+        let rom = [0x60, 0xFF, 0xA6, 0x00, 0xF1, 0x55, 0xD1, 0x11, 0x16, 0x08];
+        let mut e = Builder::new()
+            .with_start_addr(0x600)
+            .load_bytes(&rom)
+            .unwrap();
+        e.load_segment(0x700, [1, 2]).unwrap();
+        e.run_for(RunLimit::Ticks(6));
+        // The program overwrote its first byte
+        assert_eq!(e.memory[0x600], 0xFF);
+        e.set_breakpoint(0x602);
+
+        e.restart().unwrap();
+        assert_eq!(e.read_memory(0x600..0x60A).unwrap(), &rom);
+        assert_eq!(e.read_memory(0x700..0x702).unwrap(), &[1, 2]);
+        assert_eq!(e.program_counter, 0x600);
+        assert_eq!(e.registries, [0; REGISTRY_COUNT]);
+        assert_eq!(e.copy_graphics_buffer(), FrameBuffer::default());
+        assert_eq!(e.breakpoints().collect::<Vec<_>>(), vec![0x602]);
+
+        // Also after a reset wiped the memory
+        e.reset();
+        let machine = Machine::start_paused(e);
+        machine.restart().unwrap();
+        let e = machine.stop().unwrap();
+        assert_eq!(e.read_memory(0x600..0x60A).unwrap(), &rom);
+    }
+
+    #[test]
+    fn test_rom_profile() {
+        let profile = Profile::parse("hertz = 900\n[quirks]\ndisplay_wait = true").unwrap();
+        let e = Builder::new()
+            .with_hertz(500)
+            .with_timeboxes(20)
+            .with_profile(profile.clone())
+            .build();
+        assert_eq!((e.hertz, e.timeboxes), (900, 20));
+        assert!(e.quirks.display_wait);
+        assert_eq!(e.profile(), Some(&profile));
+
+        let dir = std::env::temp_dir().join(format!("chip8-profile-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        std::fs::write(&rom, [0x12, 0x00]).unwrap();
+        std::fs::write(dir.join("game.toml"), "timeboxes = 10").unwrap();
+        let path = rom.to_str().unwrap();
+        let e = Builder::new().load_program(path).unwrap();
+        assert_eq!(e.timeboxes, 10);
+        let e = Builder::new()
+            .with_sidecar_profiles(false)
+            .load_program(path)
+            .unwrap();
+        assert_eq!(e.timeboxes, 100);
+        assert!(e.profile().is_none());
+
+        std::fs::write(dir.join("game.toml"), "timeboxes = many").unwrap();
+        assert!(matches!(
+            Builder::new().load_program(path),
+            Err(EmulatorError::Profile(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rom_info() {
+        let mut e = Builder::new().build();
+        assert!(e.rom_info().is_none());
+        // high, jmp 0x202
+        let rom = [0x00, 0xFF, 0x12, 0x02];
+        e.load_rom_at(&rom, START_ADDR).unwrap();
+        assert_eq!(e.rom_info(), Some(&RomInfo::new(&rom)));
+        assert_eq!(
+            e.rom_info().unwrap().target,
+            crate::instructions::Target::Schip
         );
 
-        assert_eq!(e.graphics_buffer[7], 0x02, "byte {} is invalid", 7);
-        assert_eq!(e.graphics_buffer[8], 0x00, "byte {} is invalid", 8);
-        assert_eq!(e.graphics_buffer[15], 0x06, "byte {} is invalid", 15);
-        assert_eq!(e.graphics_buffer[16], 0x00, "byte {} is invalid", 16);
-        assert_eq!(e.graphics_buffer[23], 0x02, "byte {} is invalid", 23);
-        assert_eq!(e.graphics_buffer[24], 0x00, "byte {} is invalid", 24);
-        assert_eq!(e.graphics_buffer[31], 0x02, "byte {} is invalid", 31);
-        assert_eq!(e.graphics_buffer[32], 0x00, "byte {} is invalid", 32);
-        assert_eq!(e.graphics_buffer[39], 0x07, "byte {} is invalid", 39);
-        assert_eq!(e.graphics_buffer[40], 0x00, "byte {} is invalid", 40);
-        for i in 0..GRAPHICS_BUFFER_SIZE {
-            match i {
-                7 | 8 | 15 | 16 | 23 | 24 | 31 | 32 | 39 | 40 => continue,
-                x => assert_eq!(
-                    e.graphics_buffer[i], 0x00,
-                    "byte {} is invalid (0x{:02x})",
-                    x, e.graphics_buffer[i]
-                ),
+        let machine = Machine::start_paused(e);
+        assert_eq!(machine.rom_info().unwrap(), Some(RomInfo::new(&rom)));
+        let mut e = machine.stop().unwrap();
+        e.reset();
+        assert!(e.rom_info().is_none());
+    }
+
+    #[test]
+    fn test_step_back() {
+        // rnd r0 0xff, add r1 1, ldd r1, draw r0 r1 1, jmp 0x200
+        let rom = [0xC0, 0xFF, 0x71, 0x01, 0xF1, 0x15, 0xD0, 0x11, 0x12, 0x00];
+        let emulator = |rewind: usize| {
+            let clock = ManualClock::new();
+            let mut e = Builder::new()
+                .with_clock(Box::new(clock.clone()))
+                .with_seed(7)
+                .with_trace(8)
+                .with_rewind(rewind, 4)
+                .load_bytes(&rom)
+                .unwrap();
+            e.set_breakpoint(0x206);
+            (e, clock)
+        };
+        let run = |e: &mut Emulator, clock: &ManualClock, ticks: usize| {
+            for _ in 0..ticks {
+                clock.advance(Duration::from_millis(7));
+                e.tick().unwrap();
             }
+        };
+        let assert_same = |e: &Emulator, expected: &Emulator| {
+            assert_eq!(e.registries, expected.registries);
+            assert_eq!(e.program_counter, expected.program_counter);
+            assert_eq!(e.delay_timer, expected.delay_timer);
+            assert_eq!(e.resume_address, expected.resume_address);
+            assert_eq!(e.copy_graphics_buffer(), expected.copy_graphics_buffer());
+            // Entries dropped from the full trace don't come back
+            assert!(expected.trace().ends_with(&e.trace()));
+            assert_eq!(e.stats(), expected.stats());
+        };
+
+        let (mut e, clock) = emulator(3);
+        run(&mut e, &clock, 25);
+        assert!(e.step_back());
+        assert_eq!(e.trace().len(), 7);
+        let (mut expected, expected_clock) = emulator(0);
+        run(&mut expected, &expected_clock, 24);
+        assert_same(&e, &expected);
+
+        // Back to the stop before the draw, and past it to the ldd
+        for ticks in [23, 22, 20] {
+            assert!(e.step_back());
+            let (mut expected, expected_clock) = emulator(0);
+            run(&mut expected, &expected_clock, ticks);
+            assert_same(&e, &expected);
+        }
+        assert_eq!(e.program_counter, 0x204);
+
+        // Runs the same after stepping back, the clock went on though
+        run(&mut e, &clock, 5);
+        let (mut expected, expected_clock) = emulator(0);
+        run(&mut expected, &expected_clock, 25);
+        assert_eq!(e.registries, expected.registries);
+        assert_eq!(e.program_counter, expected.program_counter);
+        assert_eq!(e.copy_graphics_buffer(), expected.copy_graphics_buffer());
+        assert_eq!(e.stats().instructions, expected.stats().instructions);
+
+        // Only as far back as the oldest checkpoint
+        let mut steps = 0;
+        while e.step_back() {
+            steps += 1;
         }
+        assert!(steps > 0);
+        assert_eq!(e.rewind.as_ref().unwrap().tick, 16);
+
+        let (mut e, clock) = emulator(0);
+        run(&mut e, &clock, 3);
+        assert!(!e.step_back());
+        let (mut e, clock) = emulator(3);
+        run(&mut e, &clock, 3);
+        e.reset();
+        assert!(!e.step_back());
     }
 
     #[test]
-    fn test_draw_no_collision() {
-        // Render 1 twice on the same location, should give a collision
-        let e = create_execute(
-            "
-        main:
-            ldb r1 1
-            ldf r1
-            ldb r1 0
-            ldb r2 0
-            draw r1 r2 5
-            ",
-        );
-        assert_eq!(e.registries[0x0F_usize], 0);
+    fn test_start_addr() {
+        let mut registers = [0; REGISTRY_COUNT];
+        registers[0] = 3;
+        // add r0 1
+        let mut e = Builder::new()
+            .with_start_addr(0x600)
+            .with_initial_registers(registers)
+            .load_bytes(&[0x70, 0x01])
+            .unwrap();
+        assert_eq!(e.program_counter, 0x600);
+        assert_eq!(e.registries[0], 3);
+        e.tick().unwrap();
+        assert_eq!(e.registries[0], 4);
+
+        // Both are restored by a reset and a load
+        e.reset();
+        assert_eq!(e.program_counter, 0x600);
+        assert_eq!(e.registries[0], 3);
+        e.load(Cursor::new(vec![0x70, 0x02])).unwrap();
+        e.tick().unwrap();
+        assert_eq!(e.registries[0], 5);
+        assert_eq!(e.read_memory(0x600..0x602).unwrap(), &[0x70, 0x02]);
     }
 
     #[test]
-    fn test_draw_collision() {
-        // Render 1 twice on the same location, should give a collision
-        let e = create_execute(
-            "
-        main:
-            ldb r1 1
-            ldf r1
-            ldb r1 0
-            ldb r2 0
-            draw r1 r2 5
-            ldb r1 1
-            ldf r1
-            ldb r1 0
-            ldb r2 0
-            draw r1 r2 5
-            ",
-        );
-        assert_eq!(e.registries[0x0F_usize], 1);
+    fn test_load_complete() {
+        // Short reads are continued until the end
+        let reader = Cursor::new([0x61]).chain(Cursor::new([0x05]));
+        let mut e = Builder::new().load_reader(reader).unwrap();
+        e.tick().unwrap();
+        assert_eq!(e.registries[1], 5);
+
+        let rom = vec![0; MEMSIZE - START_ADDR + 1];
+        assert!(matches!(
+            e.load(Cursor::new(&rom)),
+            Err(EmulatorError::RomTooLarge {
+                size: 3585,
+                max: 3584
+            })
+        ));
+        // Nothing is loaded when the program doesn't fit
+        assert_eq!(e.registries[1], 5);
+        assert!(e.load(Cursor::new(&rom[1..])).is_ok());
     }
 
     #[test]
-    fn test_delay_timer_start() {
-        let input = "
-        main:
-            ldb r1 3
-            ldb r3 0
-            delay r1
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ";
-        let reader = BufReader::new(input.as_bytes());
-        let lexer = StreamLexer::new(reader);
-        let mut parser = Parser::new(Box::new(lexer));
-        let binary = parser.parse().unwrap().binary().unwrap();
-        let mut emulator = Emulator::new(400, 100);
-        let cursor = Cursor::new(binary);
-        emulator.load(cursor).unwrap();
+    fn test_run_headless() {
+        // add r1 1, jmp 0x200
+        let rom = [0x71, 0x01, 0x12, 0x00];
+        let mut e = Builder::new().load_bytes(&rom).unwrap();
+        let report = e.run_for(RunLimit::Ticks(10));
+        assert!(matches!(report.stop_reason, StopReason::Limit));
+        assert_eq!(report.instructions, 10);
+        assert_eq!(report.snapshot.registries[1], 5);
 
-        // 3 ticks to start delay timer
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
+        let report = e.run_until(|e| e.create_snapshot().registries[1] == 8);
+        assert!(matches!(report.stop_reason, StopReason::Condition));
+        assert_eq!(report.ticks, 5);
 
-        assert!(emulator.delay_timer > 0);
+        let report = e.run_for(RunLimit::Duration(Duration::from_millis(5)));
+        assert!(matches!(report.stop_reason, StopReason::Limit));
+
+        // wkey r1
+        let mut e = Builder::new().load_bytes(&[0xF1, 0x0A]).unwrap();
+        let report = e.run_for(RunLimit::Ticks(10));
+        assert!(matches!(report.stop_reason, StopReason::WaitingForKey));
+        assert_eq!(report.instructions, 1);
+
+        // ret
+        let mut e = Builder::new().load_bytes(&[0x00, 0xEE]).unwrap();
+        let report = e.run_until(|_| false);
+        assert!(matches!(
+            report.stop_reason,
+            StopReason::Error(EmulatorError::StackEmpty)
+        ));
     }
 
     #[test]
-    fn test_delay_timer_tick() {
-        let input = "
-        main:
-            ldb r1 3
-            ldb r3 0
-            delay r1
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ";
-        let reader = BufReader::new(input.as_bytes());
-        let lexer = StreamLexer::new(reader);
-        let mut parser = Parser::new(Box::new(lexer));
-        let binary = parser.parse().unwrap().binary().unwrap();
-        let mut emulator = Emulator::new(400, 100);
-        let cursor = Cursor::new(binary);
-        emulator.load(cursor).unwrap();
+    fn test_audio_sink() {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let clock = ManualClock::new();
+        let mut e = Builder::new()
+            .with_audio_sink(Box::new(RecordingSink(events.clone())))
+            .with_clock(Box::new(clock.clone()))
+            .build();
+        e.registries[1] = 2;
+        e.execute(Instruction::SetSoundTimer(1.into())).unwrap();
+        // Setting a running timer again doesn't restart the sink
+        e.execute(Instruction::SetSoundTimer(1.into())).unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["start"]);
 
-        // 3 ticks to start delay timer
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
+        // Counting down to zero
+        e.decrement_timers();
+        for _ in 0..2 {
+            clock.advance(Duration::from_millis(20));
+            e.decrement_timers();
+        }
+        assert_eq!(*events.lock().unwrap(), vec!["start", "stop"]);
 
-        // Teleport 2 seconds into the future to force a timer decrement
-        // reaction - we're limited to one decrement per tick
-        let t = Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
-        emulator.last_delay_decrement = Some(t);
-        emulator.tick().unwrap();
-        emulator.last_delay_decrement = Some(t);
-        emulator.tick().unwrap();
-        // Two decrements should've occured
-        assert_eq!(emulator.delay_timer, 1);
+        // Setting the timer to zero, and resetting, silences it
+        e.execute(Instruction::SetSoundTimer(1.into())).unwrap();
+        e.execute(Instruction::SetSoundTimer(0.into())).unwrap();
+        e.execute(Instruction::SetSoundTimer(1.into())).unwrap();
+        e.reset();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["start", "stop", "start", "stop", "start", "stop"]
+        );
     }
 
+    #[cfg(feature = "serde")]
     #[test]
-    fn test_sound_timer_start() {
-        let input = "
-        main:
-            ldb r1 3
-            ldb r3 0
+    fn test_save_state() {
+        let mut e = create_execute(
+            "
+            opcode 0x00FF
+            ldb r1 7
+            ldf r1
+            draw r1 r1 5
+            call sub
+            sub:
             sound r1
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ";
-        let reader = BufReader::new(input.as_bytes());
-        let lexer = StreamLexer::new(reader);
-        let mut parser = Parser::new(Box::new(lexer));
-        let binary = parser.parse().unwrap().binary().unwrap();
-        let mut emulator = Emulator::new(400, 100);
-        let cursor = Cursor::new(binary);
-        emulator.load(cursor).unwrap();
-        //
-        // 3 ticks to start delay timer
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
+            break",
+        );
+        e.key_pressed(3.into());
+        let state = e.save_state();
+        assert_eq!(&state[..6], b"C8SS\x00\x01");
 
-        assert!(emulator.sound_timer > 0);
+        let mut restored = Builder::new().build();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.memory, e.memory);
+        assert_eq!(restored.graphics, e.graphics);
+        assert_eq!(restored.program_counter, e.program_counter);
+        assert_eq!(restored.stack_pointer, 1);
+        assert_eq!(restored.sound_timer, 7);
+        assert_eq!(restored.key_status[3], KeyStatus::Pressed);
+
+        assert!(matches!(
+            restored.load_state(&state[..4]),
+            Err(EmulatorError::InvalidSaveState(_))
+        ));
+        let mut newer = state.clone();
+        newer[5] = 2;
+        assert!(matches!(
+            restored.load_state(&newer),
+            Err(EmulatorError::UnsupportedSaveStateVersion(2))
+        ));
+        assert!(matches!(
+            restored.load_state(&state[..state.len() - 1]),
+            Err(EmulatorError::InvalidSaveState(_))
+        ));
     }
 
+    #[cfg(feature = "serde")]
     #[test]
-    fn test_sound_timer_tick() {
-        let input = "
-        main:
-            ldb r1 3
-            ldb r3 0
-            sound r1
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ldb r4 0
-            ";
-        let reader = BufReader::new(input.as_bytes());
-        let lexer = StreamLexer::new(reader);
-        let mut parser = Parser::new(Box::new(lexer));
-        let binary = parser.parse().unwrap().binary().unwrap();
-        let mut emulator = Emulator::new(400, 100);
-        let cursor = Cursor::new(binary);
-        emulator.load(cursor).unwrap();
-        //
-        // 3 ticks to start delay timer
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
-        emulator.tick().unwrap();
-
-        // Teleport 2 seconds into the future to force a timer decrement
-        // reaction - we're limited to one decrement per tick
-        let t = Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
-        emulator.last_sound_decrement = Some(t);
-        emulator.tick().unwrap();
-        emulator.last_sound_decrement = Some(t);
-        emulator.tick().unwrap();
-        // Two decrements should've occured
-        assert_eq!(emulator.sound_timer, 1);
+    fn test_save_state_out_of_range() {
+        let state = Builder::new().build().save_state();
+        let with = |field: &str, value: serde_json::Value| {
+            let mut json: serde_json::Value = serde_json::from_slice(&state[6..]).unwrap();
+            json[field] = value;
+            let mut bytes = state[..6].to_vec();
+            bytes.extend(serde_json::to_vec(&json).unwrap());
+            bytes
+        };
+        let mut e = Builder::new().build();
+        for (field, value, reason) in [
+            ("wait_for_key", 16, "register waiting for a key"),
+            ("pressed_key", 16, "pressed key"),
+            ("program_counter", MEMSIZE, "program counter"),
+            ("address_register", MEMSIZE, "address register"),
+        ] {
+            assert!(matches!(
+                e.load_state(&with(field, value.into())),
+                Err(EmulatorError::InvalidSaveState(ref r)) if r == reason
+            ));
+        }
+        e.load_state(&with("wait_for_key", 15.into())).unwrap();
+        e.load_state(&with("address_register", (MEMSIZE - 1).into()))
+            .unwrap();
     }
 
     #[test]
@@ -1084,13 +5986,7 @@ mod test {
         let cursor = Cursor::new(binary);
         emulator.load(cursor).unwrap();
         emulator.key_pressed(2.into());
-        loop {
-            match emulator.tick() {
-                Ok(false) => break,
-                Err(_) => break,
-                _ => {}
-            }
-        }
+        while let Ok(TickOutcome::Continue) = emulator.tick() {}
 
         assert_eq!(emulator.registries[1], 4);
     }
@@ -1115,13 +6011,7 @@ mod test {
         let cursor = Cursor::new(binary);
         emulator.load(cursor).unwrap();
         emulator.key_pressed(2.into());
-        loop {
-            match emulator.tick() {
-                Ok(false) => break,
-                Err(_) => break,
-                _ => {}
-            }
-        }
+        while let Ok(TickOutcome::Continue) = emulator.tick() {}
 
         assert_eq!(emulator.registries[1], 2);
     }
@@ -0,0 +1,275 @@
+//!
+//! Random but structurally valid programs
+//!
+//! Generated programs are meant for differential testing against other
+//! emulators and for fuzzing the execution loop. The instruction set is
+//! enumerated from [Instruction], with random operands, while control flow
+//! is constrained:
+//!
+//! * jumps land on instructions of the main body, which ends in a jump to
+//!   itself
+//! * calls target subroutines placed after the main body, which don't
+//!   jump or call and always end in a return
+//! * skips never skip the final jump or return of a block
+//! * `ldi` points into the scratch memory after the code, `addi` may
+//!   still move I past it
+//!
+use rand::Rng;
+use strum::IntoEnumIterator;
+
+use crate::emulator::{MEMSIZE, START_ADDR};
+use crate::instructions::{Instruction, Target};
+
+/// Bytes of memory after the code which `ldi` may point into
+pub const SCRATCH_SIZE: usize = 0x100;
+/// Maximum instructions of a subroutine, including the return
+const MAX_SUBROUTINE_LENGTH: usize = 8;
+
+pub struct Generator {
+    length: usize,
+    subroutines: usize,
+    target: Target,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Self {
+            length: 64,
+            subroutines: 4,
+            target: Target::Chip8,
+        }
+    }
+
+    /// Instructions of the main body, at least one
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = length.max(1);
+        self
+    }
+
+    pub fn with_subroutines(mut self, subroutines: usize) -> Self {
+        self.subroutines = subroutines;
+        self
+    }
+
+    /// Only instructions supported by the target are generated
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Generates the instructions of a program loaded at [START_ADDR].
+    ///
+    /// Panics if the code and scratch memory don't fit in memory.
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> Vec<Instruction> {
+        let code_size = 2 * (self.length + self.subroutines * MAX_SUBROUTINE_LENGTH);
+        assert!(
+            START_ADDR + code_size + SCRATCH_SIZE <= MEMSIZE,
+            "program of {} bytes doesn't fit in memory",
+            code_size
+        );
+
+        let templates: Vec<Instruction> = Instruction::iter()
+            .filter(|i| self.target.supports(i) && !is_control_flow(i))
+            .collect();
+
+        let lengths: Vec<usize> = (0..self.subroutines)
+            .map(|_| rng.gen_range(1..=MAX_SUBROUTINE_LENGTH))
+            .collect();
+        let mut subroutine_addresses = Vec::new();
+        let mut address = START_ADDR + 2 * self.length;
+        for length in &lengths {
+            subroutine_addresses.push(address);
+            address += 2 * length;
+        }
+        let scratch = address;
+
+        let mut generator = BlockGenerator {
+            rng,
+            templates: &templates,
+            target: self.target,
+            scratch,
+        };
+
+        let mut program = Vec::new();
+        let end = START_ADDR + 2 * (self.length - 1);
+        for index in 0..self.length - 1 {
+            let last = index == self.length - 2;
+            let instruction = match generator.rng.gen_range(0..templates.len() + 2) {
+                0 => {
+                    let target = generator.rng.gen_range(0..self.length);
+                    Instruction::Jump(((START_ADDR + 2 * target) as u16).into())
+                }
+                1 if !subroutine_addresses.is_empty() => {
+                    let index = generator.rng.gen_range(0..subroutine_addresses.len());
+                    Instruction::Call((subroutine_addresses[index] as u16).into())
+                }
+                _ => generator.instruction(last),
+            };
+            program.push(instruction);
+        }
+        program.push(Instruction::Jump((end as u16).into()));
+
+        for length in lengths {
+            for index in 0..length - 1 {
+                program.push(generator.instruction(index == length - 2));
+            }
+            program.push(Instruction::Return);
+        }
+        program
+    }
+
+    /// Generates the binary of a program, see [Generator::generate]
+    pub fn generate_rom<R: Rng>(&self, rng: &mut R) -> Vec<u8> {
+        self.generate(rng)
+            .iter()
+            .flat_map(|i| i.opcode().to_be_bytes())
+            .collect()
+    }
+}
+
+struct BlockGenerator<'a, R: Rng> {
+    rng: &'a mut R,
+    templates: &'a [Instruction],
+    target: Target,
+    scratch: usize,
+}
+
+impl<R: Rng> BlockGenerator<'_, R> {
+    /// A random instruction without control flow, the instruction before
+    /// the end of a block must not be a skip
+    fn instruction(&mut self, last: bool) -> Instruction {
+        loop {
+            let template = &self.templates[self.rng.gen_range(0..self.templates.len())];
+            // Operand nibbles are the non-hex characters of the pattern
+            let opcode = template.info().pattern.chars().fold(0u16, |opcode, c| {
+                let nibble = c.to_digit(16).unwrap_or_else(|| self.rng.gen_range(0..16));
+                opcode << 4 | nibble as u16
+            });
+            let instruction = match Instruction::from_opcode_u16(opcode) {
+                Some(Instruction::SetMemRegister(_)) => {
+                    let address = self
+                        .rng
+                        .gen_range(self.scratch..self.scratch + SCRATCH_SIZE);
+                    Instruction::SetMemRegister((address as u16).into())
+                }
+                // SUPER-CHIP only has 8 flag registers
+                Some(Instruction::SaveFlags(x) | Instruction::LoadFlags(x))
+                    if self.target == Target::Schip && x.value() > 7 =>
+                {
+                    continue
+                }
+                // Operands may decode to other instructions, e.g. `00c0`
+                Some(i) if self.target.supports(&i) && !is_control_flow(&i) => i,
+                _ => continue,
+            };
+            if !(last && is_skip(&instruction)) {
+                return instruction;
+            }
+        }
+    }
+}
+
+/// Instructions placed by the generator rather than chosen at random
+fn is_control_flow(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump(_)
+            | Instruction::JumpOffset(_)
+            | Instruction::Call(_)
+            | Instruction::Return
+            | Instruction::Raw(_)
+    )
+}
+
+fn is_skip(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SkipEqual(..)
+            | Instruction::SkipNotEqual(..)
+            | Instruction::SkipRegistersEqual(..)
+            | Instruction::SkipRegistersNotEqual(..)
+            | Instruction::SkipKeyPressed(_)
+            | Instruction::SkipKeyNotPressed(_)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_generate() {
+        let generator = Generator::new().with_length(32).with_subroutines(3);
+        for seed in 0..50 {
+            let program = generator.generate(&mut StdRng::seed_from_u64(seed));
+            let address = |index: usize| START_ADDR + 2 * index;
+            let returns: Vec<usize> = (0..program.len())
+                .filter(|i| program[*i] == Instruction::Return)
+                .collect();
+            assert_eq!(returns.len(), 3);
+            assert_eq!(*returns.last().unwrap(), program.len() - 1);
+            assert_eq!(program[31], Instruction::Jump((address(31) as u16).into()));
+
+            // Subroutines start after the main body or a return
+            let mut starts = vec![address(32)];
+            starts.extend(returns.iter().map(|i| address(i + 1)));
+            for (index, instruction) in program.iter().enumerate() {
+                match instruction {
+                    Instruction::Jump(target) => {
+                        assert!(index < 32);
+                        assert!((target.value() as usize) < address(32));
+                    }
+                    Instruction::Call(target) => {
+                        assert!(index < 32);
+                        assert!(starts.contains(&(target.value() as usize)));
+                    }
+                    Instruction::JumpOffset(_) | Instruction::Raw(_) => {
+                        panic!("unexpected {:?}", instruction)
+                    }
+                    Instruction::SetMemRegister(target) => {
+                        assert!(target.value() as usize >= address(program.len()));
+                    }
+                    i => assert!(Target::Chip8.supports(i)),
+                }
+                if is_skip(instruction) {
+                    assert_ne!(index + 1, 31);
+                    assert_ne!(program[index + 1], Instruction::Return);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_flags() {
+        let generator = Generator::new()
+            .with_length(256)
+            .with_subroutines(0)
+            .with_target(Target::Schip);
+        let flags: Vec<u8> = (0..20)
+            .flat_map(|seed| generator.generate(&mut StdRng::seed_from_u64(seed)))
+            .filter_map(|instruction| match instruction {
+                Instruction::SaveFlags(x) | Instruction::LoadFlags(x) => Some(x.value()),
+                _ => None,
+            })
+            .collect();
+        assert!(!flags.is_empty());
+        assert!(flags.iter().all(|x| *x < 8));
+    }
+
+    #[test]
+    fn test_generate_rom() {
+        let generator = Generator::new().with_subroutines(0);
+        let rom = generator.generate_rom(&mut StdRng::seed_from_u64(1));
+        assert_eq!(rom.len(), 128);
+        assert_eq!(rom, generator.generate_rom(&mut StdRng::seed_from_u64(1)));
+        assert_eq!(&rom[126..], &[0x12, 0x7e]);
+    }
+}
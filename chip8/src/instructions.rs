@@ -1,8 +1,36 @@
 //!
 //! Chip-8 instructions
 //!
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "decode-table")]
+use std::sync::OnceLock;
+#[cfg(feature = "std")]
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::RwLock,
+};
+
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
 use strum_macros::EnumIter;
 
+#[cfg(feature = "std")]
+use crate::assembly::lexer::StreamLexer;
+#[cfg(feature = "std")]
+use crate::assembly::parser::{Parser, ParserOptions, ParsingError};
+#[cfg(feature = "std")]
+use crate::assembly::resolve_labels;
+
 /// Represents a 4 bit value
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 #[allow(non_camel_case_types)]
@@ -41,6 +69,25 @@ impl u4 {
     }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for u4 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.value)
+    }
+}
+
+/// Any of the 16 values, for fuzzing
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for u4 {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::little(u.int_in_range(0..=0xF)?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u8::size_hint(depth)
+    }
+}
+
 impl PartialEq<u8> for u4 {
     fn eq(&self, other: &u8) -> bool {
         self.value == *other
@@ -84,6 +131,25 @@ impl u12 {
     }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for u12 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.value)
+    }
+}
+
+/// Any of the 4096 values, for fuzzing
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for u12 {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_u16(u.int_in_range(0..=0xFFF)?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u16::size_hint(depth)
+    }
+}
+
 impl PartialEq<u16> for u12 {
     fn eq(&self, other: &u16) -> bool {
         self.value == *other
@@ -97,13 +163,21 @@ impl From<u16> for u12 {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub enum Instruction {
     /// f1ee - Custom code - make emulator exit
     Exit,
-    /// fxef - Custom code - debug-log some data (determined by value of x)
+    /// fxef - Custom code - debug-log some data (determined by value of x),
+    /// captured by the emulator's debug output
     Debug(u4),
     /// fxff - Custom code - breakpoint - pauses the execution
     Breakpoint,
+    /// Any 16 bit opcode, emitted verbatim by the `opcode` directive.
+    /// Never produced when decoding.
+    Raw(u16),
+    /// An opcode matching a pattern registered with [register_custom_opcode]
+    Custom(u16),
     /// 00e0
     Clear,
     /// 00EE - return from subroutine
@@ -134,13 +208,13 @@ pub enum Instruction {
     AddChecked(u4, u4),
     /// 8xy5 - Set Vx = Vx - Vy, VF = Not borrow
     SubChecked(u4, u4),
-    /// 8xy6 - Set Vx = Vx >> 1, VF = overflow?  
-    /// reg y is ignored here, but some intereprters use Vx = Vy >> 1
+    /// 8xy6 - Set Vx = Vy >> 1, VF = shifted out bit
+    /// Vx is shifted instead with [shift_in_place](crate::emulator::Quirks::shift_in_place)
     ShiftRight(u4, u4),
     /// 8xy7 - Set Vx = Vy - Vx, VF = overflow?
     SubNChecked(u4, u4),
-    /// 8xyE - Set Vx = Vx << 1, VF = overflow
-    /// reg y is ignored here, but some intereprters use Vx = Vy << 1
+    /// 8xyE - Set Vx = Vy << 1, VF = shifted out bit
+    /// Vx is shifted instead with [shift_in_place](crate::emulator::Quirks::shift_in_place)
     ShiftLeft(u4, u4),
     /// 9xy0 - Skip next instruction if Vx != Vy
     SkipRegistersNotEqual(u4, u4),
@@ -177,6 +251,195 @@ pub enum Instruction {
     MemWrite(u4),
     /// Fx65 - Read registers v0 through vx from memory starting at location I
     MemRead(u4),
+    /// 00Cn - SUPER-CHIP - Scroll the display down n lines
+    ScrollDown(u4),
+    /// 00FB - SUPER-CHIP - Scroll the display right 4 pixels
+    ScrollRight,
+    /// 00FC - SUPER-CHIP - Scroll the display left 4 pixels
+    ScrollLeft,
+    /// 00FD - SUPER-CHIP - Exit the interpreter
+    Halt,
+    /// 00FE - SUPER-CHIP - Switch to the 64x32 low resolution display
+    LowRes,
+    /// 00FF - SUPER-CHIP - Switch to the 128x64 high resolution display
+    HighRes,
+    /// Dxy0 - SUPER-CHIP - Draw a 16x16 sprite starting at mem I at (Vx, Vy),
+    /// set VF = collision
+    DrawLarge(u4, u4),
+    /// Fx30 - SUPER-CHIP - Set I = location of the large sprite for digit Vx
+    SetMemRegisterLargeSprite(u4),
+    /// Fx75 - SUPER-CHIP - Store v0 through vx in the RPL user flags
+    SaveFlags(u4),
+    /// Fx85 - SUPER-CHIP - Read v0 through vx from the RPL user flags
+    LoadFlags(u4),
+    /// Fn01 - XO-CHIP - Select the display planes drawn to by the bitmask n
+    SelectPlane(u4),
+}
+
+/// Metadata of an instruction, independent of its operands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionInfo {
+    /// Opcode pattern, e.g. `4xkk`
+    pub pattern: &'static str,
+    /// Mnemonic in the native assembly
+    pub mnemonic: &'static str,
+    /// What the instruction does, with the operands written as `{x}`,
+    /// `{y}`, `{n}`, `{kk}`, `{nnn}` or `{nnnn}` for the whole opcode
+    pub description: &'static str,
+}
+
+const fn info(
+    pattern: &'static str,
+    mnemonic: &'static str,
+    description: &'static str,
+) -> InstructionInfo {
+    InstructionInfo {
+        pattern,
+        mnemonic,
+        description,
+    }
+}
+
+/// Platforms with differing instruction sets, each extending the previous
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Target {
+    /// The original COSMAC VIP interpreter
+    Chip8,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP
+    XoChip,
+}
+
+impl Target {
+    /// Whether the platform can execute the instruction, the custom codes
+    /// of this emulator and registered custom opcodes aren't available on
+    /// any other platform.
+    /// Raw opcodes are always allowed, as they're emitted on purpose.
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        match instruction {
+            Instruction::Exit
+            | Instruction::Debug(_)
+            | Instruction::Breakpoint
+            | Instruction::Custom(_) => false,
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Halt
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::DrawLarge(..)
+            | Instruction::SetMemRegisterLargeSprite(_)
+            | Instruction::SaveFlags(_)
+            | Instruction::LoadFlags(_) => *self >= Target::Schip,
+            Instruction::SelectPlane(_) => *self >= Target::XoChip,
+            _ => true,
+        }
+    }
+}
+
+/// An opcode pattern recognized by the decoder as [Instruction::Custom],
+/// for experimenting with instructions without extending [Instruction]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CustomOpcode {
+    /// The bits of the opcode compared with `value`
+    pub mask: u16,
+    pub value: u16,
+    pub mnemonic: &'static str,
+    /// What the instruction does, see [InstructionInfo::description]
+    pub description: &'static str,
+}
+
+impl CustomOpcode {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value & self.mask
+    }
+}
+
+#[cfg(feature = "std")]
+static CUSTOM_OPCODES: RwLock<Vec<CustomOpcode>> = RwLock::new(Vec::new());
+/// Set once a pattern is registered, so decoding skips the lock until then
+#[cfg(feature = "std")]
+static HAS_CUSTOM_OPCODES: AtomicBool = AtomicBool::new(false);
+
+/// Registers the pattern for every following decode, in every thread.
+/// Custom patterns take precedence over the standard instructions, the
+/// first registered pattern matching an opcode is used.
+#[cfg(feature = "std")]
+pub fn register_custom_opcode(custom: CustomOpcode) {
+    CUSTOM_OPCODES.write().unwrap().push(custom);
+    HAS_CUSTOM_OPCODES.store(true, Ordering::Release);
+}
+
+/// The registered pattern matching the opcode
+#[cfg(feature = "std")]
+pub fn custom_opcode(opcode: u16) -> Option<CustomOpcode> {
+    if !HAS_CUSTOM_OPCODES.load(Ordering::Acquire) {
+        return None;
+    }
+    let customs = CUSTOM_OPCODES.read().unwrap();
+    customs.iter().find(|c| c.matches(opcode)).copied()
+}
+
+/// Patterns can only be registered with std
+#[cfg(not(feature = "std"))]
+pub fn custom_opcode(_opcode: u16) -> Option<CustomOpcode> {
+    None
+}
+
+/// The extension platform introducing the opcode, with its mnemonic.
+/// `dxy0` is included, as it only draws 16x16 sprites on SUPER-CHIP.
+pub fn extension_opcode(opcode: u16) -> Option<(Target, &'static str)> {
+    let [upper, lower] = opcode.to_be_bytes();
+    let extension = match (upper >> 4, upper & 0x0F, lower >> 4, lower & 0x0F) {
+        (0x0, 0x0, 0xC, _) => (Target::Schip, "scd"),
+        (0x0, 0x0, 0xF, 0xB) => (Target::Schip, "scr"),
+        (0x0, 0x0, 0xF, 0xC) => (Target::Schip, "scl"),
+        (0x0, 0x0, 0xF, 0xD) => (Target::Schip, "halt"),
+        (0x0, 0x0, 0xF, 0xE) => (Target::Schip, "lores"),
+        (0x0, 0x0, 0xF, 0xF) => (Target::Schip, "hires"),
+        (0xD, _, _, 0x0) => (Target::Schip, "draw 16x16"),
+        (0xF, _, 0x3, 0x0) => (Target::Schip, "ldhf"),
+        (0xF, _, 0x7, 0x5) => (Target::Schip, "saveflags"),
+        (0xF, _, 0x8, 0x5) => (Target::Schip, "loadflags"),
+        (0x0, 0x0, 0xD, _) => (Target::XoChip, "scu"),
+        (0x5, _, _, 0x2) => (Target::XoChip, "save range"),
+        (0x5, _, _, 0x3) => (Target::XoChip, "load range"),
+        (0xF, 0x0, 0x0, 0x0) => (Target::XoChip, "ldi long"),
+        (0xF, _, 0x0, 0x1) => (Target::XoChip, "plane"),
+        (0xF, 0x0, 0x0, 0x2) => (Target::XoChip, "audio"),
+        (0xF, _, 0x3, 0xA) => (Target::XoChip, "pitch"),
+        _ => return None,
+    };
+    Some(extension)
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Chip8 => "chip8",
+            Self::Schip => "schip",
+            Self::XoChip => "xochip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Self::Chip8),
+            "schip" | "superchip" => Ok(Self::Schip),
+            "xochip" | "xo-chip" => Ok(Self::XoChip),
+            _ => Err(format!(
+                "unknown target '{}', expected chip8, schip or xochip",
+                s
+            )),
+        }
+    }
 }
 
 impl Instruction {
@@ -188,9 +451,26 @@ impl Instruction {
 
     /// Deconstructs the opcode into an instruction if possible
     pub fn from_opcode_u8(upper: u8, lower: u8) -> Option<Instruction> {
+        let opcode = u16::from_be_bytes([upper, lower]);
+        if custom_opcode(opcode).is_some() {
+            return Some(Self::Custom(opcode));
+        }
+        #[cfg(feature = "decode-table")]
+        return decode_table()[opcode as usize];
+        #[cfg(not(feature = "decode-table"))]
+        Self::decode(upper, lower)
+    }
+
+    fn decode(upper: u8, lower: u8) -> Option<Instruction> {
         match (upper & 0xF0, upper & 0x0F, lower & 0xF0, lower & 0x0F) {
             (0x00, 0x00, 0xe0, 0x00) => Some(Self::Clear),
             (0x00, 0x00, 0xe0, 0x0e) => Some(Self::Return),
+            (0x00, 0x00, 0xc0, n) => Some(Self::ScrollDown(n.into())),
+            (0x00, 0x00, 0xf0, 0x0b) => Some(Self::ScrollRight),
+            (0x00, 0x00, 0xf0, 0x0c) => Some(Self::ScrollLeft),
+            (0x00, 0x00, 0xf0, 0x0d) => Some(Self::Halt),
+            (0x00, 0x00, 0xf0, 0x0e) => Some(Self::LowRes),
+            (0x00, 0x00, 0xf0, 0x0f) => Some(Self::HighRes),
             (0x10, _, _, _) => {
                 let address = u12::from_bytes(upper, lower);
                 Some(Self::Jump(address))
@@ -244,6 +524,7 @@ impl Instruction {
                 Some(Self::JumpOffset(address))
             }
             (0xC0, regx, _, _) => Some(Self::Randomize(regx.into(), lower)),
+            (0xD0, regx, regy, 0x00) => Some(Self::DrawLarge(regx.into(), (regy >> 4).into())),
             (0xD0, regx, regy, n) => Some(Self::Draw(regx.into(), (regy >> 4).into(), n.into())),
             (0xE0, regx, 0x90, 0x0E) => Some(Self::SkipKeyPressed(regx.into())),
             (0xE0, regx, 0xA0, 0x01) => Some(Self::SkipKeyNotPressed(regx.into())),
@@ -259,6 +540,10 @@ impl Instruction {
             (0xF0, regx, 0x30, 0x03) => Some(Self::SetBcd(regx.into())),
             (0xF0, regx, 0x50, 0x05) => Some(Self::MemWrite(regx.into())),
             (0xF0, regx, 0x60, 0x05) => Some(Self::MemRead(regx.into())),
+            (0xF0, regx, 0x30, 0x00) => Some(Self::SetMemRegisterLargeSprite(regx.into())),
+            (0xF0, regx, 0x70, 0x05) => Some(Self::SaveFlags(regx.into())),
+            (0xF0, regx, 0x80, 0x05) => Some(Self::LoadFlags(regx.into())),
+            (0xF0, planes, 0x00, 0x01) => Some(Self::SelectPlane(planes.into())),
             (0xF0, 0x01, 0xE0, 0x0E) => Some(Self::Exit),
             (0xF0, val, 0xE0, 0x0F) => Some(Self::Debug(val.into())),
             (0xF0, _, 0xF0, 0x0F) => Some(Self::Breakpoint),
@@ -275,6 +560,7 @@ impl Instruction {
                 (big << 8) | small
             }
             Self::Breakpoint => 0xF0FF,
+            Self::Raw(opcode) | Self::Custom(opcode) => *opcode,
             Self::Clear => 0x00e0,
             Self::Return => 0x00ee,
             Self::Jump(addr) => 0x1000 | addr.value(),
@@ -291,7 +577,7 @@ impl Instruction {
             }
             Self::SkipRegistersEqual(regx, regy) => {
                 let big: u16 = 0x50 | (regx.value() as u16);
-                let small: u16 = (regy.value() as u16) << 4 | 0x00_u16;
+                let small: u16 = (regy.value() as u16) << 4;
                 (big << 8) | small
             }
             Self::SetRegisterByte(reg, value) => {
@@ -346,7 +632,7 @@ impl Instruction {
             }
             Self::SkipRegistersNotEqual(regx, regy) => {
                 let big: u16 = 0x90 | (regx.value() as u16);
-                let small: u16 = (regy.value() as u16) << 4 | 0x00_u16;
+                let small: u16 = (regy.value() as u16) << 4;
                 (big << 8) | small
             }
             Self::SetMemRegister(addr) => 0xA000 | addr.value(),
@@ -421,14 +707,210 @@ impl Instruction {
                 let small: u16 = 0x65;
                 (big << 8) | small
             }
+            Self::ScrollDown(n) => 0x00C0 | (n.value() as u16),
+            Self::ScrollRight => 0x00FB,
+            Self::ScrollLeft => 0x00FC,
+            Self::Halt => 0x00FD,
+            Self::LowRes => 0x00FE,
+            Self::HighRes => 0x00FF,
+            Self::DrawLarge(regx, regy) => {
+                let big: u16 = 0xd0 | (regx.value() as u16);
+                let small: u16 = (regy.value() as u16) << 4;
+                (big << 8) | small
+            }
+            Self::SetMemRegisterLargeSprite(regx) => {
+                let big: u16 = 0xF0 | (regx.value() as u16);
+                let small: u16 = 0x30;
+                (big << 8) | small
+            }
+            Self::SaveFlags(regx) => {
+                let big: u16 = 0xF0 | (regx.value() as u16);
+                let small: u16 = 0x75;
+                (big << 8) | small
+            }
+            Self::LoadFlags(regx) => {
+                let big: u16 = 0xF0 | (regx.value() as u16);
+                let small: u16 = 0x85;
+                (big << 8) | small
+            }
+            Self::SelectPlane(planes) => {
+                let big: u16 = 0xF0 | (planes.value() as u16);
+                let small: u16 = 0x01;
+                (big << 8) | small
+            }
         }
     }
 
+    /// The metadata of the instruction
+    pub fn info(&self) -> InstructionInfo {
+        match self {
+            Self::Exit => info("f1ee", "exit", "exit the emulator"),
+            Self::Debug(_) => info(
+                "fxef",
+                "debug",
+                "debug-log the data selected by {x}: 1 registers, 2 string at I, 3 v0",
+            ),
+            Self::Breakpoint => info("f0ff", "break", "pause the execution"),
+            Self::Raw(_) => info("nnnn", "opcode", "raw opcode {nnnn}"),
+            Self::Custom(opcode) => match custom_opcode(*opcode) {
+                Some(custom) => info("nnnn", custom.mnemonic, custom.description),
+                None => info("nnnn", "opcode", "custom opcode {nnnn}"),
+            },
+            Self::Clear => info("00e0", "clear", "clear the display"),
+            Self::Return => info("00ee", "ret", "return from subroutine"),
+            Self::Jump(_) => info("1nnn", "jmp", "jump to {nnn}"),
+            Self::Call(_) => info("2nnn", "call", "call subroutine at {nnn}"),
+            Self::SkipEqual(..) => info("3xkk", "se", "skip next if V{x} == {kk}"),
+            Self::SkipNotEqual(..) => info("4xkk", "sne", "skip next if V{x} != {kk}"),
+            Self::SkipRegistersEqual(..) => info("5xy0", "sre", "skip next if V{x} == V{y}"),
+            Self::SetRegisterByte(..) => info("6xkk", "ldb", "V{x} = {kk}"),
+            Self::Add(..) => info("7xkk", "add", "V{x} = V{x} + {kk}, without carry"),
+            Self::SetRegisterRegister(..) => info("8xy0", "ldr", "V{x} = V{y}"),
+            Self::Or(..) => info("8xy1", "or", "V{x} = V{x} | V{y}"),
+            Self::And(..) => info("8xy2", "and", "V{x} = V{x} & V{y}"),
+            Self::Xor(..) => info("8xy3", "xor", "V{x} = V{x} ^ V{y}"),
+            Self::AddChecked(..) => info("8xy4", "addc", "V{x} = V{x} + V{y}, VF = carry"),
+            Self::SubChecked(..) => info("8xy5", "subc", "V{x} = V{x} - V{y}, VF = not borrow"),
+            Self::ShiftRight(..) => info("8xy6", "shr", "V{x} = V{y} >> 1, VF = shifted out bit"),
+            Self::SubNChecked(..) => info("8xy7", "subnc", "V{x} = V{y} - V{x}, VF = not borrow"),
+            Self::ShiftLeft(..) => info("8xye", "shl", "V{x} = V{y} << 1, VF = shifted out bit"),
+            Self::SkipRegistersNotEqual(..) => info("9xy0", "srne", "skip next if V{x} != V{y}"),
+            Self::SetMemRegister(_) => info("annn", "ldi", "I = {nnn}"),
+            Self::JumpOffset(_) => info("bnnn", "jmpr", "jump to {nnn} + V0"),
+            Self::Randomize(..) => info("cxkk", "rand", "V{x} = random byte & {kk}"),
+            Self::Draw(..) => info(
+                "dxyn",
+                "draw",
+                "draw {n} byte sprite at I to (V{x}, V{y}), VF = collision",
+            ),
+            Self::SkipKeyPressed(_) => info("ex9e", "skp", "skip next if key V{x} is pressed"),
+            Self::SkipKeyNotPressed(_) => {
+                info("exa1", "sknp", "skip next if key V{x} isn't pressed")
+            }
+            Self::WaitForKey(_) => info("fx0a", "input", "wait for a key press, V{x} = key"),
+            Self::SetRegisterDelayTimer(_) => info("fx07", "ldd", "V{x} = delay timer"),
+            Self::SetDelayTimer(_) => info("fx15", "delay", "delay timer = V{x}"),
+            Self::SetSoundTimer(_) => info("fx18", "sound", "sound timer = V{x}"),
+            Self::AddMemReg(_) => info("fx1e", "addi", "I = I + V{x}"),
+            Self::SetMemRegisterDefaultSprit(_) => {
+                info("fx29", "ldf", "I = font sprite of digit V{x}")
+            }
+            Self::SetBcd(_) => info("fx33", "sbcd", "store BCD of V{x} at I, I+1 and I+2"),
+            Self::MemWrite(_) => info("fx55", "write", "store V0 through V{x} at I"),
+            Self::MemRead(_) => info("fx65", "read", "load V0 through V{x} from I"),
+            Self::ScrollDown(_) => info("00cn", "scd", "scroll the display down {n} lines"),
+            Self::ScrollRight => info("00fb", "scr", "scroll the display right 4 pixels"),
+            Self::ScrollLeft => info("00fc", "scl", "scroll the display left 4 pixels"),
+            Self::Halt => info("00fd", "halt", "exit the interpreter"),
+            Self::LowRes => info("00fe", "lores", "switch to 64x32 low resolution"),
+            Self::HighRes => info("00ff", "hires", "switch to 128x64 high resolution"),
+            Self::DrawLarge(..) => info(
+                "dxy0",
+                "draw",
+                "draw 16x16 sprite at I to (V{x}, V{y}), VF = collision",
+            ),
+            Self::SetMemRegisterLargeSprite(_) => {
+                info("fx30", "ldhf", "I = large font sprite of digit V{x}")
+            }
+            Self::SaveFlags(_) => info("fx75", "saveflags", "store V0 through V{x} in flags"),
+            Self::LoadFlags(_) => info("fx85", "loadflags", "load V0 through V{x} from flags"),
+            Self::SelectPlane(_) => info("fn01", "plane", "draw to the planes in bitmask {x}"),
+        }
+    }
+
+    /// The registers the instruction reads, VF isn't included when it's
+    /// only read as a flag. Shifts read both registers, as either is
+    /// shifted depending on the quirks.
+    pub fn registers_read(&self) -> Vec<u4> {
+        match *self {
+            Self::SkipEqual(x, _)
+            | Self::SkipNotEqual(x, _)
+            | Self::Add(x, _)
+            | Self::SkipKeyPressed(x)
+            | Self::SkipKeyNotPressed(x)
+            | Self::SetDelayTimer(x)
+            | Self::SetSoundTimer(x)
+            | Self::AddMemReg(x)
+            | Self::SetMemRegisterDefaultSprit(x)
+            | Self::SetMemRegisterLargeSprite(x)
+            | Self::SetBcd(x) => vec![x],
+            Self::SetRegisterRegister(_, y) => vec![y],
+            Self::SkipRegistersEqual(x, y)
+            | Self::SkipRegistersNotEqual(x, y)
+            | Self::Or(x, y)
+            | Self::And(x, y)
+            | Self::Xor(x, y)
+            | Self::AddChecked(x, y)
+            | Self::SubChecked(x, y)
+            | Self::SubNChecked(x, y)
+            | Self::ShiftRight(x, y)
+            | Self::ShiftLeft(x, y)
+            | Self::Draw(x, y, _)
+            | Self::DrawLarge(x, y) => vec![x, y],
+            Self::JumpOffset(_) => vec![0.into()],
+            Self::MemWrite(x) | Self::SaveFlags(x) => (0..=x.value()).map(u4::from).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The registers the instruction writes, including VF when it's set
+    /// as a flag
+    pub fn registers_written(&self) -> Vec<u4> {
+        let vf = u4::from(0xF);
+        match *self {
+            Self::SetRegisterByte(x, _)
+            | Self::SetRegisterRegister(x, _)
+            | Self::Add(x, _)
+            | Self::Or(x, _)
+            | Self::And(x, _)
+            | Self::Xor(x, _)
+            | Self::Randomize(x, _)
+            | Self::WaitForKey(x)
+            | Self::SetRegisterDelayTimer(x) => vec![x],
+            Self::AddChecked(x, _)
+            | Self::SubChecked(x, _)
+            | Self::SubNChecked(x, _)
+            | Self::ShiftRight(x, _)
+            | Self::ShiftLeft(x, _) => vec![x, vf],
+            Self::Draw(..) | Self::DrawLarge(..) => vec![vf],
+            Self::MemRead(x) | Self::LoadFlags(x) => (0..=x.value()).map(u4::from).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The address the instruction jumps to, calls or loads into I
+    pub fn target_address(&self) -> Option<u12> {
+        match *self {
+            Self::Jump(addr)
+            | Self::Call(addr)
+            | Self::SetMemRegister(addr)
+            | Self::JumpOffset(addr) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Describes what the instruction does with its operands, e.g.
+    /// `skip next if V1 != 0x04`
+    pub fn describe(&self) -> String {
+        let opcode = self.opcode();
+        self.info()
+            .description
+            .replace("{x}", &format!("{:X}", (opcode >> 8) & 0xF))
+            .replace("{y}", &format!("{:X}", (opcode >> 4) & 0xF))
+            .replace("{n}", &format!("{}", opcode & 0xF))
+            .replace("{kk}", &format!("0x{:02x}", opcode & 0xFF))
+            .replace("{nnnn}", &format!("0x{:04x}", opcode))
+            .replace("{nnn}", &format!("0x{:03x}", opcode & 0xFFF))
+    }
+
     pub fn to_assembly(&self) -> String {
         match self {
             Self::Exit => "exit".to_string(),
             Self::Debug(val) => format!("debug {}", val.value()),
             Self::Breakpoint => "break".to_string(),
+            // Reassembled as raw opcodes, the mnemonics aren't known to the
+            // parser
+            Self::Raw(opcode) | Self::Custom(opcode) => format!("opcode 0x{:04x}", opcode),
             Self::Clear => "clear".to_string(),
             Self::Return => "ret".to_string(),
             Self::Jump(addr) => format!("jmp {}", addr.value()),
@@ -463,7 +945,7 @@ impl Instruction {
             Self::SkipKeyPressed(reg) => format!("skp r{}", reg.value()),
             Self::SkipKeyNotPressed(reg) => format!("sknp r{}", reg.value()),
             Self::WaitForKey(reg) => format!("input r{}", reg.value()),
-            Self::SetMemRegisterDefaultSprit(reg) => format!("ldf {}", reg.value()),
+            Self::SetMemRegisterDefaultSprit(reg) => format!("ldf r{}", reg.value()),
             Self::SetRegisterDelayTimer(reg) => format!("ldd r{}", reg.value()),
             Self::SetDelayTimer(reg) => format!("delay r{}", reg.value()),
             Self::SetSoundTimer(reg) => format!("sound r{}", reg.value()),
@@ -471,6 +953,67 @@ impl Instruction {
             Self::SetBcd(reg) => format!("sbcd r{}", reg.value()),
             Self::MemWrite(reg) => format!("write r{}", reg.value()),
             Self::MemRead(reg) => format!("read r{}", reg.value()),
+            Self::ScrollDown(n) => format!("scd {}", n.value()),
+            Self::ScrollRight => "scr".to_string(),
+            Self::ScrollLeft => "scl".to_string(),
+            Self::Halt => "halt".to_string(),
+            Self::LowRes => "lores".to_string(),
+            Self::HighRes => "hires".to_string(),
+            // Same opcode as a draw of 0 rows
+            Self::DrawLarge(regx, regy) => format!("draw r{} r{} 0", regx.value(), regy.value()),
+            Self::SetMemRegisterLargeSprite(reg) => format!("ldhf r{}", reg.value()),
+            Self::SaveFlags(reg) => format!("saveflags r{}", reg.value()),
+            Self::LoadFlags(reg) => format!("loadflags r{}", reg.value()),
+            Self::SelectPlane(planes) => format!("plane {}", planes.value()),
+        }
+    }
+}
+
+/// Every opcode decoded once, so decoding is a single lookup
+#[cfg(feature = "decode-table")]
+fn decode_table() -> &'static [Option<Instruction>] {
+    static TABLE: OnceLock<Vec<Option<Instruction>>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|opcode| {
+                let [upper, lower] = opcode.to_be_bytes();
+                Instruction::decode(upper, lower)
+            })
+            .collect()
+    })
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_assembly())
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for Instruction {
+    type Err = ParsingError;
+
+    /// Parses a single instruction with the default parser options, e.g.
+    /// `ldb r1 4`. Pseudo-instructions expanding to several instructions
+    /// are rejected. Extension instructions are accepted without selecting
+    /// a target.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |target| {
+            let lexer = StreamLexer::new(Cursor::new(s.as_bytes().to_vec()));
+            let options = ParserOptions {
+                target,
+                ..Default::default()
+            };
+            Parser::new_with_options(Box::new(lexer), options).parse()
+        };
+        let assembly = match parse(None) {
+            Err(ParsingError::ExtensionInstruction(..)) => parse(Some(Target::XoChip)),
+            result => result,
+        }?;
+        match assembly.instructions.as_slice() {
+            [parsed] => resolve_labels(parsed, &assembly.labels)
+                .map_err(|e| ParsingError::Unknown(e.to_string())),
+            instructions => Err(ParsingError::NotSingleInstruction(instructions.len())),
         }
     }
 }
@@ -478,6 +1021,7 @@ impl Instruction {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use strum::IntoEnumIterator;
 
     #[test]
     fn test_u4() {
@@ -543,7 +1087,7 @@ mod tests {
             ),
             (0xAABC, Instruction::SetMemRegister(u12::from_u16(0xABC))),
             (0xBABC, Instruction::JumpOffset(u12::from_u16(0xABC))),
-            (0xC102, Instruction::Randomize(0x01.into(), 0x02.into())),
+            (0xC102, Instruction::Randomize(0x01.into(), 0x02)),
             (
                 0xD265,
                 Instruction::Draw(0x02.into(), 0x06.into(), 0x05.into()),
@@ -559,6 +1103,17 @@ mod tests {
             (0xF933, Instruction::SetBcd(0x09.into())),
             (0xF955, Instruction::MemWrite(0x09.into())),
             (0xF965, Instruction::MemRead(0x09.into())),
+            (0x00C3, Instruction::ScrollDown(0x03.into())),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FD, Instruction::Halt),
+            (0x00FE, Instruction::LowRes),
+            (0x00FF, Instruction::HighRes),
+            (0xD120, Instruction::DrawLarge(0x01.into(), 0x02.into())),
+            (0xF930, Instruction::SetMemRegisterLargeSprite(0x09.into())),
+            (0xF375, Instruction::SaveFlags(0x03.into())),
+            (0xF385, Instruction::LoadFlags(0x03.into())),
+            (0xF301, Instruction::SelectPlane(0x03.into())),
         ];
 
         for case in cases {
@@ -570,12 +1125,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_target() {
+        for target in [Target::Chip8, Target::Schip, Target::XoChip] {
+            assert_eq!(target.to_string().parse::<Target>(), Ok(target));
+            assert!(target.supports(&Instruction::Clear));
+            assert!(target.supports(&Instruction::Raw(0xF1EE)));
+            assert!(!target.supports(&Instruction::Exit));
+            assert!(!target.supports(&Instruction::Debug(1.into())));
+            assert!(!target.supports(&Instruction::Breakpoint));
+        }
+        assert!(!Target::Chip8.supports(&Instruction::HighRes));
+        assert!(Target::Schip.supports(&Instruction::HighRes));
+        assert!(Target::XoChip.supports(&Instruction::SaveFlags(1.into())));
+        assert!(!Target::Schip.supports(&Instruction::SelectPlane(1.into())));
+        assert!(Target::XoChip.supports(&Instruction::SelectPlane(1.into())));
+        assert_eq!("XO-CHIP".parse::<Target>(), Ok(Target::XoChip));
+        assert!("cosmac".parse::<Target>().is_err());
+    }
+
     #[test]
     fn test_instruction_to_opcode() {
         let cases: Vec<(Instruction, u16)> = vec![
             (Instruction::Exit, 0xF1EE),
             (Instruction::Debug(0x03.into()), 0xF3EF),
             (Instruction::Breakpoint, 0xF0FF),
+            (Instruction::Raw(0x5AB1), 0x5AB1),
             (Instruction::Clear, 0x00E0),
             (Instruction::Return, 0x00EE),
             (Instruction::Jump(0x123.into()), 0x1123),
@@ -606,7 +1181,7 @@ mod tests {
             ),
             (Instruction::SetMemRegister(0x321.into()), 0xA321),
             (Instruction::JumpOffset(0x321.into()), 0xB321),
-            (Instruction::Randomize(0x02.into(), 0x21.into()), 0xC221),
+            (Instruction::Randomize(0x02.into(), 0x21), 0xC221),
             (
                 Instruction::Draw(0x04.into(), 0x05.into(), 0x0F.into()),
                 0xD45F,
@@ -622,6 +1197,17 @@ mod tests {
             (Instruction::SetBcd(0x02.into()), 0xF233),
             (Instruction::MemWrite(0x02.into()), 0xF255),
             (Instruction::MemRead(0x02.into()), 0xF265),
+            (Instruction::ScrollDown(0x0A.into()), 0x00CA),
+            (Instruction::ScrollRight, 0x00FB),
+            (Instruction::ScrollLeft, 0x00FC),
+            (Instruction::Halt, 0x00FD),
+            (Instruction::LowRes, 0x00FE),
+            (Instruction::HighRes, 0x00FF),
+            (Instruction::DrawLarge(0x03.into(), 0x04.into()), 0xD340),
+            (Instruction::SetMemRegisterLargeSprite(0x02.into()), 0xF230),
+            (Instruction::SaveFlags(0x02.into()), 0xF275),
+            (Instruction::LoadFlags(0x02.into()), 0xF285),
+            (Instruction::SelectPlane(0x02.into()), 0xF201),
         ];
         for case in cases {
             let opcode = case.0.opcode();
@@ -632,4 +1218,183 @@ mod tests {
             );
         }
     }
+
+    #[cfg(feature = "decode-table")]
+    #[test]
+    fn test_decode_table() {
+        for opcode in 0..=u16::MAX {
+            let [upper, lower] = opcode.to_be_bytes();
+            if custom_opcode(opcode).is_some() {
+                continue;
+            }
+            assert_eq!(
+                Instruction::from_opcode_u16(opcode),
+                Instruction::decode(upper, lower)
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Every decodable opcode encodes back to an opcode decoding to the
+        // same instruction, and only the breakpoint ignores operand bits
+        for opcode in 0..=u16::MAX {
+            let Some(instruction) = Instruction::from_opcode_u16(opcode) else {
+                continue;
+            };
+            let encoded = instruction.opcode();
+            assert_eq!(
+                Instruction::from_opcode_u16(encoded),
+                Some(instruction),
+                "0x{:04x}",
+                opcode
+            );
+            if instruction != Instruction::Breakpoint {
+                assert_eq!(encoded, opcode, "{:?}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_opcode() {
+        // 5xy1 isn't used by any platform. Registered patterns apply to
+        // every test, so only unused opcodes are claimed
+        register_custom_opcode(CustomOpcode {
+            mask: 0xF00F,
+            value: 0x5001,
+            mnemonic: "mul",
+            description: "V{x} = V{x} * V{y}",
+        });
+        let instruction = Instruction::from_opcode_u16(0x5121);
+        assert_eq!(instruction, Some(Instruction::Custom(0x5121)));
+        let instruction = instruction.unwrap();
+        assert_eq!(instruction.opcode(), 0x5121);
+        assert_eq!(instruction.info().mnemonic, "mul");
+        assert_eq!(instruction.describe(), "V1 = V1 * V2");
+        assert_eq!(instruction.to_assembly(), "opcode 0x5121");
+        assert_eq!(Instruction::from_opcode_u16(0x5122), None);
+        assert_eq!(
+            Instruction::Custom(0x5F02).info().description,
+            "custom opcode {nnnn}"
+        );
+    }
+
+    #[test]
+    fn test_extension_opcode() {
+        assert_eq!(extension_opcode(0x00FF), Some((Target::Schip, "hires")));
+        assert_eq!(
+            extension_opcode(0xD120),
+            Some((Target::Schip, "draw 16x16"))
+        );
+        assert_eq!(extension_opcode(0xF375), Some((Target::Schip, "saveflags")));
+        assert_eq!(
+            extension_opcode(0x5123),
+            Some((Target::XoChip, "load range"))
+        );
+        assert_eq!(extension_opcode(0xF000), Some((Target::XoChip, "ldi long")));
+        assert_eq!(extension_opcode(0xF201), Some((Target::XoChip, "plane")));
+        assert_eq!(extension_opcode(0xD123), None);
+        assert_eq!(extension_opcode(0xF1EE), None);
+        assert_eq!(extension_opcode(0x00E0), None);
+    }
+
+    #[test]
+    fn test_display_from_str() {
+        for instruction in Instruction::iter() {
+            let asm = instruction.to_string();
+            assert_eq!(asm, instruction.to_assembly());
+            // A 16x16 draw is written as a draw of 0 rows
+            assert_eq!(
+                asm.parse::<Instruction>().ok().map(|i| i.opcode()),
+                Some(instruction.opcode()),
+                "{}",
+                asm
+            );
+        }
+        assert_eq!(
+            "sne r1, 0x04".parse::<Instruction>().ok(),
+            Some(Instruction::SkipNotEqual(1.into(), 4))
+        );
+        assert_eq!(
+            "start:\njmp start".parse::<Instruction>().ok(),
+            Some(Instruction::Jump(0x200.into()))
+        );
+        assert!(matches!(
+            "ldb r1 1\nret".parse::<Instruction>(),
+            Err(ParsingError::NotSingleInstruction(2))
+        ));
+        assert!(matches!(
+            "; comment".parse::<Instruction>(),
+            Err(ParsingError::NotSingleInstruction(0))
+        ));
+        assert!(matches!(
+            "frob r1".parse::<Instruction>(),
+            Err(ParsingError::UnknownInstruction(..))
+        ));
+    }
+
+    #[test]
+    fn test_operands() {
+        let values = |registers: Vec<u4>| registers.iter().map(u4::value).collect::<Vec<_>>();
+        let i = Instruction::SubChecked(1.into(), 2.into());
+        assert_eq!(values(i.registers_read()), vec![1, 2]);
+        assert_eq!(values(i.registers_written()), vec![1, 0xF]);
+        let i = Instruction::ShiftRight(1.into(), 2.into());
+        assert_eq!(values(i.registers_read()), vec![1, 2]);
+        assert_eq!(values(i.registers_written()), vec![1, 0xF]);
+        assert_eq!(
+            i.info().description,
+            "V{x} = V{y} >> 1, VF = shifted out bit"
+        );
+        let i = Instruction::MemWrite(2.into());
+        assert_eq!(values(i.registers_read()), vec![0, 1, 2]);
+        assert!(i.registers_written().is_empty());
+        let i = Instruction::MemRead(1.into());
+        assert_eq!(values(i.registers_written()), vec![0, 1]);
+        let i = Instruction::JumpOffset(0x300.into());
+        assert_eq!(values(i.registers_read()), vec![0]);
+        assert_eq!(i.target_address(), Some(0x300.into()));
+        assert_eq!(
+            values(Instruction::Draw(3.into(), 4.into(), 5.into()).registers_written()),
+            vec![0xF]
+        );
+        assert_eq!(Instruction::Clear.target_address(), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            Instruction::SkipNotEqual(1.into(), 4).describe(),
+            "skip next if V1 != 0x04"
+        );
+        assert_eq!(
+            Instruction::Draw(0xA.into(), 2.into(), 5.into()).describe(),
+            "draw 5 byte sprite at I to (VA, V2), VF = collision"
+        );
+        assert_eq!(
+            Instruction::Call(0x206.into()).describe(),
+            "call subroutine at 0x206"
+        );
+        assert_eq!(Instruction::Raw(0x00FF).describe(), "raw opcode 0x00ff");
+    }
+
+    #[test]
+    fn test_info() {
+        // The fixed digits of the pattern match the opcode, and the mnemonic
+        // matches the assembly
+        for instruction in Instruction::iter() {
+            let info = instruction.info();
+            let opcode = format!("{:04x}", instruction.opcode());
+            for (p, o) in info.pattern.chars().zip(opcode.chars()) {
+                assert!(
+                    !p.is_ascii_digit() && !"abcdef".contains(p) || p == o,
+                    "{:?}: pattern {} doesn't match {}",
+                    instruction,
+                    info.pattern,
+                    opcode
+                );
+            }
+            assert!(instruction.to_assembly().starts_with(info.mnemonic));
+        }
+    }
 }
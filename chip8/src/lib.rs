@@ -1,3 +1,30 @@
+//!
+//! Chip-8 instruction set, assembler, disassembler and emulator
+//!
+//! Without the default `std` feature only the instruction set and the
+//! emulator core are built, with `alloc`, e.g. to run programs on embedded
+//! targets, see [emulator].
+//!
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod assembly;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod differential;
+#[cfg(feature = "std")]
+pub mod disassembly;
 pub mod emulator;
+#[cfg(feature = "std")]
+pub mod generator;
 pub mod instructions;
+#[cfg(feature = "std")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod rom;
+#[cfg(feature = "scripting")]
+pub mod script;
@@ -0,0 +1,192 @@
+//!
+//! The emulator executing in a thread
+//!
+//! A [Machine] owns the thread and controls it with [Message]s, the
+//! execution suspends on pauses, breakpoints, exits and failures while the
+//! thread keeps answering requests. Stopping the machine ends the thread
+//! and returns the emulator.
+//!
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use tracing::error;
+
+use crate::emulator::{
+    Cheat, CoverageReport, Emulator, EmulatorError, Frame, Message, Snapshot, Stats, TickOutcome,
+};
+use crate::rom::RomInfo;
+
+pub struct Machine {
+    sender: Sender<Message>,
+    handle: JoinHandle<Emulator>,
+}
+
+impl Machine {
+    /// Executes the emulator in a new thread
+    pub fn start(emulator: Emulator) -> Self {
+        Self::spawn(emulator, false)
+    }
+
+    /// Starts the thread with the execution suspended, see
+    /// [Machine::resume]
+    pub fn start_paused(emulator: Emulator) -> Self {
+        Self::spawn(emulator, true)
+    }
+
+    fn spawn(mut emulator: Emulator, suspended: bool) -> Self {
+        let (sender, receiver) = channel();
+        let handle = thread::spawn(move || {
+            emulator.run_thread(&receiver, suspended);
+            emulator
+        });
+        Self { sender, handle }
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), EmulatorError> {
+        Ok(self.sender.send(message)?)
+    }
+
+    /// Sends the message with a response channel and waits for the
+    /// response, e.g. `machine.request(Message::SendBreakpoints)`
+    pub fn request<T>(
+        &self,
+        message: impl FnOnce(Sender<T>) -> Message,
+    ) -> Result<T, EmulatorError> {
+        let (sender, receiver) = channel();
+        self.send(message(sender))?;
+        Ok(receiver.recv()?)
+    }
+
+    pub fn pause(&self) -> Result<(), EmulatorError> {
+        self.send(Message::Pause)
+    }
+
+    pub fn resume(&self) -> Result<(), EmulatorError> {
+        self.send(Message::Resume)
+    }
+
+    /// Whether the execution is suspended, by [Machine::pause], a
+    /// breakpoint, an exit or a failure
+    pub fn is_suspended(&self) -> Result<bool, EmulatorError> {
+        self.request(Message::SendSuspended)
+    }
+
+    pub fn snapshot(&self, include_memory: bool) -> Result<Snapshot, EmulatorError> {
+        self.request(|sender| Message::SendSnapshot(include_memory, sender))
+    }
+
+    /// See [Emulator::restart]
+    pub fn restart(&self) -> Result<(), EmulatorError> {
+        self.request(Message::Restart)?
+    }
+
+    /// Executes a single tick, also while suspended
+    pub fn step(&self) -> Result<TickOutcome, EmulatorError> {
+        self.request(Message::Step)?
+    }
+
+    /// See [Emulator::tick_frame], also while suspended
+    pub fn step_frame(&self) -> Result<TickOutcome, EmulatorError> {
+        self.request(Message::StepFrame)?
+    }
+
+    /// See [Emulator::step_back]
+    pub fn step_back(&self) -> Result<bool, EmulatorError> {
+        self.request(Message::StepBack)
+    }
+
+    /// Receives the current frame followed by every changed one
+    pub fn subscribe(&self) -> Result<Receiver<Frame>, EmulatorError> {
+        let (sender, receiver) = channel();
+        self.send(Message::Subscribe(sender))?;
+        Ok(receiver)
+    }
+
+    /// See [Emulator::add_cheat]
+    pub fn add_cheat(&self, cheat: Cheat) -> Result<usize, EmulatorError> {
+        self.request(|sender| Message::AddCheat(cheat, sender))?
+    }
+
+    /// Returns false if no cheat has the id
+    pub fn remove_cheat(&self, id: usize) -> Result<bool, EmulatorError> {
+        self.request(|sender| Message::RemoveCheat(id, sender))
+    }
+
+    pub fn cheats(&self) -> Result<Vec<(usize, Cheat)>, EmulatorError> {
+        self.request(Message::SendCheats)
+    }
+
+    /// See [Emulator::coverage_report]
+    pub fn coverage_report(&self) -> Result<Option<CoverageReport>, EmulatorError> {
+        self.request(Message::SendCoverage)
+    }
+
+    pub fn stats(&self) -> Result<Stats, EmulatorError> {
+        self.request(Message::SendStats)
+    }
+
+    /// See [Emulator::take_debug_output]
+    pub fn take_debug_output(&self) -> Result<Vec<String>, EmulatorError> {
+        self.request(Message::TakeDebugOutput)
+    }
+
+    /// Where the report of the last failure was written, see
+    /// [crate::emulator::Builder::with_crash_dir]
+    pub fn crash_report(&self) -> Result<Option<PathBuf>, EmulatorError> {
+        self.request(Message::SendCrashReport)
+    }
+
+    /// See [Emulator::rom_info]
+    pub fn rom_info(&self) -> Result<Option<RomInfo>, EmulatorError> {
+        self.request(Message::SendRomInfo)
+    }
+
+    /// Whether the thread ended, which only happens on panics before
+    /// [Machine::stop]
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Ends the thread and returns the emulator
+    pub fn stop(self) -> Result<Emulator, EmulatorError> {
+        // The thread may already have ended
+        let _ = self.sender.send(Message::Stop);
+        self.handle.join().map_err(|error| {
+            error!(?error, "emulator thread panicked");
+            EmulatorError::Disconnected
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Builder;
+
+    #[test]
+    fn test_lifecycle() {
+        // add r0 1, jmp 0x200
+        let e = Builder::new()
+            .load_bytes(&[0x70, 0x01, 0x12, 0x00])
+            .unwrap();
+        let machine = Machine::start_paused(e);
+        assert!(machine.is_suspended().unwrap());
+        assert_eq!(machine.step().unwrap(), TickOutcome::Continue);
+        assert_eq!(machine.snapshot(false).unwrap().registries[0], 1);
+
+        machine.resume().unwrap();
+        assert!(!machine.is_suspended().unwrap());
+        while machine.snapshot(false).unwrap().registries[0] < 3 {}
+        machine.pause().unwrap();
+        assert!(machine.is_suspended().unwrap());
+        assert!(!machine.is_finished());
+
+        assert!(machine.stats().unwrap().instructions >= 5);
+
+        let e = machine.stop().unwrap();
+        assert!(e.create_snapshot().registries[0] >= 3);
+    }
+}
@@ -0,0 +1,333 @@
+//!
+//! Per-rom configuration profiles, read from a TOML file next to the rom
+//! with the `.toml` extension, e.g. `pong.toml` for `pong.bin`:
+//!
+//! ```toml
+//! hertz = 600
+//! timeboxes = 100
+//!
+//! [quirks]
+//! wait_for_release = true
+//! display_wait = true
+//!
+//! # keyboard key = chip-8 key
+//! [keymap]
+//! w = 0x5
+//! x = 0x8
+//!
+//! [colors]
+//! foreground = "#33ff66"
+//! background = "#000000"
+//! plane2 = "#ff3333"
+//! overlap = "#ffffff"
+//! ```
+//!
+//! Only this subset of TOML is read: tables, strings, integers and
+//! booleans. The keymap and colors are left to frontends, which may
+//! reserve keys of their own.
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::{EmulatorError, Quirks};
+use crate::instructions::u4;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("profile line {line}: {reason}")]
+pub struct ProfileError {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Red, green and blue
+pub type Rgb = [u8; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Colors {
+    /// Pixels of the first plane
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+    /// Pixels of the second XO-CHIP plane
+    pub plane2: Option<Rgb>,
+    /// Pixels set in both planes
+    pub overlap: Option<Rgb>,
+}
+
+/// Settings for a rom, unset ones keep the configured values
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Profile {
+    pub hertz: Option<usize>,
+    pub timeboxes: Option<usize>,
+    /// Set by a `[quirks]` table, the quirks it leaves out are disabled
+    pub quirks: Option<Quirks>,
+    /// Chip-8 keys by keyboard key
+    pub keymap: BTreeMap<char, u4>,
+    pub colors: Colors,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Profile {
+    pub fn parse(source: &str) -> Result<Self, ProfileError> {
+        let mut profile = Self::default();
+        let mut table = String::new();
+        let mut seen = BTreeSet::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_error = |reason: &str| ProfileError {
+                line: index + 1,
+                reason: reason.to_string(),
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| line_error("expected ']' after the table"))?
+                    .trim();
+                if !["quirks", "keymap", "colors"].contains(&name) {
+                    return Err(line_error(&format!("unknown table {}", name)));
+                }
+                if !seen.insert((name.to_string(), String::new())) {
+                    return Err(line_error(&format!("duplicate table {}", name)));
+                }
+                if name == "quirks" {
+                    profile.quirks = Some(Quirks::default());
+                }
+                table = name.to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| line_error("expected '=' after the key"))?;
+            let key = parse_key(key.trim()).map_err(|reason| line_error(&reason))?;
+            let value = parse_value(value.trim()).map_err(|reason| line_error(&reason))?;
+            if !seen.insert((table.clone(), key.clone())) {
+                return Err(line_error(&format!("duplicate key {}", key)));
+            }
+            profile
+                .set(&table, &key, value)
+                .map_err(|reason| line_error(&reason))?;
+        }
+        Ok(profile)
+    }
+
+    /// Where the profile of the rom is read from, the rom path with the
+    /// `.toml` extension
+    pub fn sidecar_path(rom: impl AsRef<Path>) -> PathBuf {
+        rom.as_ref().with_extension("toml")
+    }
+
+    /// Reads the profile next to the rom, none if there's no such file
+    pub fn for_rom(rom: impl AsRef<Path>) -> Result<Option<Self>, EmulatorError> {
+        let path = Self::sidecar_path(rom);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let source = std::fs::read_to_string(path)?;
+        Ok(Some(Self::parse(&source)?))
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: Value) -> Result<(), String> {
+        match (table, key, value) {
+            ("", "hertz", Value::Integer(hertz)) if hertz > 0 => self.hertz = Some(hertz as usize),
+            ("", "timeboxes", Value::Integer(timeboxes)) if timeboxes > 0 => {
+                self.timeboxes = Some(timeboxes as usize)
+            }
+            ("quirks", quirk, Value::Boolean(enabled)) => {
+                let quirks = self.quirks.get_or_insert_with(Quirks::default);
+                match quirk {
+                    "wait_for_release" => quirks.wait_for_release = enabled,
+                    "display_wait" => quirks.display_wait = enabled,
+                    "shift_in_place" => quirks.shift_in_place = enabled,
+                    _ => return Err(format!("unknown quirk {}", quirk)),
+                }
+            }
+            ("keymap", key, Value::Integer(value)) => {
+                let mut chars = key.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    return Err(format!("keymap key {} isn't a single character", key));
+                };
+                if !(0..=0xF).contains(&value) {
+                    return Err(format!("chip-8 key {} isn't between 0 and 0xf", value));
+                }
+                self.keymap.insert(c, (value as u8).into());
+            }
+            ("colors", name, Value::String(color)) => {
+                let rgb = parse_color(&color)
+                    .ok_or_else(|| format!("color {} isn't written as \"#rrggbb\"", color))?;
+                let slot = match name {
+                    "foreground" => &mut self.colors.foreground,
+                    "background" => &mut self.colors.background,
+                    "plane2" => &mut self.colors.plane2,
+                    "overlap" => &mut self.colors.overlap,
+                    _ => return Err(format!("unknown color {}", name)),
+                };
+                *slot = Some(rgb);
+            }
+            (table, key, _) => {
+                let name = if table.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", table, key)
+                };
+                return Err(format!("unknown key or invalid value for {}", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The line up to a `#` outside of strings
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') => return &line[..index],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_key(text: &str) -> Result<String, String> {
+    if let Some(Value::String(key)) = parse_string(text) {
+        return Ok(key);
+    }
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if text.is_empty() || !text.chars().all(bare) {
+        return Err(format!("invalid key {}", text));
+    }
+    Ok(text.to_string())
+}
+
+fn parse_string(text: &str) -> Option<Value> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let inner = text[1..].strip_suffix(quote)?;
+    if inner.contains(quote) {
+        return None;
+    }
+    Some(Value::String(inner.to_string()))
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(value) = parse_string(text) {
+        return Ok(value);
+    }
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    let digits = text.replace('_', "");
+    let number = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    number
+        .map(Value::Integer)
+        .map_err(|_| format!("invalid value {}", text))
+}
+
+fn parse_color(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Some([r, g, b])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let source = "
+            # pong at a higher speed
+            hertz = 1_000
+            timeboxes = 50
+
+            [quirks]
+            display_wait = true
+
+            [keymap]
+            w = 0x1
+            'x' = 4 # comment
+
+            [colors]
+            foreground = \"#33ff66\"
+            overlap = '#FFFFFF'
+        ";
+        let profile = Profile::parse(source).unwrap();
+        assert_eq!(profile.hertz, Some(1000));
+        assert_eq!(profile.timeboxes, Some(50));
+        assert_eq!(
+            profile.quirks,
+            Some(Quirks {
+                display_wait: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            profile.keymap,
+            BTreeMap::from([('w', 1.into()), ('x', 4.into())])
+        );
+        assert_eq!(profile.colors.foreground, Some([0x33, 0xff, 0x66]));
+        assert_eq!(profile.colors.overlap, Some([0xff, 0xff, 0xff]));
+        assert_eq!(profile.colors.background, None);
+
+        assert_eq!(Profile::parse("").unwrap(), Profile::default());
+        // An empty quirks table disables every quirk
+        assert_eq!(
+            Profile::parse("[quirks]").unwrap().quirks,
+            Some(Quirks::default())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (source, line) in [
+            ("hertz = 0", 1),
+            ("hertz = fast", 1),
+            ("\nhertz", 2),
+            ("[quirks]\nturbo = true", 2),
+            ("[sound]", 1),
+            ("[quirks\n", 1),
+            ("[keymap]\nab = 1", 2),
+            ("[keymap]\na = 16", 2),
+            ("[colors]\nforeground = \"red\"", 2),
+            ("hertz = 1\nhertz = 2", 2),
+            ("[colors]\n[colors]", 2),
+        ] {
+            assert_eq!(Profile::parse(source).unwrap_err().line, line, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_for_rom() {
+        let dir = std::env::temp_dir().join(format!("chip8-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        assert_eq!(Profile::sidecar_path(&rom), dir.join("game.toml"));
+        assert_eq!(Profile::for_rom(&rom).unwrap(), None);
+
+        std::fs::write(dir.join("game.toml"), "hertz = 700").unwrap();
+        let profile = Profile::for_rom(&rom).unwrap().unwrap();
+        assert_eq!(profile.hertz, Some(700));
+
+        std::fs::write(dir.join("game.toml"), "hertz = 0").unwrap();
+        assert!(matches!(
+            Profile::for_rom(&rom),
+            Err(EmulatorError::Profile(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+//!
+//! Identification of roms, by their hash and the platform they likely
+//! require, with an optional database of known roms behind the
+//! `rom-database` feature
+//!
+use std::fmt;
+
+use crate::disassembly::{disassemble, DisassemblyOptions};
+use crate::emulator::{Quirks, MEMSIZE, START_ADDR};
+use crate::instructions::Target;
+
+/// A rom in the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownRom {
+    /// SHA-1 of the rom, in lowercase hexadecimal
+    pub sha1: &'static str,
+    pub title: &'static str,
+    pub target: Target,
+    /// The quirks the rom runs correctly with
+    pub quirks: Quirks,
+}
+
+/// Computed when a rom is loaded, see [crate::emulator::Emulator::rom_info]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    /// Bytes in the rom
+    pub size: usize,
+    pub sha1: [u8; 20],
+    /// The platform the rom likely requires, judging by its opcodes and
+    /// size
+    pub target: Target,
+    /// The rom in the database, when the `rom-database` feature is enabled
+    pub known: Option<&'static KnownRom>,
+}
+
+impl RomInfo {
+    pub fn new(rom: &[u8]) -> Self {
+        let sha1 = sha1(rom);
+        let options = DisassemblyOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let mut target = disassemble(rom, &options).required_target();
+        // Only XO-CHIP has memory beyond the default
+        if rom.len() > MEMSIZE - START_ADDR {
+            target = Target::XoChip;
+        }
+        Self {
+            size: rom.len(),
+            sha1,
+            target,
+            known: lookup(&sha1),
+        }
+    }
+
+    /// The SHA-1 in lowercase hexadecimal
+    pub fn sha1_hex(&self) -> String {
+        self.sha1.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn title(&self) -> Option<&'static str> {
+        self.known.map(|known| known.title)
+    }
+
+    /// The quirks of the known rom, or the defaults
+    pub fn recommended_quirks(&self) -> Quirks {
+        self.known.map(|known| known.quirks).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(title) = self.title() {
+            write!(f, "{}, ", title)?;
+        }
+        write!(
+            f,
+            "{} bytes, {}, sha1 {}",
+            self.size,
+            self.target,
+            self.sha1_hex()
+        )
+    }
+}
+
+/// The rom in the database with the SHA-1, always none without the
+/// `rom-database` feature
+pub fn lookup(sha1: &[u8; 20]) -> Option<&'static KnownRom> {
+    #[cfg(feature = "rom-database")]
+    {
+        let hex: String = sha1.iter().map(|b| format!("{:02x}", b)).collect();
+        KNOWN_ROMS.iter().find(|known| known.sha1 == hex)
+    }
+    #[cfg(not(feature = "rom-database"))]
+    {
+        let _ = sha1;
+        None
+    }
+}
+
+/// The roms distributed in the repository
+#[cfg(feature = "rom-database")]
+pub static KNOWN_ROMS: &[KnownRom] = &[
+    chip8("ea9af3c09b0d9e265fcd92bcc5d51a2939fdf27a", "15 Puzzle"),
+    shifting_in_place("d40abc54374e4343639f993e897e00904ddf85d9", "Blinky"),
+    chip8("6f6509f38220e057a7e32ebb22dd353c1078e3e7", "Blitz"),
+    chip8("f13766c14aeb02ad8d4d103cb5eadd282d20cddc", "Brix"),
+    chip8("2d10c07b532f4fa7c07a07324ba26ca39fe484fd", "Connect 4"),
+    chip8("5260f8931e0e9f41e555b382a14a88368e3ed886", "Guess"),
+    chip8("050f07a54371da79f924dd0227b89d07b4f2aed0", "Hidden"),
+    chip8("f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571", "Space Invaders"),
+    chip8("d6fa9dc9005dc0496f39ba52fef56f9fd0a5a158", "Kaleidoscope"),
+    chip8("b9272ae1acdaaa79ab649f6b48b72088ca2b1d74", "Maze"),
+    chip8("d979858bb9ffd07b48f52f92a8bcac0199f3623e", "Merlin"),
+    chip8(
+        "0d0cc129dad3c45ba672f85fec71a668232212cc",
+        "Missile Command",
+    ),
+    chip8("b232ef880bd6060fb45fa6effed7edf0ae95670e", "Pong"),
+    chip8("a60611339661e3ab2d8af024ad1da5880a6f8665", "Pong 2"),
+    chip8("1293db0ccccbe7dd3fc5a09a2abc5d7b175e18e0", "Puzzle"),
+    chip8("1bdb4ddaa7049266fa3226851f28855a365cfd12", "Syzygy"),
+    chip8("18b9d15f4c159e1f0ed58c2d8ec1d89325d3a3b6", "Tank"),
+    chip8("5f518084744bf3cb8733f6e5454dfd1634320563", "Tetris"),
+    chip8("429d455a4bc53167942bf6fd934d72b0f648dce3", "Tic-Tac-Toe"),
+    chip8("bdb92475acfe11bc7814a2f5eade13fcd09b756a", "UFO"),
+    chip8("da710f631f8e35534d0b9170bcf892a60f49c43d", "Vertical Brix"),
+    chip8("ade839585ddeb0e3633177df03c1d91589e629eb", "Vers"),
+    chip8("d666688a8fce468a7d88b536bc1ef5f35ba12031", "Wipe Off"),
+];
+
+/// A CHIP-8 rom running with the default quirks
+#[cfg(feature = "rom-database")]
+const fn chip8(sha1: &'static str, title: &'static str) -> KnownRom {
+    KnownRom {
+        sha1,
+        title,
+        target: Target::Chip8,
+        quirks: Quirks {
+            wait_for_release: false,
+            display_wait: false,
+            shift_in_place: false,
+        },
+    }
+}
+
+/// A CHIP-8 rom shifting Vx in place, as on SUPER-CHIP, see
+/// [Quirks::shift_in_place]
+#[cfg(feature = "rom-database")]
+const fn shifting_in_place(sha1: &'static str, title: &'static str) -> KnownRom {
+    let mut rom = chip8(sha1, title);
+    rom.quirks.shift_in_place = true;
+    rom
+}
+
+/// SHA-1 digest of the bytes
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sha1() {
+        let hex = |bytes: &[u8]| {
+            RomInfo {
+                size: 0,
+                sha1: sha1(bytes),
+                target: Target::Chip8,
+                known: None,
+            }
+            .sha1_hex()
+        };
+        assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Spans two blocks
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_rom_info() {
+        // cls, jmp 0x200
+        let info = RomInfo::new(&[0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(info.size, 4);
+        assert_eq!(info.target, Target::Chip8);
+        assert_eq!(info.recommended_quirks(), Quirks::default());
+
+        // high, jmp 0x202
+        let info = RomInfo::new(&[0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(info.target, Target::Schip);
+
+        // Larger than the default memory
+        let info = RomInfo::new(&vec![0x12; MEMSIZE]);
+        assert_eq!(info.target, Target::XoChip);
+    }
+
+    #[cfg(feature = "rom-database")]
+    #[test]
+    fn test_known_rom() {
+        let info = RomInfo::new(include_bytes!("../../roms/pong.bin"));
+        assert_eq!(info.title(), Some("Pong"));
+        assert_eq!(info.target, Target::Chip8);
+        assert_eq!(
+            info.to_string(),
+            "Pong, 246 bytes, chip8, sha1 b232ef880bd6060fb45fa6effed7edf0ae95670e"
+        );
+        assert!(RomInfo::new(&[0x12, 0x00]).known.is_none());
+
+        let info = RomInfo::new(include_bytes!("../../roms/blinky.bin"));
+        assert_eq!(info.title(), Some("Blinky"));
+        assert_eq!(
+            info.recommended_quirks(),
+            Quirks {
+                shift_in_place: true,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//!
+//! Debugger scripts, hooks running while the emulator executes
+//!
+//! A script attaches to the emulator as an [ExecutionObserver], one hook
+//! per line, with `#` starting a comment:
+//!
+//! ```text
+//! # before executing the instruction at 0x245
+//! at 0x245: print v3, i; set v3 0
+//! # after an instruction changed the byte at 0x300
+//! watch 0x300: print [0x300], pc
+//! ```
+//!
+//! Values are registers `v0`-`vf`, `i`, `pc`, the timers `dt` and `st`,
+//! memory bytes `[addr]` and numbers, hexadecimal with `0x`. Anything but
+//! the timers and numbers can be set. The program counter has moved past
+//! the instruction when the hooks run.
+//!
+use std::sync::{Arc, Mutex};
+
+use tracing::{error, info};
+
+use crate::emulator::{Emulator, EmulatorError, ExecutionObserver};
+use crate::instructions::{u4, Instruction};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("script line {line}: {reason}")]
+pub struct ScriptError {
+    pub line: usize,
+    pub reason: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// Before the instruction at the address is executed
+    At(usize),
+    /// After an instruction changed the byte at the address
+    Watch(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+    Register(u4),
+    AddressRegister,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+    Memory(usize),
+    Number(usize),
+}
+
+impl Value {
+    fn settable(&self) -> bool {
+        !matches!(self, Self::DelayTimer | Self::SoundTimer | Self::Number(_))
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Register(register) => format!("v{:x}", register.value()),
+            Self::AddressRegister => "i".to_string(),
+            Self::ProgramCounter => "pc".to_string(),
+            Self::DelayTimer => "dt".to_string(),
+            Self::SoundTimer => "st".to_string(),
+            Self::Memory(address) => format!("[0x{:x}]", address),
+            Self::Number(number) => format!("0x{:x}", number),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Print(Vec<Value>),
+    /// Sets the first value, see [Value::settable]
+    Set(Value, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub trigger: Trigger,
+    pub actions: Vec<Action>,
+}
+
+/// The hooks of a script. Clones share the printed output.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    hooks: Vec<Hook>,
+    /// Bytes at the watched addresses before the instruction
+    watched: Vec<(usize, Option<u8>)>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        let mut hooks = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_error = |reason: &str| ScriptError {
+                line: index + 1,
+                reason: reason.to_string(),
+            };
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (trigger, actions) = line
+                .split_once(':')
+                .ok_or_else(|| line_error("expected ':' after the trigger"))?;
+            let trigger = parse_trigger(trigger).ok_or_else(|| line_error("invalid trigger"))?;
+            let actions = actions
+                .split(';')
+                .map(|action| parse_action(action.trim()).map_err(|reason| line_error(&reason)))
+                .collect::<Result<Vec<_>, _>>()?;
+            hooks.push(Hook { trigger, actions });
+        }
+        Ok(Self {
+            hooks,
+            ..Default::default()
+        })
+    }
+
+    pub fn hooks(&self) -> &[Hook] {
+        &self.hooks
+    }
+
+    /// Takes the lines printed so far, they are also logged
+    pub fn take_output(&self) -> Vec<String> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    fn run(&self, emulator: &mut Emulator, actions: &[Action]) {
+        for action in actions {
+            match action {
+                Action::Print(values) => {
+                    let line = values
+                        .iter()
+                        .map(|value| format!("{}=0x{:x}", value.name(), read(emulator, value)))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    info!(%line, "script output");
+                    self.output.lock().unwrap().push(line);
+                }
+                Action::Set(place, value) => {
+                    let value = read(emulator, value);
+                    if let Err(error) = write(emulator, place, value) {
+                        error!(%error, place = place.name(), "script failed to set value");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ExecutionObserver for Script {
+    fn before_execute(&mut self, emulator: &mut Emulator, address: usize, _: Instruction) {
+        self.watched = self
+            .hooks
+            .iter()
+            .filter_map(|hook| match hook.trigger {
+                Trigger::Watch(watched) => Some((watched, read_byte(emulator, watched))),
+                Trigger::At(_) => None,
+            })
+            .collect();
+        for hook in &self.hooks {
+            if hook.trigger == Trigger::At(address) {
+                self.run(emulator, &hook.actions);
+            }
+        }
+    }
+
+    fn after_execute(&mut self, emulator: &mut Emulator, _: usize, _: Instruction) {
+        let watched = std::mem::take(&mut self.watched);
+        for (hook, (address, before)) in self
+            .hooks
+            .iter()
+            .filter(|hook| matches!(hook.trigger, Trigger::Watch(_)))
+            .zip(watched)
+        {
+            if read_byte(emulator, address) != before {
+                self.run(emulator, &hook.actions);
+            }
+        }
+    }
+}
+
+fn read_byte(emulator: &Emulator, address: usize) -> Option<u8> {
+    emulator
+        .read_memory(address..address + 1)
+        .ok()
+        .map(|bytes| bytes[0])
+}
+
+fn read(emulator: &Emulator, value: &Value) -> usize {
+    let snapshot = emulator.create_snapshot();
+    match value {
+        Value::Register(register) => snapshot.registries[register.value() as usize] as usize,
+        Value::AddressRegister => snapshot.address_register,
+        Value::ProgramCounter => snapshot.program_counter,
+        Value::DelayTimer => snapshot.delay_timer as usize,
+        Value::SoundTimer => snapshot.sound_timer as usize,
+        Value::Memory(address) => read_byte(emulator, *address).unwrap_or_default() as usize,
+        Value::Number(number) => *number,
+    }
+}
+
+fn write(emulator: &mut Emulator, place: &Value, value: usize) -> Result<(), EmulatorError> {
+    match place {
+        Value::Register(register) => emulator.set_register(*register, value as u8),
+        Value::AddressRegister => emulator.set_address_register(value)?,
+        Value::ProgramCounter => emulator.set_pc(value)?,
+        Value::Memory(address) => emulator.write_memory(*address, &[value as u8])?,
+        Value::DelayTimer | Value::SoundTimer | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_trigger(text: &str) -> Option<Trigger> {
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        ["at", address] => parse_number(address).map(Trigger::At),
+        ["watch", address] => parse_number(address).map(Trigger::Watch),
+        _ => None,
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    let value = match text {
+        "i" => Some(Value::AddressRegister),
+        "pc" => Some(Value::ProgramCounter),
+        "dt" => Some(Value::DelayTimer),
+        "st" => Some(Value::SoundTimer),
+        _ if text.starts_with('[') && text.ends_with(']') => {
+            parse_number(&text[1..text.len() - 1]).map(Value::Memory)
+        }
+        _ => match text.strip_prefix('v') {
+            Some(register) if register.len() == 1 => u8::from_str_radix(register, 16)
+                .ok()
+                .map(|register| Value::Register(register.into())),
+            _ => parse_number(text).map(Value::Number),
+        },
+    };
+    value.ok_or_else(|| format!("invalid value '{}'", text))
+}
+
+fn parse_action(text: &str) -> Result<Action, String> {
+    let (command, arguments) = text.split_once(' ').unwrap_or((text, ""));
+    match command {
+        "print" => {
+            let values = arguments
+                .split(',')
+                .map(parse_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Action::Print(values))
+        }
+        "set" => {
+            let arguments: Vec<&str> = arguments.split_whitespace().collect();
+            let [place, value] = arguments[..] else {
+                return Err("expected 'set <place> <value>'".to_string());
+            };
+            let place = parse_value(place)?;
+            if !place.settable() {
+                return Err(format!("'{}' can't be set", place.name()));
+            }
+            Ok(Action::Set(place, parse_value(value)?))
+        }
+        _ => Err(format!("unknown action '{}'", command)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{Builder, RunLimit};
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(
+            "# comment\n\
+             at 0x202: print v3, i; set v3 0x10\n\
+             \n\
+             watch 768: print [0x300]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            script.hooks(),
+            &[
+                Hook {
+                    trigger: Trigger::At(0x202),
+                    actions: vec![
+                        Action::Print(vec![Value::Register(3.into()), Value::AddressRegister]),
+                        Action::Set(Value::Register(3.into()), Value::Number(0x10)),
+                    ],
+                },
+                Hook {
+                    trigger: Trigger::Watch(0x300),
+                    actions: vec![Action::Print(vec![Value::Memory(0x300)])],
+                },
+            ]
+        );
+
+        for (source, line) in [
+            ("at 0x200 print v0", 1),
+            ("\nat 0x200: set dt 1", 2),
+            ("at 0x200: print vg", 1),
+            ("at 0x200: jump 0x300", 1),
+            ("when 0x200: print v0", 1),
+        ] {
+            assert_eq!(Script::parse(source).unwrap_err().line, line);
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let script = Script::parse(
+            "at 0x202: print v3; set v3 0x10\n\
+             watch 0x300: print [0x300], pc",
+        )
+        .unwrap();
+        // ldb r3 5, add r3 1, ldi 0x300, bcd r3, ret
+        let rom = [0x63, 0x05, 0x73, 0x01, 0xA3, 0x00, 0xF3, 0x33, 0x00, 0xEE];
+        let mut e = Builder::new()
+            .with_observer(Box::new(script.clone()))
+            .load_bytes(&rom)
+            .unwrap();
+        e.run_for(RunLimit::Ticks(4));
+        // The hook at 0x202 set v3 before it was incremented, storing 017
+        assert_eq!(e.read_memory(0x300..0x303).unwrap(), &[0, 1, 7]);
+        // The hundreds digit is unchanged, so the watch didn't trigger
+        assert_eq!(script.take_output(), vec!["v3=0x5"]);
+
+        let script = Script::parse("watch 0x302: print [0x302], pc").unwrap();
+        let mut e = Builder::new()
+            .with_observer(Box::new(script.clone()))
+            .load_bytes(&rom)
+            .unwrap();
+        e.run_for(RunLimit::Ticks(4));
+        assert_eq!(script.take_output(), vec!["[0x302]=0x6 pc=0x208"]);
+    }
+}
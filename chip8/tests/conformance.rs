@@ -0,0 +1,117 @@
+//!
+//! Golden-ROM conformance, runs ROMs headlessly for a number of frames and
+//! compares the display against the expected frames in tests/frames
+//!
+//! cargo test -p chip8 --features conformance --test conformance
+//!
+//! Each line of golden.txt is `<rom> <frames>`, the ROM relative to the roms
+//! directory of the workspace. Its expected display is drawn in
+//! tests/frames/<rom>.txt, `#` for a set pixel, so that every frame can be
+//! checked by eye. roms/tests/opcodes.bin is assembled from opcodes.asm, its
+//! frame follows from the program rather than from this emulator. A missing
+//! ROM or frame fails the test. Run with CHIP8_BLESS=1 to write the frames of
+//! the current emulator instead of comparing them, and review them before
+//! committing.
+//!
+#![cfg(feature = "conformance")]
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chip8::emulator::{Builder, Emulator, ManualClock, RunLimit, StopReason};
+
+const HERTZ: u64 = 600;
+const TICKS_PER_FRAME: u64 = HERTZ / 60;
+const SEED: u64 = 0;
+
+struct Golden {
+    rom: String,
+    frames: u64,
+}
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms")
+}
+
+fn frame_path(rom: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/frames")
+        .join(rom)
+        .with_extension("txt")
+}
+
+fn parse_golden(source: &str) -> Vec<Golden> {
+    source
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [rom, frames] => Golden {
+                    rom: rom.to_string(),
+                    frames: frames.parse().expect("invalid frame count"),
+                },
+                _ => panic!("invalid golden line '{}'", line),
+            },
+        )
+        .collect()
+}
+
+/// Runs the ROM for the frames, advancing the clock with every tick
+fn run(rom: &[u8], frames: u64) -> Result<Emulator, String> {
+    let clock = ManualClock::new();
+    let mut emulator = Builder::new()
+        .with_clock(Box::new(clock.clone()))
+        .with_seed(SEED)
+        .load_bytes(rom)
+        .map_err(|e| e.to_string())?;
+    for _ in 0..frames * TICKS_PER_FRAME {
+        clock.advance(Duration::from_micros(1_000_000 / HERTZ));
+        match emulator.run_for(RunLimit::Ticks(1)).stop_reason {
+            StopReason::Limit | StopReason::WaitingForKey => {}
+            StopReason::Exit => break,
+            reason => return Err(format!("stopped: {:?}", reason)),
+        }
+    }
+    Ok(emulator)
+}
+
+#[test]
+fn test_golden_roms() {
+    let source =
+        std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden.txt"))
+            .unwrap();
+    let bless = std::env::var_os("CHIP8_BLESS").is_some();
+    let mut failures = Vec::new();
+    for golden in parse_golden(&source) {
+        let rom = match std::fs::read(roms_dir().join(&golden.rom)) {
+            Ok(rom) => rom,
+            Err(error) => {
+                failures.push(format!("{}: {}", golden.rom, error));
+                continue;
+            }
+        };
+        let frame = match run(&rom, golden.frames) {
+            Ok(emulator) => emulator.copy_graphics_buffer().to_ascii(),
+            Err(error) => {
+                failures.push(format!("{}: {}", golden.rom, error));
+                continue;
+            }
+        };
+        let path = frame_path(&golden.rom);
+        if bless {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &frame).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(expected) if expected == frame => {}
+            Ok(expected) => failures.push(format!(
+                "{}: display after {} frames\n{}expected\n{}",
+                golden.rom, golden.frames, frame, expected
+            )),
+            Err(error) => failures.push(format!("{}: {}: {}", golden.rom, path.display(), error)),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
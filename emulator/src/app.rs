@@ -1,24 +1,68 @@
-use std::{
-    sync::mpsc::{channel, Sender},
-    thread::JoinHandle,
-};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
 
 use tracing::{error, info};
 
 use chip8::{
-    emulator::{self, Emulator, KeyStatus, Message, Snapshot, GRAPHICS_BUFFER_SIZE},
+    emulator::{
+        self, Breakpoint, Cheat, DrawMode, Emulator, EmulatorError, Font, Frame, FrameBuffer,
+        InputRecording, KeyStatus, LargeFont, MemoryProtection, Message, Quirks, Snapshot,
+        StackFault, Stats, TickOutcome, DEFAULT_LARGE_SPRITES, DEFAULT_SPRITES,
+    },
     instructions::u4,
+    machine::Machine,
+    profile::Profile,
+    rom::RomInfo,
+    script::Script,
 };
 
+/// Lines kept by the console
+const CONSOLE_LINES: usize = 100;
+/// Ticks between the checkpoints stepped back from
+const REWIND_INTERVAL: u64 = 100;
+
 pub struct App {
     fps: usize,
     hertz: usize,
     timeboxes: usize,
+    font: Font,
+    large_font: LargeFont,
+    draw_mode: DrawMode,
+    trace_size: usize,
+    profiling: bool,
+    coverage: bool,
+    tick_tracing: bool,
+    memory_protection: MemoryProtection,
+    stack_fault: StackFault,
+    memory_size: usize,
+    quirks: Quirks,
+    crash_dir: Option<PathBuf>,
+    /// Checkpoints kept to step back from
+    rewind: usize,
+    /// Where the last failure of the emulator was reported
+    crash_report: Option<PathBuf>,
+    console: VecDeque<String>,
+    /// Loaded after the program, see [Emulator::load_segment]
+    segments: Vec<(usize, Vec<u8>)>,
+    /// Added to every program, see [Emulator::add_cheat]
+    cheats: Vec<Cheat>,
+    record_input: bool,
+    replay: Option<InputRecording>,
+    script: Option<Script>,
     file: Option<String>,
+    /// Identifies the loaded program, see [Emulator::rom_info]
+    rom_info: Option<RomInfo>,
+    /// Whether the profiles next to programs are applied
+    sidecar_profiles: bool,
+    /// The profile applied to the loaded program
+    profile: Option<Profile>,
     should_quit: bool,
     view_state: ViewState,
     emulator_state: EmulatorState,
-    graphics_buffer: [u8; GRAPHICS_BUFFER_SIZE],
+    graphics_buffer: FrameBuffer,
+    /// The emulator frame of the graphics buffer
+    graphics_frame: Option<u64>,
     last_snapshot: Snapshot,
 }
 
@@ -29,10 +73,34 @@ impl App {
             fps,
             hertz,
             timeboxes,
+            font: DEFAULT_SPRITES,
+            large_font: DEFAULT_LARGE_SPRITES,
+            draw_mode: DrawMode::Clip,
+            trace_size: 0,
+            profiling: false,
+            coverage: false,
+            tick_tracing: false,
+            memory_protection: MemoryProtection::Off,
+            stack_fault: StackFault::Error,
+            memory_size: emulator::MEMSIZE,
+            quirks: Quirks::default(),
+            crash_dir: None,
+            rewind: 0,
+            crash_report: None,
+            console: VecDeque::new(),
+            segments: Vec::new(),
+            cheats: Vec::new(),
+            record_input: false,
+            replay: None,
+            script: None,
             file: None,
+            rom_info: None,
+            sidecar_profiles: true,
+            profile: None,
             view_state: ViewState::GameView,
             emulator_state: EmulatorState::Unloaded,
-            graphics_buffer: [0; GRAPHICS_BUFFER_SIZE],
+            graphics_buffer: FrameBuffer::default(),
+            graphics_frame: None,
             last_snapshot: Snapshot::default(),
         }
     }
@@ -59,17 +127,32 @@ impl App {
         match emulator_state {
             EmulatorState::Running(state) => {
                 info!("pausing emulator");
-                match state.sender.send(Message::Pause) {
-                    Ok(_) => {}
-                    Err(error) => error!(%error, "failed to send pause command to emulator"),
-                };
-                let emulator = match state.handle.join() {
+                let emulator = match state.machine.stop() {
                     Ok(e) => e,
                     Err(error) => {
-                        error!(?error, "failed to thread::join on emulator");
+                        error!(%error, "failed to stop the emulator machine");
                         return;
                     }
                 };
+                if let Some(report) = emulator.profile_report() {
+                    info!(
+                        hertz = report.hertz(),
+                        instructions = report.instructions,
+                        hottest = ?report.hottest(10),
+                        "execution profile"
+                    );
+                }
+                if let Some(report) = emulator.coverage_report() {
+                    info!(
+                        percentage = report.percentage(),
+                        executed = report.executed_percentage(),
+                        uncovered = ?report.uncovered(),
+                        "program coverage"
+                    );
+                }
+                if let Some(path) = emulator.crash_report_path() {
+                    self.crash_report = Some(path.to_path_buf());
+                }
                 self.emulator_state = EmulatorState::Paused(PausedEmulator { emulator });
             }
             _ => {
@@ -83,9 +166,15 @@ impl App {
         match emulator_state {
             EmulatorState::Paused(state) => {
                 info!("starting emulator");
-                let (sender, receiver) = channel::<Message>();
-                let handle = state.emulator.run(Some(receiver));
-                let state = RunningEmulator { handle, sender };
+                let machine = Machine::start(state.emulator);
+                let frames = match machine.subscribe() {
+                    Ok(frames) => frames,
+                    Err(error) => {
+                        error!(%error, "failed to subscribe to emulator frames");
+                        channel().1
+                    }
+                };
+                let state = RunningEmulator { machine, frames };
                 self.emulator_state = EmulatorState::Running(state);
             }
             _ => {
@@ -102,7 +191,7 @@ impl App {
         &mut self.emulator_state
     }
 
-    pub fn graphics_buffer(&self) -> &[u8; GRAPHICS_BUFFER_SIZE] {
+    pub fn graphics_buffer(&self) -> &FrameBuffer {
         &self.graphics_buffer
     }
 
@@ -110,6 +199,20 @@ impl App {
         self.file.as_ref()
     }
 
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    /// Applies the profiles next to loaded programs, overriding the
+    /// settings, see [emulator::Builder::with_sidecar_profiles]
+    pub fn set_sidecar_profiles(&mut self, sidecar_profiles: bool) {
+        self.sidecar_profiles = sidecar_profiles;
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
     pub fn hertz(&self) -> usize {
         self.hertz
     }
@@ -123,44 +226,203 @@ impl App {
     }
 
     pub fn tick(&mut self) {
+        self.update_console();
         match &mut self.emulator_state {
             EmulatorState::Unloaded => {}
             EmulatorState::Paused(state) => {
-                self.graphics_buffer = state.emulator.copy_graphics_buffer();
+                let frame = state.emulator.graphics_frame();
+                if self.graphics_frame != Some(frame) {
+                    self.graphics_buffer = state.emulator.copy_graphics_buffer();
+                    self.graphics_frame = Some(frame);
+                }
             }
             EmulatorState::Running(state) => {
-                let (gs, gr) = channel();
-                if state.sender.send(Message::SendGraphics(gs)).is_ok() {
-                    if let Ok(buffer) = gr.recv() {
-                        self.graphics_buffer = buffer;
+                if let Some(frame) = state.frames.try_iter().last() {
+                    self.graphics_buffer = frame.buffer;
+                    self.graphics_frame = Some(frame.number);
+                }
+                // Breakpoints, exits and failures suspend the execution
+                if let Ok(false) = state.machine.is_suspended() {
+                    if !matches!(self.view_state, ViewState::DebugView) {
+                        return;
+                    }
+                    // The debug view follows the running emulator
+                    if let Ok(snapshot) = state.machine.snapshot(false) {
+                        self.last_snapshot = snapshot;
                         return;
                     }
                 }
 
-                // An error occurred == channel disconnect
-                // the emulator has paused.
-                error!("detected emulator termination, pausing emulator");
+                // The execution is suspended or the thread hung up
+                info!("detected suspended emulator, pausing emulator");
                 self.pause();
             }
         }
     }
 
-    pub fn emulator_from_file(&mut self, file: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let emulator = emulator::Builder::new()
+    /// Moves the lines printed by the program into the console
+    fn update_console(&mut self) {
+        let output = match &mut self.emulator_state {
+            EmulatorState::Unloaded => return,
+            EmulatorState::Paused(state) => state.emulator.take_debug_output(),
+            EmulatorState::Running(state) => state.machine.take_debug_output().unwrap_or_default(),
+        };
+        for line in output {
+            if self.console.len() == CONSOLE_LINES {
+                self.console.pop_front();
+            }
+            self.console.push_back(line);
+        }
+    }
+
+    /// The last lines printed by the program with the `debug` instruction
+    pub fn console(&self) -> impl Iterator<Item = &String> {
+        self.console.iter()
+    }
+
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+    }
+
+    pub fn set_large_font(&mut self, large_font: LargeFont) {
+        self.large_font = large_font;
+    }
+
+    pub fn set_draw_mode(&mut self, draw_mode: DrawMode) {
+        self.draw_mode = draw_mode;
+    }
+
+    /// Records the last executed instructions, see [App::export_trace]
+    pub fn set_trace_size(&mut self, trace_size: usize) {
+        self.trace_size = trace_size;
+    }
+
+    /// Logs the execution profile whenever the emulator pauses
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    /// Logs the coverage of the program whenever the emulator pauses
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = coverage;
+    }
+
+    /// Logs every tick and executed instruction, see
+    /// [emulator::Builder::with_tick_tracing]
+    pub fn set_tick_tracing(&mut self, tick_tracing: bool) {
+        self.tick_tracing = tick_tracing;
+    }
+
+    pub fn set_memory_protection(&mut self, memory_protection: MemoryProtection) {
+        self.memory_protection = memory_protection;
+    }
+
+    pub fn set_stack_fault(&mut self, stack_fault: StackFault) {
+        self.stack_fault = stack_fault;
+    }
+
+    pub fn set_memory_size(&mut self, memory_size: usize) {
+        self.memory_size = memory_size;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Loads the bytes at the address after every program
+    pub fn add_segment(&mut self, address: usize, bytes: Vec<u8>) {
+        self.segments.push((address, bytes));
+    }
+
+    /// Keeps the cheat applied in every program
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    /// Writes a crash report to the directory when the emulator fails
+    pub fn set_crash_dir(&mut self, crash_dir: Option<PathBuf>) {
+        self.crash_dir = crash_dir;
+    }
+
+    /// Keeps the checkpoints to step back from, see [App::emulator_step_back]
+    pub fn set_rewind(&mut self, checkpoints: usize) {
+        self.rewind = checkpoints;
+    }
+
+    pub fn crash_report(&self) -> Option<&PathBuf> {
+        self.crash_report.as_ref()
+    }
+
+    /// Records the key events of loaded programs, see [App::export_recording]
+    pub fn set_record_input(&mut self, record_input: bool) {
+        self.record_input = record_input;
+    }
+
+    /// Replays the recording in loaded programs
+    pub fn set_replay(&mut self, replay: Option<InputRecording>) {
+        self.replay = replay;
+    }
+
+    /// Runs the script's hooks in loaded programs
+    pub fn set_script(&mut self, script: Option<Script>) {
+        self.script = script;
+    }
+
+    pub fn emulator_from_file(&mut self, file: &str) -> Result<(), EmulatorError> {
+        let mut builder = emulator::Builder::new()
             .with_hertz(self.hertz)
             .with_timeboxes(self.timeboxes)
-            .load_program(file)?;
+            .with_font(self.font)
+            .with_large_font(self.large_font)
+            .with_draw_mode(self.draw_mode)
+            .with_trace(self.trace_size)
+            .with_profiling(self.profiling)
+            .with_coverage(self.coverage)
+            .with_tick_tracing(self.tick_tracing)
+            .with_memory_protection(self.memory_protection)
+            .with_stack_fault(self.stack_fault)
+            .with_memory_size(self.memory_size)
+            .with_rewind(self.rewind, REWIND_INTERVAL)
+            .with_sidecar_profiles(self.sidecar_profiles)
+            .with_quirks(self.quirks);
+        if let Some(crash_dir) = &self.crash_dir {
+            builder = builder.with_crash_dir(crash_dir);
+        }
+        let mut emulator = builder.load_program(file)?;
+        for (address, bytes) in &self.segments {
+            emulator.load_segment(*address, bytes.clone())?;
+        }
+        for cheat in &self.cheats {
+            emulator.add_cheat(cheat.clone())?;
+        }
+        if self.record_input {
+            emulator.start_recording();
+        }
+        if let Some(recording) = &self.replay {
+            emulator.replay(recording.clone());
+        }
+        if let Some(script) = &self.script {
+            emulator.add_observer(Box::new(script.clone()));
+        }
         self.file = Some(file.to_string());
+        self.rom_info = emulator.rom_info().cloned();
+        self.profile = emulator.profile().cloned();
+        if let Some(profile) = &self.profile {
+            self.hertz = profile.hertz.unwrap_or(self.hertz);
+            self.timeboxes = profile.timeboxes.unwrap_or(self.timeboxes);
+            self.quirks = profile.quirks.unwrap_or(self.quirks);
+        }
+        if let Some(rom_info) = &self.rom_info {
+            info!(%rom_info, "loaded program");
+        }
+        self.crash_report = None;
+        self.graphics_frame = None;
         self.last_snapshot = emulator.create_snapshot();
         self.emulator_state = EmulatorState::Paused(PausedEmulator { emulator });
         Ok(())
     }
 
-    pub fn set_key(
-        &mut self,
-        key: u4,
-        status: KeyStatus,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn set_key(&mut self, key: u4, status: KeyStatus) -> Result<(), EmulatorError> {
         match &mut self.emulator_state {
             EmulatorState::Paused(state) => {
                 // Key presses become toggles in pause mode
@@ -175,11 +437,11 @@ impl App {
                 self.last_snapshot = state.emulator.create_snapshot();
             }
             EmulatorState::Running(state) => {
-                match state.sender.send(Message::KeyEvent(key, status)) {
+                match state.machine.send(Message::KeyEvent(key, status)) {
                     Ok(_) => {}
                     Err(error) => {
                         error!(%error, "failed to send key event to emulator");
-                        return Err(error.into());
+                        return Err(error);
                     }
                 };
             }
@@ -195,20 +457,125 @@ impl App {
     }
 
     pub fn emulator_step(&mut self) {
-        match &mut self.emulator_state {
-            EmulatorState::Paused(state) => match state.emulator.tick() {
+        if let EmulatorState::Paused(state) = &mut self.emulator_state {
+            let mut outcome = state.emulator.tick();
+            // A breakpoint stops before the instruction, a step executes it
+            if let Ok(TickOutcome::Stopped(Breakpoint::Address(_) | Breakpoint::Instruction(..))) =
+                outcome
+            {
+                outcome = state.emulator.tick();
+            }
+            match outcome {
                 Ok(_) => {
                     self.last_snapshot = state.emulator.create_snapshot();
                 }
                 Err(error) => error!(%error, "failed to step emulator"),
-            },
-            _ => {}
+            }
+        }
+    }
+
+    /// Undoes the last instruction while paused, see [Emulator::step_back]
+    pub fn emulator_step_back(&mut self) {
+        if let EmulatorState::Paused(state) = &mut self.emulator_state {
+            if state.emulator.step_back() {
+                self.last_snapshot = state.emulator.create_snapshot();
+            } else {
+                info!("no history to step back into");
+            }
         }
     }
 
+    /// Starts the loaded program over, see [Emulator::restart]
+    pub fn restart(&mut self) -> Result<(), EmulatorError> {
+        match &mut self.emulator_state {
+            EmulatorState::Unloaded => return Ok(()),
+            EmulatorState::Paused(state) => {
+                state.emulator.restart()?;
+                self.last_snapshot = state.emulator.create_snapshot();
+            }
+            EmulatorState::Running(state) => state.machine.restart()?,
+        }
+        info!("restarted the program");
+        self.crash_report = None;
+        self.graphics_frame = None;
+        Ok(())
+    }
+
+    /// Writes the state of the paused emulator next to the program, as
+    /// `<file>.state`
+    pub fn save_state(&mut self) -> Result<(), EmulatorError> {
+        let (EmulatorState::Paused(state), Some(file)) = (&self.emulator_state, &self.file) else {
+            return Ok(());
+        };
+        let path = format!("{}.state", file);
+        std::fs::write(&path, state.emulator.save_state())?;
+        info!(%path, "saved emulator state");
+        Ok(())
+    }
+
+    /// Restores the state written by [App::save_state] into the paused
+    /// emulator
+    pub fn load_state(&mut self) -> Result<(), EmulatorError> {
+        let (EmulatorState::Paused(state), Some(file)) = (&mut self.emulator_state, &self.file)
+        else {
+            return Ok(());
+        };
+        let path = format!("{}.state", file);
+        state.emulator.load_state(&std::fs::read(&path)?)?;
+        info!(%path, "loaded emulator state");
+        self.last_snapshot = state.emulator.create_snapshot();
+        Ok(())
+    }
+
+    /// Writes the trace of the emulator next to the program, as
+    /// `<file>.trace.jsonl`
+    pub fn export_trace(&mut self) -> Result<(), EmulatorError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let trace = match &self.emulator_state {
+            EmulatorState::Unloaded => return Ok(()),
+            EmulatorState::Paused(state) => state.emulator.trace(),
+            EmulatorState::Running(state) => state.machine.request(Message::SendTrace)?,
+        };
+        let path = format!("{}.trace.jsonl", file);
+        emulator::export_trace(&trace, std::fs::File::create(&path)?)?;
+        info!(%path, entries = trace.len(), "exported instruction trace");
+        Ok(())
+    }
+
+    /// Writes the input recorded by the emulator next to the program, as
+    /// `<file>.input`
+    pub fn export_recording(&mut self) -> Result<(), EmulatorError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let recording = match &self.emulator_state {
+            EmulatorState::Unloaded => return Ok(()),
+            EmulatorState::Paused(state) => state.emulator.recording(),
+            EmulatorState::Running(state) => state.machine.request(Message::SendRecording)?,
+        };
+        let Some(recording) = recording else {
+            return Ok(());
+        };
+        let path = format!("{}.input", file);
+        recording.save(std::fs::File::create(&path)?)?;
+        info!(%path, events = recording.events.len(), "exported input recording");
+        Ok(())
+    }
+
     pub fn emulator_snapshot(&mut self) -> &Snapshot {
         &self.last_snapshot
     }
+
+    /// The pacing of the loaded emulator, also while running
+    pub fn emulator_stats(&self) -> Option<Stats> {
+        match &self.emulator_state {
+            EmulatorState::Unloaded => None,
+            EmulatorState::Paused(state) => Some(state.emulator.stats()),
+            EmulatorState::Running(state) => state.machine.stats().ok(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -217,6 +584,7 @@ pub enum ViewState {
     DebugView,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum EmulatorState {
     Unloaded,
     Paused(PausedEmulator),
@@ -228,6 +596,7 @@ pub struct PausedEmulator {
 }
 
 pub struct RunningEmulator {
-    pub handle: JoinHandle<Emulator>,
-    pub sender: Sender<Message>,
+    pub machine: Machine,
+    /// The frames broadcast by the emulator
+    pub frames: Receiver<Frame>,
 }
@@ -15,8 +15,10 @@ pub enum Event {
     /// Key press
     KeyEvent(KeyEvent),
     /// Mouse click/scroll
+    #[allow(dead_code)]
     Mouse(MouseEvent),
     /// Terminal resize
+    #[allow(dead_code)]
     Resize(u16, u16),
 }
 
@@ -2,7 +2,7 @@ use clap::Parser;
 
 use std::{fs::File, time::Duration};
 
-use tracing::Level;
+use tracing::{warn, Level};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::fmt;
 
@@ -14,6 +14,8 @@ mod update;
 mod widgets;
 
 use app::App;
+use chip8::emulator::Cheat;
+use chip8::instructions::Target;
 use event::{Event, EventHandler};
 use ratatui::{backend::CrosstermBackend, Terminal};
 use tui::Tui;
@@ -34,7 +36,79 @@ struct Arguments {
     timeboxes: usize,
     #[arg(long, default_value_t = 100)]
     key_press_delay: u64,
+    /// 80 byte font file to load instead of the default sprites
+    #[arg(long)]
+    font: Option<String>,
+    /// 160 byte font file to load instead of the default large sprites
+    #[arg(long)]
+    large_font: Option<String>,
+    /// Wrap sprites around the display edges instead of clipping them
+    #[arg(long)]
+    wrap_sprites: bool,
+    /// Record the last N executed instructions, exported with <F6>
+    #[arg(long, default_value_t = 0)]
+    trace: usize,
+    /// Log the hottest addresses and executed hertz when pausing
+    #[arg(long)]
+    profile: bool,
+    /// Log the share of the program executed or read, and the addresses
+    /// which weren't, when pausing
+    #[arg(long)]
+    coverage: bool,
+    /// Pause on writes to the font area or execution outside of the program
+    #[arg(long)]
+    protect_memory: bool,
+    /// Wrap the stack pointer around on calls with a full stack and
+    /// returns with an empty stack instead of pausing
+    #[arg(long)]
+    wrap_stack: bool,
+    /// Bytes of memory, up to 65536 for large XO-CHIP programs
+    #[arg(long, default_value_t = chip8::emulator::MEMSIZE)]
+    memory_size: usize,
+    /// Platform to run the program as, chip8 for the COSMAC VIP, schip or
+    /// xochip, selecting its quirks. The quirk flags add to them.
+    #[arg(long)]
+    platform: Option<Target>,
+    /// Complete key waits on release, as on the COSMAC VIP. Keys are
+    /// released after the key press delay.
+    #[arg(long)]
+    wait_for_release: bool,
+    /// Draw at most one sprite per frame, waiting for the display as on
+    /// the COSMAC VIP
+    #[arg(long)]
+    display_wait: bool,
+    /// Shift X in place, as on SUPER-CHIP, instead of storing the shifted Y
+    #[arg(long)]
+    shift_in_place: bool,
+    /// Record the key events, exported with <F7>
+    #[arg(long)]
+    record: bool,
+    /// Input recording to replay, as exported with <F7>
+    #[arg(long)]
+    replay: Option<String>,
+    /// Debugger script with hooks run while executing, output is logged
+    #[arg(long)]
+    script: Option<String>,
+    /// Binary loaded at an address after the program, as ADDR:FILE with a
+    /// 0x prefix for hexadecimal addresses. May be repeated.
+    #[arg(long)]
+    segment: Vec<String>,
+    /// Directory to write a crash report to when the emulator fails
+    #[arg(long)]
+    crash_dir: Option<String>,
+    /// Ignore the profile next to the program, e.g. pong.toml for
+    /// pong.bin, which otherwise overrides the speed and quirks flags
+    #[arg(long)]
+    no_profile: bool,
+    /// Checkpoints kept to step back from with 'S', none disables it
+    #[arg(long, default_value_t = 64)]
+    rewind: usize,
+    /// Value kept in place after every tick, as vX=BYTE for a register or
+    /// ADDR=BYTE,... for memory, e.g. v3=9. May be repeated.
+    #[arg(long)]
+    cheat: Vec<Cheat>,
 
+    /// Log at the debug level, including every executed instruction
     #[arg(short, long)]
     debug: bool,
 }
@@ -60,15 +134,89 @@ fn main() -> Result<()> {
 
     let mut app = App::new(args.fps, args.hz, args.timeboxes);
 
+    if let Some(f) = args.font {
+        let bytes = std::fs::read(f)?;
+        app.set_font(chip8::emulator::font_from_bytes(&bytes)?);
+    }
+
+    if let Some(f) = args.large_font {
+        let bytes = std::fs::read(f)?;
+        app.set_large_font(chip8::emulator::large_font_from_bytes(&bytes)?);
+    }
+
+    if args.wrap_sprites {
+        app.set_draw_mode(chip8::emulator::DrawMode::Wrap);
+    }
+
+    app.set_trace_size(args.trace);
+    app.set_profiling(args.profile);
+    app.set_coverage(args.coverage);
+    app.set_tick_tracing(args.debug);
+    let mut quirks = args
+        .platform
+        .map(chip8::emulator::Quirks::for_target)
+        .unwrap_or_default();
+    quirks.wait_for_release |= args.wait_for_release;
+    quirks.display_wait |= args.display_wait;
+    quirks.shift_in_place |= args.shift_in_place;
+    app.set_quirks(quirks);
+    let memory_size = match args.platform {
+        // Large XO-CHIP programs need the whole memory
+        Some(Target::XoChip) => args.memory_size.max(chip8::emulator::XO_CHIP_MEMSIZE),
+        _ => args.memory_size,
+    };
+    app.set_memory_size(memory_size);
+
+    app.set_record_input(args.record);
+    if let Some(f) = args.replay {
+        let file = File::open(f)?;
+        app.set_replay(Some(chip8::emulator::InputRecording::load(file)?));
+    }
+
+    if let Some(f) = args.script {
+        let source = std::fs::read_to_string(f)?;
+        app.set_script(Some(chip8::script::Script::parse(&source)?));
+    }
+
+    app.set_crash_dir(args.crash_dir.map(Into::into));
+    app.set_rewind(args.rewind);
+    app.set_sidecar_profiles(!args.no_profile);
+
+    for segment in args.segment {
+        let (address, file) = segment
+            .split_once(':')
+            .ok_or("segments are given as ADDR:FILE")?;
+        let address = match address.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16)?,
+            None => address.parse()?,
+        };
+        app.add_segment(address, std::fs::read(file)?);
+    }
+    for cheat in args.cheat {
+        app.add_cheat(cheat);
+    }
+
+    if args.protect_memory {
+        app.set_memory_protection(chip8::emulator::MemoryProtection::Error);
+    }
+
+    if args.wrap_stack {
+        app.set_stack_fault(chip8::emulator::StackFault::Wrap);
+    }
+
     if let Some(f) = args.file {
-        app.emulator_from_file(&f).unwrap();
+        app.emulator_from_file(&f)?;
     }
 
     let backend = CrosstermBackend::new(std::io::stderr());
     let terminal = Terminal::new(backend)?;
     let tick_rate = 1_000_000 / args.fps;
     let events = EventHandler::new(tick_rate as u64);
-    let renderer = ui::RendererBuilder::new().build();
+    let mut renderer = ui::RendererBuilder::new();
+    if let Some(profile) = app.profile() {
+        renderer = renderer.with_colors(&profile.colors);
+    }
+    let renderer = renderer.build();
     let mut tui = Tui::new(terminal, events, renderer);
 
     let mut key_handler = update::KeyHandler::new(Duration::from_millis(args.key_press_delay));
@@ -88,6 +236,15 @@ fn main() -> Result<()> {
     key_handler.bind('d', 13);
     key_handler.bind('e', 14);
     key_handler.bind('f', 15);
+    if let Some(profile) = app.profile() {
+        for (key, value) in &profile.keymap {
+            if update::CONTROL_KEYS.contains(key) {
+                warn!(key = ?key, "ignoring keymap key, it's a control key");
+                continue;
+            }
+            key_handler.bind(*key, value.value());
+        }
+    }
 
     tui.enter()?;
     while !app.should_quit() {
@@ -3,6 +3,8 @@ use ratatui::{
     widgets::{Block, BorderType, Borders, Paragraph},
 };
 
+use chip8::profile::{Colors, Rgb};
+
 use crate::{
     app::{App, EmulatorState, ViewState},
     widgets::display::Display,
@@ -15,6 +17,9 @@ pub struct RendererBuilder {
     color_general_bg: Color,
     color_view_fg: Color,
     color_view_bg: Color,
+    /// Pixels lit only in the second XO-CHIP plane, and in both planes
+    color_plane2_fg: Color,
+    color_planes_fg: Color,
     pixel_filled: String,
     pixel_empty: String,
 }
@@ -28,11 +33,31 @@ impl RendererBuilder {
             color_general_bg: Color::DarkGray,
             color_view_fg: Color::Yellow,
             color_view_bg: Color::Blue,
+            color_plane2_fg: Color::LightRed,
+            color_planes_fg: Color::White,
             pixel_filled: "█".to_string(),
             pixel_empty: " ".to_string(),
         }
     }
 
+    /// Draws the display with the colors set in a rom profile
+    pub fn with_colors(mut self, colors: &Colors) -> Self {
+        let rgb = |[r, g, b]: Rgb| Color::Rgb(r, g, b);
+        if let Some(foreground) = colors.foreground {
+            self.color_view_fg = rgb(foreground);
+        }
+        if let Some(background) = colors.background {
+            self.color_view_bg = rgb(background);
+        }
+        if let Some(plane2) = colors.plane2 {
+            self.color_plane2_fg = rgb(plane2);
+        }
+        if let Some(overlap) = colors.overlap {
+            self.color_planes_fg = rgb(overlap);
+        }
+        self
+    }
+
     pub fn build(&self) -> Renderer {
         Renderer {
             style_main: Style::default()
@@ -44,6 +69,12 @@ impl RendererBuilder {
             style_view: Style::default()
                 .fg(self.color_view_fg)
                 .bg(self.color_view_bg),
+            style_plane2: Style::default()
+                .fg(self.color_plane2_fg)
+                .bg(self.color_view_bg),
+            style_planes: Style::default()
+                .fg(self.color_planes_fg)
+                .bg(self.color_view_bg),
             pixel_filled: self.pixel_filled.clone(),
             pixel_empty: self.pixel_empty.clone(),
         }
@@ -54,6 +85,8 @@ pub struct Renderer {
     style_main: Style,
     style_general: Style,
     style_view: Style,
+    style_plane2: Style,
+    style_planes: Style,
     pixel_filled: String,
     pixel_empty: String,
 }
@@ -96,13 +129,23 @@ impl Renderer {
                         app.graphics_buffer(),
                         self.pixel_filled.clone(),
                         self.pixel_empty.clone(),
-                        self.style_view,
+                        [
+                            self.style_view,
+                            self.style_view,
+                            self.style_plane2,
+                            self.style_planes,
+                        ],
                     ),
                     layout[1],
                 );
             }
             ViewState::DebugView => {
-                self.render_debug_view(app, frame, layout[1]);
+                let layout = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(vec![Constraint::Fill(1), Constraint::Length(10)])
+                    .split(layout[1]);
+                self.render_debug_view(app, frame, layout[0]);
+                self.render_console(app, frame, layout[1]);
             }
         }
     }
@@ -119,20 +162,29 @@ impl Renderer {
         let (hz, file) = match app.emulator() {
             EmulatorState::Unloaded => ("N/A".to_string(), "N/A".to_string()),
             _ => {
-                let f = if let Some(f) = app.file() {
+                let mut f = if let Some(f) = app.file() {
                     f.clone()
                 } else {
                     "N/A".to_string()
                 };
+                if let Some(info) = app.rom_info() {
+                    let title = info.title().unwrap_or("unknown");
+                    f.push_str(&format!(" ({}, {})", title, info.target));
+                }
                 (format!("{}hz/{}", app.hertz(), app.timeboxes()), f)
             }
         };
+        let crash = match app.crash_report() {
+            Some(path) => format!(" | Crash report: {}", path.display()),
+            None => String::new(),
+        };
         let msg = format!(
-            "Emulator: {} | FPS: {} | CPU Hz: {} | File: {} | 'q' - quit, 'p' - play/pause, 's' - step, '<F2>' - game, '<F3>' - debug",
+            "Emulator: {} | FPS: {} | CPU Hz: {} | File: {}{} | 'q' - quit, 'p' - play/pause, 's' - step, 'S' - step back, 'r' - restart, '<F2>' - game, '<F3>' - debug, '<F5>'/'<F9>' - save/load state (paused), '<F6>'/'<F7>' - export trace/input",
             emu_state,
             app.fps(),
             hz,
             file,
+            crash,
         );
         frame.render_widget(
             Paragraph::new(msg)
@@ -148,9 +200,45 @@ impl Renderer {
     }
 
     ///
+    /// The last lines printed by the program, newest at the bottom
     ///
+    fn render_console(&self, app: &App, frame: &mut Frame, rect: Rect) {
+        // Leave room for the borders
+        let rows = (rect.height as usize).saturating_sub(2);
+        let lines: Vec<&String> = app.console().collect();
+        let text = lines[lines.len().saturating_sub(rows)..]
+            .iter()
+            .map(|line| line.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        frame.render_widget(
+            Paragraph::new(text)
+                .style(self.style_main)
+                .alignment(Alignment::Left)
+                .block(
+                    Block::default()
+                        .title("console")
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded),
+                ),
+            rect,
+        );
+    }
+
+    ///
+    /// Registries, timers, stack and keyboard state of the last snapshot
     ///
     fn render_debug_view(&self, app: &mut App, frame: &mut Frame, rect: Rect) {
+        let stats = match app.emulator_stats() {
+            Some(stats) => format!(
+                "{} instructions at {:.0} hz, {} frames drawn, {:.1}s waiting for keys",
+                stats.instructions,
+                stats.hertz(),
+                stats.frames_drawn,
+                stats.key_wait.as_secs_f64()
+            ),
+            None => "N/A".to_string(),
+        };
         let snapshot = app.emulator_snapshot();
 
         let regs: String = snapshot
@@ -169,7 +257,7 @@ impl Renderer {
             .join(" ");
         let instr = match snapshot.instruction {
             Some(i) => format!("{:?}", i),
-            None => format!("INVALID"),
+            None => "INVALID".to_string(),
         };
         let data = format!(
             "
@@ -181,6 +269,7 @@ Next: {}
 PC: {:02x}
 SP: {:02x}
 DT: {:02x}
+ST: {:02x}
 I:  {:04x}
 
 Stack:
@@ -188,15 +277,20 @@ Stack:
 
 Keyboard status:
 {}
+
+Stats:
+{}
 ",
             regs,
             instr,
             snapshot.program_counter,
             snapshot.stack_pointer,
             snapshot.delay_timer,
+            snapshot.sound_timer,
             snapshot.address_register,
             snapshot.stack,
             keyboard_status,
+            stats,
         );
         frame.render_widget(
             Paragraph::new(data)
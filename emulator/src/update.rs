@@ -5,10 +5,13 @@ use std::{
 };
 
 use chip8::{emulator::KeyStatus, instructions::u4};
-use tracing::info;
+use tracing::{error, info};
 
 use crate::app::{App, EmulatorState, ViewState};
 
+/// Keys handled before the bound keys, see [KeyHandler::handle_key]
+pub const CONTROL_KEYS: [char; 5] = ['q', 'p', 's', 'S', 'r'];
+
 // Used to deal with artifical key releases
 pub struct KeyHandler {
     // store keybinds and when they where last pressed
@@ -42,19 +45,45 @@ impl KeyHandler {
             KeyCode::Char('s') => {
                 app.emulator_step();
             }
+            KeyCode::Char('S') => {
+                app.emulator_step_back();
+            }
+            KeyCode::Char('r') => {
+                if let Err(error) = app.restart() {
+                    error!(%error, "failed to restart the program");
+                }
+            }
             KeyCode::F(2) => {
                 app.set_view_state(ViewState::GameView);
             }
             KeyCode::F(3) => {
                 app.set_view_state(ViewState::DebugView);
             }
-            KeyCode::Char(c) => {
-                if self.keys.contains_key(&c) {
-                    let value = self.keys[&c].0;
-                    info!(key=?c, value=value.value(), "registered bound key press");
-                    if let Ok(_) = app.set_key(value, KeyStatus::Pressed) {
-                        self.keys.insert(c, (value, Some(Instant::now())));
-                    }
+            KeyCode::F(5) => {
+                if let Err(error) = app.save_state() {
+                    error!(%error, "failed to save emulator state");
+                }
+            }
+            KeyCode::F(6) => {
+                if let Err(error) = app.export_trace() {
+                    error!(%error, "failed to export instruction trace");
+                }
+            }
+            KeyCode::F(7) => {
+                if let Err(error) = app.export_recording() {
+                    error!(%error, "failed to export input recording");
+                }
+            }
+            KeyCode::F(9) => {
+                if let Err(error) = app.load_state() {
+                    error!(%error, "failed to load emulator state");
+                }
+            }
+            KeyCode::Char(c) if self.keys.contains_key(&c) => {
+                let value = self.keys[&c].0;
+                info!(key=?c, value=value.value(), "registered bound key press");
+                if app.set_key(value, KeyStatus::Pressed).is_ok() {
+                    self.keys.insert(c, (value, Some(Instant::now())));
                 }
             }
             _ => {}
@@ -71,7 +100,7 @@ impl KeyHandler {
             if let Some(last) = value.1.take() {
                 if last.elapsed() > self.delay {
                     info!(key=?*key, value=value.0.value(), "released key");
-                    if let Err(_) = app.set_key(value.0, KeyStatus::Up) {
+                    if app.set_key(value.0, KeyStatus::Up).is_err() {
                         value.1 = Some(last)
                     }
                 } else {
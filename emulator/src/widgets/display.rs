@@ -7,8 +7,9 @@ use ratatui::{
 pub struct Display<'a> {
     pixel_filled: String,
     pixel_empty: String,
-    buffer: &'a [u8; emulator::GRAPHICS_BUFFER_SIZE],
-    style: Style,
+    buffer: &'a emulator::FrameBuffer,
+    /// Style of each pixel color, XO-CHIP has four
+    styles: [Style; 4],
 }
 
 impl<'a> Widget for Display<'a> {
@@ -25,43 +26,37 @@ impl<'a> WidgetRef for Display<'a> {
 
 impl<'a> Display<'a> {
     pub fn new(
-        buffer: &'a [u8; emulator::GRAPHICS_BUFFER_SIZE],
+        buffer: &'a emulator::FrameBuffer,
         pixel_filled: String,
         pixel_empty: String,
-        style: Style,
+        styles: [Style; 4],
     ) -> Self {
         Self {
             pixel_filled,
             pixel_empty,
             buffer,
-            style,
+            styles,
         }
     }
 
     ///
-    /// bit-index: 0 1 2 3 4 5 6 7
-    /// (0, 0) => upper bit first byte
-    /// (24, 0) => upper bit, fourth byte
-    /// (7, 0) => lowest bit first byte
-    ///
+    /// Low resolution pixels are two cells wide, high resolution pixels
+    /// one cell to fit the doubled width in a terminal
     ///
     pub fn render_pixels(&self, area: Rect, buf: &mut Buffer) {
-        let cell_width: u16 = 2;
-        for y in 0..(emulator::DISPLAY_HEIGHT as u8) {
-            let mut x_byte = 0;
-            for x in 0..(emulator::DISPLAY_WIDTH as u8) {
-                let bit_index = x % 8;
-                if bit_index == 0 {
-                    x_byte += 1;
-                }
-                let byte = (x_byte - 1) + (8 * y);
-                let mask = (0x1_u32 << (7 - bit_index)) as u8;
-                let bit = self.buffer[byte as usize] & mask;
-                let pixel = if bit > 0 {
+        let cell_width: u16 = match self.buffer.resolution {
+            emulator::Resolution::Low => 2,
+            emulator::Resolution::High => 1,
+        };
+        for y in 0..self.buffer.height() {
+            for x in 0..self.buffer.width() {
+                let color = self.buffer.color(x, y);
+                let pixel = if color > 0 {
                     &self.pixel_filled
                 } else {
                     &self.pixel_empty
                 };
+                let style = self.styles[color as usize];
 
                 for w in 0..cell_width {
                     let x = area.left() + (x as u16) * cell_width + w;
@@ -72,7 +67,7 @@ impl<'a> Display<'a> {
                     if y >= area.height {
                         break;
                     }
-                    buf.get_mut(x, y).set_symbol(pixel).set_style(self.style);
+                    buf.get_mut(x, y).set_symbol(pixel).set_style(style);
                 }
             }
         }
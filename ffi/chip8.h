@@ -0,0 +1,49 @@
+/*
+ * C API of the chip-8 emulator, built as the chip8_ffi library
+ *
+ * Functions returning int return CHIP8_OK or a negative error code,
+ * chip8_tick returns the outcome of the tick or an error code. A panic of
+ * the emulator is returned as CHIP8_FAILED, or as NULL and 0 by the
+ * functions returning pointers and values.
+ */
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define CHIP8_OK 0
+#define CHIP8_INVALID_ARGUMENT -1
+#define CHIP8_FAILED -2
+
+#define CHIP8_CONTINUE 0
+#define CHIP8_EXIT 1
+#define CHIP8_STOPPED 2
+#define CHIP8_WAITING_FOR_KEY 3
+
+typedef struct Chip8 Chip8;
+
+/* Creates an emulator without any program loaded, release with chip8_free */
+Chip8 *chip8_new(void);
+void chip8_free(Chip8 *chip8);
+
+/* Resets the emulator and loads the program at the start address */
+int chip8_load(Chip8 *chip8, const uint8_t *rom, size_t length);
+/* Executes a single instruction */
+int chip8_tick(Chip8 *chip8);
+/* Presses or releases the key, 0 to 15 */
+int chip8_set_key(Chip8 *chip8, uint8_t key, bool pressed);
+
+/*
+ * The display as a byte per pixel, row by row from the top left, with the
+ * color of the pixel, 0 is unlit. The pixels are valid until the next call
+ * with the emulator.
+ */
+const uint8_t *chip8_framebuffer(Chip8 *chip8, size_t *width, size_t *height);
+/* Copies the 16 V registers */
+int chip8_registers(const Chip8 *chip8, uint8_t *registers);
+size_t chip8_program_counter(const Chip8 *chip8);
+size_t chip8_address_register(const Chip8 *chip8);
+
+#endif
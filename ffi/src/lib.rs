@@ -0,0 +1,270 @@
+//!
+//! C API of the emulator, see `chip8.h`
+//!
+//! An emulator is created with [chip8_new] and must be released with
+//! [chip8_free]. Functions returning `int` return [CHIP8_OK] or a negative
+//! error code, [chip8_tick] returns a positive [TickOutcome] code.
+//! Panics are caught at the boundary, returning [CHIP8_FAILED], or null
+//! and 0 from the functions returning pointers and values.
+//!
+use std::{
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use chip8::{
+    emulator::{Builder, Emulator, KeyStatus, TickOutcome, KEY_COUNT, REGISTRY_COUNT},
+    instructions::u4,
+};
+
+pub const CHIP8_OK: i32 = 0;
+/// A null pointer or an out of range argument
+pub const CHIP8_INVALID_ARGUMENT: i32 = -1;
+/// The emulator failed, e.g. on an invalid opcode or a too large ROM, or
+/// panicked
+pub const CHIP8_FAILED: i32 = -2;
+
+pub const CHIP8_CONTINUE: i32 = 0;
+pub const CHIP8_EXIT: i32 = 1;
+pub const CHIP8_STOPPED: i32 = 2;
+pub const CHIP8_WAITING_FOR_KEY: i32 = 3;
+
+/// The emulator behind the opaque C handle
+pub struct Chip8 {
+    emulator: Emulator,
+    /// A byte per pixel, kept for the pointer of [chip8_framebuffer]
+    pixels: Vec<u8>,
+}
+
+/// Runs the body of an entry point, a panic mustn't unwind into C
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(failed)
+}
+
+/// Creates an emulator without any program loaded
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(Chip8 {
+            emulator: Builder::new().build(),
+            pixels: Vec::new(),
+        }))
+    })
+}
+
+/// # Safety
+///
+/// The handle must come from [chip8_new] and isn't usable afterwards, null
+/// is ignored
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        guard((), || drop(Box::from_raw(chip8)));
+    }
+}
+
+/// Resets the emulator and loads the program at the start address
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new] and `rom` must point to `length`
+/// readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load(chip8: *mut Chip8, rom: *const u8, length: usize) -> i32 {
+    let Some(chip8) = chip8.as_mut() else {
+        return CHIP8_INVALID_ARGUMENT;
+    };
+    if rom.is_null() {
+        return CHIP8_INVALID_ARGUMENT;
+    }
+    guard(CHIP8_FAILED, || {
+        match chip8.emulator.load(slice::from_raw_parts(rom, length)) {
+            Ok(_) => CHIP8_OK,
+            Err(_) => CHIP8_FAILED,
+        }
+    })
+}
+
+/// Executes a single instruction, returning the outcome or an error code
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new]
+#[no_mangle]
+pub unsafe extern "C" fn chip8_tick(chip8: *mut Chip8) -> i32 {
+    let Some(chip8) = chip8.as_mut() else {
+        return CHIP8_INVALID_ARGUMENT;
+    };
+    guard(CHIP8_FAILED, || match chip8.emulator.tick() {
+        Ok(TickOutcome::Continue) => CHIP8_CONTINUE,
+        Ok(TickOutcome::Exit) => CHIP8_EXIT,
+        Ok(TickOutcome::Stopped(_)) => CHIP8_STOPPED,
+        Ok(TickOutcome::WaitingForKey) => CHIP8_WAITING_FOR_KEY,
+        Err(_) => CHIP8_FAILED,
+    })
+}
+
+/// Presses or releases the key, 0 to 15
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new]
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) -> i32 {
+    let Some(chip8) = chip8.as_mut() else {
+        return CHIP8_INVALID_ARGUMENT;
+    };
+    if key as usize >= KEY_COUNT {
+        return CHIP8_INVALID_ARGUMENT;
+    }
+    let status = match pressed {
+        true => KeyStatus::Pressed,
+        false => KeyStatus::Up,
+    };
+    guard(CHIP8_FAILED, || {
+        chip8.emulator.set_key(u4::from(key), status);
+        CHIP8_OK
+    })
+}
+
+/// The display as a byte per pixel, row by row from the top left, with
+/// the color of the pixel, 0 is unlit. Stores the dimensions in `width`
+/// and `height` if they aren't null.
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new]. The pixels are valid until the
+/// next call with the handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(
+    chip8: *mut Chip8,
+    width: *mut usize,
+    height: *mut usize,
+) -> *const u8 {
+    let Some(chip8) = chip8.as_mut() else {
+        return ptr::null();
+    };
+    guard(ptr::null(), || {
+        let frame = chip8.emulator.copy_graphics_buffer();
+        chip8.pixels = (0..frame.height())
+            .flat_map(|y| (0..frame.width()).map(move |x| (x, y)))
+            .map(|(x, y)| frame.color(x, y))
+            .collect();
+        if let Some(width) = width.as_mut() {
+            *width = frame.width();
+        }
+        if let Some(height) = height.as_mut() {
+            *height = frame.height();
+        }
+        chip8.pixels.as_ptr()
+    })
+}
+
+/// Copies the 16 V registers into `registers`
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new] and `registers` must point to 16
+/// writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_registers(chip8: *const Chip8, registers: *mut u8) -> i32 {
+    let Some(chip8) = chip8.as_ref() else {
+        return CHIP8_INVALID_ARGUMENT;
+    };
+    if registers.is_null() {
+        return CHIP8_INVALID_ARGUMENT;
+    }
+    guard(CHIP8_FAILED, || {
+        let snapshot = chip8.emulator.create_snapshot();
+        ptr::copy_nonoverlapping(snapshot.registries.as_ptr(), registers, REGISTRY_COUNT);
+        CHIP8_OK
+    })
+}
+
+/// The program counter
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new]
+#[no_mangle]
+pub unsafe extern "C" fn chip8_program_counter(chip8: *const Chip8) -> usize {
+    guard(0, || {
+        chip8
+            .as_ref()
+            .map_or(0, |chip8| chip8.emulator.create_snapshot().program_counter)
+    })
+}
+
+/// The I register
+///
+/// # Safety
+///
+/// The handle must come from [chip8_new]
+#[no_mangle]
+pub unsafe extern "C" fn chip8_address_register(chip8: *const Chip8) -> usize {
+    guard(0, || {
+        chip8
+            .as_ref()
+            .map_or(0, |chip8| chip8.emulator.create_snapshot().address_register)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chip8::emulator::ExecutionObserver;
+    use chip8::instructions::Instruction;
+
+    #[test]
+    fn test_api() {
+        unsafe {
+            let chip8 = chip8_new();
+            // ldb r1 5, ldi 0, draw r0 r0 5, ret
+            let rom = [0x61, 0x05, 0xA0, 0x00, 0xD0, 0x05, 0x00, 0xEE];
+            assert_eq!(chip8_load(chip8, rom.as_ptr(), rom.len()), CHIP8_OK);
+            assert_eq!(chip8_tick(chip8), CHIP8_CONTINUE);
+            assert_eq!(chip8_program_counter(chip8), 0x202);
+
+            let mut registers = [0u8; 16];
+            assert_eq!(chip8_registers(chip8, registers.as_mut_ptr()), CHIP8_OK);
+            assert_eq!(registers[1], 5);
+
+            assert_eq!(chip8_tick(chip8), CHIP8_CONTINUE);
+            assert_eq!(chip8_address_register(chip8), 0);
+            assert_eq!(chip8_tick(chip8), CHIP8_CONTINUE);
+            let (mut width, mut height) = (0, 0);
+            let pixels = chip8_framebuffer(chip8, &mut width, &mut height);
+            assert_eq!((width, height), (64, 32));
+            let pixels = slice::from_raw_parts(pixels, width * height);
+            // The top row of the 0 sprite, 0xF0
+            assert_eq!(&pixels[..5], &[1, 1, 1, 1, 0]);
+            assert_eq!(chip8_tick(chip8), CHIP8_FAILED);
+
+            assert_eq!(chip8_set_key(chip8, 3, true), CHIP8_OK);
+            assert_eq!(chip8_set_key(chip8, 16, true), CHIP8_INVALID_ARGUMENT);
+            assert_eq!(chip8_tick(ptr::null_mut()), CHIP8_INVALID_ARGUMENT);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn test_panic() {
+        struct PanicOnExecute;
+        impl ExecutionObserver for PanicOnExecute {
+            fn before_execute(&mut self, _: &mut Emulator, _: usize, _: Instruction) {
+                panic!("observer");
+            }
+        }
+        unsafe {
+            let chip8 = chip8_new();
+            // jmp 0x200
+            let rom = [0x12, 0x00];
+            assert_eq!(chip8_load(chip8, rom.as_ptr(), rom.len()), CHIP8_OK);
+            (*chip8).emulator.add_observer(Box::new(PanicOnExecute));
+            assert_eq!(chip8_tick(chip8), CHIP8_FAILED);
+            // Still usable afterwards
+            assert_eq!(chip8_program_counter(chip8), 0x202);
+            chip8_free(chip8);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//!
+//! Decodes arbitrary opcodes, which must never panic
+//!
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8::instructions::Instruction;
+
+fuzz_target!(|data: &[u8]| {
+    for chunk in data.chunks_exact(2) {
+        if let Some(instruction) = Instruction::from_opcode_u8(chunk[0], chunk[1]) {
+            let _ = instruction.to_assembly();
+            let _ = instruction.describe();
+        }
+    }
+});
@@ -0,0 +1,21 @@
+//!
+//! Encodes arbitrary instructions, whose opcodes must decode to an
+//! instruction of the same opcode, and reassembles their assembly
+//!
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8::instructions::Instruction;
+
+fuzz_target!(|instruction: Instruction| {
+    let opcode = instruction.opcode();
+    // Raw opcodes and draws of 0 rows share their opcodes with other
+    // instructions, so the opcodes are compared rather than the
+    // instructions
+    if let Some(decoded) = Instruction::from_opcode_u16(opcode) {
+        assert_eq!(decoded.opcode(), opcode);
+    }
+    let parsed: Instruction = instruction.to_string().parse().unwrap();
+    assert_eq!(parsed.opcode(), opcode);
+});
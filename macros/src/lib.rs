@@ -0,0 +1,99 @@
+//!
+//! Macros embedding chip-8 programs in Rust code
+//!
+//! [chip8_asm!] assembles the program at compile time into a `Vec<u8>`
+//! expression, so tests and examples don't have to parse sources at
+//! runtime. Parse errors are reported as compile errors.
+//!
+use std::io::Cursor;
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+use chip8::assembly::diagnostic::Diagnostic;
+use chip8::assembly::lexer::StreamLexer;
+use chip8::assembly::parser::Parser;
+
+/// Assembles the instructions into the binary, e.g.
+///
+/// ```
+/// use chip8_macros::chip8_asm;
+///
+/// let rom: Vec<u8> = chip8_asm! { ldb v1, 5; loop: add v1, 1; jmp loop; };
+/// assert_eq!(rom, vec![0x61, 0x05, 0x71, 0x01, 0x12, 0x02]);
+/// ```
+///
+/// Instructions are separated by `;`, and labels are followed by `:`.
+/// Registers can be written as `r1` or `v1`. Comments are Rust comments.
+#[proc_macro]
+pub fn chip8_asm(input: TokenStream) -> TokenStream {
+    let source = to_source(input);
+    let lexer = StreamLexer::new(Cursor::new(source.into_bytes()));
+    let result = Parser::new(Box::new(lexer)).parse().map_err(|e| {
+        let diagnostic = Diagnostic::from(&e);
+        diagnostic.to_string()
+    });
+    let binary = result.and_then(|assembly| {
+        assembly.binary().map_err(|e| {
+            let diagnostic = Diagnostic::from(&e);
+            diagnostic.to_string()
+        })
+    });
+    let output = match binary {
+        Ok(bytes) => {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("{:#04x}u8", b)).collect();
+            format!("::std::vec![{}]", bytes.join(", "))
+        }
+        Err(message) => format!("::core::compile_error!({:?})", message),
+    };
+    output.parse().unwrap()
+}
+
+/// Rebuilds assembly source from the tokens, one instruction or label per
+/// line
+fn to_source(input: TokenStream) -> String {
+    let mut source = String::new();
+    let mut line: Vec<String> = Vec::new();
+    // Set after punctuation which is joined with the next token, e.g. `%`
+    let mut joined = false;
+    for token in input {
+        let text = match token {
+            TokenTree::Punct(ref p) if p.as_char() == ';' => {
+                source.push_str(&line.join(" "));
+                source.push('\n');
+                line.clear();
+                continue;
+            }
+            TokenTree::Punct(ref p) if p.as_char() == ':' && line.len() == 1 => {
+                source.push_str(&format!("{}:\n", line[0]));
+                line.clear();
+                continue;
+            }
+            TokenTree::Ident(ref ident) => register(&ident.to_string()),
+            TokenTree::Group(ref group) if group.delimiter() == Delimiter::Parenthesis => {
+                format!("({})", group.stream())
+            }
+            ref token => token.to_string(),
+        };
+        match line.last_mut() {
+            Some(last) if joined => last.push_str(&text),
+            _ => line.push(text),
+        }
+        joined = match token {
+            TokenTree::Punct(ref p) => p.as_char() == '%' || p.spacing() == Spacing::Joint,
+            _ => false,
+        };
+    }
+    source.push_str(&line.join(" "));
+    source
+}
+
+/// Translates CHIPPER style `v<x>` registers to the native `r<n>`
+fn register(ident: &str) -> String {
+    let mut chars = ident.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(x), None) if x.is_ascii_hexdigit() => {
+            format!("r{}", x.to_digit(16).unwrap())
+        }
+        _ => ident.to_string(),
+    }
+}
@@ -0,0 +1,25 @@
+use chip8_macros::chip8_asm;
+
+#[test]
+fn test_chip8_asm() {
+    let rom = chip8_asm! { ldb v1, 5; loop: add v1, 1; jmp loop; };
+    assert_eq!(rom, vec![0x61, 0x05, 0x71, 0x01, 0x12, 0x02]);
+
+    let rom = chip8_asm! {
+        // Constants and expressions are folded by the assembler
+        SPEED equ 3;
+        ldb r2 (SPEED * 2);
+        call square;
+        exit;
+        square:
+        ldi sprite;
+        draw vA vB 1;
+        ret;
+        sprite:
+        db 0xF0 0x90;
+    };
+    assert_eq!(
+        rom,
+        vec![0x62, 0x06, 0x22, 0x06, 0xF1, 0xEE, 0xA2, 0x0C, 0xDA, 0xB1, 0x00, 0xEE, 0xF0, 0x90]
+    );
+}
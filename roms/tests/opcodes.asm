@@ -0,0 +1,302 @@
+; Opcode test, runs 16 tests and draws the hexadecimal number of each, in
+; two rows of eight. A failed test is underlined, a test which hangs stops
+; the numbers. Only depends on the original CHIP-8 instructions, and on no
+; quirk: shifts use the same register twice and the address register is set
+; again after every write and read.
+;
+; r10 test number, r11 and r12 where the number is drawn, r13 is 1 until a
+; check of the test fails
+;
+;   assembly asm -i roms/tests/opcodes.asm -o roms/tests/opcodes.bin
+main:
+    ldb r10 0
+    ldb r11 1
+    ldb r12 1
+
+; 0: se and sne
+    ldb r13 1
+    ldb r0 5
+    se r0 5
+    ldb r13 0
+    sne r0 6
+    ldb r13 0
+    se r0 6
+    jmp test0_se
+    ldb r13 0
+test0_se:
+    sne r0 5
+    jmp test0_sne
+    ldb r13 0
+test0_sne:
+    call result
+
+; 1: sre and srne
+    ldb r13 1
+    ldb r0 7
+    ldb r1 7
+    ldb r2 8
+    sre r0 r1
+    ldb r13 0
+    srne r0 r2
+    ldb r13 0
+    sre r0 r2
+    jmp test1_sre
+    ldb r13 0
+test1_sre:
+    srne r0 r1
+    jmp test1_srne
+    ldb r13 0
+test1_srne:
+    call result
+
+; 2: ldb, ldr and add, which wraps without touching the flag
+    ldb r13 1
+    ldb r15 7
+    ldb r0 255
+    add r0 3
+    se r0 2
+    ldb r13 0
+    se r15 7
+    ldb r13 0
+    ldr r1 r0
+    se r1 2
+    ldb r13 0
+    call result
+
+; 3: or, and and xor
+    ldb r13 1
+    ldb r0 0x0C
+    ldb r1 0x0A
+    or r0 r1
+    se r0 0x0E
+    ldb r13 0
+    ldb r0 0x0C
+    and r0 r1
+    se r0 0x08
+    ldb r13 0
+    ldb r0 0x0C
+    xor r0 r1
+    se r0 0x06
+    ldb r13 0
+    call result
+
+; 4: addc, with and without a carry
+    ldb r13 1
+    ldb r0 255
+    ldb r1 3
+    addc r0 r1
+    se r0 2
+    ldb r13 0
+    se r15 1
+    ldb r13 0
+    ldb r0 1
+    addc r0 r1
+    se r0 4
+    ldb r13 0
+    se r15 0
+    ldb r13 0
+    call result
+
+; 5: subc, without and with a borrow
+    ldb r13 1
+    ldb r0 5
+    ldb r1 3
+    subc r0 r1
+    se r0 2
+    ldb r13 0
+    se r15 1
+    ldb r13 0
+    ldb r0 3
+    ldb r1 5
+    subc r0 r1
+    se r0 254
+    ldb r13 0
+    se r15 0
+    ldb r13 0
+    call result
+
+; 6: subnc, without and with a borrow
+    ldb r13 1
+    ldb r0 3
+    ldb r1 5
+    subnc r0 r1
+    se r0 2
+    ldb r13 0
+    se r15 1
+    ldb r13 0
+    ldb r0 5
+    ldb r1 3
+    subnc r0 r1
+    se r0 254
+    ldb r13 0
+    se r15 0
+    ldb r13 0
+    call result
+
+; 7: shr and shl, with the shifted out bit in the flag
+    ldb r13 1
+    ldb r0 5
+    shr r0 r0
+    se r0 2
+    ldb r13 0
+    se r15 1
+    ldb r13 0
+    ldb r0 0x81
+    shl r0 r0
+    se r0 2
+    ldb r13 0
+    se r15 1
+    ldb r13 0
+    ldb r0 0x40
+    shl r0 r0
+    se r15 0
+    ldb r13 0
+    call result
+
+; 8: call and ret
+    ldb r13 1
+    ldb r0 0
+    call test8_sub
+    se r0 1
+    ldb r13 0
+    call result
+    jmp test9
+
+test8_sub:
+    ldb r0 1
+    ret
+
+; 9: ldi, write, read and addi
+test9:
+    ldb r13 1
+    ldb r0 1
+    ldb r1 2
+    ldb r2 3
+    ldi scratch
+    write r2
+    ldb r0 0
+    ldb r1 0
+    ldb r2 0
+    ldi scratch
+    read r2
+    se r0 1
+    ldb r13 0
+    se r1 2
+    ldb r13 0
+    se r2 3
+    ldb r13 0
+    ldi scratch
+    ldb r0 2
+    addi r0
+    read r0
+    se r0 3
+    ldb r13 0
+    call result
+
+; 10: sbcd
+    ldb r13 1
+    ldb r0 123
+    ldi scratch
+    sbcd r0
+    ldi scratch
+    read r2
+    se r0 1
+    ldb r13 0
+    se r1 2
+    ldb r13 0
+    se r2 3
+    ldb r13 0
+    call result
+
+; 11: ldf, which only uses the low digit of the register
+    ldb r13 1
+    ldb r0 0x11
+    ldf r0
+    read r0
+    se r0 0x20
+    ldb r13 0
+    call result
+
+; 12: jmpr, which jumps past the failure
+    ldb r13 1
+    ldb r0 2
+    jmpr test12_base
+test12_base:
+    ldb r13 0
+    call result
+
+; 13: draw, which flags erased pixels, twice at the same place erases
+    ldb r13 1
+    ldb r0 56
+    ldb r1 24
+    ldi underline
+    draw r0 r1 1
+    se r15 0
+    ldb r13 0
+    draw r0 r1 1
+    se r15 1
+    ldb r13 0
+    draw r0 r1 1
+    ldb r0 60
+    draw r0 r1 1
+    se r15 0
+    ldb r13 0
+    draw r0 r1 1
+    ldb r0 56
+    draw r0 r1 1
+    call result
+
+; 14: delay and ldd, the delay timer counts down to 0
+    ldb r13 1
+    ldb r0 10
+    delay r0
+    ldd r1
+    se r1 0
+    jmp test14_wait
+    ldb r13 0
+test14_wait:
+    ldd r1
+    se r1 0
+    jmp test14_wait
+    call result
+
+; 15: rand, the random byte is masked
+    ldb r13 1
+    rand r0 0
+    se r0 0
+    ldb r13 0
+    rand r0 0x0F
+    ldb r1 0xF0
+    and r1 r0
+    se r1 0
+    ldb r13 0
+    call result
+
+end:
+    jmp end
+
+; Draws the test number, underlined when the test failed, and moves to the
+; next test
+result:
+    ldf r10
+    draw r11 r12 5
+    se r13 0
+    jmp result_next
+    ldi underline
+    ldr r0 r12
+    add r0 6
+    draw r11 r0 1
+result_next:
+    add r10 1
+    add r11 8
+    se r11 65
+    ret
+    ldb r11 1
+    add r12 8
+    ret
+
+underline:
+    db 0xF0
+scratch:
+    db 0 0
+    db 0 0